@@ -0,0 +1,32 @@
+//! Records the git commit being built as `BWS_GIT_SHA`, unless it is already
+//! set in the environment (e.g. by CI) or the source is not a git checkout.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=BWS_GIT_SHA");
+    // Missing paths would rerun the script on every build
+    for path in [".git/HEAD", ".git/refs"] {
+        if std::path::Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    if std::env::var_os("BWS_GIT_SHA").is_some() {
+        return;
+    }
+
+    let sha = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+
+    if let Some(sha) = sha {
+        let sha = sha.trim();
+        if !sha.is_empty() {
+            println!("cargo:rustc-env=BWS_GIT_SHA={}", sha);
+        }
+    }
+}
@@ -1,12 +1,18 @@
 use bws_web_server::config::{
-    LoggingConfig, PerformanceConfig, SecurityConfig, ServerConfig, ServerInfo, SiteConfig,
+    LoggingConfig, ManagementConfig, PerformanceConfig, SecurityConfig, ServerConfig, ServerInfo,
+    SiteConfig,
 };
+use bws_web_server::core::cli::{status_summary, BwsCtl};
 use bws_web_server::server::{ManagementApiService, WebServerService};
-use clap::Parser;
+use bws_web_server::ssl::{Certificate, SslConfig, SslManager};
+use clap::{Args, Parser, Subcommand};
 #[cfg(unix)]
 use daemonize::Daemonize;
 use pingora::listeners::tls::TlsSettings;
+use pingora::listeners::TcpSocketOptions;
 use pingora::prelude::*;
+use pingora::protocols::TcpKeepalive;
+use pingora::server::configuration::ServerConf;
 use std::collections::HashMap;
 #[cfg(unix)]
 use std::fs::File;
@@ -42,6 +48,29 @@ mod tests {
         assert_eq!(cleaned, "/usr/local/bin");
     }
 
+    #[test]
+    fn test_certificate_report() {
+        let now = chrono::Utc::now();
+        let certificate = Certificate {
+            domain: "example.com".to_string(),
+            cert_path: "./certs/example.com.crt".into(),
+            key_path: "./certs/example.com.key".into(),
+            issued_at: now - chrono::Duration::days(60),
+            expires_at: now + chrono::Duration::days(20) + chrono::Duration::hours(1),
+            issuer: "Test CA".to_string(),
+            san_domains: vec!["example.com".to_string(), "www.example.com".to_string()],
+            auto_renew: true,
+            last_renewal_check: None,
+        };
+
+        let report = certificate_report(&certificate, true);
+        assert_eq!(report["issuer"], "Test CA");
+        assert_eq!(report["san_domains"][1], "www.example.com");
+        assert_eq!(report["days_until_expiry"], 20);
+        assert_eq!(report["expired"], false);
+        assert_eq!(report["needs_renewal"], true);
+    }
+
     #[test]
     fn test_generate_random_port_range() {
         for _ in 0..100 {
@@ -72,9 +101,9 @@ struct Cli {
     #[arg(help = "Directory to serve as static files")]
     directory: Option<String>,
 
-    /// Configuration file path
+    /// Configuration file or directory; repeat to merge overlays onto a base
     #[arg(short, long)]
-    config: Option<String>,
+    config: Vec<String>,
 
     /// Enable verbose logging
     #[arg(short, long)]
@@ -102,6 +131,53 @@ struct Cli {
     /// Validate configuration and exit (do not start server)
     #[arg(long)]
     dry_run: bool,
+
+    /// Report the issuer, domains and expiry of a configured domain's certificate and exit
+    #[arg(long, value_name = "DOMAIN")]
+    check_cert: Option<String>,
+
+    /// Print the --check-cert report as JSON
+    #[arg(long, requires = "check_cert")]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Commands for an instance that is already running
+#[derive(Subcommand)]
+enum Command {
+    /// Reload the configuration of a running instance
+    Reload {
+        /// PID file of the instance, which is sent SIGHUP - Unix only
+        #[arg(long, conflicts_with = "management_url")]
+        pid_file: Option<String>,
+
+        #[command(flatten)]
+        management: ManagementArgs,
+    },
+
+    /// Show the health, sites, certificates and traffic of a running instance
+    Status {
+        #[command(flatten)]
+        management: ManagementArgs,
+
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// How to reach the management API of a running instance
+#[derive(Args)]
+struct ManagementArgs {
+    /// Base URL of the instance's management API (default: from the configuration)
+    #[arg(long)]
+    management_url: Option<String>,
+
+    /// API key for the management API (default: from the configuration)
+    #[arg(long)]
+    api_key: Option<String>,
 }
 
 /// Create a temporary server configuration for serving a single directory.
@@ -136,24 +212,15 @@ fn create_temporary_config(directory: &str, port: u16) -> ServerConfig {
     let site = SiteConfig {
         name: "main".to_string(),
         hostname: "localhost".to_string(),
-        hostnames: vec![],
         port,
         static_dir: absolute_dir,
         default: true,
-        api_only: false,
-        headers: HashMap::new(),
-        redirect_to_https: false,
         index_files: vec![
             "index.html".to_string(),
             "index.htm".to_string(),
             "default.html".to_string(),
         ],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: Default::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     // Create server configuration
@@ -168,6 +235,66 @@ fn create_temporary_config(directory: &str, port: u16) -> ServerConfig {
         performance: PerformanceConfig::default(),
         security: SecurityConfig::default(),
         management: Default::default(),
+        tracing: Default::default(),
+        statsd: Default::default(),
+        metrics: Default::default(),
+        trusted_proxies: Default::default(),
+        acme_challenge_dir: None,
+        well_known: HashMap::new(),
+    }
+}
+
+/// Point the daemon's stdout and stderr at `path` again, e.g. after the
+/// log file was rotated away
+#[cfg(unix)]
+fn reopen_daemon_log(path: &str) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // SAFETY: both descriptors are valid; dup2 atomically replaces the target
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Socket options for the site listeners, or `None` to keep Pingora's defaults
+fn tcp_socket_options(performance: &PerformanceConfig) -> Option<TcpSocketOptions> {
+    let keepalive = performance.tcp_keepalive.as_ref()?;
+    let mut options = TcpSocketOptions::default();
+    options.tcp_keepalive = Some(TcpKeepalive {
+        idle: std::time::Duration::from_secs(keepalive.idle),
+        interval: std::time::Duration::from_secs(keepalive.interval),
+        count: keepalive.count,
+        #[cfg(target_os = "linux")]
+        user_timeout: std::time::Duration::ZERO,
+    });
+    Some(options)
+}
+
+/// Listen for plain TCP on `addr` with the configured socket options
+fn add_tcp_listener<A>(
+    service: &mut pingora::services::listening::Service<A>,
+    addr: &str,
+    options: &Option<TcpSocketOptions>,
+) {
+    match options {
+        Some(options) => service.add_tcp_with_settings(addr, options.clone()),
+        None => service.add_tcp(addr),
+    }
+}
+
+/// The configuration files named on the command line, for messages
+fn config_source(cli: &Cli) -> String {
+    if cli.config.is_empty() {
+        "config.toml".to_string()
+    } else {
+        cli.config.join("', '")
     }
 }
 
@@ -176,15 +303,17 @@ fn handle_dry_run(config: &ServerConfig, cli: &Cli) {
     println!("BWS Configuration Validation");
     println!("============================");
 
-    if cli.directory.is_some() {
+    if let Some(directory) = &cli.directory {
         println!(" Temporary directory configuration created successfully");
-        println!("    Directory: {}", cli.directory.as_ref().unwrap());
+        println!("    Directory: {}", directory);
         // Extract port from the first site in the config
         let port = config.sites.first().map(|s| s.port).unwrap_or(8080);
         println!("    Port: {}", port);
     } else {
-        let config_path = cli.config.as_deref().unwrap_or("config.toml");
-        println!(" Configuration file '{}' loaded successfully", config_path);
+        println!(
+            " Configuration file '{}' loaded successfully",
+            config_source(cli)
+        );
     }
 
     println!("\n Configuration Summary:");
@@ -194,10 +323,7 @@ fn handle_dry_run(config: &ServerConfig, cli: &Cli) {
     );
     println!("   Sites: {}", config.sites.len());
 
-    let mut validation_errors = Vec::new();
-    let mut warnings = Vec::new();
-
-    // Validate each site configuration
+    // Summarize each site
     for (index, site) in config.sites.iter().enumerate() {
         println!("\n Site {}: {}", index + 1, site.name);
         println!("   Hostname: {}", site.hostname);
@@ -205,98 +331,35 @@ fn handle_dry_run(config: &ServerConfig, cli: &Cli) {
             println!("   Additional hostnames: {}", site.hostnames.join(", "));
         }
         println!("   Port: {}", site.port);
-        println!("   Static directory: {}", site.static_dir);
-
-        // Validate static directory exists
-        if !std::path::Path::new(&site.static_dir).exists() {
-            validation_errors.push(format!(
-                "Site '{}': Static directory '{}' does not exist",
-                site.name, site.static_dir
-            ));
-        } else if !std::path::Path::new(&site.static_dir).is_dir() {
-            validation_errors.push(format!(
-                "Site '{}': Static path '{}' is not a directory",
-                site.name, site.static_dir
-            ));
-        } else {
-            println!("    Static directory exists");
-        }
-
-        // Validate index files exist
-        let mut index_found = false;
-        for index_file in &site.index_files {
-            let index_path = std::path::Path::new(&site.static_dir).join(index_file);
-            if index_path.exists() {
-                println!("    Index file found: {}", index_file);
-                index_found = true;
-                break;
-            }
-        }
-        if !index_found && !site.index_files.is_empty() {
-            warnings.push(format!(
-                "Site '{}': No index files found in static directory",
-                site.name
-            ));
+        if !site.ports.is_empty() {
+            println!(
+                "   Additional ports: {}",
+                site.ports
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
         }
+        println!("   Static directory: {}", site.static_dir);
 
-        // Validate SSL configuration
         if site.ssl.enabled {
             println!("    SSL enabled");
             if site.ssl.auto_cert {
                 println!("    Auto-certificate (ACME) enabled");
-                if let Some(acme) = &site.ssl.acme {
-                    if acme.enabled {
-                        if acme.email.is_empty() {
-                            validation_errors.push(format!(
-                                "Site '{}': ACME email is required when auto_cert is enabled",
-                                site.name
-                            ));
-                        } else {
-                            println!("    ACME email: {}", acme.email);
-                        }
-                    }
-                }
-            } else {
-                // Check for manual certificates
-                let cert_path = format!("./certs/{}.crt", site.hostname);
-                let key_path = format!("./certs/{}.key", site.hostname);
-
-                if std::path::Path::new(&cert_path).exists()
-                    && std::path::Path::new(&key_path).exists()
-                {
-                    println!("    SSL certificates found");
-                } else {
-                    warnings.push(format!(
-                        "Site '{}': SSL enabled but certificates not found at {} and {}",
-                        site.name, cert_path, key_path
-                    ));
+                if let Some(acme) = site.ssl.acme.as_ref().filter(|acme| acme.enabled) {
+                    println!("    ACME email: {}", acme.email);
                 }
             }
         }
 
-        // Validate proxy configuration
         if site.proxy.enabled {
             println!("    Proxy enabled");
-            if site.proxy.upstreams.is_empty() {
-                validation_errors.push(format!(
-                    "Site '{}': Proxy enabled but no upstreams configured",
-                    site.name
-                ));
-            } else {
-                println!("    Upstreams: {}", site.proxy.upstreams.len());
-                for upstream in &site.proxy.upstreams {
-                    println!("     - {}: {}", upstream.name, upstream.url);
-                }
-            }
-
-            if site.proxy.routes.is_empty() {
-                warnings.push(format!(
-                    "Site '{}': Proxy enabled but no routes configured",
-                    site.name
-                ));
-            } else {
-                println!("     Routes: {}", site.proxy.routes.len());
+            println!("    Upstreams: {}", site.proxy.upstreams.len());
+            for upstream in &site.proxy.upstreams {
+                println!("     - {}: {}", upstream.name, upstream.url);
             }
+            println!("     Routes: {}", site.proxy.routes.len());
         }
 
         // Check for custom headers
@@ -305,75 +368,31 @@ fn handle_dry_run(config: &ServerConfig, cli: &Cli) {
         }
     }
 
-    // Check for port conflicts
-    let mut port_usage = std::collections::HashMap::new();
-    for site in &config.sites {
-        port_usage
-            .entry(site.port)
-            .or_insert_with(Vec::new)
-            .push(&site.name);
-    }
-
-    for (port, sites) in &port_usage {
-        if sites.len() > 1 {
-            // Multiple sites on same port - check if they have different hostnames
-            let mut hostnames = std::collections::HashSet::new();
-            let mut has_default = false;
-
-            for site_name in sites {
-                if let Some(site) = config.sites.iter().find(|s| &s.name == *site_name) {
-                    hostnames.insert(&site.hostname);
-                    hostnames.extend(&site.hostnames);
-                    if site.default {
-                        if has_default {
-                            validation_errors
-                                .push(format!("Port {}: Multiple sites marked as default", port));
-                        }
-                        has_default = true;
-                    }
-                } else {
-                    validation_errors.push(format!("Invalid site reference: {}", site_name));
-                }
-            }
-
-            if hostnames.len() == sites.len() || has_default {
-                println!(
-                    "\n Port {} shared by {} sites with virtual hosting",
-                    port,
-                    sites.len()
-                );
-            } else {
-                warnings.push(format!(
-                    "Port {}: Multiple sites with overlapping hostnames",
-                    port
-                ));
-            }
-        }
-    }
+    let report = config.validate_detailed();
 
     // Print validation results
     println!("\n==========================================");
     println!("           VALIDATION RESULTS");
     println!("==========================================");
 
-    if !warnings.is_empty() {
-        println!("  Warnings ({}): ", warnings.len());
-        for warning in &warnings {
+    if !report.warnings.is_empty() {
+        println!("  Warnings ({}): ", report.warnings.len());
+        for warning in &report.warnings {
             println!("     {}", warning);
         }
         println!();
     }
 
-    if validation_errors.is_empty() {
+    if report.is_valid() {
         println!(" Configuration validation passed!");
         println!(" Configuration is ready for deployment");
         std::process::exit(0);
     } else {
         println!(
             " Configuration validation failed ({} errors):",
-            validation_errors.len()
+            report.errors.len()
         );
-        for error in &validation_errors {
+        for error in &report.errors {
             println!("    {}", error);
         }
         println!("\n Fix the errors above and try again");
@@ -381,6 +400,187 @@ fn handle_dry_run(config: &ServerConfig, cli: &Cli) {
     }
 }
 
+/// The parts of a certificate `--check-cert` reports
+fn certificate_report(certificate: &Certificate, needs_renewal: bool) -> serde_json::Value {
+    serde_json::json!({
+        "domain": certificate.domain,
+        "issuer": certificate.issuer,
+        "san_domains": certificate.san_domains,
+        "issued_at": certificate.issued_at,
+        "expires_at": certificate.expires_at,
+        "days_until_expiry": certificate.days_until_expiry(),
+        "expired": certificate.is_expired(),
+        "needs_renewal": needs_renewal,
+    })
+}
+
+/// Handle --check-cert: report the certificate of a configured domain and exit.
+/// The certificate comes from the certificate store, or from the site's
+/// certificate files when the store has none.
+fn handle_check_cert(config: &ServerConfig, domain: &str, json: bool) {
+    let Some(site) = config.sites.iter().find(|site| {
+        site.ssl.enabled
+            && (site.handles_hostname(domain) || site.ssl.domains.iter().any(|d| d == domain))
+    }) else {
+        eprintln!("Error: No SSL-enabled site serves '{}'", domain);
+        std::process::exit(1);
+    };
+
+    let runtime = tokio::runtime::Runtime::new().unwrap_or_else(|e| {
+        eprintln!("Failed to create async runtime: {e}");
+        std::process::exit(1);
+    });
+    let certificate = runtime.block_on(async {
+        let manager =
+            match SslManager::from_site_config(site, config.acme_challenge_dir.as_deref()).await {
+                Ok(Some(manager)) => manager,
+                Ok(None) => return None,
+                Err(e) => {
+                    eprintln!("Failed to open certificates of site '{}': {e}", site.name);
+                    std::process::exit(1);
+                }
+            };
+
+        let certificate = match manager.get_certificate_info(domain).await {
+            Some(certificate) => Some(certificate),
+            None => {
+                let cert_path = site
+                    .ssl
+                    .cert_file
+                    .clone()
+                    .unwrap_or_else(|| format!("./certs/{}.crt", domain));
+                let key_path = site
+                    .ssl
+                    .key_file
+                    .clone()
+                    .unwrap_or_else(|| format!("./certs/{}.key", domain));
+                Certificate::from_files(
+                    domain.to_string(),
+                    cert_path.into(),
+                    key_path.into(),
+                    false,
+                )
+                .await
+                .ok()
+            }
+        };
+        certificate
+    });
+
+    let Some(certificate) = certificate else {
+        if json {
+            println!(
+                "{}",
+                serde_json::json!({ "domain": domain, "certificate": null })
+            );
+        } else {
+            println!("No certificate found for {}", domain);
+        }
+        std::process::exit(1);
+    };
+    let renewal_days = SslConfig::for_site(site, None).renewal_days_before_expiry;
+    let needs_renewal = certificate.needs_renewal(renewal_days);
+
+    if json {
+        println!("{}", certificate_report(&certificate, needs_renewal));
+    } else {
+        println!("Certificate for {}", domain);
+        println!("   Issuer: {}", certificate.issuer);
+        println!("   Domains: {}", certificate.san_domains.join(", "));
+        println!("   Issued: {}", certificate.issued_at);
+        println!(
+            "   Expires: {} ({} days)",
+            certificate.expires_at,
+            certificate.days_until_expiry()
+        );
+        let status = if certificate.is_expired() {
+            "expired"
+        } else if needs_renewal {
+            "valid, renewal due"
+        } else {
+            "valid"
+        };
+        println!("   Status: {}", status);
+    }
+    std::process::exit(if certificate.is_expired() { 1 } else { 0 });
+}
+
+/// The management API settings of the configuration named on the command
+/// line, or of `config.toml` if it exists
+fn configured_management(cli: &Cli) -> ManagementConfig {
+    let config = if !cli.config.is_empty() {
+        ServerConfig::load_from_files(&cli.config)
+    } else if Path::new("config.toml").exists() {
+        ServerConfig::load_from_file("config.toml")
+    } else {
+        return ManagementConfig::default();
+    };
+    match config {
+        Ok(config) => config.management,
+        Err(e) => {
+            eprintln!(
+                "Failed to load configuration from '{}': {e}",
+                config_source(cli)
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// A client for the management API given on the command line, completed
+/// from the configuration
+fn management_client(args: &ManagementArgs, cli: &Cli) -> BwsCtl {
+    let mut management = configured_management(cli);
+    if args.api_key.is_some() {
+        management.api_key = args.api_key.clone();
+    }
+    match &args.management_url {
+        Some(url) => BwsCtl::from_management_url(url, management.api_key),
+        None => BwsCtl::from_management_config(&management),
+    }
+}
+
+/// Handle the commands for a running instance and exit
+fn handle_command(command: &Command, cli: &Cli) {
+    let runtime = tokio::runtime::Runtime::new().unwrap_or_else(|e| {
+        eprintln!("Failed to create async runtime: {e}");
+        std::process::exit(1);
+    });
+
+    match command {
+        Command::Reload {
+            pid_file,
+            management,
+        } => {
+            let ctl = match pid_file {
+                Some(pid_file) => BwsCtl::from_pid_file(pid_file).unwrap_or_else(|e| {
+                    eprintln!("Error: {e}");
+                    std::process::exit(1);
+                }),
+                None => management_client(management, cli),
+            };
+            match runtime.block_on(ctl.reload()) {
+                Ok(message) => println!("{}", message),
+                Err(e) => {
+                    eprintln!("Reload failed: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Status { management, json } => {
+            let ctl = management_client(management, cli);
+            match runtime.block_on(ctl.status()) {
+                Ok(status) if *json => println!("{}", status),
+                Ok(status) => print!("{}", status_summary(&status)),
+                Err(e) => {
+                    eprintln!("Status failed: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
 fn main() {
     let cli = match Cli::try_parse() {
         Ok(cli) => cli,
@@ -396,6 +596,10 @@ fn main() {
         }
     };
 
+    if let Some(command) = &cli.command {
+        return handle_command(command, &cli);
+    }
+
     // Initialize Rustls crypto provider
     if let Err(e) = rustls::crypto::aws_lc_rs::default_provider().install_default() {
         eprintln!("Failed to install default crypto provider: {e:?}");
@@ -451,10 +655,13 @@ fn main() {
         // Create temporary configuration for serving a directory
         let port = cli.port.unwrap_or_else(generate_random_port);
         create_temporary_config(directory, port)
-    } else if let Some(config_path) = &cli.config {
-        // Load configuration from explicitly specified file
-        ServerConfig::load_from_file(config_path).unwrap_or_else(|e| {
-            eprintln!("Failed to load configuration from '{}': {e}", config_path);
+    } else if !cli.config.is_empty() {
+        // Load configuration from the explicitly specified files, merged in order
+        ServerConfig::load_from_files(&cli.config).unwrap_or_else(|e| {
+            eprintln!(
+                "Failed to load configuration from '{}': {e}",
+                config_source(&cli)
+            );
             std::process::exit(1);
         })
     } else {
@@ -491,18 +698,26 @@ fn main() {
         }
     };
 
+    if let Some(domain) = &cli.check_cert {
+        return handle_check_cert(&config, domain, cli.json);
+    }
+
     // Handle dry-run mode: validate configuration and exit
     if cli.dry_run {
         return handle_dry_run(&config, &cli);
     }
 
+    if let Err(e) = config.create_static_dirs() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
     if cli.directory.is_some() {
         println!(" Temporary web server ready!");
     } else {
-        let config_path = cli.config.as_deref().unwrap_or("config.toml");
         println!(
             "Loaded configuration from '{}' for {} sites:",
-            config_path,
+            config_source(&cli),
             config.sites.len()
         );
     }
@@ -529,24 +744,80 @@ fn main() {
         }
     }
 
-    let mut my_server = Server::new(None).unwrap_or_else(|e| {
-        eprintln!("Failed to create server: {e}");
+    if let Err(e) = config.check_ports_available() {
+        eprintln!("{e}");
         std::process::exit(1);
-    });
+    }
+
+    // Size each service's runtime from the performance configuration
+    let server_conf = ServerConf {
+        threads: config.performance.worker_threads,
+        ..Default::default()
+    };
+    let mut my_server = Server::new_with_opt_and_conf(None, server_conf);
+    log::info!(
+        "Using {} worker threads per service",
+        my_server.configuration.threads
+    );
 
     // Create the main web service instance
     let web_service = WebServerService::new(config.clone());
 
-    // Set the config path for hot reload (only if not in temporary directory mode)
-    if cli.directory.is_none() && cli.config.is_some() {
+    // Set the config path for hot reload (only if not in temporary directory
+    // mode). Reloading re-reads one path, so overlays given as separate files
+    // are not reloadable; a config directory is.
+    if let ([config_path], None) = (cli.config.as_slice(), &cli.directory) {
         let rt = tokio::runtime::Runtime::new().unwrap_or_else(|e| {
             log::error!("Failed to create runtime for config path setup: {e}");
             std::process::exit(1);
         });
-        rt.block_on(web_service.set_config_path(cli.config.clone().unwrap()));
+        rt.block_on(web_service.set_config_path(config_path.clone()));
         log::info!(" Config hot reload enabled via API at POST /api/config/reload");
+
+        // SIGHUP, as sent by `bws reload --pid-file`, reloads the same path
+        #[cfg(unix)]
+        {
+            let service = web_service.clone();
+            let path = config_path.clone();
+            let reload = move || {
+                let service = service.clone();
+                let path = path.clone();
+                async move {
+                    if let Err(e) = service.reload_config_file().await {
+                        log::error!("Failed to reload configuration from '{path}': {e}");
+                    }
+                }
+            };
+            if let Err(e) = bws_web_server::core::signals::on_reload(reload) {
+                log::warn!("Failed to handle SIGHUP, reload through the API instead: {e}");
+            }
+        }
+    } else if cli.config.len() > 1 {
+        log::warn!(
+            "Config hot reload is disabled with several --config files; pass a directory instead"
+        );
     }
 
+    // SIGUSR1 reopens the log files after logrotate moved them away
+    #[cfg(unix)]
+    {
+        let service = web_service.clone();
+        let daemon_log = cli.daemon.then(|| cli.log_file.clone());
+        let reopen = move || {
+            service.reopen_logs();
+            if let Some(path) = &daemon_log {
+                if let Err(e) = reopen_daemon_log(path) {
+                    log::error!("Failed to reopen log file '{path}': {e}");
+                }
+            }
+        };
+        if let Err(e) = bws_web_server::core::signals::on_reopen_logs(reopen) {
+            log::warn!("Failed to handle SIGUSR1, log files cannot be reopened: {e}");
+        }
+    }
+
+    let socket_options = tcp_socket_options(&config.performance);
+
     // Check if any site has ACME enabled and create a dedicated HTTP challenge service on port 80
     let has_acme_enabled = config.sites.iter().any(|site| {
         site.ssl.enabled
@@ -556,7 +827,7 @@ fn main() {
 
     if has_acme_enabled {
         // Check if we already have a service listening on port 80
-        let has_port_80 = config.sites.iter().any(|site| site.port == 80);
+        let has_port_80 = config.sites.iter().any(|site| site.listens_on(80));
 
         if has_port_80 {
             log::info!("Port 80 already configured for ACME challenges");
@@ -564,7 +835,7 @@ fn main() {
             log::info!("Creating dedicated HTTP challenge service on port 80 for ACME validation");
             let mut acme_service =
                 pingora::proxy::http_proxy_service(&my_server.configuration, web_service.clone());
-            acme_service.add_tcp("0.0.0.0:80");
+            add_tcp_listener(&mut acme_service, "0.0.0.0:80", &socket_options);
             my_server.add_service(acme_service);
         }
     }
@@ -609,7 +880,11 @@ fn main() {
 
                 match TlsSettings::intermediate(&cert_path, &key_path) {
                     Ok(tls_settings) => {
-                        proxy_service.add_tls_with_settings(&listen_addr, None, tls_settings);
+                        proxy_service.add_tls_with_settings(
+                            &listen_addr,
+                            socket_options.clone(),
+                            tls_settings,
+                        );
                         log::info!(
                             "HTTPS listener configured successfully for site '{}'",
                             site.name
@@ -618,7 +893,7 @@ fn main() {
                     Err(e) => {
                         log::error!("Failed to load TLS settings for {}: {}", site.name, e);
                         log::warn!("Falling back to HTTP for site '{}'", site.name);
-                        proxy_service.add_tcp(&listen_addr);
+                        add_tcp_listener(&mut proxy_service, &listen_addr, &socket_options);
                     }
                 }
             } else {
@@ -628,14 +903,25 @@ fn main() {
                     site.name
                 );
                 log::info!("Expected: {cert_path} and {key_path}");
-                proxy_service.add_tcp(&listen_addr);
+                add_tcp_listener(&mut proxy_service, &listen_addr, &socket_options);
             }
         } else {
             // Regular HTTP site
-            proxy_service.add_tcp(&listen_addr);
+            add_tcp_listener(&mut proxy_service, &listen_addr, &socket_options);
             log::info!("HTTP listener configured for site '{}'", site.name);
         }
 
+        // Additional ports are always plain HTTP (e.g. port 80 for HTTPS redirects)
+        for port in site.all_ports().into_iter().skip(1) {
+            let extra_addr = format!("0.0.0.0:{}", port);
+            add_tcp_listener(&mut proxy_service, &extra_addr, &socket_options);
+            log::info!(
+                "HTTP listener configured for site '{}' on {}",
+                site.name,
+                extra_addr
+            );
+        }
+
         log::info!("Starting service '{service_name}' on {listen_addr}");
         my_server.add_service(proxy_service);
     }
@@ -665,6 +951,12 @@ fn main() {
         log::info!("Management API disabled");
     }
 
+    log::info!(
+        "Connection timeouts: keep-alive {}s, request read {}s",
+        config.performance.keep_alive_timeout,
+        config.performance.request_timeout
+    );
+
     log::info!("Starting BWS multi-site server...");
     my_server.bootstrap();
 
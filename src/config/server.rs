@@ -1,12 +1,21 @@
-﻿use crate::config::SiteConfig;
+use crate::config::{validate_well_known, SiteConfig, WellKnownDocument};
+use crate::core::{net, BwsError, BwsResult};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
+use std::net::IpAddr;
 use std::path::Path;
+use std::time::Duration;
+
+/// Port of the HTTP listener added to redirect to HTTPS sites
+const HTTP_PORT: u16 = 80;
+
+/// Most header fields Pingora parses in an HTTP/1 request
+const MAX_PARSED_HEADERS: usize = 256;
 
 /// Top-level server configuration for BWS.
 /// Contains global server info, site definitions, and all major subsystems.
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct ServerConfig {
     /// General server info (name, version, description)
     pub server: ServerInfo,
@@ -21,13 +30,33 @@ pub struct ServerConfig {
     /// Security-related configuration
     #[serde(default)]
     pub security: SecurityConfig,
+    /// Proxies (addresses or CIDR blocks) whose forwarding headers are
+    /// believed: `X-Forwarded-For`, `X-Real-IP` and `X-Forwarded-Proto`
+    #[serde(default)]
+    pub trusted_proxies: net::TrustedProxies,
+    /// Directory all ACME sites write their HTTP-01 challenges to and the
+    /// challenge handler reads them from. Unset, each site uses its own.
+    #[serde(default)]
+    pub acme_challenge_dir: Option<String>,
     /// Management API configuration
     #[serde(default)]
     pub management: ManagementConfig,
+    /// Request tracing configuration
+    #[serde(default)]
+    pub tracing: TracingConfig,
+    /// StatsD metrics export configuration
+    #[serde(default)]
+    pub statsd: StatsdConfig,
+    /// Prometheus metrics configuration
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    /// Documents served under `/.well-known/` for every site
+    #[serde(default)]
+    pub well_known: HashMap<String, WellKnownDocument>,
 }
 
 /// Information about the server (name, version, description)
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct ServerInfo {
     /// Server name
     pub name: String,
@@ -40,7 +69,7 @@ pub struct ServerInfo {
 }
 
 /// Logging configuration for the server
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct LoggingConfig {
     /// Log level (e.g., info, debug, warn)
     #[serde(default = "default_log_level")]
@@ -51,27 +80,30 @@ pub struct LoggingConfig {
     /// Path to error log file (optional)
     #[serde(default)]
     pub error_log: Option<String>,
-    /// Log format (e.g., combined, json)
+    /// Access log format: common, combined, combinedio or json
     #[serde(default = "default_log_format")]
     pub format: String,
     /// Whether to log all requests
     #[serde(default)]
     pub log_requests: bool,
+    /// Requests taking longer than this many milliseconds are logged as slow (disabled if unset)
+    #[serde(default)]
+    pub slow_request_ms: Option<u64>,
 }
 
 /// Performance tuning configuration for the server
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct PerformanceConfig {
     /// Number of worker threads
     #[serde(default = "default_worker_threads")]
     pub worker_threads: usize,
-    /// Maximum concurrent connections
+    /// Maximum connections served at once; beyond it requests get 503
     #[serde(default = "default_max_connections")]
     pub max_connections: usize,
-    /// Keep-alive timeout in seconds
+    /// Idle keep-alive timeout in seconds, applied to each downstream connection
     #[serde(default = "default_keep_alive_timeout")]
     pub keep_alive_timeout: u64,
-    /// Request timeout in seconds
+    /// Request read timeout in seconds, bounding slow clients
     #[serde(default = "default_request_timeout")]
     pub request_timeout: u64,
     /// Read buffer size (e.g., "32KB")
@@ -80,17 +112,62 @@ pub struct PerformanceConfig {
     /// Write buffer size (e.g., "32KB")
     #[serde(default = "default_buffer_size")]
     pub write_buffer_size: String,
+    /// Serve static files from memory maps instead of reading them per request.
+    /// Only safe when files are replaced by renaming new versions into place:
+    /// a mapped file that is truncated or rewritten while it is being served
+    /// makes the process crash with SIGBUS.
+    #[serde(default)]
+    pub mmap_static_files: bool,
+    /// Smallest file that is memory-mapped (e.g., "64KB")
+    #[serde(default = "default_mmap_min_size")]
+    pub mmap_min_size: String,
+    /// Largest file that is memory-mapped (e.g., "256MB")
+    #[serde(default = "default_mmap_max_size")]
+    pub mmap_max_size: String,
+    /// Number of small static files kept in memory (0 disables the cache)
+    #[serde(default = "default_file_cache_entries")]
+    pub file_cache_entries: usize,
+    /// Largest file kept in the in-memory file cache (e.g., "64KB")
+    #[serde(default = "default_file_cache_max_size")]
+    pub file_cache_max_size: String,
+    /// TCP keepalive probes on accepted connections (off if unset)
+    #[serde(default)]
+    pub tcp_keepalive: Option<TcpKeepaliveConfig>,
+}
+
+/// TCP keepalive settings for accepted connections, so connections to
+/// clients that vanished without closing them are eventually dropped
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TcpKeepaliveConfig {
+    /// Seconds a connection is idle before the first probe
+    #[serde(default = "default_keepalive_idle")]
+    pub idle: u64,
+    /// Seconds between probes
+    #[serde(default = "default_keepalive_interval")]
+    pub interval: u64,
+    /// Unanswered probes before the connection is dropped
+    #[serde(default = "default_keepalive_count")]
+    pub count: usize,
 }
 
 /// Security-related configuration for the server
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct SecurityConfig {
     /// Whether to hide the server header in responses
     #[serde(default)]
     pub hide_server_header: bool,
+    /// Custom Server header value (e.g., "BWS") replacing "<name>/<version>"
+    #[serde(default)]
+    pub server_header: Option<String>,
     /// Maximum allowed request size (e.g., "10MB")
     #[serde(default = "default_max_request_size")]
     pub max_request_size: String,
+    /// Maximum total size of the request header fields (e.g., "32KB")
+    #[serde(default = "default_max_header_size")]
+    pub max_header_size: String,
+    /// Maximum number of request header fields
+    #[serde(default = "default_max_headers")]
+    pub max_headers: usize,
     /// List of allowed CORS origins
     #[serde(default)]
     pub allowed_origins: Vec<String>,
@@ -100,6 +177,15 @@ pub struct SecurityConfig {
     /// Optional rate limiting configuration
     #[serde(default)]
     pub rate_limiting: Option<RateLimitConfig>,
+    /// Where rate limit counters are kept
+    #[serde(default)]
+    pub rate_limit_store: RateLimitStoreConfig,
+    /// Deprecated: use the top-level `trusted_proxies`, which these are added to
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// Serve `GET /api/version` with the server name, version and build
+    #[serde(default = "default_version_endpoint")]
+    pub version_endpoint: bool,
 }
 
 /// Rate limiting configuration
@@ -114,8 +200,22 @@ pub struct RateLimitConfig {
     pub whitelist: Vec<String>,
 }
 
+/// Storage for rate limit counters
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct RateLimitStoreConfig {
+    /// "memory" (per instance) or "redis" (shared by every instance using the same server)
+    #[serde(default = "default_rate_limit_backend")]
+    pub backend: String,
+    /// Redis server for the "redis" backend, e.g. `redis://:password@127.0.0.1:6379/0`
+    #[serde(default)]
+    pub redis_url: Option<String>,
+    /// Prefix for Redis keys
+    #[serde(default = "default_rate_limit_key_prefix")]
+    pub key_prefix: String,
+}
+
 /// Management API configuration
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct ManagementConfig {
     /// Whether the management API is enabled
     #[serde(default = "default_management_enabled")]
@@ -131,6 +231,45 @@ pub struct ManagementConfig {
     pub api_key: Option<String>,
 }
 
+/// Request tracing configuration (OpenTelemetry spans exported over OTLP)
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TracingConfig {
+    /// Whether to trace requests; nothing is recorded when disabled
+    #[serde(default)]
+    pub enabled: bool,
+    /// OTLP/HTTP traces endpoint of the collector
+    #[serde(default = "default_tracing_endpoint")]
+    pub endpoint: String,
+    /// Service name reported with exported spans
+    #[serde(default = "default_tracing_service_name")]
+    pub service_name: String,
+}
+
+/// StatsD metrics export configuration
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct StatsdConfig {
+    /// Whether to push request metrics to a StatsD agent
+    #[serde(default)]
+    pub enabled: bool,
+    /// UDP `host:port` of the StatsD agent
+    #[serde(default = "default_statsd_address")]
+    pub address: String,
+    /// Prefix for all metric names
+    #[serde(default = "default_statsd_prefix")]
+    pub prefix: String,
+    /// How often buffered metrics are sent, in milliseconds
+    #[serde(default = "default_statsd_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+}
+
+/// Prometheus metrics configuration
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct MetricsConfig {
+    /// Upper bounds of the request duration histogram buckets, in seconds
+    #[serde(default = "default_metrics_buckets")]
+    pub buckets: Vec<f64>,
+}
+
 // Default value functions
 fn default_version() -> String {
     env!("CARGO_PKG_VERSION").to_string()
@@ -164,10 +303,58 @@ fn default_buffer_size() -> String {
     "32KB".to_string()
 }
 
+fn default_mmap_min_size() -> String {
+    "64KB".to_string()
+}
+
+fn default_mmap_max_size() -> String {
+    "256MB".to_string()
+}
+
+fn default_file_cache_entries() -> usize {
+    1024
+}
+
+fn default_file_cache_max_size() -> String {
+    "64KB".to_string()
+}
+
+fn default_keepalive_idle() -> u64 {
+    60
+}
+
+fn default_keepalive_interval() -> u64 {
+    10
+}
+
+fn default_keepalive_count() -> usize {
+    5
+}
+
 fn default_max_request_size() -> String {
     "10MB".to_string()
 }
 
+fn default_max_header_size() -> String {
+    "32KB".to_string()
+}
+
+fn default_max_headers() -> usize {
+    100
+}
+
+fn default_version_endpoint() -> bool {
+    true
+}
+
+fn default_rate_limit_backend() -> String {
+    "memory".to_string()
+}
+
+fn default_rate_limit_key_prefix() -> String {
+    "bws:ratelimit".to_string()
+}
+
 fn default_management_enabled() -> bool {
     false
 }
@@ -180,6 +367,32 @@ fn default_management_port() -> u16 {
     7654
 }
 
+fn default_tracing_endpoint() -> String {
+    "http://127.0.0.1:4318/v1/traces".to_string()
+}
+
+fn default_tracing_service_name() -> String {
+    "bws".to_string()
+}
+
+fn default_statsd_address() -> String {
+    "127.0.0.1:8125".to_string()
+}
+
+fn default_statsd_prefix() -> String {
+    "bws".to_string()
+}
+
+fn default_metrics_buckets() -> Vec<f64> {
+    vec![
+        0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+    ]
+}
+
+fn default_statsd_flush_interval_ms() -> u64 {
+    1000
+}
+
 impl Default for LoggingConfig {
     fn default() -> Self {
         Self {
@@ -188,10 +401,19 @@ impl Default for LoggingConfig {
             error_log: None,
             format: default_log_format(),
             log_requests: true,
+            slow_request_ms: None,
         }
     }
 }
 
+impl LoggingConfig {
+    /// Whether a request that took `elapsed` exceeds the slow request threshold
+    pub fn is_slow_request(&self, elapsed: Duration) -> bool {
+        self.slow_request_ms
+            .is_some_and(|threshold| elapsed > Duration::from_millis(threshold))
+    }
+}
+
 impl Default for PerformanceConfig {
     fn default() -> Self {
         Self {
@@ -201,6 +423,22 @@ impl Default for PerformanceConfig {
             request_timeout: default_request_timeout(),
             read_buffer_size: default_buffer_size(),
             write_buffer_size: default_buffer_size(),
+            mmap_static_files: false,
+            mmap_min_size: default_mmap_min_size(),
+            mmap_max_size: default_mmap_max_size(),
+            file_cache_entries: default_file_cache_entries(),
+            file_cache_max_size: default_file_cache_max_size(),
+            tcp_keepalive: None,
+        }
+    }
+}
+
+impl Default for TcpKeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            idle: default_keepalive_idle(),
+            interval: default_keepalive_interval(),
+            count: default_keepalive_count(),
         }
     }
 }
@@ -218,10 +456,26 @@ impl Default for SecurityConfig {
 
         Self {
             hide_server_header: false,
+            server_header: None,
             max_request_size: default_max_request_size(),
+            max_header_size: default_max_header_size(),
+            max_headers: default_max_headers(),
             allowed_origins: vec![],
             security_headers,
             rate_limiting: None,
+            rate_limit_store: RateLimitStoreConfig::default(),
+            trusted_proxies: vec![],
+            version_endpoint: default_version_endpoint(),
+        }
+    }
+}
+
+impl Default for RateLimitStoreConfig {
+    fn default() -> Self {
+        Self {
+            backend: default_rate_limit_backend(),
+            redis_url: None,
+            key_prefix: default_rate_limit_key_prefix(),
         }
     }
 }
@@ -237,10 +491,156 @@ impl Default for ManagementConfig {
     }
 }
 
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: default_tracing_endpoint(),
+            service_name: default_tracing_service_name(),
+        }
+    }
+}
+
+impl Default for StatsdConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: default_statsd_address(),
+            prefix: default_statsd_prefix(),
+            flush_interval_ms: default_statsd_flush_interval_ms(),
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            buckets: default_metrics_buckets(),
+        }
+    }
+}
+
+/// Merge `overlay` into `base`: nested tables key by key, `sites` by name,
+/// anything else replaced
+fn merge_config_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(table)) => {
+                merge_config_tables(existing, table);
+            }
+            (Some(toml::Value::Array(existing)), toml::Value::Array(sites)) if key == "sites" => {
+                for site in sites {
+                    let name = site.get("name").and_then(toml::Value::as_str);
+                    let matching = existing.iter_mut().find(|existing| {
+                        name.is_some() && existing.get("name").and_then(toml::Value::as_str) == name
+                    });
+                    match (matching, site) {
+                        (Some(toml::Value::Table(existing)), toml::Value::Table(site)) => {
+                            merge_config_tables(existing, site);
+                        }
+                        (_, site) => existing.push(site),
+                    }
+                }
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// A problem found in a configuration by `ServerConfig::validate_detailed`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidationIssue {
+    /// Site the problem is in, if it concerns a single site
+    pub site: Option<String>,
+    pub message: String,
+}
+
+impl ValidationIssue {
+    fn server(message: impl Into<String>) -> Self {
+        Self {
+            site: None,
+            message: message.into(),
+        }
+    }
+
+    fn site(site: &SiteConfig, message: impl Into<String>) -> Self {
+        Self {
+            site: Some(site.name.clone()),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.site {
+            Some(site) => write!(f, "Site '{}': {}", site, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Errors, which keep a configuration from being used, and warnings, which
+/// deserve a look but do not
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ValidationReport {
+    pub errors: Vec<ValidationIssue>,
+    pub warnings: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
 impl ServerConfig {
+    /// Load a configuration file, or every `.toml` file of a directory
+    /// merged as by `load_from_files`
     pub fn load_from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let content = fs::read_to_string(path)?;
-        let mut config: ServerConfig = toml::from_str(&content)?;
+        Self::load_from_files(&[path])
+    }
+
+    /// Load configuration files merged in order: tables are merged key by
+    /// key with later files winning, other values are replaced, and sites
+    /// are matched by `name`, so a later file can override part of a site or
+    /// add new ones. A directory stands for its `.toml` files in name order.
+    pub fn load_from_files<P: AsRef<Path>>(
+        paths: &[P],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut files = Vec::new();
+        for path in paths {
+            let path = path.as_ref();
+            if path.is_dir() {
+                let mut entries = fs::read_dir(path)?
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                entries.retain(|entry| {
+                    entry.is_file() && entry.extension().is_some_and(|ext| ext == "toml")
+                });
+                entries.sort();
+                files.extend(entries);
+            } else {
+                files.push(path.to_path_buf());
+            }
+        }
+
+        let mut config: ServerConfig = match files.as_slice() {
+            [] => return Err("No configuration files found".into()),
+            // A single file keeps the parser's line numbers in errors
+            [file] => toml::from_str(&fs::read_to_string(file)?)?,
+            _ => {
+                let mut merged = toml::Table::new();
+                for file in &files {
+                    let overlay: toml::Table = toml::from_str(&fs::read_to_string(file)?)
+                        .map_err(|e| format!("{}: {}", file.display(), e))?;
+                    merge_config_tables(&mut merged, overlay);
+                }
+                toml::Value::Table(merged).try_into()?
+            }
+        };
 
         // Post-process configuration first (to set automatic defaults)
         config.post_process()?;
@@ -251,6 +651,205 @@ impl ServerConfig {
         Ok(config)
     }
 
+    /// Check that every port BWS is about to listen on is free: the site
+    /// ports, port 80 for ACME challenges, and the management API. A port
+    /// taken by another process is reported with the site using it, instead
+    /// of surfacing as a bind failure inside Pingora.
+    pub fn check_ports_available(&self) -> BwsResult<()> {
+        let mut listeners = Vec::new();
+        for site in &self.sites {
+            for port in site.all_ports() {
+                listeners.push((format!("0.0.0.0:{}", port), format!("site '{}'", site.name)));
+            }
+        }
+        let acme_enabled = self.sites.iter().any(|site| {
+            site.ssl.enabled
+                && site.ssl.auto_cert
+                && site.ssl.acme.as_ref().is_some_and(|acme| acme.enabled)
+        });
+        if acme_enabled {
+            listeners.push(("0.0.0.0:80".to_string(), "ACME challenges".to_string()));
+        }
+        if self.management.enabled {
+            listeners.push((
+                format!("{}:{}", self.management.host, self.management.port),
+                "the management API".to_string(),
+            ));
+        }
+
+        let mut checked = std::collections::HashSet::new();
+        for (addr, user) in &listeners {
+            if !checked.insert(addr) {
+                continue;
+            }
+            net::check_bindable(addr).map_err(|e| {
+                let reason = match e.kind() {
+                    std::io::ErrorKind::AddrInUse => "already in use".to_string(),
+                    std::io::ErrorKind::PermissionDenied => "permission denied".to_string(),
+                    _ => e.to_string(),
+                };
+                BwsError::Config(format!(
+                    "Cannot listen on {} for {}: {}",
+                    addr, user, reason
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Everything `validate` checks, plus the configuration against the
+    /// machine it would run on: static directories, certificates, upstreams,
+    /// sites sharing ports and ports taken by other processes. Used by
+    /// `--dry-run` and the management API's `/api/config/validate`.
+    pub fn validate_detailed(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        if let Err(e) = self.validate() {
+            report.errors.push(ValidationIssue::server(e.to_string()));
+        }
+
+        for site in &self.sites {
+            let static_dir = Path::new(&site.static_dir);
+            if !static_dir.exists() {
+                report.errors.push(ValidationIssue::site(
+                    site,
+                    format!("Static directory '{}' does not exist", site.static_dir),
+                ));
+            } else if !static_dir.is_dir() {
+                report.errors.push(ValidationIssue::site(
+                    site,
+                    format!("Static path '{}' is not a directory", site.static_dir),
+                ));
+            }
+
+            if !site.index_files.is_empty()
+                && !site
+                    .index_files
+                    .iter()
+                    .any(|index_file| static_dir.join(index_file).exists())
+            {
+                report.warnings.push(ValidationIssue::site(
+                    site,
+                    "No index files found in static directory",
+                ));
+            }
+
+            if site.ssl.enabled && site.ssl.auto_cert {
+                if site
+                    .ssl
+                    .acme
+                    .as_ref()
+                    .is_some_and(|acme| acme.enabled && acme.email.is_empty())
+                {
+                    report.errors.push(ValidationIssue::site(
+                        site,
+                        "ACME email is required when auto_cert is enabled",
+                    ));
+                }
+                let site_challenge_dir = site
+                    .ssl
+                    .acme
+                    .as_ref()
+                    .and_then(|acme| acme.challenge_dir.as_deref());
+                if let (Some(shared), Some(own)) = (&self.acme_challenge_dir, site_challenge_dir) {
+                    if shared != own {
+                        report.warnings.push(ValidationIssue::site(
+                            site,
+                            format!(
+                                "ACME challenge_dir '{}' is ignored, challenges go to acme_challenge_dir '{}'",
+                                own, shared
+                            ),
+                        ));
+                    }
+                }
+            } else if site.ssl.enabled {
+                let cert_path = site
+                    .ssl
+                    .cert_file
+                    .clone()
+                    .unwrap_or_else(|| format!("./certs/{}.crt", site.hostname));
+                let key_path = site
+                    .ssl
+                    .key_file
+                    .clone()
+                    .unwrap_or_else(|| format!("./certs/{}.key", site.hostname));
+                if Path::new(&cert_path).exists() && Path::new(&key_path).exists() {
+                    let key_check = fs::read(&cert_path)
+                        .and_then(|cert| fs::read(&key_path).map(|key| (cert, key)))
+                        .map_err(BwsError::from)
+                        .and_then(|(cert, key)| crate::ssl::verify_key_pair(&cert, &key));
+                    if let Err(e) = key_check {
+                        report
+                            .errors
+                            .push(ValidationIssue::site(site, e.to_string()));
+                    }
+                } else {
+                    report.warnings.push(ValidationIssue::site(
+                        site,
+                        format!(
+                            "SSL enabled but certificates not found at {} and {}",
+                            cert_path, key_path
+                        ),
+                    ));
+                }
+            }
+
+            if site.proxy.enabled {
+                if site.proxy.upstreams.is_empty() {
+                    report.errors.push(ValidationIssue::site(
+                        site,
+                        "Proxy enabled but no upstreams configured",
+                    ));
+                }
+                if site.proxy.routes.is_empty() {
+                    report.warnings.push(ValidationIssue::site(
+                        site,
+                        "Proxy enabled but no routes configured",
+                    ));
+                }
+            }
+        }
+
+        // Sites sharing a port need distinct hostnames or a single default
+        let mut port_usage: HashMap<u16, Vec<&SiteConfig>> = HashMap::new();
+        for site in &self.sites {
+            for port in site.all_ports() {
+                port_usage.entry(port).or_default().push(site);
+            }
+        }
+        let mut shared_ports: Vec<_> = port_usage
+            .iter()
+            .filter(|(_, sites)| sites.len() > 1)
+            .collect();
+        shared_ports.sort_by_key(|(port, _)| **port);
+        for (port, sites) in shared_ports {
+            let mut hostnames = std::collections::HashSet::new();
+            for site in sites {
+                hostnames.insert(&site.hostname);
+                hostnames.extend(&site.hostnames);
+            }
+            let defaults = sites.iter().filter(|site| site.default).count();
+            if defaults > 1 {
+                report.errors.push(ValidationIssue::server(format!(
+                    "Port {}: Multiple sites marked as default",
+                    port
+                )));
+            }
+            if hostnames.len() != sites.len() && defaults == 0 {
+                report.warnings.push(ValidationIssue::server(format!(
+                    "Port {}: Multiple sites with overlapping hostnames",
+                    port
+                )));
+            }
+        }
+
+        // A running instance holds its own ports, so a taken port is only a warning
+        if let Err(e) = self.check_ports_available() {
+            report.warnings.push(ValidationIssue::server(e.to_string()));
+        }
+
+        report
+    }
+
     pub fn save_to_file(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
         let content = toml::to_string_pretty(self)?;
 
@@ -281,22 +880,30 @@ impl ServerConfig {
         for (i, site) in self.sites.iter().enumerate() {
             site.validate().map_err(|e| format!("Site {}: {}", i, e))?;
 
+            if let Some(size) = &site.max_request_size {
+                self.security.parse_size(size).map_err(|_| {
+                    format!("Site '{}': invalid max_request_size '{}'", site.name, size)
+                })?;
+            }
+
             if site.default {
                 default_sites += 1;
             }
 
             // Check for hostname:port conflicts across all hostnames for this site
             // Allow multiple sites on same port with different hostnames (virtual hosting)
-            for hostname in site.get_all_hostnames() {
-                let hostname_port_key = (hostname, site.port);
-                if used_hostname_ports.contains(&hostname_port_key) {
-                    return Err(format!(
-                        "Duplicate hostname:port combination: {}:{}. Each hostname must be unique per port.",
-                        hostname, site.port
-                    )
-                    .into());
+            for port in site.all_ports() {
+                for hostname in site.get_all_hostnames() {
+                    let hostname_port_key = (hostname, port);
+                    if used_hostname_ports.contains(&hostname_port_key) {
+                        return Err(format!(
+                            "Duplicate hostname:port combination: {}:{}. Each hostname must be unique per port.",
+                            hostname, port
+                        )
+                        .into());
+                    }
+                    used_hostname_ports.insert(hostname_port_key);
                 }
-                used_hostname_ports.insert(hostname_port_key);
             }
         }
 
@@ -345,12 +952,27 @@ impl ServerConfig {
             }
         }
 
+        // Validate logging configuration
+        self.logging
+            .format
+            .parse::<crate::monitoring::AccessLogFormat>()?;
+
         // Validate performance configuration
         self.performance.validate()?;
 
         // Validate security configuration
         self.security.validate()?;
 
+        // Validate tracing configuration
+        self.tracing.validate()?;
+
+        // Validate StatsD configuration
+        self.statsd.validate()?;
+        self.metrics.validate()?;
+
+        // Validate well-known documents
+        validate_well_known(&self.well_known)?;
+
         Ok(())
     }
 
@@ -372,23 +994,82 @@ impl ServerConfig {
             _ => a.name.cmp(&b.name),
         });
 
+        self.add_https_redirect_ports();
+
+        if !self.security.trusted_proxies.is_empty() {
+            log::warn!("security.trusted_proxies is deprecated; use the top-level trusted_proxies");
+            let legacy = net::TrustedProxies::try_from(self.security.trusted_proxies.clone())?;
+            self.trusted_proxies.extend(&legacy);
+        }
+
         // No additional post-processing needed for per-site SSL
         // Each site manages its own SSL configuration
 
         Ok(())
     }
 
-    pub fn find_site_by_host_port(&self, host: &str, port: u16) -> Option<&SiteConfig> {
-        // First try to match both hostname and port exactly using the new method
+    /// Create the missing static directories of sites with
+    /// `create_static_dir`. Done when the configuration is put to use, not
+    /// when it is loaded or validated (e.g. by `--dry-run`).
+    pub fn create_static_dirs(&self) -> Result<(), Box<dyn std::error::Error>> {
         for site in &self.sites {
-            if site.handles_hostname_port(host, port) {
-                return Some(site);
+            site.ensure_static_dir()?;
+        }
+        Ok(())
+    }
+
+    /// Give HTTPS sites with `redirect_to_https` a plain HTTP listener on
+    /// port 80 that redirects, without a second site entry. A site that
+    /// explicitly listens on port 80 for one of the same hostnames keeps
+    /// serving those requests, so no redirect port is added in that case.
+    fn add_https_redirect_ports(&mut self) {
+        for i in 0..self.sites.len() {
+            let site = &self.sites[i];
+            if !site.ssl.enabled || !site.redirect_to_https || site.listens_on(HTTP_PORT) {
+                continue;
+            }
+            let conflict = self.sites.iter().find(|other| {
+                other.listens_on(HTTP_PORT)
+                    && site
+                        .get_all_hostnames()
+                        .iter()
+                        .any(|hostname| other.handles_hostname(hostname))
+            });
+            if let Some(other) = conflict {
+                log::warn!(
+                    "Site '{}' serves port {} for site '{}', not redirecting it to HTTPS",
+                    other.name,
+                    HTTP_PORT,
+                    site.name
+                );
+                continue;
             }
+
+            log::info!(
+                "Redirecting HTTP on port {} to HTTPS for site '{}'",
+                HTTP_PORT,
+                site.name
+            );
+            self.sites[i].ports.push(HTTP_PORT);
+        }
+    }
+
+    pub fn find_site_by_host_port(&self, host: &str, port: u16) -> Option<&SiteConfig> {
+        // Match hostname and port, preferring exact over wildcard over regex matches
+        if let Some(site) = self
+            .sites
+            .iter()
+            .filter(|site| site.listens_on(port))
+            .filter_map(|site| site.hostname_match(host).map(|kind| (kind, site)))
+            .min_by_key(|(kind, _)| *kind)
+            .map(|(_, site)| site)
+        {
+            return Some(site);
         }
 
         // Then try to match just the port (for cases where hostname might not match exactly)
         for site in &self.sites {
-            if site.port == port {
+            if site.listens_on(port) {
                 return Some(site);
             }
         }
@@ -416,6 +1097,30 @@ impl ServerConfig {
             .collect()
     }
 
+    /// The rate limit that applies to `site` and the bucket scope it is counted in:
+    /// the site's own limit (counted per site) or the global one (shared by all sites)
+    pub fn rate_limit_for<'a>(
+        &'a self,
+        site: &'a SiteConfig,
+    ) -> Option<(&'a str, &'a RateLimitConfig)> {
+        match &site.rate_limiting {
+            Some(limit) => Some((site.name.as_str(), limit)),
+            None => self
+                .security
+                .rate_limiting
+                .as_ref()
+                .map(|limit| ("", limit)),
+        }
+    }
+
+    /// Largest request body `site` accepts, in bytes: its own
+    /// `max_request_size` or the global one
+    pub fn max_request_bytes_for(&self, site: Option<&SiteConfig>) -> usize {
+        site.and_then(|site| site.max_request_size.as_deref())
+            .and_then(|size| self.security.parse_size(size).ok())
+            .unwrap_or_else(|| self.security.max_request_bytes())
+    }
+
     pub fn get_site_by_domain(&self, domain: &str) -> Option<&SiteConfig> {
         self.sites.iter().find(|site| {
             site.handles_hostname(domain) || site.ssl.domains.contains(&domain.to_string())
@@ -428,6 +1133,74 @@ impl ServerConfig {
         log::info!("Configuration reloaded from {}", path);
         Ok(())
     }
+
+    /// Names of the configuration sections that differ between `self` and `other`
+    pub fn changed_sections(&self, other: &ServerConfig) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.server != other.server {
+            changed.push("server");
+        }
+        if self.sites != other.sites {
+            changed.push("sites");
+        }
+        if self.logging != other.logging {
+            changed.push("logging");
+        }
+        if self.performance != other.performance {
+            changed.push("performance");
+        }
+        if self.security != other.security {
+            changed.push("security");
+        }
+        if self.trusted_proxies != other.trusted_proxies {
+            changed.push("trusted_proxies");
+        }
+        if self.acme_challenge_dir != other.acme_challenge_dir {
+            changed.push("acme_challenge_dir");
+        }
+        if self.management != other.management {
+            changed.push("management");
+        }
+        if self.tracing != other.tracing {
+            changed.push("tracing");
+        }
+        if self.statsd != other.statsd {
+            changed.push("statsd");
+        }
+        if self.metrics != other.metrics {
+            changed.push("metrics");
+        }
+        if self.well_known != other.well_known {
+            changed.push("well_known");
+        }
+        changed
+    }
+
+    /// Whether any part of the configuration differs from `other`
+    pub fn has_config_changed(&self, other: &ServerConfig) -> bool {
+        !self.changed_sections(other).is_empty()
+    }
+
+    /// Whether forwarding headers sent by `ip` are to be believed
+    pub fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.contains(ip)
+    }
+}
+
+impl TcpKeepaliveConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // Limits of TCP_KEEPIDLE, TCP_KEEPINTVL and TCP_KEEPCNT
+        if !(1..=32767).contains(&self.idle) {
+            return Err("tcp_keepalive idle must be between 1 and 32767 seconds".into());
+        }
+        if !(1..=32767).contains(&self.interval) {
+            return Err("tcp_keepalive interval must be between 1 and 32767 seconds".into());
+        }
+        if !(1..=127).contains(&self.count) {
+            return Err("tcp_keepalive count must be between 1 and 127".into());
+        }
+        Ok(())
+    }
 }
 
 impl PerformanceConfig {
@@ -454,9 +1227,46 @@ impl PerformanceConfig {
         self.parse_buffer_size(&self.write_buffer_size)
             .map_err(|_| "Invalid write buffer size format")?;
 
+        // Validate memory-mapped file sizes
+        let mmap_min = self
+            .parse_buffer_size(&self.mmap_min_size)
+            .map_err(|_| "Invalid mmap_min_size format")?;
+        let mmap_max = self
+            .parse_buffer_size(&self.mmap_max_size)
+            .map_err(|_| "Invalid mmap_max_size format")?;
+        if mmap_min > mmap_max {
+            return Err("mmap_min_size must not be larger than mmap_max_size".into());
+        }
+
+        self.parse_buffer_size(&self.file_cache_max_size)
+            .map_err(|_| "Invalid file_cache_max_size format")?;
+
+        if let Some(keepalive) = &self.tcp_keepalive {
+            keepalive.validate()?;
+        }
+
         Ok(())
     }
 
+    /// Sizes of static files to memory-map (inclusive), when enabled
+    pub fn mmap_size_range(&self) -> Option<(usize, usize)> {
+        if !self.mmap_static_files {
+            return None;
+        }
+        let min = self.parse_buffer_size(&self.mmap_min_size).ok()?;
+        let max = self.parse_buffer_size(&self.mmap_max_size).ok()?;
+        Some((min.max(1), max))
+    }
+
+    /// Capacity and largest file size of the in-memory file cache, when enabled
+    pub fn file_cache_settings(&self) -> Option<(usize, usize)> {
+        if self.file_cache_entries == 0 {
+            return None;
+        }
+        let max_size = self.parse_buffer_size(&self.file_cache_max_size).ok()?;
+        Some((self.file_cache_entries, max_size))
+    }
+
     pub fn parse_buffer_size(&self, size_str: &str) -> Result<usize, Box<dyn std::error::Error>> {
         let size_str = size_str.trim().to_uppercase();
 
@@ -475,25 +1285,194 @@ impl PerformanceConfig {
     }
 }
 
+impl TracingConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let url = url::Url::parse(&self.endpoint)
+            .map_err(|e| format!("Invalid tracing endpoint '{}': {}", self.endpoint, e))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(
+                format!("Tracing endpoint must use http or https: {}", self.endpoint).into(),
+            );
+        }
+
+        if self.service_name.is_empty() {
+            return Err("Tracing service_name cannot be empty".into());
+        }
+
+        Ok(())
+    }
+}
+
+impl StatsdConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let valid_address = self
+            .address
+            .rsplit_once(':')
+            .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+        if !valid_address {
+            return Err(format!("StatsD address must be host:port: {}", self.address).into());
+        }
+
+        if self.flush_interval_ms == 0 {
+            return Err("StatsD flush_interval_ms must be greater than 0".into());
+        }
+
+        Ok(())
+    }
+}
+
+impl MetricsConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.buckets.is_empty() {
+            return Err("Metrics buckets must not be empty".into());
+        }
+        if let Some(bucket) = self
+            .buckets
+            .iter()
+            .find(|bucket| !bucket.is_finite() || **bucket <= 0.0)
+        {
+            return Err(format!("Metrics buckets must be positive: {}", bucket).into());
+        }
+        if let Some(pair) = self.buckets.windows(2).find(|pair| pair[0] >= pair[1]) {
+            return Err(format!(
+                "Metrics buckets must be in ascending order: {} is followed by {}",
+                pair[0], pair[1]
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl RateLimitConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.requests_per_minute == 0 {
+            return Err("Rate limit requests per minute must be greater than 0".into());
+        }
+        if self.burst_size == 0 {
+            return Err("Rate limit burst size must be greater than 0".into());
+        }
+        for entry in &self.whitelist {
+            if crate::core::utils::net::parse_cidr(entry).is_none() {
+                return Err(format!("Invalid rate limit whitelist entry: {}", entry).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `ip` is exempt from this rate limit
+    pub fn is_whitelisted(&self, ip: IpAddr) -> bool {
+        self.whitelist.iter().any(|entry| {
+            crate::core::utils::net::parse_cidr(entry).is_some_and(|(network, prefix)| {
+                crate::core::utils::net::cidr_contains(network, prefix, ip)
+            })
+        })
+    }
+}
+
+impl RateLimitStoreConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.backend.as_str() {
+            "memory" => Ok(()),
+            "redis" => {
+                let redis_url = self
+                    .redis_url
+                    .as_deref()
+                    .ok_or("Rate limit backend 'redis' requires redis_url")?;
+                let url = url::Url::parse(redis_url)
+                    .map_err(|e| format!("Invalid redis_url '{}': {}", redis_url, e))?;
+                if url.scheme() != "redis" {
+                    return Err(
+                        format!("redis_url must use the redis:// scheme: {}", redis_url).into(),
+                    );
+                }
+                Ok(())
+            }
+            other => Err(format!(
+                "Invalid rate limit backend: {}. Valid options: memory, redis",
+                other
+            )
+            .into()),
+        }
+    }
+}
+
 impl SecurityConfig {
     fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
         // Validate max request size format
         self.parse_size(&self.max_request_size)
             .map_err(|_| "Invalid max request size format")?;
 
+        // Validate request header limits
+        if self
+            .parse_size(&self.max_header_size)
+            .map_or(true, |size| size == 0)
+        {
+            return Err("Invalid max header size format".into());
+        }
+        // Pingora refuses requests with more header fields on its own
+        if self.max_headers == 0 || self.max_headers > MAX_PARSED_HEADERS {
+            return Err(format!("max_headers must be between 1 and {}", MAX_PARSED_HEADERS).into());
+        }
+
         // Validate rate limiting configuration
         if let Some(rate_limit) = &self.rate_limiting {
-            if rate_limit.requests_per_minute == 0 {
-                return Err("Rate limit requests per minute must be greater than 0".into());
+            rate_limit.validate()?;
+        }
+        self.rate_limit_store.validate()?;
+
+        // Validate the custom Server header value
+        if let Some(value) = &self.server_header {
+            if value.trim().is_empty() {
+                return Err(
+                    "server_header cannot be empty; use hide_server_header to remove it".into(),
+                );
             }
-            if rate_limit.burst_size == 0 {
-                return Err("Rate limit burst size must be greater than 0".into());
+            if value.chars().any(char::is_control) {
+                return Err("server_header cannot contain control characters".into());
             }
         }
 
+        // Validate trusted proxy addresses
+        net::TrustedProxies::try_from(self.trusted_proxies.clone())?;
+
         Ok(())
     }
 
+    /// Value of the Server response header, or None when it is hidden
+    pub fn server_header_value(&self, server: &ServerInfo) -> Option<String> {
+        if self.hide_server_header {
+            None
+        } else if let Some(value) = &self.server_header {
+            Some(value.clone())
+        } else {
+            Some(format!("{}/{}", server.name, server.version))
+        }
+    }
+
+    /// `max_request_size` in bytes (an invalid size is rejected by validation)
+    pub fn max_request_bytes(&self) -> usize {
+        self.parse_size(&self.max_request_size)
+            .or_else(|_| self.parse_size(&default_max_request_size()))
+            .unwrap_or(usize::MAX)
+    }
+
+    /// `max_header_size` in bytes (an invalid size is rejected by validation)
+    pub fn max_header_bytes(&self) -> usize {
+        self.parse_size(&self.max_header_size)
+            .or_else(|_| self.parse_size(&default_max_header_size()))
+            .unwrap_or(usize::MAX)
+    }
+
     pub fn parse_size(&self, size_str: &str) -> Result<usize, Box<dyn std::error::Error>> {
         let size_str = size_str.trim().to_uppercase();
 
@@ -545,25 +1524,22 @@ mod tests {
             sites: vec![SiteConfig {
                 name: "single-site".to_string(),
                 hostname: "localhost".to_string(),
-                hostnames: vec![],
                 port: 8080,
                 static_dir: "/tmp/static".to_string(),
                 default: false, // Explicitly NOT marked as default
-                api_only: false,
-                headers: HashMap::new(),
-                redirect_to_https: false,
                 index_files: vec!["index.html".to_string()],
-                error_pages: HashMap::new(),
-                compression: Default::default(),
-                cache: Default::default(),
-                access_control: Default::default(),
-                ssl: Default::default(),
-                proxy: Default::default(),
+                ..Default::default()
             }],
             logging: LoggingConfig::default(),
             performance: PerformanceConfig::default(),
             security: SecurityConfig::default(),
             management: ManagementConfig::default(),
+            tracing: Default::default(),
+            statsd: Default::default(),
+            metrics: Default::default(),
+            trusted_proxies: Default::default(),
+            acme_challenge_dir: None,
+            well_known: HashMap::new(),
         };
 
         // Before post_process, the site should not be marked as default
@@ -580,20 +1556,10 @@ mod tests {
         config.sites.push(SiteConfig {
             name: "second-site".to_string(),
             hostname: "example.com".to_string(),
-            hostnames: vec![],
             port: 8081,
             static_dir: "/tmp/static2".to_string(),
-            default: false,
-            api_only: false,
-            headers: HashMap::new(),
-            redirect_to_https: false,
             index_files: vec!["index.html".to_string()],
-            error_pages: HashMap::new(),
-            compression: Default::default(),
-            cache: Default::default(),
-            access_control: Default::default(),
-            ssl: Default::default(),
-            proxy: Default::default(),
+            ..Default::default()
         });
 
         // Reset first site's default flag
@@ -608,6 +1574,55 @@ mod tests {
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn test_hostname_match_priority() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            [server]
+            name = "test"
+
+            [[sites]]
+            name = "default"
+            hostname = "localhost"
+            port = 8080
+            static_dir = "/tmp"
+            default = true
+
+            [[sites]]
+            name = "regex"
+            hostname = "regex.internal"
+            hostname_regex = '[a-z]+\.example\.com'
+            port = 8080
+            static_dir = "/tmp"
+
+            [[sites]]
+            name = "wildcard"
+            hostname = "wild.internal"
+            hostnames = ["*.example.com"]
+            port = 8080
+            static_dir = "/tmp"
+
+            [[sites]]
+            name = "exact"
+            hostname = "api.example.com"
+            port = 8080
+            static_dir = "/tmp"
+            "#,
+        )
+        .unwrap();
+
+        let site = |host| {
+            config
+                .find_site_by_host_port(host, 8080)
+                .unwrap()
+                .name
+                .as_str()
+        };
+        assert_eq!(site("api.example.com"), "exact");
+        assert_eq!(site("www.example.com"), "wildcard");
+        assert_eq!(site("localhost"), "default");
+    }
+
     #[test]
     fn test_security_config_validation() {
         let mut config = SecurityConfig::default();
@@ -617,12 +1632,313 @@ mod tests {
         assert!(config.validate().is_err());
 
         config.max_request_size = "10MB".to_string();
+        assert_eq!(config.max_header_bytes(), 32 * 1024);
+        config.max_header_size = "0KB".to_string();
+        assert!(config.validate().is_err());
+        config.max_header_size = "8KB".to_string();
+        config.max_headers = 257;
+        assert!(config.validate().is_err());
+        config.max_headers = 50;
+        assert!(config.validate().is_ok());
+        assert_eq!(config.max_header_bytes(), 8 * 1024);
+
         config.rate_limiting = Some(RateLimitConfig {
             requests_per_minute: 0,
             burst_size: 10,
             whitelist: vec![],
         });
         assert!(config.validate().is_err());
+
+        config.rate_limiting = None;
+        config.trusted_proxies = vec!["10.0.0.1".to_string(), "::1".to_string()];
+        assert!(config.validate().is_ok());
+
+        config.trusted_proxies = vec!["not-an-ip".to_string()];
+        assert!(config.validate().is_err());
+
+        config.trusted_proxies = vec![];
+        config.rate_limit_store.backend = "redis".to_string();
+        assert!(config.validate().is_err());
+        config.rate_limit_store.redis_url = Some("rediss://cache:6380".to_string());
+        assert!(config.validate().is_err());
+        config.rate_limit_store.redis_url = Some("redis://:secret@cache:6379/1".to_string());
+        assert!(config.validate().is_ok());
+        config.rate_limit_store.backend = "memcached".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_server_header_modes() {
+        let server = ServerInfo {
+            name: "BWS".to_string(),
+            version: "1.2.3".to_string(),
+            description: String::new(),
+        };
+        let mut security = SecurityConfig::default();
+
+        // Full disclosure by default
+        assert_eq!(
+            security.server_header_value(&server).as_deref(),
+            Some("BWS/1.2.3")
+        );
+
+        // Custom value without the version
+        security.server_header = Some("BWS".to_string());
+        assert!(security.validate().is_ok());
+        assert_eq!(
+            security.server_header_value(&server).as_deref(),
+            Some("BWS")
+        );
+
+        // Hiding wins over a custom value
+        security.hide_server_header = true;
+        assert_eq!(security.server_header_value(&server), None);
+
+        security.server_header = Some("BWS\r\nX-Injected: 1".to_string());
+        assert!(security.validate().is_err());
+        security.server_header = Some(" ".to_string());
+        assert!(security.validate().is_err());
+    }
+
+    #[test]
+    fn test_performance_timeout_validation() {
+        let mut config = PerformanceConfig::default();
+        assert!(config.validate().is_ok());
+
+        config.keep_alive_timeout = 0;
+        assert!(config.validate().is_err());
+
+        config.keep_alive_timeout = 60;
+        config.request_timeout = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_file_cache_settings() {
+        let mut config = PerformanceConfig::default();
+        assert_eq!(config.file_cache_settings(), Some((1024, 64 * 1024)));
+
+        config.file_cache_entries = 0;
+        assert_eq!(config.file_cache_settings(), None);
+
+        config.file_cache_max_size = "lots".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_https_redirect_port() {
+        let config = |extra_site: &str| {
+            let mut config: ServerConfig = toml::from_str(&format!(
+                r#"
+                [server]
+                name = "test"
+
+                [[sites]]
+                name = "secure"
+                hostname = "example.com"
+                port = 443
+                static_dir = "/tmp"
+                redirect_to_https = true
+                default = true
+
+                [sites.ssl]
+                enabled = true
+                cert_file = "/tmp/example.crt"
+                key_file = "/tmp/example.key"
+
+                {}
+                "#,
+                extra_site
+            ))
+            .unwrap();
+            config.post_process().unwrap();
+            config
+        };
+        let secure = |config: &ServerConfig| {
+            config
+                .sites
+                .iter()
+                .find(|site| site.name == "secure")
+                .unwrap()
+                .all_ports()
+        };
+
+        let alone = config("");
+        assert_eq!(secure(&alone), [443, 80]);
+        assert_eq!(
+            alone
+                .find_site_by_host_port("example.com", 80)
+                .unwrap()
+                .name,
+            "secure"
+        );
+
+        // Other hostnames on port 80 share the listener
+        let shared = config(
+            r#"
+            [[sites]]
+            name = "blog"
+            hostname = "blog.example.org"
+            port = 80
+            static_dir = "/tmp"
+            "#,
+        );
+        assert_eq!(secure(&shared), [443, 80]);
+        assert_eq!(
+            shared
+                .find_site_by_host_port("example.com", 80)
+                .unwrap()
+                .name,
+            "secure"
+        );
+        assert_eq!(
+            shared
+                .find_site_by_host_port("blog.example.org", 80)
+                .unwrap()
+                .name,
+            "blog"
+        );
+
+        // A site explicitly serving the hostname on port 80 keeps it
+        let explicit = config(
+            r#"
+            [[sites]]
+            name = "plain"
+            hostname = "example.com"
+            port = 80
+            static_dir = "/tmp"
+            "#,
+        );
+        assert_eq!(secure(&explicit), [443]);
+        assert_eq!(
+            explicit
+                .find_site_by_host_port("example.com", 80)
+                .unwrap()
+                .name,
+            "plain"
+        );
+    }
+
+    #[test]
+    fn test_trusted_proxies_section() {
+        let config = |trusted: &str| {
+            toml::from_str::<ServerConfig>(&format!(
+                r#"
+                trusted_proxies = {}
+
+                [server]
+                name = "test"
+
+                [security]
+                trusted_proxies = ["192.0.2.10"]
+
+                [[sites]]
+                name = "main"
+                hostname = "localhost"
+                port = 8080
+                static_dir = "/tmp"
+                "#,
+                trusted
+            ))
+        };
+
+        let mut parsed = config(r#"["10.0.0.0/8", "2001:db8::/32"]"#).unwrap();
+        assert!(parsed.is_trusted_proxy("10.200.0.1".parse().unwrap()));
+        assert!(parsed.is_trusted_proxy("2001:db8::7".parse().unwrap()));
+        assert!(!parsed.is_trusted_proxy("192.0.2.10".parse().unwrap()));
+
+        // The deprecated security list is folded in
+        parsed.post_process().unwrap();
+        assert!(parsed.is_trusted_proxy("192.0.2.10".parse().unwrap()));
+        assert!(!parsed.is_trusted_proxy("192.0.2.11".parse().unwrap()));
+
+        let err = config(r#"["10.0.0.0/8", "proxy.internal"]"#).unwrap_err();
+        assert!(err.to_string().contains("proxy.internal"));
+    }
+
+    #[test]
+    fn test_port_in_use_is_reported() {
+        let taken = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let config: ServerConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            name = "test"
+
+            [[sites]]
+            name = "main"
+            hostname = "localhost"
+            port = {}
+            static_dir = "/tmp"
+            "#,
+            port
+        ))
+        .unwrap();
+        let error = config.check_ports_available().unwrap_err().to_string();
+        assert!(error.contains(&format!("0.0.0.0:{}", port)), "{}", error);
+        assert!(error.contains("site 'main'"));
+        assert!(error.contains("already in use"));
+
+        drop(taken);
+        assert!(config.check_ports_available().is_ok());
+    }
+
+    #[test]
+    fn test_tcp_keepalive_validation() {
+        let config: PerformanceConfig = toml::from_str("[tcp_keepalive]\nidle = 30").unwrap();
+        let keepalive = config.tcp_keepalive.clone().unwrap();
+        assert_eq!(
+            (keepalive.idle, keepalive.interval, keepalive.count),
+            (30, 10, 5)
+        );
+        assert!(config.validate().is_ok());
+        assert!(PerformanceConfig::default().tcp_keepalive.is_none());
+
+        let mut config = PerformanceConfig {
+            tcp_keepalive: Some(TcpKeepaliveConfig {
+                count: 0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        config.tcp_keepalive = Some(TcpKeepaliveConfig {
+            idle: 40000,
+            ..Default::default()
+        });
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_slow_request_threshold() {
+        let mut config = LoggingConfig::default();
+        assert!(!config.is_slow_request(Duration::from_secs(3600)));
+
+        config.slow_request_ms = Some(500);
+        assert!(!config.is_slow_request(Duration::from_millis(500)));
+        assert!(config.is_slow_request(Duration::from_millis(501)));
+    }
+
+    #[test]
+    fn test_metrics_buckets_validation() {
+        let mut config = MetricsConfig::default();
+        assert!(config.validate().is_ok());
+
+        config.buckets = vec![0.0005, 0.001, 0.002];
+        assert!(config.validate().is_ok());
+
+        config.buckets = vec![0.1, 0.1];
+        assert!(config.validate().is_err());
+        config.buckets = vec![1.0, 0.5];
+        assert!(config.validate().is_err());
+        config.buckets = vec![0.0, 1.0];
+        assert!(config.validate().is_err());
+        config.buckets = vec![f64::INFINITY];
+        assert!(config.validate().is_err());
+        config.buckets.clear();
+        assert!(config.validate().is_err());
     }
 
     #[tokio::test]
@@ -638,25 +1954,22 @@ mod tests {
             sites: vec![SiteConfig {
                 name: "test-site".to_string(),
                 hostname: "localhost".to_string(),
-                hostnames: vec![],
                 port: 8080,
                 static_dir: "/tmp/static".to_string(),
                 default: true,
-                api_only: false,
-                headers: HashMap::new(),
-                redirect_to_https: false,
                 index_files: vec!["index.html".to_string()],
-                error_pages: HashMap::new(),
-                compression: Default::default(),
-                cache: Default::default(),
-                access_control: Default::default(),
-                ssl: Default::default(),
-                proxy: Default::default(),
+                ..Default::default()
             }],
             logging: LoggingConfig::default(),
             performance: PerformanceConfig::default(),
             security: SecurityConfig::default(),
             management: ManagementConfig::default(),
+            tracing: Default::default(),
+            statsd: Default::default(),
+            metrics: Default::default(),
+            trusted_proxies: Default::default(),
+            acme_challenge_dir: None,
+            well_known: HashMap::new(),
         };
 
         let temp_file = NamedTempFile::new().unwrap();
@@ -670,4 +1983,466 @@ mod tests {
         assert_eq!(config.server.name, loaded_config.server.name);
         assert_eq!(config.sites.len(), loaded_config.sites.len());
     }
+
+    #[test]
+    fn test_changed_sections() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            [server]
+            name = "test"
+
+            [[sites]]
+            name = "main"
+            hostname = "localhost"
+            port = 8080
+            static_dir = "/tmp"
+            default = true
+            "#,
+        )
+        .unwrap();
+        assert!(!config.has_config_changed(&config.clone()));
+
+        let mut changed = config.clone();
+        changed.server.description = "updated".to_string();
+        assert_eq!(config.changed_sections(&changed), ["server"]);
+
+        let mut changed = config.clone();
+        changed.sites[0].index_files.push("home.html".to_string());
+        assert_eq!(config.changed_sections(&changed), ["sites"]);
+
+        let mut changed = config.clone();
+        changed.logging.level = "debug".to_string();
+        assert_eq!(config.changed_sections(&changed), ["logging"]);
+
+        let mut changed = config.clone();
+        changed.performance.request_timeout += 1;
+        assert_eq!(config.changed_sections(&changed), ["performance"]);
+
+        let mut changed = config.clone();
+        changed
+            .security
+            .trusted_proxies
+            .push("10.0.0.1".to_string());
+        assert_eq!(config.changed_sections(&changed), ["security"]);
+
+        let mut changed = config.clone();
+        changed.acme_challenge_dir = Some("/var/lib/bws/challenges".to_string());
+        assert_eq!(config.changed_sections(&changed), ["acme_challenge_dir"]);
+
+        let mut changed = config.clone();
+        changed.management.port += 1;
+        assert_eq!(config.changed_sections(&changed), ["management"]);
+        assert!(config.has_config_changed(&changed));
+    }
+
+    #[test]
+    fn test_site_with_multiple_ports() {
+        let toml_config = r#"
+            [server]
+            name = "test"
+
+            [[sites]]
+            name = "main"
+            hostname = "example.com"
+            port = 8443
+            ports = [8080, 8443]
+            static_dir = "/tmp"
+            default = true
+
+            [[sites]]
+            name = "other"
+            hostname = "other.com"
+            port = 8080
+            static_dir = "/tmp"
+            "#;
+        let config: ServerConfig = toml::from_str(toml_config).unwrap();
+        config.validate().unwrap();
+
+        let site = &config.sites[0];
+        assert_eq!(site.all_ports(), [8443, 8080]);
+        assert_eq!(
+            config
+                .find_site_by_host_port("example.com", 8080)
+                .unwrap()
+                .name,
+            "main"
+        );
+        assert_eq!(
+            config
+                .find_site_by_host_port("other.com", 8080)
+                .unwrap()
+                .name,
+            "other"
+        );
+
+        // The same hostname may not appear twice on an additional port
+        let conflicting = toml_config.replace("other.com", "example.com");
+        let config: ServerConfig = toml::from_str(&conflicting).unwrap();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("example.com:8080"));
+    }
+
+    #[test]
+    fn test_site_max_request_size_overrides_global() {
+        let toml_config = r#"
+            [server]
+            name = "test"
+
+            [security]
+            max_request_size = "1MB"
+
+            [[sites]]
+            name = "static"
+            hostname = "example.com"
+            port = 8080
+            static_dir = "/tmp"
+            default = true
+            max_request_size = "4KB"
+
+            [[sites]]
+            name = "uploads"
+            hostname = "upload.example.com"
+            port = 8080
+            static_dir = "/tmp"
+            max_request_size = "100MB"
+
+            [[sites]]
+            name = "api"
+            hostname = "api.example.com"
+            port = 8080
+            static_dir = "/tmp"
+            "#;
+        let mut config: ServerConfig = toml::from_str(toml_config).unwrap();
+        config.validate().unwrap();
+
+        let limit = |name: &str| {
+            config.max_request_bytes_for(config.sites.iter().find(|site| site.name == name))
+        };
+        assert_eq!(limit("static"), 4 * 1024);
+        assert_eq!(limit("uploads"), 100 * 1024 * 1024);
+        assert_eq!(limit("api"), 1024 * 1024);
+        assert_eq!(config.max_request_bytes_for(None), 1024 * 1024);
+
+        // A 2MB upload is refused by the restrictive site only
+        let mut upload = pingora::http::RequestHeader::build("POST", b"/", None).unwrap();
+        upload.insert_header("Content-Length", "2097152").unwrap();
+        upload.insert_header("Expect", "100-continue").unwrap();
+        assert!(crate::middleware::validate_expectation(&upload, limit("static")).is_err());
+        assert!(crate::middleware::validate_expectation(&upload, limit("uploads")).is_ok());
+
+        config.sites[1].max_request_size = Some("lots".to_string());
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("uploads") && err.contains("lots"));
+    }
+
+    #[test]
+    fn test_static_dirs_created_only_on_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let static_dir = dir.path().join("public");
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                [server]
+                name = "test"
+
+                [[sites]]
+                name = "main"
+                hostname = "localhost"
+                port = 8080
+                static_dir = "{}"
+                create_static_dir = true
+                require_static_dir = true
+                "#,
+                static_dir.display()
+            ),
+        )
+        .unwrap();
+
+        // Loading and validating, as a dry run does, leaves the disk alone
+        let config = ServerConfig::load_from_file(&path.to_string_lossy()).unwrap();
+        config.validate().unwrap();
+        assert!(!static_dir.exists());
+
+        config.create_static_dirs().unwrap();
+        assert!(static_dir.is_dir());
+    }
+
+    #[test]
+    fn test_load_merged_config_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.toml");
+        std::fs::write(
+            &base,
+            r#"
+            [server]
+            name = "base"
+
+            [performance]
+            worker_threads = 4
+            request_timeout = 10
+
+            [[sites]]
+            name = "main"
+            hostname = "example.com"
+            port = 8080
+            static_dir = "/tmp"
+            default = true
+            index_files = ["index.html", "index.htm"]
+
+            [sites.headers]
+            "X-Env" = "base"
+            "X-Frame-Options" = "DENY"
+
+            [[sites]]
+            name = "docs"
+            hostname = "docs.example.com"
+            port = 8080
+            static_dir = "/tmp"
+            "#,
+        )
+        .unwrap();
+        let overlays = dir.path().join("overlays");
+        std::fs::create_dir(&overlays).unwrap();
+        std::fs::write(
+            overlays.join("10-production.toml"),
+            r#"
+            [performance]
+            worker_threads = 16
+
+            [[sites]]
+            name = "main"
+            port = 80
+            index_files = ["home.html"]
+
+            [sites.headers]
+            "X-Env" = "production"
+
+            [[sites]]
+            name = "status"
+            hostname = "status.example.com"
+            port = 80
+            static_dir = "/tmp"
+            "#,
+        )
+        .unwrap();
+        std::fs::write(overlays.join("20-name.toml"), "[server]\nname = \"prod\"\n").unwrap();
+        std::fs::write(overlays.join("README.md"), "not a config").unwrap();
+
+        let config = ServerConfig::load_from_files(&[&base, &overlays]).unwrap();
+        assert_eq!(config.server.name, "prod");
+        // Tables merge key by key, later files winning
+        assert_eq!(config.performance.worker_threads, 16);
+        assert_eq!(config.performance.request_timeout, 10);
+
+        // Sites merge by name, and new ones are added
+        let site = |name: &str| config.sites.iter().find(|site| site.name == name).unwrap();
+        assert_eq!(config.sites.len(), 3);
+        assert_eq!(site("status").hostname, "status.example.com");
+        let main = site("main");
+        assert_eq!(main.hostname, "example.com");
+        assert_eq!(main.port, 80);
+        assert_eq!(main.headers["X-Env"], "production");
+        assert_eq!(main.headers["X-Frame-Options"], "DENY");
+        // Lists are replaced, not concatenated
+        assert_eq!(main.index_files, ["home.html"]);
+        assert_eq!(site("docs").port, 8080);
+
+        // Files load in the order given
+        let config = ServerConfig::load_from_files(&[&overlays, &base]).unwrap();
+        assert_eq!(config.server.name, "base");
+        assert_eq!(config.performance.worker_threads, 4);
+
+        // Only the directory's own .toml files are read
+        let config = ServerConfig::load_from_file(&dir.path().to_string_lossy()).unwrap();
+        assert_eq!(config.server.name, "base");
+        assert_eq!(config.sites.len(), 2);
+        let empty = tempfile::tempdir().unwrap();
+        assert!(ServerConfig::load_from_file(&empty.path().to_string_lossy()).is_err());
+    }
+
+    #[test]
+    fn test_validate_detailed() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml_config = format!(
+            r#"
+            [server]
+            name = "test"
+
+            [[sites]]
+            name = "main"
+            hostname = "example.com"
+            port = 18081
+            static_dir = "{}"
+            index_files = ["index.html"]
+            "#,
+            dir.path().display()
+        );
+        let mut config: ServerConfig = toml::from_str(&toml_config).unwrap();
+        config.post_process().unwrap();
+        let site_warnings = |report: &ValidationReport| {
+            report
+                .warnings
+                .iter()
+                .filter(|warning| warning.site.is_some())
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        let report = config.validate_detailed();
+        assert!(report.is_valid(), "{:?}", report);
+        assert_eq!(
+            site_warnings(&report),
+            ["Site 'main': No index files found in static directory"]
+        );
+        std::fs::write(dir.path().join("index.html"), "home").unwrap();
+        assert!(site_warnings(&config.validate_detailed()).is_empty());
+
+        config.sites[0].static_dir = "/does/not/exist".to_string();
+        let report = config.validate_detailed();
+        assert!(!report.is_valid());
+        assert_eq!(
+            report.errors,
+            [ValidationIssue {
+                site: Some("main".to_string()),
+                message: "Static directory '/does/not/exist' does not exist".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_shared_acme_challenge_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml_config = format!(
+            r#"
+            acme_challenge_dir = "/var/lib/bws/challenges"
+
+            [server]
+            name = "test"
+
+            [[sites]]
+            name = "blog"
+            hostname = "blog.example.com"
+            port = 443
+            static_dir = "{static_dir}"
+            index_files = []
+
+            [sites.ssl]
+            enabled = true
+            auto_cert = true
+
+            [sites.ssl.acme]
+            enabled = true
+            email = "admin@example.com"
+
+            [[sites]]
+            name = "shop"
+            hostname = "shop.example.com"
+            port = 443
+            static_dir = "{static_dir}"
+            index_files = []
+
+            [sites.ssl]
+            enabled = true
+            auto_cert = true
+
+            [sites.ssl.acme]
+            enabled = true
+            email = "admin@example.com"
+            challenge_dir = "/srv/shop/challenges"
+            "#,
+            static_dir = dir.path().display()
+        );
+        let mut config: ServerConfig = toml::from_str(&toml_config).unwrap();
+        let shared = config.acme_challenge_dir.clone();
+
+        // Every site writes to the shared directory, whatever its own setting
+        for site in &config.sites {
+            let ssl = crate::ssl::SslConfig::for_site(site, shared.as_deref());
+            assert_eq!(ssl.acme.unwrap().challenge_dir, "/var/lib/bws/challenges");
+        }
+        let site_warnings = |config: &ServerConfig| {
+            config
+                .validate_detailed()
+                .warnings
+                .iter()
+                .filter(|warning| warning.site.is_some())
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            site_warnings(&config),
+            [
+                "Site 'shop': ACME challenge_dir '/srv/shop/challenges' is ignored, \
+                 challenges go to acme_challenge_dir '/var/lib/bws/challenges'"
+            ]
+        );
+
+        // Without it, each site keeps its own directory
+        config.acme_challenge_dir = None;
+        let dirs: Vec<_> = config
+            .sites
+            .iter()
+            .map(|site| crate::ssl::SslConfig::challenge_dir(site, None))
+            .collect();
+        assert_eq!(dirs, ["./certs/challenges", "/srv/shop/challenges"]);
+        assert!(site_warnings(&config).is_empty());
+    }
+
+    #[test]
+    fn test_site_rate_limit_overrides_global() {
+        let toml_config = r#"
+            [server]
+            name = "test"
+
+            [security.rate_limiting]
+            requests_per_minute = 60
+            burst_size = 10
+            whitelist = ["10.0.0.0/8"]
+
+            [[sites]]
+            name = "public"
+            hostname = "example.com"
+            port = 8080
+            static_dir = "/tmp"
+            default = true
+
+            [[sites]]
+            name = "internal"
+            hostname = "internal.example.com"
+            port = 8080
+            static_dir = "/tmp"
+
+            [sites.rate_limiting]
+            requests_per_minute = 600
+            burst_size = 100
+            "#;
+        let mut config: ServerConfig = toml::from_str(toml_config).unwrap();
+        config.validate().unwrap();
+
+        let (scope, limit) = config.rate_limit_for(&config.sites[0]).unwrap();
+        assert_eq!((scope, limit.requests_per_minute), ("", 60));
+        assert!(limit.is_whitelisted("10.1.2.3".parse().unwrap()));
+        assert!(!limit.is_whitelisted("203.0.113.7".parse().unwrap()));
+
+        let (scope, limit) = config.rate_limit_for(&config.sites[1]).unwrap();
+        assert_eq!((scope, limit.requests_per_minute), ("internal", 600));
+
+        // Two sites with their own limits are counted independently
+        let limiter = crate::middleware::RateLimiter::new();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let (public, public_limit) = config.rate_limit_for(&config.sites[0]).unwrap();
+        for _ in 0..public_limit.burst_size {
+            assert!(limiter.check(public, client, 60, 10).is_ok());
+        }
+        assert!(limiter.check(public, client, 60, 10).is_err());
+        assert!(limiter.check("internal", client, 600, 100).is_ok());
+
+        config.security.rate_limiting = None;
+        assert!(config.rate_limit_for(&config.sites[0]).is_none());
+
+        config.sites[1].rate_limiting.as_mut().unwrap().whitelist = vec!["bad/99".to_string()];
+        assert!(config.validate().is_err());
+    }
 }
@@ -1,21 +1,49 @@
+use crate::config::RateLimitConfig;
+use crate::core::utils::fs;
+use crate::core::{constants, BwsError, LoadBalancingStrategy};
+use crate::ssl::AcmeKeyType;
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Mutex;
 
 /// Configuration for a single site (virtual host)
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct SiteConfig {
     /// Site name (for identification)
     pub name: String,
     /// Primary hostname for the site
     pub hostname: String,
-    /// Additional hostnames that share the same port and config
+    /// Additional hostnames that share the same port and config.
+    /// Entries may be wildcards such as `*.example.com`.
     #[serde(default)]
     pub hostnames: Vec<String>,
+    /// Optional regex matched against the full request hostname
+    #[serde(default)]
+    pub hostname_regex: Option<String>,
     /// Port to listen on
     pub port: u16,
+    /// Additional plain HTTP ports to listen on (e.g. 80 alongside an HTTPS `port`)
+    #[serde(default)]
+    pub ports: Vec<u16>,
     /// Directory for static files
     pub static_dir: String,
+    /// Create static_dir at startup if it does not exist
+    #[serde(default)]
+    pub create_static_dir: bool,
+    /// Fail validation if static_dir does not exist (instead of warning)
+    #[serde(default)]
+    pub require_static_dir: bool,
+    /// URL prefixes served from other directories than static_dir, as
+    /// `[prefix, directory]` pairs (e.g. `["/downloads/", "/mnt/downloads"]`)
+    #[serde(default)]
+    pub aliases: Vec<(String, String)>,
+    /// URL prefixes only reachable through an upstream's `X-Accel-Redirect`;
+    /// direct requests for them are answered with 404
+    #[serde(default)]
+    pub internal_locations: Vec<String>,
     /// Whether this site is the default for its port
     #[serde(default)]
     pub default: bool,
@@ -32,25 +60,179 @@ pub struct SiteConfig {
     #[serde(default)]
     pub index_files: Vec<String>,
     /// Custom error pages by status code
-    #[serde(default)]
+    #[serde(default, with = "status_keys")]
     pub error_pages: HashMap<u16, String>,
+    /// Document served for directory requests when no index file is found
+    #[serde(default)]
+    pub fallback_document: Option<String>,
+    /// Pick file variants by the Accept header: `/page` can serve `page.html`,
+    /// and `img.jpg` can serve `img.avif` or `img.webp` when the client accepts them
+    #[serde(default)]
+    pub content_negotiation: bool,
+    /// List directories that have no index file (HTML, or JSON for `Accept: application/json`)
+    #[serde(default)]
+    pub autoindex: bool,
+    /// File extensions served in addition to the built-in safe set (e.g. `["yaml", "csv"]`)
+    #[serde(default)]
+    pub allowed_extensions: Vec<String>,
+    /// Status for files whose extension is outside the safe set: 404 (stealth) or 403
+    #[serde(default = "default_blocked_extension_status")]
+    pub blocked_extension_status: u16,
+    /// Status for requests that try to escape `static_dir`: 404 (stealth) or 403
+    #[serde(default = "default_traversal_status")]
+    pub traversal_status: u16,
+    /// Charset appended to textual MIME types (e.g. "iso-8859-1" for legacy content)
+    #[serde(default = "default_charset")]
+    pub default_charset: String,
+    /// Requests this site serves at once before rejecting new ones with 503
+    #[serde(default)]
+    pub max_concurrent_requests: Option<usize>,
+    /// Answer every request with 503 while the site is under maintenance
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+    /// Icon answered for `/favicon.ico` when the site has none
+    #[serde(default)]
+    pub favicon: FaviconConfig,
+    /// Largest request body for this site (e.g. `"100MB"`), overriding
+    /// `security.max_request_size`
+    #[serde(default)]
+    pub max_request_size: Option<String>,
+    /// Rate limit for this site, overriding `security.rate_limiting`
+    #[serde(default)]
+    pub rate_limiting: Option<RateLimitConfig>,
+    /// Documents served under `/.well-known/`, keyed by name (e.g. `security.txt`).
+    /// Overrides the server-wide `well_known` documents of the same name.
+    #[serde(default)]
+    pub well_known: HashMap<String, WellKnownDocument>,
+    /// Access log file for this site, overriding the global access_log
+    #[serde(default)]
+    pub access_log: Option<String>,
+    /// Access log format for this site, overriding `logging.format`
+    #[serde(default)]
+    pub log_format: Option<String>,
     /// Compression configuration
     #[serde(default)]
     pub compression: CompressionConfig,
     /// Caching configuration
     #[serde(default)]
     pub cache: CacheConfig,
+    /// Referer-based hotlink protection for images and media
+    #[serde(default)]
+    pub hotlink_protection: HotlinkProtectionConfig,
+    /// Blocking and throttling of clients by User-Agent
+    #[serde(default)]
+    pub user_agent_filter: UserAgentFilterConfig,
+    /// Country-based access control
+    #[serde(default)]
+    pub geoip: GeoIpConfig,
     /// CORS and access control configuration
     #[serde(default)]
     pub access_control: AccessControlConfig,
     /// SSL configuration for the site
     #[serde(default)]
     pub ssl: SiteSslConfig,
+    /// Strict-Transport-Security configuration
+    #[serde(default)]
+    pub hsts: HstsConfig,
     /// Proxy configuration for the site
     #[serde(default)]
     pub proxy: ProxyConfig,
 }
 
+/// A site with every optional setting as when it is left out of the
+/// configuration file. `name`, `hostname`, `port` and `static_dir` have no
+/// default and are left empty.
+impl Default for SiteConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            hostname: String::new(),
+            hostnames: Vec::new(),
+            hostname_regex: None,
+            port: 0,
+            ports: Vec::new(),
+            static_dir: String::new(),
+            create_static_dir: false,
+            require_static_dir: false,
+            aliases: Vec::new(),
+            internal_locations: Vec::new(),
+            default: false,
+            api_only: false,
+            headers: HashMap::new(),
+            redirect_to_https: false,
+            index_files: Vec::new(),
+            error_pages: HashMap::new(),
+            fallback_document: None,
+            content_negotiation: false,
+            autoindex: false,
+            allowed_extensions: Vec::new(),
+            blocked_extension_status: default_blocked_extension_status(),
+            traversal_status: default_traversal_status(),
+            default_charset: default_charset(),
+            max_concurrent_requests: None,
+            maintenance: MaintenanceConfig::default(),
+            favicon: FaviconConfig::default(),
+            max_request_size: None,
+            rate_limiting: None,
+            well_known: HashMap::new(),
+            access_log: None,
+            log_format: None,
+            compression: CompressionConfig::default(),
+            cache: CacheConfig::default(),
+            hotlink_protection: HotlinkProtectionConfig::default(),
+            user_agent_filter: UserAgentFilterConfig::default(),
+            geoip: GeoIpConfig::default(),
+            access_control: AccessControlConfig::default(),
+            ssl: SiteSslConfig::default(),
+            hsts: HstsConfig::default(),
+            proxy: ProxyConfig::default(),
+        }
+    }
+}
+
+/// Kind of hostname match, in priority order (exact beats wildcard beats regex)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HostnameMatch {
+    Exact,
+    Wildcard,
+    Regex,
+}
+
+/// Match `*.example.com` against any subdomain of example.com (not the apex)
+fn wildcard_matches(pattern: &str, hostname: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => {
+            let hostname = hostname.to_ascii_lowercase();
+            let suffix = format!(".{}", domain.to_ascii_lowercase());
+            hostname.len() > suffix.len() && hostname.ends_with(&suffix)
+        }
+        None => false,
+    }
+}
+
+/// The rest of `path` after a URL prefix, when the prefix covers whole path
+/// segments: `/downloads/` matches `/downloads` and `/downloads/a`, not `/downloadsx`
+fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(prefix.trim_end_matches('/'))?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+/// Compile a hostname regex (anchored to the full hostname), caching the result
+fn compiled_hostname_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    static CACHE: Lazy<Mutex<HashMap<String, Regex>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(re) = cache.get(pattern) {
+        return Ok(re.clone());
+    }
+
+    let re = RegexBuilder::new(&format!("^(?:{})$", pattern))
+        .case_insensitive(true)
+        .build()?;
+    cache.insert(pattern.to_string(), re.clone());
+    Ok(re)
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
 pub struct SiteSslConfig {
     #[serde(default)]
@@ -67,7 +249,7 @@ pub struct SiteSslConfig {
     pub acme: Option<SiteAcmeConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct SiteAcmeConfig {
     #[serde(default)]
     pub enabled: bool,
@@ -77,6 +259,43 @@ pub struct SiteAcmeConfig {
     pub staging: bool,
     #[serde(default)]
     pub challenge_dir: Option<String>, // Make optional for automatic management
+    #[serde(default)]
+    pub directory_url: Option<String>, // Custom ACME CA, defaults to Let's Encrypt
+    #[serde(default)]
+    pub key_type: AcmeKeyType, // "ecdsa" (P-256, default) or "rsa"
+    /// Days before expiry the certificate is renewed
+    #[serde(default = "default_renewal_days_before_expiry")]
+    pub renewal_days_before_expiry: i64,
+}
+
+fn default_renewal_days_before_expiry() -> i64 {
+    constants::CERT_RENEWAL_DAYS
+}
+
+impl Default for SiteAcmeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            email: String::new(),
+            staging: false,
+            challenge_dir: None,
+            directory_url: None,
+            key_type: AcmeKeyType::default(),
+            renewal_days_before_expiry: default_renewal_days_before_expiry(),
+        }
+    }
+}
+
+impl SiteAcmeConfig {
+    /// ACME directory to use: the configured `directory_url`, or Let's Encrypt
+    /// (staging or production depending on `staging`)
+    pub fn directory_url(&self) -> &str {
+        match &self.directory_url {
+            Some(url) => url,
+            None if self.staging => "https://acme-staging-v02.api.letsencrypt.org/directory",
+            None => "https://acme-v02.api.letsencrypt.org/directory",
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
@@ -94,7 +313,13 @@ pub struct ProxyConfig {
     #[serde(default)]
     pub timeout: TimeoutConfig,
     #[serde(default)]
+    pub pool: PoolConfig,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    #[serde(default)]
     pub headers: ProxyHeadersConfig,
+    #[serde(default)]
+    pub cache: ProxyCacheConfig,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
@@ -103,8 +328,29 @@ pub struct UpstreamConfig {
     pub url: String,
     #[serde(default = "default_weight")]
     pub weight: u32,
+    /// Connections opened to this upstream at most; further requests wait
     #[serde(default)]
     pub max_conns: Option<u32>,
+    #[serde(default)]
+    pub health_path: Option<String>, // Overrides health_check.path for this upstream
+    /// Verify the certificate of an HTTPS upstream
+    #[serde(default = "default_tls_verify")]
+    pub tls_verify: bool,
+    /// PEM file of CA certificates trusted for this upstream, in addition to the built-in roots
+    #[serde(default)]
+    pub ca_file: Option<String>,
+    /// Server name to present and verify instead of the URL's host, e.g. when
+    /// connecting by IP to a backend whose certificate names a host
+    #[serde(default)]
+    pub sni: Option<String>,
+    /// Standby server: only receives traffic while every primary (non-backup)
+    /// server of its group is down
+    #[serde(default)]
+    pub backup: bool,
+    /// Seconds over which traffic ramps up after the server recovers; 0 sends
+    /// full traffic at once. Applies to the weighted balancing methods.
+    #[serde(default)]
+    pub slow_start: u64,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
@@ -117,6 +363,14 @@ pub struct ProxyRoute {
     pub rewrite_target: Option<String>,
     #[serde(default)]
     pub websocket: bool, // Enable WebSocket proxying for this route
+    #[serde(default)]
+    pub request_headers: HashMap<String, String>, // Sent upstream, overriding headers.add
+    #[serde(default)]
+    pub response_headers: HashMap<String, String>, // Added to responses from this route
+    #[serde(default)]
+    pub proxy_redirect: bool, // Point upstream Location/Refresh headers at the public URL
+    #[serde(default)]
+    pub cookie_domain: Option<String>, // Replaces Set-Cookie Domain; "" removes it
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
@@ -136,7 +390,7 @@ pub struct HealthCheckConfig {
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct LoadBalancingConfig {
     #[serde(default = "default_lb_method")]
-    pub method: String, // "round_robin", "least_conn", "weighted"
+    pub method: String, // "round_robin", "weighted", "least_connections", "ip_hash", "random"
     #[serde(default)]
     pub sticky_sessions: bool,
 }
@@ -151,6 +405,62 @@ pub struct TimeoutConfig {
     pub write: u64, // seconds
 }
 
+/// Keep-alive connections kept open to each upstream between requests
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct PoolConfig {
+    /// Seconds an idle connection is kept before it is closed
+    #[serde(default = "default_pool_idle_timeout")]
+    pub idle_timeout: u64,
+    /// Idle connections kept per upstream
+    #[serde(default = "default_pool_max_idle")]
+    pub max_idle: usize,
+}
+
+/// Stops routing to an upstream that keeps failing, then lets a single
+/// request through to test whether it has recovered
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CircuitBreakerConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 5xx responses and connection errors that open the circuit
+    #[serde(default = "default_circuit_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Seconds within which the failures must happen
+    #[serde(default = "default_circuit_window")]
+    pub window: u64,
+    /// Seconds the circuit stays open before a test request is let through
+    #[serde(default = "default_circuit_cooldown")]
+    pub cooldown: u64,
+}
+
+/// Shared cache of upstream responses, kept for as long as the upstream's
+/// `Cache-Control` or `Expires` allows
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ProxyCacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Responses kept for the site; the least recently used is dropped first
+    #[serde(default = "default_proxy_cache_entries")]
+    pub max_entries: usize,
+    /// Largest response body cached (e.g., "1MB")
+    #[serde(default = "default_proxy_cache_max_size")]
+    pub max_size: String,
+    /// Seconds to keep responses without freshness information; 0 leaves them uncached
+    #[serde(default)]
+    pub default_ttl: u64,
+    /// Seconds an expired response is still served while it is refreshed,
+    /// unless the upstream sends `stale-while-revalidate`
+    #[serde(default)]
+    pub stale_while_revalidate: u64,
+}
+
+impl ProxyCacheConfig {
+    /// `max_size` in bytes
+    pub fn max_size_bytes(&self) -> usize {
+        crate::core::utils::string::parse_size(&self.max_size).unwrap_or(0) as usize
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct ProxyHeadersConfig {
     #[serde(default)]
@@ -168,6 +478,15 @@ pub struct ProxyHeadersConfig {
 fn default_weight() -> u32 {
     1
 }
+
+fn default_tls_verify() -> bool {
+    true
+}
+
+fn default_well_known_content_type() -> String {
+    "text/plain; charset=utf-8".to_string()
+}
+
 fn default_health_path() -> String {
     "/health".to_string()
 }
@@ -192,6 +511,27 @@ fn default_read_timeout() -> u64 {
 fn default_write_timeout() -> u64 {
     30
 }
+fn default_pool_idle_timeout() -> u64 {
+    90
+}
+fn default_pool_max_idle() -> usize {
+    32
+}
+fn default_circuit_failure_threshold() -> u32 {
+    5
+}
+fn default_circuit_window() -> u64 {
+    10
+}
+fn default_circuit_cooldown() -> u64 {
+    30
+}
+fn default_proxy_cache_entries() -> usize {
+    1000
+}
+fn default_proxy_cache_max_size() -> String {
+    "1MB".to_string()
+}
 
 impl Default for HealthCheckConfig {
     fn default() -> Self {
@@ -205,6 +545,13 @@ impl Default for HealthCheckConfig {
     }
 }
 
+impl LoadBalancingConfig {
+    /// Parse the configured method into a load balancing strategy
+    pub fn strategy(&self) -> Result<LoadBalancingStrategy, String> {
+        self.method.parse()
+    }
+}
+
 impl Default for LoadBalancingConfig {
     fn default() -> Self {
         Self {
@@ -224,6 +571,38 @@ impl Default for TimeoutConfig {
     }
 }
 
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: default_pool_idle_timeout(),
+            max_idle: default_pool_max_idle(),
+        }
+    }
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            failure_threshold: default_circuit_failure_threshold(),
+            window: default_circuit_window(),
+            cooldown: default_circuit_cooldown(),
+        }
+    }
+}
+
+impl Default for ProxyCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_entries: default_proxy_cache_entries(),
+            max_size: default_proxy_cache_max_size(),
+            default_ttl: 0,
+            stale_while_revalidate: 0,
+        }
+    }
+}
+
 impl Default for ProxyHeadersConfig {
     fn default() -> Self {
         Self {
@@ -236,7 +615,7 @@ impl Default for ProxyHeadersConfig {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct CompressionConfig {
     #[serde(default)]
     pub enabled: bool,
@@ -246,9 +625,12 @@ pub struct CompressionConfig {
     pub level: u32,
     #[serde(default = "default_min_size")]
     pub min_size: usize,
+    /// Encodings to offer, most preferred first ("br", "zstd", "gzip", "deflate")
+    #[serde(default = "default_compression_algorithms")]
+    pub algorithms: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct CacheConfig {
     #[serde(default)]
     pub enabled: bool,
@@ -262,9 +644,50 @@ pub struct CacheConfig {
     pub max_age_static: u32,
     #[serde(default)]
     pub max_age_dynamic: u32,
+    /// Add `immutable` to the Cache-Control header of static assets
+    #[serde(default)]
+    pub immutable: bool,
+    /// Cache-Control by path pattern (e.g., "/assets/*"); the longest
+    /// matching pattern wins over the max_age settings
+    #[serde(default)]
+    pub overrides: HashMap<String, CacheOverride>,
+}
+
+/// Cache-Control for paths matching an override pattern
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum CacheOverride {
+    /// Exact header value, e.g. "no-cache"
+    Value(String),
+    /// `public, max-age=<max_age>`, optionally `immutable`
+    MaxAge {
+        max_age: u32,
+        #[serde(default)]
+        immutable: bool,
+    },
+}
+
+impl CacheOverride {
+    pub fn header_value(&self) -> String {
+        match self {
+            CacheOverride::Value(value) => value.clone(),
+            CacheOverride::MaxAge { max_age, immutable } => {
+                cache_control_value(*max_age, *immutable)
+            }
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// `public, max-age=<max_age>` with `immutable` appended when requested
+fn cache_control_value(max_age: u32, immutable: bool) -> String {
+    if immutable {
+        format!("public, max-age={}, immutable", max_age)
+    } else {
+        format!("public, max-age={}", max_age)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct AccessControlConfig {
     #[serde(default)]
     pub allow_methods: Vec<String>,
@@ -278,6 +701,133 @@ pub struct AccessControlConfig {
     pub max_age: u32,
 }
 
+/// Hotlink protection: deny image/media requests whose Referer is another site
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct HotlinkProtectionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Referer hosts allowed besides the site's own (`*.example.com` matches subdomains)
+    #[serde(default)]
+    pub allowed_referers: Vec<String>,
+    /// Whether requests without a Referer are allowed
+    #[serde(default = "default_allow_empty_referer")]
+    pub allow_empty_referer: bool,
+    /// "deny" (403) or "redirect" (302 to `redirect_url`)
+    #[serde(default = "default_hotlink_action")]
+    pub action: String,
+    #[serde(default)]
+    pub redirect_url: Option<String>,
+    /// File extensions that are protected
+    #[serde(default = "default_hotlink_extensions")]
+    pub extensions: Vec<String>,
+}
+
+/// User-Agent filtering. Patterns are case-insensitive regular expressions.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct UserAgentFilterConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Agents answered with 403
+    #[serde(default)]
+    pub block: Vec<String>,
+    /// Agents held to `throttle_limit`
+    #[serde(default)]
+    pub throttle: Vec<String>,
+    #[serde(default)]
+    pub throttle_limit: Option<RateLimitConfig>,
+    /// Known-good agents, exempt from `block` and `throttle`
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Require allowed agents claiming to be a major search engine crawler to
+    /// pass reverse DNS verification; impostors are blocked
+    #[serde(default)]
+    pub verify_crawlers: bool,
+}
+
+/// Country-based access control using a MaxMind GeoLite2/GeoIP2 database
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct GeoIpConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Path to a country (or city) `.mmdb` database
+    #[serde(default)]
+    pub database: Option<String>,
+    /// ISO 3166-1 alpha-2 codes; when set, only these countries are allowed
+    #[serde(default)]
+    pub allow_countries: Vec<String>,
+    /// ISO 3166-1 alpha-2 codes that are denied
+    #[serde(default)]
+    pub deny_countries: Vec<String>,
+    /// Whether clients whose country is unknown (e.g. private addresses) are allowed
+    #[serde(default = "default_geoip_allow_unknown")]
+    pub allow_unknown: bool,
+}
+
+/// Maintenance mode: a 503 page for every request, from inline content, a
+/// file, or a built-in page when neither is set
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct MaintenanceConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Inline HTML for the maintenance page
+    #[serde(default)]
+    pub content: Option<String>,
+    /// File holding the maintenance page
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Seconds sent in the Retry-After header
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+}
+
+/// Icon for `/favicon.ico` on sites without a `favicon.ico` of their own:
+/// a configured file or a built-in icon
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct FaviconConfig {
+    /// Serve the icon; when disabled such requests get the site's usual 404
+    #[serde(default = "default_favicon_enabled")]
+    pub enabled: bool,
+    /// ICO file to serve instead of the built-in icon
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+impl Default for FaviconConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_favicon_enabled(),
+            file: None,
+        }
+    }
+}
+
+fn default_favicon_enabled() -> bool {
+    true
+}
+
+/// A document served under `/.well-known/`, from inline content or a file
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct WellKnownDocument {
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default = "default_well_known_content_type")]
+    pub content_type: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct HstsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_hsts_max_age")]
+    pub max_age: u64,
+    #[serde(default)]
+    pub include_subdomains: bool,
+    #[serde(default)]
+    pub preload: bool,
+}
+
 // Default value functions
 fn default_compression_types() -> Vec<String> {
     vec![
@@ -300,6 +850,56 @@ fn default_min_size() -> usize {
     1024 // 1KB
 }
 
+fn default_compression_algorithms() -> Vec<String> {
+    vec![
+        "br".to_string(),
+        "zstd".to_string(),
+        "gzip".to_string(),
+        "deflate".to_string(),
+    ]
+}
+
+fn default_hsts_max_age() -> u64 {
+    HSTS_PRELOAD_MIN_MAX_AGE
+}
+
+/// Minimum max-age accepted by the HSTS preload list (one year)
+pub const HSTS_PRELOAD_MIN_MAX_AGE: u64 = 31536000;
+
+fn default_allow_empty_referer() -> bool {
+    true
+}
+
+fn default_blocked_extension_status() -> u16 {
+    404
+}
+
+fn default_traversal_status() -> u16 {
+    404
+}
+
+fn default_charset() -> String {
+    "utf-8".to_string()
+}
+
+fn default_hotlink_action() -> String {
+    "deny".to_string()
+}
+
+fn default_hotlink_extensions() -> Vec<String> {
+    [
+        "jpg", "jpeg", "png", "gif", "webp", "avif", "svg", "bmp", "ico", "mp4", "webm", "ogg",
+        "mp3", "wav", "m4a", "mov",
+    ]
+    .iter()
+    .map(|ext| ext.to_string())
+    .collect()
+}
+
+fn default_geoip_allow_unknown() -> bool {
+    true
+}
+
 fn default_cache_control() -> String {
     "public, max-age=3600".to_string()
 }
@@ -311,6 +911,7 @@ impl Default for CompressionConfig {
             types: default_compression_types(),
             level: default_compression_level(),
             min_size: default_min_size(),
+            algorithms: default_compression_algorithms(),
         }
     }
 }
@@ -324,44 +925,103 @@ impl Default for CacheConfig {
             last_modified_enabled: true,
             max_age_static: 3600, // 1 hour for static files
             max_age_dynamic: 300, // 5 minutes for dynamic content
+            immutable: false,
+            overrides: HashMap::new(),
         }
     }
 }
 
-impl Default for AccessControlConfig {
+impl Default for HotlinkProtectionConfig {
     fn default() -> Self {
         Self {
-            allow_methods: vec!["GET".to_string(), "HEAD".to_string(), "OPTIONS".to_string()],
-            allow_headers: vec!["Content-Type".to_string(), "Authorization".to_string()],
-            allow_origins: vec!["*".to_string()],
-            allow_credentials: false,
-            max_age: 86400, // 24 hours
+            enabled: false,
+            allowed_referers: Vec::new(),
+            allow_empty_referer: default_allow_empty_referer(),
+            action: default_hotlink_action(),
+            redirect_url: None,
+            extensions: default_hotlink_extensions(),
         }
     }
 }
 
-impl SiteConfig {
-    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
-        // Validate required fields
-        if self.name.is_empty() {
-            return Err("Site name cannot be empty".into());
+impl Default for GeoIpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            database: None,
+            allow_countries: Vec::new(),
+            deny_countries: Vec::new(),
+            allow_unknown: default_geoip_allow_unknown(),
         }
+    }
+}
 
-        if self.hostname.is_empty() {
-            return Err("Site hostname cannot be empty".into());
+impl Default for HstsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_age: default_hsts_max_age(),
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+}
+
+impl Default for WellKnownDocument {
+    fn default() -> Self {
+        Self {
+            content: None,
+            file: None,
+            content_type: default_well_known_content_type(),
+        }
+    }
+}
+
+impl Default for AccessControlConfig {
+    fn default() -> Self {
+        Self {
+            allow_methods: vec!["GET".to_string(), "HEAD".to_string(), "OPTIONS".to_string()],
+            allow_headers: vec!["Content-Type".to_string(), "Authorization".to_string()],
+            allow_origins: vec!["*".to_string()],
+            allow_credentials: false,
+            max_age: 86400, // 24 hours
+        }
+    }
+}
+
+impl SiteConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // Validate required fields
+        if self.name.is_empty() {
+            return Err("Site name cannot be empty".into());
+        }
+
+        if self.hostname.is_empty() {
+            return Err("Site hostname cannot be empty".into());
         }
 
         if self.port == 0 {
             return Err("Site port must be greater than 0".into());
         }
 
+        if self.ports.contains(&0) {
+            return Err("Site ports must be greater than 0".into());
+        }
+
         if self.static_dir.is_empty() {
             return Err("Site static_dir cannot be empty".into());
         }
 
-        // Validate static directory exists (or can be created)
+        // Validate static directory exists, unless it is created at startup
         let static_path = Path::new(&self.static_dir);
-        if !static_path.exists() {
+        if !static_path.exists() && !self.create_static_dir {
+            if self.require_static_dir && !self.api_only {
+                return Err(BwsError::Config(format!(
+                    "Static directory does not exist for site '{}': {}",
+                    self.name, self.static_dir
+                ))
+                .into());
+            }
             log::warn!(
                 "Static directory does not exist for site '{}': {}",
                 self.name,
@@ -384,6 +1044,27 @@ impl SiteConfig {
             }
         }
 
+        // Validate hostname regex
+        if let Some(pattern) = &self.hostname_regex {
+            compiled_hostname_regex(pattern)
+                .map_err(|e| format!("Invalid hostname_regex '{}': {}", pattern, e))?;
+        }
+
+        // Wildcards cannot be validated over HTTP-01
+        if self.ssl.enabled && self.ssl.auto_cert {
+            if let Some(wildcard) = self
+                .get_all_hostnames()
+                .into_iter()
+                .find(|h| h.starts_with("*."))
+            {
+                return Err(format!(
+                    "Wildcard hostname '{}' cannot use automatic certificates",
+                    wildcard
+                )
+                .into());
+            }
+        }
+
         // Validate port range
         if self.port < 1 {
             return Err(format!("Invalid port number: {}", self.port).into());
@@ -396,6 +1077,29 @@ impl SiteConfig {
                     if acme.email.is_empty() {
                         return Err("ACME email is required when auto_cert is enabled".into());
                     }
+                    if let Some(directory_url) = &acme.directory_url {
+                        let url = url::Url::parse(directory_url).map_err(|e| {
+                            format!("Invalid ACME directory_url '{}': {}", directory_url, e)
+                        })?;
+                        if url.scheme() != "https" {
+                            return Err(format!(
+                                "ACME directory_url must use https: {}",
+                                directory_url
+                            )
+                            .into());
+                        }
+                    }
+                    // Renewing as early as the lifetime would renew on every check
+                    if !(1..constants::ACME_CERT_LIFETIME_DAYS)
+                        .contains(&acme.renewal_days_before_expiry)
+                    {
+                        return Err(format!(
+                            "ACME renewal_days_before_expiry must be between 1 and {}: {}",
+                            constants::ACME_CERT_LIFETIME_DAYS - 1,
+                            acme.renewal_days_before_expiry
+                        )
+                        .into());
+                    }
                 } else {
                     return Err("ACME configuration is required when auto_cert is enabled".into());
                 }
@@ -423,6 +1127,143 @@ impl SiteConfig {
             }
         }
 
+        // Validate fallback document
+        if let Some(fallback) = &self.fallback_document {
+            if fallback.is_empty() {
+                return Err("Fallback document path cannot be empty".into());
+            }
+            if fallback.split(['/', '\\']).any(|part| part == "..") {
+                return Err(format!(
+                    "Fallback document must stay within static_dir: {}",
+                    fallback
+                )
+                .into());
+            }
+        }
+
+        // Validate aliases
+        for (prefix, dir) in &self.aliases {
+            if !prefix.starts_with('/') || prefix.trim_end_matches('/').is_empty() {
+                return Err(format!(
+                    "Alias prefix must start with '/' and not be the root: '{}'",
+                    prefix
+                )
+                .into());
+            }
+            if prefix.split('/').any(|part| part == "." || part == "..") {
+                return Err(format!("Invalid alias prefix: '{}'", prefix).into());
+            }
+            if dir.is_empty() {
+                return Err(format!("Alias directory cannot be empty for '{}'", prefix).into());
+            }
+            if !Path::new(dir).exists() {
+                log::warn!(
+                    "Alias directory does not exist for site '{}': {}",
+                    self.name,
+                    dir
+                );
+            }
+        }
+
+        // Validate internal locations
+        for location in &self.internal_locations {
+            if !location.starts_with('/') || location.trim_end_matches('/').is_empty() {
+                return Err(format!(
+                    "Internal location must start with '/' and not be the root: '{}'",
+                    location
+                )
+                .into());
+            }
+        }
+
+        // Validate extension settings
+        for extension in &self.allowed_extensions {
+            if extension.is_empty() || extension.contains(['.', '/', '\\']) {
+                return Err(format!(
+                    "Invalid allowed extension '{}': use the bare extension, e.g. \"csv\"",
+                    extension
+                )
+                .into());
+            }
+        }
+        if !matches!(self.blocked_extension_status, 403 | 404) {
+            return Err(format!(
+                "Invalid blocked_extension_status: {}. Valid options: 403, 404",
+                self.blocked_extension_status
+            )
+            .into());
+        }
+        if !matches!(self.traversal_status, 403 | 404) {
+            return Err(format!(
+                "Invalid traversal_status: {}. Valid options: 403, 404",
+                self.traversal_status
+            )
+            .into());
+        }
+
+        // Validate the charset, which ends up in a header
+        if self.default_charset.is_empty()
+            || !self
+                .default_charset
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.:+".contains(c))
+        {
+            return Err(format!("Invalid default_charset: '{}'", self.default_charset).into());
+        }
+
+        // Validate load balancing method
+        self.proxy.load_balancing.strategy()?;
+
+        let breaker = &self.proxy.circuit_breaker;
+        if breaker.enabled && (breaker.failure_threshold == 0 || breaker.window == 0) {
+            return Err(
+                "Circuit breaker failure_threshold and window must be greater than 0".into(),
+            );
+        }
+
+        let cache = &self.proxy.cache;
+        if crate::core::utils::string::parse_size(&cache.max_size).is_none() {
+            return Err(format!("Invalid proxy cache max_size '{}'", cache.max_size).into());
+        }
+        if cache.enabled && cache.max_entries == 0 {
+            return Err("Proxy cache max_entries must be greater than 0".into());
+        }
+
+        // Validate per-upstream health check paths
+        for upstream in &self.proxy.upstreams {
+            if let Some(path) = &upstream.health_path {
+                if !path.starts_with('/') {
+                    return Err(format!(
+                        "Health check path for upstream '{}' must start with '/': {}",
+                        upstream.name, path
+                    )
+                    .into());
+                }
+            }
+            upstream.validate()?;
+        }
+
+        // Validate well-known documents
+        validate_well_known(&self.well_known)?;
+
+        // Validate rate limiting
+        if let Some(rate_limit) = &self.rate_limiting {
+            rate_limit.validate()?;
+        }
+
+        // Validate concurrency limit
+        if self.max_concurrent_requests == Some(0) {
+            return Err("Site max_concurrent_requests must be greater than 0".into());
+        }
+
+        // Validate access log path
+        if self.access_log.as_ref().is_some_and(|path| path.is_empty()) {
+            return Err("Access log path cannot be empty".into());
+        }
+        if let Some(format) = &self.log_format {
+            format.parse::<crate::monitoring::AccessLogFormat>()?;
+        }
+
         // Validate compression configuration
         self.compression.validate()?;
 
@@ -432,6 +1273,39 @@ impl SiteConfig {
         // Validate access control configuration
         self.access_control.validate()?;
 
+        // Validate hotlink protection
+        self.hotlink_protection.validate()?;
+
+        // Validate user agent filter
+        self.user_agent_filter.validate()?;
+
+        // Validate GeoIP access control
+        self.geoip.validate()?;
+
+        // Validate HSTS configuration
+        self.hsts.validate()?;
+
+        // Validate maintenance page
+        self.maintenance.validate()?;
+
+        Ok(())
+    }
+
+    /// Create the static directory if `create_static_dir` is set and it is missing
+    pub fn ensure_static_dir(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.create_static_dir && !Path::new(&self.static_dir).exists() {
+            std::fs::create_dir_all(&self.static_dir).map_err(|e| {
+                BwsError::Config(format!(
+                    "Failed to create static directory '{}' for site '{}': {}",
+                    self.static_dir, self.name, e
+                ))
+            })?;
+            log::info!(
+                "Created static directory for site '{}': {}",
+                self.name,
+                self.static_dir
+            );
+        }
         Ok(())
     }
 
@@ -445,6 +1319,16 @@ impl SiteConfig {
             return false;
         }
 
+        // Wildcard hostnames: "*." followed by a valid domain
+        if let Some(domain) = hostname.strip_prefix("*.") {
+            return !domain.starts_with('*') && self.is_hostname_valid(domain);
+        }
+
+        // IPv6 literals, with or without brackets
+        if unbracket(hostname).parse::<std::net::Ipv6Addr>().is_ok() {
+            return true;
+        }
+
         // Allow localhost and IP addresses for development
         if hostname == "localhost"
             || hostname.starts_with("127.")
@@ -489,6 +1373,28 @@ impl SiteConfig {
         self.ssl.enabled
     }
 
+    /// Directory serving `path` and the part of the path inside it: the alias
+    /// with the longest matching prefix, or static_dir
+    pub fn root_for<'a>(&'a self, path: &'a str) -> (&'a str, &'a str) {
+        self.aliases
+            .iter()
+            .filter_map(|(prefix, dir)| {
+                let rest = strip_path_prefix(path, prefix)?;
+                Some((prefix.trim_end_matches('/').len(), dir, rest))
+            })
+            .max_by_key(|(len, _, _)| *len)
+            .map_or((self.static_dir.as_str(), path), |(_, dir, rest)| {
+                (dir.as_str(), rest)
+            })
+    }
+
+    /// Whether `path` is under one of the site's internal locations
+    pub fn is_internal_location(&self, path: &str) -> bool {
+        self.internal_locations
+            .iter()
+            .any(|prefix| strip_path_prefix(path, prefix).is_some())
+    }
+
     pub fn get_index_files(&self) -> Vec<&str> {
         if self.index_files.is_empty() {
             vec!["index.html", "index.htm"]
@@ -497,51 +1403,58 @@ impl SiteConfig {
         }
     }
 
+    /// Whether a file with this extension may be served: the built-in safe set
+    /// plus the site's `allowed_extensions`. Files without an extension are allowed.
+    pub fn allows_extension(&self, extension: Option<&str>) -> bool {
+        match extension {
+            Some(extension) => {
+                fs::is_safe_extension(extension)
+                    || self
+                        .allowed_extensions
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(extension))
+            }
+            None => true,
+        }
+    }
+
     pub fn get_error_page(&self, status_code: u16) -> Option<&str> {
         self.error_pages.get(&status_code).map(|s| s.as_str())
     }
 
     pub fn should_compress(&self, content_type: &str, content_length: usize) -> bool {
-        self.compression.enabled
-            && content_length >= self.compression.min_size
-            && self
-                .compression
-                .types
-                .iter()
-                .any(|t| content_type.starts_with(t))
+        self.compression
+            .should_compress(content_type, content_length)
     }
 
-    pub fn get_cache_headers(&self, is_static: bool) -> Vec<(String, String)> {
+    /// Cache-Control for a response. ETag and Last-Modified depend on the file
+    /// and are added by the static file handler.
+    pub fn get_cache_headers(&self, path: &str, is_static: bool) -> Vec<(String, String)> {
         let mut headers = Vec::new();
 
         if self.cache.enabled {
-            let max_age = if is_static {
-                self.cache.max_age_static
-            } else {
-                self.cache.max_age_dynamic
+            let cache_control = match self.cache.override_for(path) {
+                Some(cache_override) => cache_override.header_value(),
+                None if is_static => {
+                    cache_control_value(self.cache.max_age_static, self.cache.immutable)
+                }
+                None => cache_control_value(self.cache.max_age_dynamic, false),
             };
-
-            headers.push((
-                "Cache-Control".to_string(),
-                format!("public, max-age={}", max_age),
-            ));
-
-            if self.cache.etag_enabled {
-                // ETag would be calculated based on file content
-                // This is a placeholder - actual implementation would calculate based on file
-                headers.push(("ETag".to_string(), "\"placeholder\"".to_string()));
-            }
-
-            if self.cache.last_modified_enabled {
-                // Last-Modified would be based on file modification time
-                // This is a placeholder - actual implementation would use file mtime
-                headers.push(("Last-Modified".to_string(), "placeholder".to_string()));
-            }
+            headers.push(("Cache-Control".to_string(), cache_control));
         }
 
         headers
     }
 
+    pub fn get_hsts_header(&self) -> Option<(String, String)> {
+        self.hsts.enabled.then(|| {
+            (
+                "Strict-Transport-Security".to_string(),
+                self.hsts.header_value(),
+            )
+        })
+    }
+
     pub fn get_cors_headers(&self) -> Vec<(String, String)> {
         let mut headers = Vec::new();
 
@@ -598,13 +1511,33 @@ impl SiteConfig {
 
     /// Check if this site handles the given hostname
     pub fn handles_hostname(&self, hostname: &str) -> bool {
-        // Check primary hostname
-        if self.hostname == hostname {
-            return true;
+        self.hostname_match(hostname).is_some()
+    }
+
+    /// How this site matches the given hostname, if at all
+    pub fn hostname_match(&self, hostname: &str) -> Option<HostnameMatch> {
+        let hostnames = self.get_all_hostnames();
+
+        // IPv6 literals match with or without their brackets
+        let hostname = unbracket(hostname);
+        if hostnames
+            .iter()
+            .any(|h| unbracket(h).eq_ignore_ascii_case(hostname))
+        {
+            return Some(HostnameMatch::Exact);
+        }
+
+        if hostnames.iter().any(|h| wildcard_matches(h, hostname)) {
+            return Some(HostnameMatch::Wildcard);
         }
 
-        // Check additional hostnames
-        self.hostnames.iter().any(|h| h == hostname)
+        if let Some(pattern) = &self.hostname_regex {
+            if compiled_hostname_regex(pattern).is_ok_and(|re| re.is_match(hostname)) {
+                return Some(HostnameMatch::Regex);
+            }
+        }
+
+        None
     }
 
     /// Get all hostnames handled by this site (primary + additional)
@@ -614,13 +1547,149 @@ impl SiteConfig {
         hostnames
     }
 
+    /// All ports this site listens on: `port` first, then any additional `ports`
+    pub fn all_ports(&self) -> Vec<u16> {
+        let mut ports = vec![self.port];
+        for port in &self.ports {
+            if !ports.contains(port) {
+                ports.push(*port);
+            }
+        }
+        ports
+    }
+
+    /// Check if this site listens on the given port
+    pub fn listens_on(&self, port: u16) -> bool {
+        self.port == port || self.ports.contains(&port)
+    }
+
     /// Check if this site handles the given hostname and port combination
     pub fn handles_hostname_port(&self, hostname: &str, port: u16) -> bool {
-        self.port == port && self.handles_hostname(hostname)
+        self.listens_on(port) && self.handles_hostname(hostname)
+    }
+}
+
+/// A hostname without the brackets of an IPv6 literal
+fn unbracket(hostname: &str) -> &str {
+    hostname
+        .strip_prefix('[')
+        .and_then(|hostname| hostname.strip_suffix(']'))
+        .unwrap_or(hostname)
+}
+
+/// Check well-known document names and sources. ACME challenges are served
+/// separately, so `acme-challenge` cannot be configured here.
+pub fn validate_well_known(
+    documents: &HashMap<String, WellKnownDocument>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for (name, document) in documents {
+        if name.is_empty() || name.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(format!("Invalid well-known document name: '{}'", name).into());
+        }
+        if name == "acme-challenge" || name.starts_with("acme-challenge/") {
+            return Err("Well-known documents cannot override acme-challenge".into());
+        }
+        if document.content.is_some() == document.file.is_some() {
+            return Err(format!(
+                "Well-known document '{}' needs exactly one of content or file",
+                name
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+impl UpstreamConfig {
+    /// Path to probe for this upstream, falling back to the shared health check path
+    pub fn health_check_path<'a>(&'a self, health_check: &'a HealthCheckConfig) -> &'a str {
+        self.health_path.as_deref().unwrap_or(&health_check.path)
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.max_conns == Some(0) {
+            return Err(
+                format!("max_conns for upstream '{}' must be at least 1", self.name).into(),
+            );
+        }
+        if let Some(sni) = &self.sni {
+            if rustls::pki_types::ServerName::try_from(sni.as_str()).is_err() {
+                return Err(format!("Invalid sni '{}' for upstream '{}'", sni, self.name).into());
+            }
+        }
+        if let Some(ca_file) = &self.ca_file {
+            let pem = std::fs::read(ca_file).map_err(|e| {
+                format!(
+                    "Cannot read ca_file '{}' for upstream '{}': {}",
+                    ca_file, self.name, e
+                )
+            })?;
+            let certs = rustls_pemfile::certs(&mut pem.as_slice())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("Invalid ca_file '{}': {}", ca_file, e))?;
+            if certs.is_empty() {
+                return Err(format!("No certificates in ca_file '{}'", ca_file).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Types never compressed whatever `types` says: event streams must reach the
+/// client as they are written, and the rest are already compressed
+const NEVER_COMPRESSED_TYPES: &[&str] = &[
+    "text/event-stream",
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "font/woff",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/zstd",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/vnd.rar",
+    "application/x-rar-compressed",
+    "audio/*",
+    "video/*",
+];
+
+/// Match a MIME type against a pattern: an exact type, `type/*` or `*/*`
+fn mime_type_matches(pattern: &str, mime_type: &str) -> bool {
+    match pattern.trim().strip_suffix("/*") {
+        Some("*") => true,
+        Some(main_type) => mime_type
+            .split_once('/')
+            .is_some_and(|(main, _)| main.eq_ignore_ascii_case(main_type)),
+        None => pattern.trim().eq_ignore_ascii_case(mime_type),
     }
 }
 
 impl CompressionConfig {
+    /// Whether responses of `content_type` are compressed: it must match one
+    /// of `types` and not be an event stream or an already-compressed format
+    pub fn compresses_type(&self, content_type: &str) -> bool {
+        let mime_type = content_type.split(';').next().unwrap_or("").trim();
+        !NEVER_COMPRESSED_TYPES
+            .iter()
+            .any(|pattern| mime_type_matches(pattern, mime_type))
+            && self
+                .types
+                .iter()
+                .any(|pattern| mime_type_matches(pattern, mime_type))
+    }
+
+    /// Whether a response of `content_type` and `content_length` bytes is compressed
+    pub fn should_compress(&self, content_type: &str, content_length: usize) -> bool {
+        self.enabled && content_length >= self.min_size && self.compresses_type(content_type)
+    }
+
     fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
         if self.level > 9 {
             return Err("Compression level must be between 0 and 9".into());
@@ -630,8 +1699,87 @@ impl CompressionConfig {
             return Err("Compression types cannot be empty when compression is enabled".into());
         }
 
+        if self.algorithms.is_empty() && self.enabled {
+            return Err(
+                "Compression algorithms cannot be empty when compression is enabled".into(),
+            );
+        }
+
+        for pattern in &self.types {
+            let valid = match pattern.split_once('/') {
+                Some(("*", subtype)) => subtype == "*",
+                Some((main, subtype)) => {
+                    !main.is_empty() && !subtype.is_empty() && !subtype.contains('/')
+                }
+                None => false,
+            };
+            if !valid {
+                return Err(format!(
+                    "Invalid compression type '{}' (expected a MIME type, type/* or */*)",
+                    pattern
+                )
+                .into());
+            }
+        }
+
+        for algorithm in &self.algorithms {
+            if !["br", "zstd", "gzip", "deflate"].contains(&algorithm.as_str()) {
+                return Err(format!(
+                    "Unknown compression algorithm '{}' (expected br, zstd, gzip or deflate)",
+                    algorithm
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl MaintenanceConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.content.is_some() && self.file.is_some() {
+            return Err("Maintenance page needs at most one of content or file".into());
+        }
+        if self.file.as_ref().is_some_and(|file| file.is_empty()) {
+            return Err("Maintenance page file cannot be empty".into());
+        }
+        Ok(())
+    }
+}
+
+impl HstsConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.enabled || !self.preload {
+            return Ok(());
+        }
+
+        // Requirements for submission to the HSTS preload list
+        if self.max_age < HSTS_PRELOAD_MIN_MAX_AGE {
+            return Err(format!(
+                "HSTS preload requires max_age of at least {} seconds",
+                HSTS_PRELOAD_MIN_MAX_AGE
+            )
+            .into());
+        }
+        if !self.include_subdomains {
+            return Err("HSTS preload requires include_subdomains".into());
+        }
+
         Ok(())
     }
+
+    /// Value for the Strict-Transport-Security header
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age);
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
 }
 
 impl CacheConfig {
@@ -646,8 +1794,149 @@ impl CacheConfig {
             log::warn!("Dynamic cache max_age is very large (> 1 day)");
         }
 
+        for (pattern, cache_override) in &self.overrides {
+            if !pattern.starts_with('/') && !pattern.starts_with('*') {
+                return Err(format!(
+                    "Cache override pattern '{}' must start with '/' or '*'",
+                    pattern
+                )
+                .into());
+            }
+            if let CacheOverride::Value(value) = cache_override {
+                if value.trim().is_empty() {
+                    return Err(format!("Cache override for '{}' cannot be empty", pattern).into());
+                }
+            }
+        }
+
         Ok(())
     }
+
+    /// Cache-Control override for a request path from the longest matching
+    /// pattern. `*` in a pattern matches any run of characters, including `/`.
+    pub fn override_for(&self, path: &str) -> Option<&CacheOverride> {
+        self.overrides
+            .iter()
+            .filter(|(pattern, _)| path_pattern_matches(pattern, path))
+            .max_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| b.cmp(a)))
+            .map(|(_, cache_override)| cache_override)
+    }
+}
+
+/// Glob-style match of a whole path, where `*` matches any run of characters
+fn path_pattern_matches(pattern: &str, path: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard: exact match
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+impl HotlinkProtectionConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.action.as_str() {
+            "deny" => {}
+            "redirect" => {
+                if self.redirect_url.as_ref().is_none_or(|url| url.is_empty()) {
+                    return Err("Hotlink protection action 'redirect' requires redirect_url".into());
+                }
+            }
+            other => {
+                return Err(format!(
+                    "Invalid hotlink protection action: {}. Valid options: deny, redirect",
+                    other
+                )
+                .into())
+            }
+        }
+
+        if self.allowed_referers.iter().any(|host| host.is_empty()) {
+            return Err("Hotlink protection referer hosts cannot be empty".into());
+        }
+
+        Ok(())
+    }
+
+    /// Whether hotlink protection applies to the requested path
+    pub fn protects(&self, path: &str) -> bool {
+        let path = path.split(['?', '#']).next().unwrap_or(path);
+        self.enabled
+            && std::path::Path::new(path)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| {
+                    self.extensions
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(ext))
+                })
+    }
+
+    /// Whether a Referer host is in the allowlist
+    pub fn allows_host(&self, host: &str) -> bool {
+        self.allowed_referers
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host) || wildcard_matches(allowed, host))
+    }
+}
+
+impl UserAgentFilterConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        for pattern in self.block.iter().chain(&self.throttle).chain(&self.allow) {
+            RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| format!("Invalid user agent pattern '{}': {}", pattern, e))?;
+        }
+
+        match &self.throttle_limit {
+            Some(limit) => limit.validate()?,
+            None if !self.throttle.is_empty() => {
+                return Err("User agent throttle patterns require throttle_limit".into())
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl GeoIpConfig {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.enabled && self.database.as_ref().is_none_or(|path| path.is_empty()) {
+            return Err("GeoIP access control requires a database path".into());
+        }
+
+        for code in self.allow_countries.iter().chain(&self.deny_countries) {
+            if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Err(format!("Invalid country code: {}", code).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a client from `country` (an ISO code) is allowed
+    pub fn allows_country(&self, country: &str) -> bool {
+        let listed = |codes: &[String]| codes.iter().any(|c| c.eq_ignore_ascii_case(country));
+        if listed(&self.deny_countries) {
+            return false;
+        }
+        self.allow_countries.is_empty() || listed(&self.allow_countries)
+    }
 }
 
 impl AccessControlConfig {
@@ -672,6 +1961,38 @@ impl AccessControlConfig {
     }
 }
 
+/// (De)serialize maps keyed by HTTP status code. TOML keys are always
+/// strings, so `404 = "404.html"` arrives as the key `"404"`.
+mod status_keys {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<S: Serializer>(
+        map: &HashMap<u16, String>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        map.iter()
+            .map(|(status, value)| (status.to_string(), value))
+            .collect::<HashMap<_, _>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<u16, String>, D::Error> {
+        HashMap::<String, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(status, value)| {
+                status
+                    .parse()
+                    .map(|status| (status, value))
+                    .map_err(|_| D::Error::custom(format!("invalid status code: {}", status)))
+            })
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -681,20 +2002,9 @@ mod tests {
         let mut site = SiteConfig {
             name: "test".to_string(),
             hostname: "example.com".to_string(),
-            hostnames: vec![],
             port: 8080,
             static_dir: "/tmp".to_string(),
-            default: false,
-            api_only: false,
-            headers: HashMap::new(),
-            redirect_to_https: false,
-            index_files: vec![],
-            error_pages: HashMap::new(),
-            compression: CompressionConfig::default(),
-            cache: CacheConfig::default(),
-            access_control: AccessControlConfig::default(),
-            ssl: SiteSslConfig::default(),
-            proxy: ProxyConfig::default(),
+            ..Default::default()
         };
 
         assert!(site.validate().is_ok());
@@ -711,6 +2021,50 @@ mod tests {
         // Test invalid port range (port 0 is invalid)
         site.port = 0;
         assert!(site.validate().is_err());
+
+        // Test hotlink protection actions
+        site.port = 8080;
+        site.hotlink_protection.action = "redirect".to_string();
+        assert!(site.validate().is_err());
+        site.hotlink_protection.redirect_url = Some("/hotlink.png".to_string());
+        assert!(site.validate().is_ok());
+        site.hotlink_protection.action = "ignore".to_string();
+        assert!(site.validate().is_err());
+
+        // Test user agent filter patterns
+        site.hotlink_protection = HotlinkProtectionConfig::default();
+        site.user_agent_filter.block = vec!["(unclosed".to_string()];
+        assert!(site.validate().is_err());
+        site.user_agent_filter.block = vec!["ahrefsbot".to_string()];
+        site.user_agent_filter.throttle = vec!["bot".to_string()];
+        assert!(site.validate().is_err());
+        site.user_agent_filter.throttle_limit = Some(RateLimitConfig {
+            requests_per_minute: 30,
+            burst_size: 5,
+            whitelist: vec![],
+        });
+        assert!(site.validate().is_ok());
+
+        // Test GeoIP settings
+        site.geoip.enabled = true;
+        assert!(site.validate().is_err());
+        site.geoip.database = Some("/var/lib/GeoIP/GeoLite2-Country.mmdb".to_string());
+        site.geoip.deny_countries = vec!["USA".to_string()];
+        assert!(site.validate().is_err());
+        site.geoip.deny_countries = vec!["us".to_string()];
+        assert!(site.validate().is_ok());
+
+        // Test internal locations
+        site.internal_locations = vec!["/protected/".to_string()];
+        assert!(site.validate().is_ok());
+        assert!(site.is_internal_location("/protected/report.pdf"));
+        assert!(site.is_internal_location("/protected"));
+        assert!(!site.is_internal_location("/protectedx/report.pdf"));
+        assert!(!site.is_internal_location("/public/report.pdf"));
+        site.internal_locations = vec!["/".to_string()];
+        assert!(site.validate().is_err());
+        site.internal_locations = vec!["protected".to_string()];
+        assert!(site.validate().is_err());
     }
 
     #[test]
@@ -718,20 +2072,9 @@ mod tests {
         let site = SiteConfig {
             name: "test".to_string(),
             hostname: "localhost".to_string(),
-            hostnames: vec![],
             port: 8080,
             static_dir: "/tmp".to_string(),
-            default: false,
-            api_only: false,
-            headers: HashMap::new(),
-            redirect_to_https: false,
-            index_files: vec![],
-            error_pages: HashMap::new(),
-            compression: CompressionConfig::default(),
-            cache: CacheConfig::default(),
-            access_control: AccessControlConfig::default(),
-            ssl: SiteSslConfig::default(),
-            proxy: ProxyConfig::default(),
+            ..Default::default()
         };
 
         assert!(site.is_valid_hostname());
@@ -749,25 +2092,34 @@ mod tests {
         let site = SiteConfig {
             name: "test".to_string(),
             hostname: "example.com".to_string(),
-            hostnames: vec![],
             port: 8080,
             static_dir: "/tmp".to_string(),
-            default: false,
-            api_only: false,
-            headers: HashMap::new(),
-            redirect_to_https: false,
-            index_files: vec![],
-            error_pages: HashMap::new(),
-            compression: CompressionConfig::default(),
-            cache: CacheConfig::default(),
-            access_control: AccessControlConfig::default(),
-            ssl: SiteSslConfig::default(),
-            proxy: ProxyConfig::default(),
+            ..Default::default()
         };
 
         assert!(site.should_compress("text/html", 2048));
         assert!(!site.should_compress("text/html", 512)); // Below min_size
         assert!(!site.should_compress("image/png", 2048)); // Not in types list
+
+        // Configured types match exactly or by wildcard, never by prefix
+        let mut compression = CompressionConfig {
+            enabled: true,
+            types: vec!["text/*".to_string(), "application/json".to_string()],
+            ..Default::default()
+        };
+        assert!(compression.compresses_type("text/css; charset=utf-8"));
+        assert!(compression.compresses_type("Application/JSON"));
+        assert!(!compression.compresses_type("application/json-seq"));
+        assert!(!compression.compresses_type("text/event-stream"));
+        compression.types = vec!["text/".to_string()];
+        assert!(compression.validate().is_err());
+
+        let mut compression = CompressionConfig::default();
+        assert!(compression.validate().is_ok());
+        compression.algorithms = vec!["zstd".to_string(), "lzma".to_string()];
+        assert!(compression.validate().is_err());
+        compression.algorithms.clear();
+        assert!(compression.validate().is_err());
     }
 
     #[test]
@@ -775,20 +2127,9 @@ mod tests {
         let mut site = SiteConfig {
             name: "test".to_string(),
             hostname: "example.com".to_string(),
-            hostnames: vec![],
             port: 8080,
             static_dir: "/tmp".to_string(),
-            default: false,
-            api_only: false,
-            headers: HashMap::new(),
-            redirect_to_https: false,
-            index_files: vec![],
-            error_pages: HashMap::new(),
-            compression: CompressionConfig::default(),
-            cache: CacheConfig::default(),
-            access_control: AccessControlConfig::default(),
-            ssl: SiteSslConfig::default(),
-            proxy: ProxyConfig::default(),
+            ..Default::default()
         };
 
         assert_eq!(site.url(), "http://example.com:8080");
@@ -809,17 +2150,7 @@ mod tests {
             hostnames: vec!["www.example.com".to_string(), "example.org".to_string()],
             port: 8080,
             static_dir: "/tmp".to_string(),
-            default: false,
-            api_only: false,
-            headers: HashMap::new(),
-            redirect_to_https: false,
-            index_files: vec![],
-            error_pages: HashMap::new(),
-            compression: CompressionConfig::default(),
-            cache: CacheConfig::default(),
-            access_control: AccessControlConfig::default(),
-            ssl: SiteSslConfig::default(),
-            proxy: ProxyConfig::default(),
+            ..Default::default()
         };
 
         // Test hostname handling
@@ -851,17 +2182,7 @@ mod tests {
             hostnames: vec!["www.example.com".to_string(), "api.example.com".to_string()],
             port: 443,
             static_dir: "/tmp".to_string(),
-            default: false,
-            api_only: false,
-            headers: HashMap::new(),
-            redirect_to_https: false,
-            index_files: vec![],
-            error_pages: HashMap::new(),
-            compression: CompressionConfig::default(),
-            cache: CacheConfig::default(),
-            access_control: AccessControlConfig::default(),
-            ssl: SiteSslConfig::default(),
-            proxy: ProxyConfig::default(),
+            ..Default::default()
         };
 
         // Enable SSL
@@ -888,17 +2209,7 @@ mod tests {
             ],
             port: 8080,
             static_dir: "/tmp".to_string(),
-            default: false,
-            api_only: false,
-            headers: HashMap::new(),
-            redirect_to_https: false,
-            index_files: vec![],
-            error_pages: HashMap::new(),
-            compression: CompressionConfig::default(),
-            cache: CacheConfig::default(),
-            access_control: AccessControlConfig::default(),
-            ssl: SiteSslConfig::default(),
-            proxy: ProxyConfig::default(),
+            ..Default::default()
         };
 
         // Valid configuration should pass
@@ -912,4 +2223,395 @@ mod tests {
         site.hostnames = vec!["".to_string()];
         assert!(site.validate().is_err());
     }
+
+    #[test]
+    fn test_load_balancing_method_validation() {
+        let mut lb = LoadBalancingConfig::default();
+        assert_eq!(lb.strategy(), Ok(LoadBalancingStrategy::RoundRobin));
+
+        for (method, expected) in [
+            ("weighted", LoadBalancingStrategy::WeightedRoundRobin),
+            ("least_conn", LoadBalancingStrategy::LeastConnections),
+            ("least_connections", LoadBalancingStrategy::LeastConnections),
+            ("ip_hash", LoadBalancingStrategy::IpHash),
+            ("Random", LoadBalancingStrategy::Random),
+        ] {
+            lb.method = method.to_string();
+            assert_eq!(lb.strategy(), Ok(expected));
+        }
+
+        let mut site = SiteConfig {
+            name: "test".to_string(),
+            hostname: "example.com".to_string(),
+            port: 8080,
+            static_dir: "/tmp".to_string(),
+            ..Default::default()
+        };
+        assert!(site.validate().is_ok());
+
+        // Unknown methods are rejected at load time rather than silently ignored
+        site.proxy.load_balancing.method = "fastest".to_string();
+        assert!(site.validate().is_err());
+    }
+
+    #[test]
+    fn test_missing_static_dir_handling() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let missing = temp_dir.path().join("public");
+
+        let mut site = SiteConfig {
+            name: "test".to_string(),
+            hostname: "example.com".to_string(),
+            port: 8080,
+            static_dir: missing.to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+
+        // Missing directory only warns by default
+        assert!(site.validate().is_ok());
+
+        site.require_static_dir = true;
+        let err = site.validate().unwrap_err();
+        assert!(err.to_string().starts_with("Configuration error"));
+
+        // With create_static_dir it is created at startup, not by validation
+        site.create_static_dir = true;
+        assert!(site.validate().is_ok());
+        assert!(!missing.exists());
+        site.ensure_static_dir().unwrap();
+        assert!(missing.is_dir());
+        assert!(site.validate().is_ok());
+    }
+
+    #[test]
+    fn test_wildcard_and_regex_hostnames() {
+        let mut site = SiteConfig {
+            name: "test".to_string(),
+            hostname: "example.com".to_string(),
+            hostnames: vec!["*.example.com".to_string()],
+            hostname_regex: Some(r"shop-\d+\.example\.net".to_string()),
+            port: 8080,
+            static_dir: "/tmp".to_string(),
+            ..Default::default()
+        };
+        assert!(site.validate().is_ok());
+
+        assert_eq!(
+            site.hostname_match("example.com"),
+            Some(HostnameMatch::Exact)
+        );
+        assert_eq!(
+            site.hostname_match("api.example.com"),
+            Some(HostnameMatch::Wildcard)
+        );
+        assert_eq!(
+            site.hostname_match("a.b.Example.com"),
+            Some(HostnameMatch::Wildcard)
+        );
+        assert_eq!(
+            site.hostname_match("shop-42.example.net"),
+            Some(HostnameMatch::Regex)
+        );
+        assert_eq!(site.hostname_match("notexample.com"), None);
+        assert_eq!(site.hostname_match("shop-42.example.net.evil.com"), None);
+
+        site.hostname_regex = Some("(unclosed".to_string());
+        assert!(site.validate().is_err());
+
+        site.hostname_regex = None;
+        site.hostnames = vec!["*.*.example.com".to_string()];
+        assert!(site.validate().is_err());
+    }
+
+    #[test]
+    fn test_acme_directory_url() {
+        let mut acme = SiteAcmeConfig {
+            enabled: true,
+            email: "admin@example.com".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            acme.directory_url(),
+            "https://acme-v02.api.letsencrypt.org/directory"
+        );
+        acme.staging = true;
+        assert_eq!(
+            acme.directory_url(),
+            "https://acme-staging-v02.api.letsencrypt.org/directory"
+        );
+        acme.directory_url = Some("https://acme.zerossl.com/v2/DV90".to_string());
+        assert_eq!(acme.directory_url(), "https://acme.zerossl.com/v2/DV90");
+
+        let mut site = SiteConfig {
+            name: "test".to_string(),
+            hostname: "example.com".to_string(),
+            port: 443,
+            static_dir: "/tmp".to_string(),
+            ssl: SiteSslConfig {
+                enabled: true,
+                auto_cert: true,
+                acme: Some(acme.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(site.validate().is_ok());
+
+        for invalid in ["not a url", "http://ca.internal/acme/directory"] {
+            acme.directory_url = Some(invalid.to_string());
+            site.ssl.acme = Some(acme.clone());
+            assert!(site.validate().is_err(), "{} should be rejected", invalid);
+        }
+    }
+
+    #[test]
+    fn test_hsts_header_and_preload_validation() {
+        let mut hsts = HstsConfig {
+            enabled: true,
+            max_age: 600,
+            ..Default::default()
+        };
+        assert_eq!(hsts.header_value(), "max-age=600");
+        assert!(hsts.validate().is_ok());
+
+        // Preload requires a one year max-age and includeSubDomains
+        hsts.preload = true;
+        assert!(hsts.validate().is_err());
+        hsts.max_age = HSTS_PRELOAD_MIN_MAX_AGE;
+        assert!(hsts.validate().is_err());
+        hsts.include_subdomains = true;
+        assert!(hsts.validate().is_ok());
+        assert_eq!(
+            hsts.header_value(),
+            "max-age=31536000; includeSubDomains; preload"
+        );
+
+        // Disabled HSTS is not validated or sent
+        let site: SiteConfig = toml::from_str(
+            r#"
+            name = "test"
+            hostname = "example.com"
+            port = 443
+            static_dir = "/tmp"
+
+            [hsts]
+            preload = true
+            max_age = 60
+            "#,
+        )
+        .unwrap();
+        assert!(site.validate().is_ok());
+        assert!(site.get_hsts_header().is_none());
+    }
+
+    #[test]
+    fn test_cache_control_overrides() {
+        let mut site: SiteConfig = toml::from_str(
+            r#"
+            name = "test"
+            hostname = "example.com"
+            port = 8080
+            static_dir = "/tmp"
+
+            [cache]
+            enabled = true
+            max_age_static = 3600
+
+            [cache.overrides]
+            "/assets/*" = "public, max-age=31536000, immutable"
+            "/assets/config/*" = "no-cache"
+            "/index.html" = "no-cache"
+            "*.json" = "no-store"
+            "#,
+        )
+        .unwrap();
+        assert!(site.validate().is_ok());
+
+        let cache_control = |path: &str| {
+            site.get_cache_headers(path, true)
+                .into_iter()
+                .find(|(key, _)| key == "Cache-Control")
+                .map(|(_, value)| value)
+                .unwrap()
+        };
+        assert_eq!(
+            cache_control("/assets/app.3f9a.js"),
+            "public, max-age=31536000, immutable"
+        );
+        assert_eq!(cache_control("/index.html"), "no-cache");
+        assert_eq!(cache_control("/docs/index.html"), "public, max-age=3600");
+        assert_eq!(cache_control("/data.json"), "no-store");
+        // The longest matching pattern wins
+        assert_eq!(cache_control("/assets/config/site.json"), "no-cache");
+
+        assert!(path_pattern_matches("/a/*/c", "/a/b/c"));
+        assert!(!path_pattern_matches("/a/*/c", "/a/b/d"));
+        assert!(!path_pattern_matches("*.js", "/app.json"));
+        assert!(path_pattern_matches("*", "/"));
+
+        site.cache.overrides.insert(
+            "assets/*".to_string(),
+            CacheOverride::Value("no-cache".to_string()),
+        );
+        assert!(site.validate().is_err());
+    }
+
+    #[test]
+    fn test_immutable_cache_control() {
+        let site: SiteConfig = toml::from_str(
+            r#"
+            name = "test"
+            hostname = "example.com"
+            port = 8080
+            static_dir = "/tmp"
+
+            [cache]
+            enabled = true
+            max_age_static = 86400
+            max_age_dynamic = 60
+            immutable = true
+
+            [cache.overrides]
+            "/assets/*" = { max_age = 31536000, immutable = true }
+            "/feed/*" = { max_age = 120 }
+            "#,
+        )
+        .unwrap();
+        assert!(site.validate().is_ok());
+
+        let cache_control = |path: &str, is_static: bool| {
+            site.get_cache_headers(path, is_static)
+                .into_iter()
+                .find(|(key, _)| key == "Cache-Control")
+                .map(|(_, value)| value)
+                .unwrap()
+        };
+        assert_eq!(
+            cache_control("/assets/app.js", true),
+            "public, max-age=31536000, immutable"
+        );
+        assert_eq!(
+            cache_control("/logo.png", true),
+            "public, max-age=86400, immutable"
+        );
+        // Dynamic content is never immutable
+        assert_eq!(cache_control("/page.html", false), "public, max-age=60");
+        assert_eq!(cache_control("/feed/rss.xml", true), "public, max-age=120");
+    }
+
+    #[test]
+    fn test_error_pages_by_status() {
+        let toml_site = |error_pages: &str| {
+            toml::from_str::<SiteConfig>(&format!(
+                r#"
+                name = "test"
+                hostname = "example.com"
+                port = 8080
+                static_dir = "/tmp"
+
+                [error_pages]
+                {}
+                "#,
+                error_pages
+            ))
+        };
+
+        let site = toml_site("404 = \"404.html\"\n429 = \"slow-down.html\"").unwrap();
+        assert_eq!(site.get_error_page(404), Some("404.html"));
+        assert_eq!(site.get_error_page(429), Some("slow-down.html"));
+        assert_eq!(site.get_error_page(500), None);
+
+        // Survives a save and reload
+        let saved: SiteConfig = toml::from_str(&toml::to_string(&site).unwrap()).unwrap();
+        assert_eq!(saved.error_pages, site.error_pages);
+
+        assert!(toml_site("oops = \"x.html\"").is_err());
+    }
+
+    #[test]
+    fn test_well_known_validation() {
+        let document = WellKnownDocument {
+            content: Some("Contact: mailto:security@example.com".to_string()),
+            ..Default::default()
+        };
+        let mut documents = HashMap::from([("security.txt".to_string(), document.clone())]);
+        assert!(validate_well_known(&documents).is_ok());
+
+        for name in [
+            "",
+            "../secret",
+            "a//b",
+            "acme-challenge",
+            "acme-challenge/x",
+        ] {
+            let documents = HashMap::from([(name.to_string(), document.clone())]);
+            assert!(validate_well_known(&documents).is_err(), "{name}");
+        }
+
+        // Exactly one source is required
+        documents.get_mut("security.txt").unwrap().file = Some("security.txt".to_string());
+        assert!(validate_well_known(&documents).is_err());
+        documents.get_mut("security.txt").unwrap().content = None;
+        assert!(validate_well_known(&documents).is_ok());
+
+        let parsed: WellKnownDocument =
+            toml::from_str(r#"file = "/etc/bws/security.txt""#).unwrap();
+        assert_eq!(parsed.content_type, "text/plain; charset=utf-8");
+    }
+
+    #[test]
+    fn test_upstream_health_path_override() {
+        let mut site: SiteConfig = toml::from_str(
+            r#"
+            name = "test"
+            hostname = "example.com"
+            port = 8080
+            static_dir = "/tmp"
+
+            [proxy]
+            enabled = true
+
+            [proxy.health_check]
+            path = "/health"
+
+            [[proxy.upstreams]]
+            name = "backend"
+            url = "http://127.0.0.1:3001"
+
+            [[proxy.upstreams]]
+            name = "backend"
+            url = "http://127.0.0.1:3002"
+            health_path = "/status"
+            "#,
+        )
+        .unwrap();
+        assert!(site.validate().is_ok());
+
+        let health_check = &site.proxy.health_check;
+        let paths: Vec<&str> = site
+            .proxy
+            .upstreams
+            .iter()
+            .map(|upstream| upstream.health_check_path(health_check))
+            .collect();
+        assert_eq!(paths, ["/health", "/status"]);
+
+        site.proxy.upstreams[1].health_path = Some("status".to_string());
+        assert!(site.validate().is_err());
+
+        // TLS options: verification on by default, sni must be a valid name
+        // and ca_file must hold certificates
+        assert!(site
+            .proxy
+            .upstreams
+            .iter()
+            .all(|upstream| upstream.tls_verify));
+        site.proxy.upstreams[1].health_path = None;
+        site.proxy.upstreams[0].sni = Some("not a host".to_string());
+        assert!(site.validate().is_err());
+        site.proxy.upstreams[0].sni = Some("api.internal".to_string());
+        assert!(site.validate().is_ok());
+        site.proxy.upstreams[0].ca_file = Some("/nonexistent/ca.pem".to_string());
+        assert!(site.validate().is_err());
+    }
 }
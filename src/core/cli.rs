@@ -0,0 +1,418 @@
+//! Control of a running BWS instance
+//!
+//! `bws reload` and `bws status` talk to a server that is already running,
+//! either by signalling the process named in its PID file or through the
+//! management API.
+
+use crate::config::ManagementConfig;
+use crate::core::{BwsError, BwsResult};
+use std::fmt::Write;
+use std::path::Path;
+
+/// How a running instance is reached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlTarget {
+    /// The process with this ID, signalled directly (Unix only)
+    Process(i32),
+    /// The management API at this base URL, with its API key if it needs one
+    Management {
+        url: String,
+        api_key: Option<String>,
+    },
+}
+
+/// Client for controlling a running instance
+#[derive(Debug, Clone)]
+pub struct BwsCtl {
+    target: ControlTarget,
+}
+
+impl BwsCtl {
+    pub fn new(target: ControlTarget) -> Self {
+        Self { target }
+    }
+
+    /// Control the process whose ID is stored in `path`, such as the PID
+    /// file written by `--daemon`
+    pub fn from_pid_file(path: impl AsRef<Path>) -> BwsResult<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            BwsError::NotFound(format!("Cannot read PID file '{}': {}", path.display(), e))
+        })?;
+        let pid = contents
+            .trim()
+            .parse::<i32>()
+            .ok()
+            .filter(|pid| *pid > 0)
+            .ok_or_else(|| {
+                BwsError::Validation(format!(
+                    "PID file '{}' does not hold a process ID",
+                    path.display()
+                ))
+            })?;
+        Ok(Self::new(ControlTarget::Process(pid)))
+    }
+
+    /// Control the instance through the management API at `url`
+    pub fn from_management_url(url: &str, api_key: Option<String>) -> Self {
+        Self::new(ControlTarget::Management {
+            url: url.trim_end_matches('/').to_string(),
+            api_key,
+        })
+    }
+
+    /// Control the instance through the management API set up by `config`
+    pub fn from_management_config(config: &ManagementConfig) -> Self {
+        Self::from_management_url(
+            &format!("http://{}:{}", config.host, config.port),
+            config.api_key.clone(),
+        )
+    }
+
+    pub fn target(&self) -> &ControlTarget {
+        &self.target
+    }
+
+    /// Ask the instance to reload its configuration file, and describe what
+    /// happened
+    pub async fn reload(&self) -> BwsResult<String> {
+        match &self.target {
+            ControlTarget::Process(pid) => {
+                send_reload_signal(*pid)?;
+                Ok(format!("Sent SIGHUP to process {}", pid))
+            }
+            ControlTarget::Management { .. } => {
+                let body = self
+                    .request(reqwest::Method::POST, "/api/config/reload")
+                    .await?;
+                Ok(body["message"]
+                    .as_str()
+                    .unwrap_or("Configuration reloaded")
+                    .to_string())
+            }
+        }
+    }
+
+    /// Ask the instance for its health, sites, certificates and traffic.
+    /// Print it with `status_summary`.
+    pub async fn status(&self) -> BwsResult<serde_json::Value> {
+        let health = self.request(reqwest::Method::GET, "/health").await?;
+        let sites = self.request(reqwest::Method::GET, "/api/sites").await?;
+        let certificates = self.request(reqwest::Method::GET, "/api/certs").await?;
+        let metrics = self.send(reqwest::Method::GET, "/metrics").await?;
+
+        Ok(serde_json::json!({
+            "status": health["status"],
+            "uptime_seconds": prometheus_value(&metrics, "bws_uptime_seconds"),
+            "active_connections": prometheus_value(&metrics, "bws_active_connections"),
+            "requests_total": prometheus_value(&metrics, "bws_requests_total"),
+            "sites": sites["sites"],
+            "certificates": certificates["certificates"],
+        }))
+    }
+
+    /// Send a request to the management API and return its JSON body
+    async fn request(&self, method: reqwest::Method, path: &str) -> BwsResult<serde_json::Value> {
+        let body = self.send(method, path).await?;
+        serde_json::from_str(&body)
+            .map_err(|e| BwsError::Http(format!("{} answered with invalid JSON: {}", path, e)))
+    }
+
+    /// Send a request to the management API and return its body
+    async fn send(&self, method: reqwest::Method, path: &str) -> BwsResult<String> {
+        let ControlTarget::Management { url, api_key } = &self.target else {
+            return Err(BwsError::Validation(
+                "This command needs the management API, not a PID file".to_string(),
+            ));
+        };
+
+        let mut request = reqwest::Client::new().request(method, format!("{}{}", url, path));
+        if let Some(api_key) = api_key {
+            request = request.header("X-API-Key", api_key);
+        }
+        let response = request.send().await.map_err(|e| {
+            if e.is_connect() {
+                BwsError::Http(format!(
+                    "Cannot connect to the management API at {}; is BWS running with the management API enabled?",
+                    url
+                ))
+            } else {
+                BwsError::Http(format!("Request to {}{} failed: {}", url, path, e))
+            }
+        })?;
+
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            let error: serde_json::Value = serde_json::from_str(&body).unwrap_or_default();
+            let reason = error["message"]
+                .as_str()
+                .or(status.canonical_reason())
+                .unwrap_or("request failed");
+            return Err(BwsError::Http(format!(
+                "{} answered {}: {}",
+                path,
+                status.as_u16(),
+                reason
+            )));
+        }
+        Ok(body)
+    }
+}
+
+/// Sum of the samples of the metric `name` in Prometheus text output
+fn prometheus_value(text: &str, name: &str) -> u64 {
+    text.lines()
+        .filter(|line| {
+            line.strip_prefix(name)
+                .is_some_and(|rest| rest.starts_with([' ', '{']))
+        })
+        .filter_map(|line| line.rsplit(' ').next()?.parse::<f64>().ok())
+        .sum::<f64>() as u64
+}
+
+/// Human-readable overview of an instance's `BwsCtl::status`
+pub fn status_summary(status: &serde_json::Value) -> String {
+    let mut out = String::new();
+    let sites = status["sites"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let sites_up = sites.iter().filter(|site| site["up"] == true).count();
+
+    let _ = writeln!(
+        out,
+        "Status: {} (up {}s)",
+        status["status"].as_str().unwrap_or("unknown"),
+        status["uptime_seconds"]
+    );
+    let _ = writeln!(
+        out,
+        "Traffic: {} active connections, {} requests served",
+        status["active_connections"], status["requests_total"]
+    );
+
+    let _ = writeln!(out, "Sites: {} of {} up", sites_up, sites.len());
+    for site in sites {
+        let state = if site["maintenance"] == true {
+            "maintenance".to_string()
+        } else if site["up"] == true {
+            "up".to_string()
+        } else {
+            format!(
+                "down ({} of {} upstreams down)",
+                site["upstreams_down"], site["upstreams"]
+            )
+        };
+        let _ = writeln!(
+            out,
+            "   {} ({}): {}",
+            site["name"].as_str().unwrap_or_default(),
+            site["hostname"].as_str().unwrap_or_default(),
+            state
+        );
+    }
+
+    let certificates = status["certificates"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    if !certificates.is_empty() {
+        let _ = writeln!(out, "Certificates:");
+        for certificate in certificates {
+            let domain = certificate["domain"].as_str().unwrap_or_default();
+            let expiry = match certificate["days_until_expiry"].as_i64() {
+                Some(days) => format!("expires in {} days", days),
+                None => "no certificate".to_string(),
+            };
+            let failures = certificate["renewal"]["consecutive_failures"]
+                .as_u64()
+                .unwrap_or(0);
+            if failures > 0 {
+                let _ = writeln!(
+                    out,
+                    "   {}: {}, {} renewals failed",
+                    domain, expiry, failures
+                );
+            } else {
+                let _ = writeln!(out, "   {}: {}", domain, expiry);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(unix)]
+fn send_reload_signal(pid: i32) -> BwsResult<()> {
+    // SAFETY: kill() only sends a signal, it has no memory safety requirements
+    if unsafe { libc::kill(pid, libc::SIGHUP) } == 0 {
+        Ok(())
+    } else {
+        Err(BwsError::Io(std::io::Error::last_os_error()))
+    }
+}
+
+#[cfg(not(unix))]
+fn send_reload_signal(_pid: i32) -> BwsResult<()> {
+    Err(BwsError::Validation(
+        "Signalling a process needs Unix; use the management API instead".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Answer one request with `status` and `body`, and return the request
+    async fn answer_once(listener: &tokio::net::TcpListener, status: &str, body: &str) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = vec![0; 4096];
+        let read = stream.read(&mut request).await.unwrap();
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8_lossy(&request[..read]).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_reload_through_management_api() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            answer_once(
+                &listener,
+                "200 OK",
+                r#"{"message": "Configuration reloaded successfully"}"#,
+            )
+            .await
+        });
+
+        let ctl = BwsCtl::from_management_url(&url, Some("secret".to_string()));
+        assert_eq!(
+            ctl.reload().await.unwrap(),
+            "Configuration reloaded successfully"
+        );
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /api/config/reload HTTP/1.1\r\n"));
+        assert!(request.to_lowercase().contains("x-api-key: secret\r\n"));
+
+        // Errors of the API are passed on
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            answer_once(
+                &listener,
+                "401 Unauthorized",
+                r#"{"error": "Unauthorized", "message": "Invalid API key", "status": 401}"#,
+            )
+            .await
+        });
+        let error = BwsCtl::from_management_url(&url, None)
+            .reload()
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("401: Invalid API key"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reload_without_running_instance() {
+        // Nothing listens on the port of a listener that was just dropped
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let error = BwsCtl::from_management_url(&format!("http://127.0.0.1:{}", port), None)
+            .reload()
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Cannot connect"), "{}", error);
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(BwsCtl::from_pid_file(dir.path().join("missing.pid")).is_err());
+        std::fs::write(dir.path().join("bws.pid"), "not a pid\n").unwrap();
+        assert!(BwsCtl::from_pid_file(dir.path().join("bws.pid")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut paths = Vec::new();
+            for body in [
+                r#"{"status":"ok"}"#,
+                r#"{"sites": [
+                    {"name": "main", "hostname": "example.com", "up": true, "maintenance": false},
+                    {"name": "api", "hostname": "api.example.com", "up": false,
+                     "maintenance": false, "upstreams": 2, "upstreams_down": 2}
+                ]}"#,
+                r#"{"certificates": [
+                    {"domain": "example.com", "days_until_expiry": 12,
+                     "renewal": {"consecutive_failures": 3}}
+                ]}"#,
+                "# TYPE bws_requests_total counter\n\
+                 bws_requests_total{status=\"200\"} 40\n\
+                 bws_requests_total{status=\"404\"} 2\n\
+                 bws_active_connections 5\n\
+                 bws_uptime_seconds 3600\n",
+            ] {
+                let request = answer_once(&listener, "200 OK", body).await;
+                paths.push(request.split(' ').nth(1).unwrap_or_default().to_string());
+            }
+            paths
+        });
+
+        let status = BwsCtl::from_management_url(&url, None)
+            .status()
+            .await
+            .unwrap();
+        assert_eq!(
+            server.await.unwrap(),
+            ["/health", "/api/sites", "/api/certs", "/metrics"]
+        );
+        assert_eq!(status["requests_total"], 42);
+        assert_eq!(status["active_connections"], 5);
+
+        let summary = status_summary(&status);
+        assert!(summary.contains("Status: ok (up 3600s)\n"), "{}", summary);
+        assert!(summary.contains("Sites: 1 of 2 up\n"), "{}", summary);
+        assert!(summary.contains("api (api.example.com): down (2 of 2 upstreams down)\n"));
+        assert!(summary.contains("example.com: expires in 12 days, 3 renewals failed\n"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_reload_through_pid_file() {
+        let (reloaded, mut reloads) = tokio::sync::mpsc::unbounded_channel();
+        crate::core::signals::on_reload(move || {
+            let _ = reloaded.send(());
+            async {}
+        })
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("bws.pid");
+        std::fs::write(&pid_file, format!("{}\n", std::process::id())).unwrap();
+        let ctl = BwsCtl::from_pid_file(&pid_file).unwrap();
+        assert_eq!(
+            ctl.target(),
+            &ControlTarget::Process(std::process::id() as i32)
+        );
+
+        ctl.reload().await.unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), reloads.recv())
+            .await
+            .expect("SIGHUP should reach the reload handler")
+            .unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+//! Deadline for starting a response
+//!
+//! `request_timeout` bounds the time a request takes until its response
+//! starts, so a stuck disk read or a slow handler answers `504` instead of
+//! holding the worker. Handlers call [`response_started`] once the header of
+//! a long body is out; downloads, event streams and WebSocket sessions then
+//! run for as long as they need.
+//!
+//! Reading the request body does not count: an upload still arriving is
+//! bounded by the read timeout between chunks, and the deadline starts
+//! afresh once the body is in, see [`reading_body`].
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+tokio::task_local! {
+    static DEADLINE: Arc<DeadlineState>;
+}
+
+#[derive(Default)]
+struct DeadlineState {
+    /// The response header is out
+    started: AtomicBool,
+    /// A request body is being read
+    reading_body: AtomicBool,
+    /// When the request body was read in full
+    body_read_at: Mutex<Option<Instant>>,
+}
+
+/// Run `handler`, giving up when it has not started a response within
+/// `deadline`. A handler that started one is left to finish.
+pub async fn until_response_started<T>(
+    deadline: Duration,
+    handler: impl Future<Output = T>,
+) -> Option<T> {
+    let state = Arc::new(DeadlineState::default());
+    let handler = DEADLINE.scope(state.clone(), handler);
+    tokio::pin!(handler);
+    let sleep = tokio::time::sleep(deadline);
+    tokio::pin!(sleep);
+
+    loop {
+        tokio::select! {
+            result = &mut handler => return Some(result),
+            _ = &mut sleep => {}
+        }
+        if state.started.load(Ordering::Relaxed) {
+            return Some(handler.await);
+        }
+
+        // Time spent reading the body is not counted
+        let now = Instant::now();
+        let expires = if state.reading_body.load(Ordering::Relaxed) {
+            now + deadline
+        } else {
+            match *state.body_read_at.lock().unwrap() {
+                Some(read_at) if read_at + deadline > now => read_at + deadline,
+                _ => return None,
+            }
+        };
+        sleep.as_mut().reset(expires);
+    }
+}
+
+/// Lift the deadline of the request being handled, right after writing its
+/// response header. Does nothing outside [`until_response_started`].
+pub fn response_started() {
+    let _ = DEADLINE.try_with(|state| state.started.store(true, Ordering::Relaxed));
+}
+
+/// Run `read`, reading the request body, with the deadline held. It starts
+/// afresh once the body is in.
+pub async fn reading_body<T>(read: impl Future<Output = T>) -> T {
+    let _ = DEADLINE.try_with(|state| state.reading_body.store(true, Ordering::Relaxed));
+    let result = read.await;
+    let _ = DEADLINE.try_with(|state| {
+        *state.body_read_at.lock().unwrap() = Some(Instant::now());
+        state.reading_body.store(false, Ordering::Relaxed);
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_deadline_until_response_started() {
+        let deadline = Duration::from_millis(50);
+        let slow = Duration::from_millis(200);
+
+        // Nothing started in time: abandoned
+        let stuck = async {
+            tokio::time::sleep(slow).await;
+            true
+        };
+        assert_eq!(until_response_started(deadline, stuck).await, None);
+
+        // Started, then streaming past the deadline: left to finish
+        let streaming = async {
+            response_started();
+            tokio::time::sleep(slow).await;
+            true
+        };
+        assert_eq!(
+            until_response_started(deadline, streaming).await,
+            Some(true)
+        );
+
+        // Outside a deadline there is nothing to lift
+        response_started();
+    }
+
+    #[tokio::test]
+    async fn test_deadline_excludes_body_reads() {
+        let deadline = Duration::from_millis(50);
+        let upload = Duration::from_millis(150);
+
+        // A long upload, then a handler quicker than the deadline
+        let quick = async {
+            reading_body(tokio::time::sleep(upload)).await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            true
+        };
+        assert_eq!(until_response_started(deadline, quick).await, Some(true));
+
+        // The deadline counts from the end of the body
+        let slow = async {
+            reading_body(tokio::time::sleep(upload)).await;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            true
+        };
+        assert_eq!(until_response_started(deadline, slow).await, None);
+    }
+}
@@ -3,7 +3,11 @@
 //! This module contains the foundational types, error handling,
 //! and utilities used throughout the application.
 
+pub mod cli;
+pub mod deadline;
 pub mod error;
+#[cfg(unix)]
+pub mod signals;
 pub mod types;
 pub mod utils;
 
@@ -0,0 +1,57 @@
+//! Unix signal handling
+//!
+//! Shutdown signals are handled by Pingora. `SIGUSR1` reopens log files, so
+//! `logrotate` and similar tools can rename a log and then signal BWS to
+//! start writing a new one. `SIGHUP` reloads the configuration file.
+
+use std::future::Future;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Run `reopen` every time the process receives `SIGUSR1`. The handler is
+/// installed before this returns; `reopen` runs on a background thread.
+pub fn on_reopen_logs(reopen: impl Fn() + Send + 'static) -> std::io::Result<()> {
+    on_signal(SignalKind::user_defined1(), move || {
+        log::info!("Received SIGUSR1, reopening log files");
+        reopen();
+        async {}
+    })
+}
+
+/// Run `reload` every time the process receives `SIGHUP`, one reload at a
+/// time. The handler is installed before this returns; `reload` runs on a
+/// background thread.
+pub fn on_reload<F, Fut>(reload: F) -> std::io::Result<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()>,
+{
+    on_signal(SignalKind::hangup(), move || {
+        log::info!("Received SIGHUP, reloading configuration");
+        reload()
+    })
+}
+
+fn on_signal<F, Fut>(kind: SignalKind, handler: F) -> std::io::Result<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()>,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+    let mut signals = {
+        let _guard = runtime.enter();
+        signal(kind)?
+    };
+
+    std::thread::Builder::new()
+        .name("bws-signals".to_string())
+        .spawn(move || {
+            runtime.block_on(async {
+                while signals.recv().await.is_some() {
+                    handler().await;
+                }
+            })
+        })?;
+    Ok(())
+}
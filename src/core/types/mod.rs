@@ -75,6 +75,8 @@ pub enum LoadBalancingStrategy {
     RoundRobin,
     /// Weighted round-robin
     WeightedRoundRobin,
+    /// Weighted random selection
+    WeightedRandom,
     /// Least connections
     LeastConnections,
     /// IP hash based routing
@@ -83,6 +85,36 @@ pub enum LoadBalancingStrategy {
     Random,
 }
 
+impl std::str::FromStr for LoadBalancingStrategy {
+    type Err = String;
+
+    fn from_str(method: &str) -> Result<Self, Self::Err> {
+        match method.to_lowercase().as_str() {
+            "round_robin" => Ok(LoadBalancingStrategy::RoundRobin),
+            "weighted" | "weighted_round_robin" => Ok(LoadBalancingStrategy::WeightedRoundRobin),
+            "weighted_random" => Ok(LoadBalancingStrategy::WeightedRandom),
+            "least_connections" | "least_conn" => Ok(LoadBalancingStrategy::LeastConnections),
+            "ip_hash" => Ok(LoadBalancingStrategy::IpHash),
+            "random" => Ok(LoadBalancingStrategy::Random),
+            _ => Err(format!("Unknown load balancing method: {}", method)),
+        }
+    }
+}
+
+impl LoadBalancingStrategy {
+    /// Convert to the configuration string representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoadBalancingStrategy::RoundRobin => "round_robin",
+            LoadBalancingStrategy::WeightedRoundRobin => "weighted",
+            LoadBalancingStrategy::WeightedRandom => "weighted_random",
+            LoadBalancingStrategy::LeastConnections => "least_connections",
+            LoadBalancingStrategy::IpHash => "ip_hash",
+            LoadBalancingStrategy::Random => "random",
+        }
+    }
+}
+
 /// Request processing priority levels
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Priority {
@@ -205,8 +237,15 @@ pub mod constants {
     /// Default write timeout
     pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(60);
 
+    /// Days before expiry a certificate is renewed, unless a site sets its own
+    pub const CERT_RENEWAL_DAYS: i64 = 30;
+
     /// Certificate renewal threshold (30 days)
-    pub const CERT_RENEWAL_THRESHOLD: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+    pub const CERT_RENEWAL_THRESHOLD: Duration =
+        Duration::from_secs(CERT_RENEWAL_DAYS as u64 * 24 * 60 * 60);
+
+    /// Lifetime of the certificates ACME CAs such as Let's Encrypt issue
+    pub const ACME_CERT_LIFETIME_DAYS: i64 = 90;
 
     /// Health check interval
     pub const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
@@ -217,6 +256,9 @@ pub mod constants {
     /// BWS version for headers
     pub const BWS_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+    /// Git commit the binary was built from, when known at build time
+    pub const BWS_GIT_SHA: Option<&str> = option_env!("BWS_GIT_SHA");
+
     /// BWS user agent
     pub const BWS_USER_AGENT: &str = concat!("BWS/", env!("CARGO_PKG_VERSION"));
 }
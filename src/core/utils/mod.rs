@@ -123,9 +123,48 @@ pub mod fs {
     /// Check if a file extension is allowed for static serving
     pub fn is_safe_extension(extension: &str) -> bool {
         const SAFE_EXTENSIONS: &[&str] = &[
-            "html", "htm", "css", "js", "json", "xml", "txt", "md", "pdf", "doc", "docx", "jpg",
-            "jpeg", "png", "gif", "svg", "webp", "mp3", "mp4", "wav", "avi", "mov", "zip", "tar",
-            "gz", "woff", "woff2", "ttf", "ico", "manifest", "map", "wasm",
+            "html",
+            "htm",
+            "css",
+            "js",
+            "mjs",
+            "json",
+            "xml",
+            "txt",
+            "md",
+            "csv",
+            "pdf",
+            "doc",
+            "docx",
+            "jpg",
+            "jpeg",
+            "png",
+            "gif",
+            "svg",
+            "webp",
+            "avif",
+            "ico",
+            "mp3",
+            "mp4",
+            "webm",
+            "ogg",
+            "wav",
+            "avi",
+            "mov",
+            "zip",
+            "tar",
+            "gz",
+            "woff",
+            "woff2",
+            "ttf",
+            "otf",
+            "eot",
+            "manifest",
+            "webmanifest",
+            "rss",
+            "atom",
+            "map",
+            "wasm",
         ];
 
         SAFE_EXTENSIONS.contains(&extension.to_lowercase().as_str())
@@ -187,6 +226,7 @@ pub mod time {
 
 /// Network utilities
 pub mod net {
+    use serde::{Deserialize, Serialize};
     use std::net::{IpAddr, SocketAddr};
 
     /// Check if an IP address is in a private range
@@ -197,30 +237,423 @@ pub mod net {
         }
     }
 
-    /// Extract client IP from various sources
+    /// Parse an IP address or CIDR block such as `10.0.0.0/8`.
+    /// A bare address is treated as a full-length prefix.
+    pub fn parse_cidr(entry: &str) -> Option<(IpAddr, u8)> {
+        let entry = entry.trim();
+        let (addr, prefix) = match entry.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (entry, None),
+        };
+
+        let addr: IpAddr = addr.parse().ok()?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|p| *p <= max_prefix)?,
+            None => max_prefix,
+        };
+
+        Some((addr, prefix))
+    }
+
+    /// Check whether `ip` falls within the block `network/prefix`
+    pub fn cidr_contains(network: IpAddr, prefix: u8, ip: IpAddr) -> bool {
+        match (network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+
+    /// Addresses and CIDR blocks of the proxies whose forwarding headers
+    /// are believed. Parsed when the configuration is read, so a malformed
+    /// entry fails the load instead of silently trusting nothing.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(try_from = "Vec<String>", into = "Vec<String>")]
+    pub struct TrustedProxies(Vec<(IpAddr, u8)>);
+
+    impl TrustedProxies {
+        /// Whether `ip` is one of the trusted proxies
+        pub fn contains(&self, ip: IpAddr) -> bool {
+            self.0
+                .iter()
+                .any(|&(network, prefix)| cidr_contains(network, prefix, ip))
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.0.is_empty()
+        }
+
+        /// Trust the entries of `other` as well
+        pub fn extend(&mut self, other: &TrustedProxies) {
+            for entry in &other.0 {
+                if !self.0.contains(entry) {
+                    self.0.push(*entry);
+                }
+            }
+        }
+    }
+
+    impl TryFrom<Vec<String>> for TrustedProxies {
+        type Error = String;
+
+        fn try_from(entries: Vec<String>) -> Result<Self, Self::Error> {
+            entries
+                .iter()
+                .map(|entry| {
+                    parse_cidr(entry).ok_or_else(|| {
+                        format!("Invalid trusted proxy address or CIDR block: {}", entry)
+                    })
+                })
+                .collect::<Result<_, _>>()
+                .map(TrustedProxies)
+        }
+    }
+
+    impl From<TrustedProxies> for Vec<String> {
+        fn from(proxies: TrustedProxies) -> Self {
+            proxies
+                .0
+                .into_iter()
+                .map(|(network, prefix)| match (network, prefix) {
+                    (IpAddr::V4(_), 32) | (IpAddr::V6(_), 128) => network.to_string(),
+                    _ => format!("{}/{}", network, prefix),
+                })
+                .collect()
+        }
+    }
+
+    /// Check that `addr` can be listened on, by binding it briefly. The
+    /// listener sets `SO_REUSEADDR` like Pingora's, so connections lingering
+    /// in `TIME_WAIT` from a previous run do not count as a conflict.
+    pub fn check_bindable(addr: &str) -> std::io::Result<()> {
+        std::net::TcpListener::bind(addr).map(drop)
+    }
+
+    /// Extract the client IP, honoring forwarding headers only when the
+    /// connecting peer is a trusted proxy. `X-Forwarded-For` is walked from
+    /// the right, skipping trusted hops, so spoofed left-most entries are ignored.
     pub fn extract_client_ip(
         socket_addr: &SocketAddr,
         x_forwarded_for: Option<&str>,
         x_real_ip: Option<&str>,
+        trusted_proxies: &TrustedProxies,
     ) -> IpAddr {
-        // Check X-Real-IP header first
-        if let Some(real_ip) = x_real_ip {
-            if let Ok(ip) = real_ip.parse() {
-                return ip;
-            }
+        let peer_ip = socket_addr.ip();
+        if !trusted_proxies.contains(peer_ip) {
+            return peer_ip;
         }
 
         // Check X-Forwarded-For header
         if let Some(forwarded) = x_forwarded_for {
-            if let Some(first_ip) = forwarded.split(',').next() {
-                if let Ok(ip) = first_ip.trim().parse() {
-                    return ip;
+            for hop in forwarded.rsplit(',') {
+                match hop.trim().parse::<IpAddr>() {
+                    Ok(ip) if trusted_proxies.contains(ip) => continue,
+                    Ok(ip) => return ip,
+                    Err(_) => break,
                 }
             }
         }
 
+        // Check X-Real-IP header
+        if let Some(real_ip) = x_real_ip {
+            if let Ok(ip) = real_ip.trim().parse() {
+                return ip;
+            }
+        }
+
         // Fall back to socket address
-        socket_addr.ip()
+        peer_ip
+    }
+}
+
+/// HTTP header utilities
+pub mod http {
+    /// Parse an `Accept` header into media ranges and their quality values
+    pub fn parse_accept(header: &str) -> Vec<(String, f32)> {
+        header
+            .split(',')
+            .filter_map(|item| {
+                let mut params = item.split(';');
+                let range = params.next()?.trim().to_ascii_lowercase();
+                if range.is_empty() {
+                    return None;
+                }
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0)
+                    .clamp(0.0, 1.0);
+                Some((range, quality))
+            })
+            .collect()
+    }
+
+    /// Quality the client gives `mime_type`, using the most specific matching
+    /// range (`type/subtype` over `type/*` over `*/*`). 0 means not acceptable.
+    pub fn accept_quality(header: &str, mime_type: &str) -> f32 {
+        let mime_type = mime_type
+            .split(';')
+            .next()
+            .unwrap_or(mime_type)
+            .trim()
+            .to_ascii_lowercase();
+        let major = mime_type.split('/').next().unwrap_or_default();
+
+        parse_accept(header)
+            .into_iter()
+            .filter_map(|(range, quality)| {
+                let specificity = if range == mime_type {
+                    2
+                } else if range.strip_suffix("/*") == Some(major) {
+                    1
+                } else if range == "*/*" {
+                    0
+                } else {
+                    return None;
+                };
+                Some((specificity, quality))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0.0, |(_, quality)| quality)
+    }
+
+    /// Whether `mime_type` is listed by name (not only via a wildcard) with q > 0
+    pub fn accepts_explicitly(header: &str, mime_type: &str) -> bool {
+        parse_accept(header)
+            .iter()
+            .any(|(range, quality)| range.eq_ignore_ascii_case(mime_type) && *quality > 0.0)
+    }
+
+    /// What a `Range` header asks of a representation
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum RangeRequest {
+        /// No usable range: serve the whole representation
+        Full,
+        /// Serve bytes `start..=end`
+        Partial { start: u64, end: u64 },
+        /// The range lies outside the representation (416)
+        Unsatisfiable,
+    }
+
+    /// Parse a `Range` header for a representation of `len` bytes. Only single
+    /// byte ranges are served; other units, multiple ranges and malformed
+    /// headers are ignored, as RFC 9110 allows.
+    pub fn parse_range(header: &str, len: u64) -> RangeRequest {
+        let Some((unit, spec)) = header.trim().split_once('=') else {
+            return RangeRequest::Full;
+        };
+        if !unit.trim().eq_ignore_ascii_case("bytes") || spec.contains(',') {
+            return RangeRequest::Full;
+        }
+        let Some((first, last)) = spec.trim().split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let (first, last) = (first.trim(), last.trim());
+
+        if first.is_empty() {
+            // Suffix range: the final `last` bytes
+            return match last.parse::<u64>() {
+                Ok(0) => RangeRequest::Unsatisfiable,
+                Ok(_) if len == 0 => RangeRequest::Unsatisfiable,
+                Ok(suffix) => RangeRequest::Partial {
+                    start: len.saturating_sub(suffix),
+                    end: len - 1,
+                },
+                Err(_) => RangeRequest::Full,
+            };
+        }
+
+        let Ok(start) = first.parse::<u64>() else {
+            return RangeRequest::Full;
+        };
+        let end = if last.is_empty() {
+            u64::MAX
+        } else {
+            match last.parse::<u64>() {
+                Ok(end) if end >= start => end,
+                _ => return RangeRequest::Full,
+            }
+        };
+
+        if start >= len {
+            RangeRequest::Unsatisfiable
+        } else {
+            RangeRequest::Partial {
+                start,
+                end: end.min(len - 1),
+            }
+        }
+    }
+
+    /// Whether an `If-Range` validator still matches the representation, so
+    /// the requested range may be served. Entity tags use the strong
+    /// comparison; a date must equal `Last-Modified`.
+    pub fn if_range_matches(
+        if_range: &str,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> bool {
+        let if_range = if_range.trim();
+        if if_range.starts_with("W/") {
+            return false;
+        }
+        if if_range.starts_with('"') {
+            return etag.is_some_and(|etag| !etag.starts_with("W/") && etag == if_range);
+        }
+
+        match (
+            parse_http_date(if_range),
+            last_modified.and_then(parse_http_date),
+        ) {
+            (Some(requested), Some(modified)) => requested == modified,
+            _ => false,
+        }
+    }
+
+    /// Format a time as an HTTP date (`Sun, 06 Nov 1994 08:49:37 GMT`)
+    pub fn http_date(time: std::time::SystemTime) -> String {
+        chrono::DateTime::<chrono::Utc>::from(time)
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string()
+    }
+
+    /// Parse an HTTP date into seconds since the Unix epoch
+    pub fn parse_http_date(value: &str) -> Option<i64> {
+        chrono::DateTime::parse_from_rfc2822(value.trim())
+            .ok()
+            .map(|date| date.timestamp())
+    }
+
+    /// Percent-decode a string, failing on malformed escapes or invalid UTF-8
+    pub fn percent_decode(input: &str) -> Option<String> {
+        let bytes = input.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'%' {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            } else {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+        String::from_utf8(decoded).ok()
+    }
+
+    /// Percent-encode a decoded path for use in a URL, keeping `/` separators
+    pub fn encode_path(path: &str) -> String {
+        path.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z'
+                | b'a'..=b'z'
+                | b'0'..=b'9'
+                | b'-'
+                | b'.'
+                | b'_'
+                | b'~'
+                | b'/'
+                | b'!'
+                | b'$'
+                | b'&'
+                | b'\''
+                | b'('
+                | b')'
+                | b'*'
+                | b'+'
+                | b','
+                | b';'
+                | b'='
+                | b':'
+                | b'@' => (b as char).to_string(),
+                _ => format!("%{:02X}", b),
+            })
+            .collect()
+    }
+
+    /// Split a Host header value into host and optional port, keeping the
+    /// brackets of IPv6 literals as `Uri::host` does: `[::1]:8080` gives
+    /// `[::1]` and port 8080. `None` when the port is not a number.
+    pub fn split_host_port(host: &str) -> Option<(&str, Option<u16>)> {
+        let (name, port) = match host.rfind(':') {
+            Some(pos) if !host[pos..].contains(']') => (&host[..pos], Some(&host[pos + 1..])),
+            _ => (host, None),
+        };
+        let port = match port {
+            Some(port) => Some(port.parse().ok()?),
+            None => None,
+        };
+        Some((name, port))
+    }
+
+    /// Size of a response header block in HTTP/1.x wire format: status line,
+    /// header lines and the blank line ending them
+    pub fn h1_response_header_len(header: &pingora::http::ResponseHeader) -> usize {
+        let mut buf = bytes::BytesMut::new();
+        header.header_to_h1_wire(&mut buf);
+        let reason = header.get_reason_phrase().map_or(0, str::len);
+        // "HTTP/1.1 " + status code + " " + reason + CRLF, then the final CRLF
+        "HTTP/1.1 ".len() + 4 + reason + 2 + buf.len() + 2
+    }
+
+    /// Error from [`normalize_path`] for paths that try to leave the root
+    pub const PATH_TRAVERSAL: &str = "Path traversal in request path";
+
+    /// Canonical form of a request path: percent-decoded once, `\` read as `/`,
+    /// empty and `.` segments dropped, and a trailing slash kept. Paths with
+    /// `..` segments are rejected, including ones hidden behind further layers
+    /// of percent-encoding, as are NUL bytes.
+    pub fn normalize_path(raw: &str) -> Result<String, &'static str> {
+        if !raw.starts_with('/') {
+            return Err("Request path must start with '/'");
+        }
+        let decoded = percent_decode(raw).ok_or("Invalid percent-encoding in request path")?;
+        if decoded.contains('\0') {
+            return Err("NUL byte in request path");
+        }
+        let decoded = decoded.replace('\\', "/");
+
+        let mut segments = Vec::new();
+        for segment in decoded.split('/') {
+            if segment.is_empty() || segment == "." {
+                continue;
+            }
+            if hides_traversal(segment) {
+                return Err(PATH_TRAVERSAL);
+            }
+            segments.push(segment);
+        }
+
+        let mut path = format!("/{}", segments.join("/"));
+        if decoded.ends_with('/') && !segments.is_empty() {
+            path.push('/');
+        }
+        Ok(path)
+    }
+
+    /// Whether a segment is `..`, or becomes a path with a `..` segment when
+    /// decoded again, as a backend that decodes once more would see it
+    fn hides_traversal(segment: &str) -> bool {
+        let mut current = segment.to_string();
+        for _ in 0..4 {
+            if current.split(['/', '\\']).any(|part| part == "..") {
+                return true;
+            }
+            match percent_decode(&current) {
+                Some(next) if next != current => current = next,
+                _ => return false,
+            }
+        }
+        // Still changing after four rounds of decoding: treat as hostile
+        true
     }
 }
 
@@ -261,4 +694,223 @@ mod tests {
         assert!(!fs::is_safe_extension("exe"));
         assert!(!fs::is_safe_extension("sh"));
     }
+
+    #[test]
+    fn test_cidr_matching() {
+        let ip = |s: &str| s.parse::<std::net::IpAddr>().unwrap();
+
+        let (network, prefix) = net::parse_cidr("10.0.0.0/8").unwrap();
+        assert!(net::cidr_contains(network, prefix, ip("10.20.30.40")));
+        assert!(!net::cidr_contains(network, prefix, ip("11.0.0.1")));
+        assert!(!net::cidr_contains(network, prefix, ip("::1")));
+
+        let (network, prefix) = net::parse_cidr("192.168.1.5").unwrap();
+        assert_eq!(prefix, 32);
+        assert!(net::cidr_contains(network, prefix, ip("192.168.1.5")));
+        assert!(!net::cidr_contains(network, prefix, ip("192.168.1.6")));
+
+        let (network, prefix) = net::parse_cidr("0.0.0.0/0").unwrap();
+        assert!(net::cidr_contains(network, prefix, ip("203.0.113.9")));
+
+        let (network, prefix) = net::parse_cidr("2001:db8::/32").unwrap();
+        assert!(net::cidr_contains(network, prefix, ip("2001:db8::1")));
+        assert!(!net::cidr_contains(network, prefix, ip("2001:db9::1")));
+
+        assert!(net::parse_cidr("10.0.0.0/33").is_none());
+        assert!(net::parse_cidr("not-an-ip").is_none());
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        let ip = |s: &str| s.parse::<std::net::IpAddr>().unwrap();
+        let entries = vec!["10.0.0.0/8".to_string(), "::1".to_string()];
+        let trusted = net::TrustedProxies::try_from(entries.clone()).unwrap();
+        assert!(trusted.contains(ip("10.1.2.3")));
+        assert!(trusted.contains(ip("::1")));
+        assert!(!trusted.contains(ip("192.168.0.1")));
+        assert_eq!(Vec::<String>::from(trusted), entries);
+
+        let err = net::TrustedProxies::try_from(vec!["10.0.0.0/40".to_string()]).unwrap_err();
+        assert!(err.contains("10.0.0.0/40"));
+        assert!(net::TrustedProxies::default().is_empty());
+    }
+
+    #[test]
+    fn test_extract_client_ip_untrusted_peer() {
+        let peer: std::net::SocketAddr = "203.0.113.7:5000".parse().unwrap();
+
+        // Headers from an untrusted peer are ignored
+        let ip = net::extract_client_ip(
+            &peer,
+            Some("1.2.3.4"),
+            Some("5.6.7.8"),
+            &net::TrustedProxies::default(),
+        );
+        assert_eq!(ip, peer.ip());
+    }
+
+    #[test]
+    fn test_request_path_normalization() {
+        assert_eq!(http::normalize_path("/").unwrap(), "/");
+        assert_eq!(http::normalize_path("//a/./b//").unwrap(), "/a/b/");
+        assert_eq!(http::normalize_path("/a%20b.txt").unwrap(), "/a b.txt");
+        assert_eq!(
+            http::normalize_path("/api%2Fhealth").unwrap(),
+            "/api/health"
+        );
+        assert_eq!(http::normalize_path("/a\\b").unwrap(), "/a/b");
+        assert_eq!(http::normalize_path("/100%25").unwrap(), "/100%");
+        assert_eq!(http::normalize_path("/a..b/c").unwrap(), "/a..b/c");
+
+        // Traversal, plain or encoded
+        assert!(http::normalize_path("/a/../b").is_err());
+        assert!(http::normalize_path("/%2e%2e%2fetc/passwd").is_err());
+        assert!(http::normalize_path("/static/%2E%2E/%2e%2e/etc").is_err());
+        assert!(http::normalize_path("/..%5cwindows").is_err());
+        // Double encoding decodes to %2e%2e%2f, which a backend would decode again
+        assert!(http::normalize_path("/%252e%252e%252fetc/passwd").is_err());
+        assert!(http::normalize_path("/%25252e%25252e/x").is_err());
+
+        assert!(http::normalize_path("/a%00b").is_err());
+        assert!(http::normalize_path("/a%zz").is_err());
+        assert!(http::normalize_path("/%ff").is_err());
+        assert!(http::normalize_path("*").is_err());
+
+        assert_eq!(http::encode_path("/a b/100%/é"), "/a%20b/100%25/%C3%A9");
+        assert_eq!(http::encode_path("/api/v1;x=1"), "/api/v1;x=1");
+    }
+
+    #[test]
+    fn test_range_parsing() {
+        use http::RangeRequest::*;
+
+        assert_eq!(
+            http::parse_range("bytes=0-99", 1000),
+            Partial { start: 0, end: 99 }
+        );
+        assert_eq!(
+            http::parse_range("bytes=900-", 1000),
+            Partial {
+                start: 900,
+                end: 999
+            }
+        );
+        assert_eq!(
+            http::parse_range("bytes=-100", 1000),
+            Partial {
+                start: 900,
+                end: 999
+            }
+        );
+        assert_eq!(
+            http::parse_range("bytes=-5000", 1000),
+            Partial { start: 0, end: 999 }
+        );
+        assert_eq!(
+            http::parse_range("bytes=500-5000", 1000),
+            Partial {
+                start: 500,
+                end: 999
+            }
+        );
+        assert_eq!(http::parse_range("bytes=1000-", 1000), Unsatisfiable);
+        assert_eq!(http::parse_range("bytes=-0", 1000), Unsatisfiable);
+        assert_eq!(http::parse_range("bytes=0-", 0), Unsatisfiable);
+
+        // Ignored: multiple ranges, other units, malformed specs
+        assert_eq!(http::parse_range("bytes=0-1,5-6", 1000), Full);
+        assert_eq!(http::parse_range("items=0-1", 1000), Full);
+        assert_eq!(http::parse_range("bytes=5-1", 1000), Full);
+        assert_eq!(http::parse_range("bytes=x-", 1000), Full);
+    }
+
+    #[test]
+    fn test_if_range_validators() {
+        let etag = Some("\"abc\"");
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(784111777);
+        let last_modified = http::http_date(modified);
+        assert_eq!(last_modified, "Sun, 06 Nov 1994 08:49:37 GMT");
+
+        assert!(http::if_range_matches("\"abc\"", etag, None));
+        assert!(!http::if_range_matches("\"old\"", etag, None));
+        assert!(!http::if_range_matches("W/\"abc\"", etag, None));
+        assert!(!http::if_range_matches(
+            "\"abc\"",
+            None,
+            Some(&last_modified)
+        ));
+
+        assert!(http::if_range_matches(
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            etag,
+            Some(&last_modified)
+        ));
+        assert!(!http::if_range_matches(
+            "Sun, 06 Nov 1994 08:49:36 GMT",
+            etag,
+            Some(&last_modified)
+        ));
+        assert!(!http::if_range_matches(
+            "yesterday",
+            etag,
+            Some(&last_modified)
+        ));
+    }
+
+    #[test]
+    fn test_accept_negotiation() {
+        let accept = "text/html, application/xhtml+xml;q=0.9, image/*;q=0.8, */*;q=0.1";
+        assert_eq!(
+            http::accept_quality(accept, "text/html; charset=utf-8"),
+            1.0
+        );
+        assert_eq!(http::accept_quality(accept, "image/png"), 0.8);
+        assert_eq!(http::accept_quality(accept, "application/json"), 0.1);
+        assert_eq!(http::accept_quality("text/html", "application/json"), 0.0);
+        assert_eq!(
+            http::accept_quality("*/*, text/plain;q=0", "text/plain"),
+            0.0
+        );
+
+        let accept = "image/avif,image/webp,image/apng,*/*;q=0.8";
+        assert!(http::accepts_explicitly(accept, "image/avif"));
+        assert!(!http::accepts_explicitly(accept, "image/jxl"));
+        assert!(!http::accepts_explicitly("image/webp;q=0", "image/webp"));
+    }
+
+    #[test]
+    fn test_h1_response_header_len() {
+        let mut header = pingora::http::ResponseHeader::build(200, None).unwrap();
+        header.insert_header("Content-Length", "5").unwrap();
+        header.insert_header("X-Site", "main").unwrap();
+        let wire = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-Site: main\r\n\r\n";
+        assert_eq!(http::h1_response_header_len(&header), wire.len());
+    }
+
+    #[test]
+    fn test_extract_client_ip_trusted_proxy() {
+        let proxy: std::net::IpAddr = "10.0.0.1".parse().unwrap();
+        let peer = std::net::SocketAddr::new(proxy, 5000);
+        let trusted = net::TrustedProxies::try_from(vec!["10.0.0.0/24".to_string()]).unwrap();
+
+        let ip = net::extract_client_ip(&peer, Some("198.51.100.9"), None, &trusted);
+        assert_eq!(ip.to_string(), "198.51.100.9");
+
+        // A client-supplied left-most entry cannot override the hop our proxy saw
+        let ip = net::extract_client_ip(&peer, Some("1.1.1.1, 198.51.100.9"), None, &trusted);
+        assert_eq!(ip.to_string(), "198.51.100.9");
+
+        // Trusted hops are skipped
+        let ip = net::extract_client_ip(&peer, Some("198.51.100.9, 10.0.0.1"), None, &trusted);
+        assert_eq!(ip.to_string(), "198.51.100.9");
+        let ip = net::extract_client_ip(&peer, Some("198.51.100.9, 10.0.0.77"), None, &trusted);
+        assert_eq!(ip.to_string(), "198.51.100.9");
+
+        // X-Real-IP is used when there is no X-Forwarded-For
+        let ip = net::extract_client_ip(&peer, None, Some("198.51.100.10"), &trusted);
+        assert_eq!(ip.to_string(), "198.51.100.10");
+
+        // Without any headers the proxy address is used
+        assert_eq!(net::extract_client_ip(&peer, None, None, &trusted), proxy);
+    }
 }
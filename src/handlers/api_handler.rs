@@ -1,4 +1,6 @@
-use crate::config::{ServerConfig, SiteConfig};
+use crate::config::{ServerConfig, ServerInfo, SiteConfig};
+use crate::core::constants;
+use crate::handlers::error_response::write_json_error;
 use pingora::http::ResponseHeader;
 use pingora::prelude::*;
 use std::sync::Arc;
@@ -49,6 +51,12 @@ impl ApiHandler {
         }
     }
 
+    /// `GET /api/version`: which build is running, e.g. behind a load balancer
+    pub async fn handle_version(&self, session: &mut Session, server: &ServerInfo) -> Result<()> {
+        self.send_json_response(session, 200, &version_info(server))
+            .await
+    }
+
     async fn handle_sites_info(
         &self,
         session: &mut Session,
@@ -84,7 +92,7 @@ impl ApiHandler {
     async fn handle_ssl_certificate_request(
         &self,
         session: &mut Session,
-        _site: Option<&SiteConfig>,
+        site: Option<&SiteConfig>,
         path: &str,
     ) -> Result<()> {
         // Extract domain from path
@@ -92,13 +100,17 @@ impl ApiHandler {
             .strip_prefix("/api/ssl/certificates/")
             .unwrap_or("unknown");
 
-        let response = serde_json::json!({
-            "message": format!("Certificate request for domain: {}", domain),
-            "domain": domain,
-            "status": "not_implemented"
-        });
-
-        self.send_json_response(session, 501, &response).await
+        write_json_error(
+            session,
+            501,
+            "Not Implemented",
+            &format!(
+                "Certificate requests are not implemented (domain: {})",
+                domain
+            ),
+            site,
+        )
+        .await
     }
 
     async fn handle_ssl_status(
@@ -136,17 +148,20 @@ impl ApiHandler {
     async fn handle_config_reload(
         &self,
         session: &mut Session,
-        _site: Option<&SiteConfig>,
+        site: Option<&SiteConfig>,
     ) -> Result<()> {
         // Get the config path
         let config_path = match Self::get_config_path().await {
             Some(path) => path,
             None => {
-                let response = serde_json::json!({
-                    "error": "Config path not set",
-                    "message": "Configuration path not found (running in temporary mode?)"
-                });
-                return self.send_json_response(session, 400, &response).await;
+                return write_json_error(
+                    session,
+                    400,
+                    "Config path not set",
+                    "Configuration path not found (running in temporary mode?)",
+                    site,
+                )
+                .await;
             }
         };
 
@@ -163,12 +178,14 @@ impl ApiHandler {
                 self.send_json_response(session, 200, &response).await
             }
             Err(e) => {
-                let response = serde_json::json!({
-                    "error": "Configuration reload failed",
-                    "message": format!("Failed to reload configuration: {}", e),
-                    "status": "error"
-                });
-                self.send_json_response(session, 400, &response).await
+                write_json_error(
+                    session,
+                    400,
+                    "Configuration reload failed",
+                    &format!("Failed to reload configuration: {}", e),
+                    site,
+                )
+                .await
             }
         }
     }
@@ -204,22 +221,16 @@ impl ApiHandler {
     async fn handle_not_found(
         &self,
         session: &mut Session,
-        _site: Option<&SiteConfig>,
+        site: Option<&SiteConfig>,
     ) -> Result<()> {
-        let response = serde_json::json!({
-            "error": "API endpoint not found",
-            "message": "The requested API endpoint does not exist",
-            "available_endpoints": [
-                "GET /api/sites",
-                "GET /api/ssl/certificates",
-                "POST /api/ssl/certificates/{domain}",
-                "GET /api/ssl/status",
-                "GET /api/config",
-                "POST /api/config/reload"
-            ]
-        });
-
-        self.send_json_response(session, 404, &response).await
+        write_json_error(
+            session,
+            404,
+            "Not Found",
+            "The requested API endpoint does not exist",
+            site,
+        )
+        .await
     }
 
     async fn send_json_response(
@@ -248,6 +259,18 @@ impl ApiHandler {
     }
 }
 
+/// Server name, BWS version and build details
+fn version_info(server: &ServerInfo) -> serde_json::Value {
+    serde_json::json!({
+        "name": server.name,
+        "version": constants::BWS_VERSION,
+        "build": {
+            "git_sha": constants::BWS_GIT_SHA,
+            "profile": if cfg!(debug_assertions) { "debug" } else { "release" },
+        }
+    })
+}
+
 impl Default for ApiHandler {
     fn default() -> Self {
         Self::new()
@@ -267,4 +290,21 @@ mod tests {
             std::mem::size_of::<ApiHandler>()
         );
     }
+
+    #[test]
+    fn test_version_info() {
+        let server = ServerInfo {
+            name: "edge-1".to_string(),
+            version: "0.1.0".to_string(),
+            description: String::new(),
+        };
+        let info = version_info(&server);
+        assert_eq!(info["name"], "edge-1");
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            info["build"]["git_sha"].as_str(),
+            option_env!("BWS_GIT_SHA")
+        );
+        assert!(info["build"]["profile"].is_string());
+    }
 }
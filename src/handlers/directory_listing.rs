@@ -0,0 +1,165 @@
+//! Directory listings for sites with `autoindex` enabled, rendered as HTML
+//! for browsers or as JSON for API clients from the same directory scan.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::Path;
+use tokio::fs;
+
+/// One entry of a directory listing
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DirectoryEntry {
+    pub name: String,
+    /// Size in bytes (0 for directories)
+    pub size: u64,
+    /// Last modification time in RFC 3339, when available
+    pub modified: Option<String>,
+    pub is_dir: bool,
+}
+
+/// List a directory, directories first and then by name. Hidden entries
+/// (starting with `.`) are left out.
+pub async fn scan_directory(dir: &Path) -> std::io::Result<Vec<DirectoryEntry>> {
+    let mut entries = Vec::new();
+    let mut read_dir = fs::read_dir(dir).await?;
+
+    while let Some(entry) = read_dir.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        // Follow symlinks so links to files and directories list as such
+        let Ok(metadata) = fs::metadata(entry.path()).await else {
+            continue;
+        };
+
+        entries.push(DirectoryEntry {
+            name,
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata
+                .modified()
+                .ok()
+                .map(|time| DateTime::<Utc>::from(time).to_rfc3339()),
+            is_dir: metadata.is_dir(),
+        });
+    }
+
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    Ok(entries)
+}
+
+/// JSON array of `{name, size, modified, is_dir}`
+pub fn render_json(entries: &[DirectoryEntry]) -> String {
+    serde_json::to_string(entries).unwrap_or_else(|_| "[]".to_string())
+}
+
+/// HTML index page for `request_path` (the directory's URL path)
+pub fn render_html(request_path: &str, entries: &[DirectoryEntry]) -> String {
+    let base = if request_path.ends_with('/') {
+        request_path.to_string()
+    } else {
+        format!("{}/", request_path)
+    };
+    let title = format!("Index of {}", escape_html(&base));
+
+    let mut rows = String::new();
+    if let Some((parent, _)) = base.trim_end_matches('/').rsplit_once('/') {
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}/\">../</a></td><td></td><td></td></tr>\n",
+            escape_html(parent)
+        ));
+    }
+    for entry in entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir {
+            "-".to_string()
+        } else {
+            entry.size.to_string()
+        };
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&base),
+            encode_path_segment(&entry.name),
+            suffix,
+            escape_html(&entry.name),
+            suffix,
+            entry.modified.as_deref().unwrap_or(""),
+            size
+        ));
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>{title}</title>
+    <style>
+        body {{ font-family: Arial, sans-serif; margin: 2em; }}
+        td {{ padding: 0.2em 1.5em 0.2em 0; }}
+    </style>
+</head>
+<body>
+    <h1>{title}</h1>
+    <table>
+<tr><th>Name</th><th>Modified</th><th>Size</th></tr>
+{rows}    </table>
+</body>
+</html>"#
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Percent-encode a file name for use as one URL path segment
+fn encode_path_segment(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_listing_html_and_json_agree() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("b file.txt"), "hello").unwrap();
+        std::fs::write(dir.path().join("a<b>.txt"), "").unwrap();
+        std::fs::write(dir.path().join(".hidden"), "").unwrap();
+        std::fs::create_dir(dir.path().join("zdir")).unwrap();
+
+        let entries = scan_directory(dir.path()).await.unwrap();
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["zdir", "a<b>.txt", "b file.txt"]);
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[2].size, 5);
+        assert!(entries[2].modified.is_some());
+
+        let json: serde_json::Value = serde_json::from_str(&render_json(&entries)).unwrap();
+        assert_eq!(json[2]["name"], "b file.txt");
+        assert_eq!(json[2]["size"], 5);
+        assert_eq!(json[0]["is_dir"], true);
+
+        let html = render_html("/files", &entries);
+        assert!(html.contains("Index of /files/"));
+        assert!(html.contains(r#"<a href="/files/zdir/">zdir/</a>"#));
+        assert!(html.contains(r#"<a href="/files/b%20file.txt">b file.txt</a>"#));
+        assert!(html.contains("a&lt;b&gt;.txt"));
+        assert!(html.contains(r#"<a href="/">../</a>"#));
+        assert!(render_html("/a/b", &entries).contains(r#"<a href="/a/">../</a>"#));
+        assert!(!render_html("/", &entries).contains("../"));
+    }
+}
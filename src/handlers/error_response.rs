@@ -0,0 +1,115 @@
+use crate::config::SiteConfig;
+use bytes::Bytes;
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+
+/// A JSON error response, `{"error": code, "message": message, "status": status}`.
+/// Every handler answers errors in this shape so clients can handle them the
+/// same way wherever they come from.
+pub struct JsonError {
+    header: ResponseHeader,
+    body: Bytes,
+}
+
+impl JsonError {
+    /// Build the response for `status`. `code` is a short, stable name for the
+    /// error (usually the reason phrase) and `message` explains it.
+    pub fn new(status: u16, code: &str, message: &str) -> Result<Self> {
+        let body = Bytes::from(json_error_body(status, code, message));
+        let mut header = ResponseHeader::build(status, Some(4))?;
+        header.insert_header("Content-Type", "application/json; charset=utf-8")?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+        Ok(Self { header, body })
+    }
+
+    /// Add the site's HSTS and custom headers
+    pub fn with_site_headers(mut self, site: Option<&SiteConfig>) -> Result<Self> {
+        if let Some(site) = site {
+            if let Some((key, value)) = site.get_hsts_header() {
+                self.header.insert_header(key, value)?;
+            }
+            for (key, value) in &site.headers {
+                self.header.insert_header(key.clone(), value.clone())?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Add a header to the response
+    pub fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Result<Self> {
+        self.header.insert_header(name, value.into())?;
+        Ok(self)
+    }
+
+    /// The response header, for headers that need more than [`Self::with_header`]
+    pub fn header_mut(&mut self) -> &mut ResponseHeader {
+        &mut self.header
+    }
+
+    /// Write the response and end it
+    pub async fn write(self, session: &mut Session) -> Result<()> {
+        session
+            .write_response_header(Box::new(self.header), false)
+            .await?;
+        session.write_response_body(Some(self.body), true).await?;
+        Ok(())
+    }
+}
+
+/// Answer with a JSON error carrying the site's headers
+pub async fn write_json_error(
+    session: &mut Session,
+    status: u16,
+    code: &str,
+    message: &str,
+    site: Option<&SiteConfig>,
+) -> Result<()> {
+    JsonError::new(status, code, message)?
+        .with_site_headers(site)?
+        .write(session)
+        .await
+}
+
+fn json_error_body(status: u16, code: &str, message: &str) -> String {
+    serde_json::json!({
+        "error": code,
+        "message": message,
+        "status": status
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_error_shape() {
+        let body: serde_json::Value = serde_json::from_str(&json_error_body(
+            502,
+            "Bad Gateway",
+            "upstream \"a\" failed",
+        ))
+        .unwrap();
+        assert_eq!(body["error"], "Bad Gateway");
+        assert_eq!(body["message"], "upstream \"a\" failed");
+        assert_eq!(body["status"], 502);
+        assert_eq!(body.as_object().unwrap().len(), 3);
+
+        let error = JsonError::new(502, "Bad Gateway", "upstream failed")
+            .unwrap()
+            .with_header("Retry-After", "5")
+            .unwrap();
+        let header = &error.header;
+        assert_eq!(header.status.as_u16(), 502);
+        assert_eq!(
+            header.headers.get("Content-Type").unwrap(),
+            "application/json; charset=utf-8"
+        );
+        assert_eq!(
+            header.headers.get("Content-Length").unwrap(),
+            &error.body.len().to_string()
+        );
+        assert_eq!(header.headers.get("Retry-After").unwrap(), "5");
+    }
+}
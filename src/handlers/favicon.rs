@@ -0,0 +1,167 @@
+use crate::config::SiteConfig;
+use bytes::Bytes;
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Path browsers request the site icon from
+pub const FAVICON_PATH: &str = "/favicon.ico";
+
+/// Icon served when a site configures none of its own
+const DEFAULT_FAVICON: &[u8] = include_bytes!("../../assets/favicon.ico");
+
+/// Icons change rarely and browsers ask for them on every page
+const FAVICON_CACHE_CONTROL: &str = "public, max-age=604800";
+
+/// Answers `/favicon.ico` for sites that have no icon, so browsers stop
+/// filling the logs with 404s. Icon files are read once and kept in memory.
+pub struct FaviconHandler {
+    files: Mutex<HashMap<String, Bytes>>,
+}
+
+impl FaviconHandler {
+    /// Create a new FaviconHandler
+    pub fn new() -> Self {
+        Self {
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Forget the icon files read so far, so edited files are read again
+    pub fn clear_cache(&self) {
+        if let Ok(mut files) = self.files.lock() {
+            files.clear();
+        }
+    }
+
+    /// Whether the site leaves `/favicon.ico` to this handler: the icon is
+    /// enabled, the site is not API-only, and neither a file in `static_dir`
+    /// nor a proxy route serves it
+    pub fn serves(site: &SiteConfig) -> bool {
+        if !site.favicon.enabled || site.api_only {
+            return false;
+        }
+        let proxied = site.proxy.enabled
+            && site
+                .proxy
+                .routes
+                .iter()
+                .any(|route| FAVICON_PATH.starts_with(&route.path));
+        !proxied && !Path::new(&site.static_dir).join("favicon.ico").is_file()
+    }
+
+    /// The configured icon file's contents, or the built-in icon when none is
+    /// set or it cannot be read
+    async fn icon(&self, site: &SiteConfig) -> Bytes {
+        let Some(file) = &site.favicon.file else {
+            return Bytes::from_static(DEFAULT_FAVICON);
+        };
+        if let Some(icon) = self.files.lock().ok().and_then(|f| f.get(file).cloned()) {
+            return icon;
+        }
+
+        match tokio::fs::read(file).await {
+            Ok(content) => {
+                let icon = Bytes::from(content);
+                if let Ok(mut files) = self.files.lock() {
+                    files.insert(file.clone(), icon.clone());
+                }
+                icon
+            }
+            Err(e) => {
+                log::warn!("Failed to read favicon {}: {}", file, e);
+                Bytes::from_static(DEFAULT_FAVICON)
+            }
+        }
+    }
+
+    /// Serve the icon for a `GET` or `HEAD` of `/favicon.ico`. Returns
+    /// `Ok(false)` when the site serves its own icon or has this disabled.
+    pub async fn handle(&self, session: &mut Session, site: &SiteConfig) -> Result<bool> {
+        let method = &session.req_header().method;
+        if !(method == "GET" || method == "HEAD") || !Self::serves(site) {
+            return Ok(false);
+        }
+
+        let icon = self.icon(site).await;
+        let mut header = ResponseHeader::build(200, Some(4))?;
+        header.insert_header("Content-Type", "image/x-icon")?;
+        header.insert_header("Content-Length", icon.len().to_string())?;
+        header.insert_header("Cache-Control", FAVICON_CACHE_CONTROL)?;
+        if let Some((key, value)) = site.get_hsts_header() {
+            header.insert_header(key, value)?;
+        }
+        for (key, value) in &site.headers {
+            header.insert_header(key.clone(), value.clone())?;
+        }
+
+        let is_head = session.req_header().method == "HEAD";
+        session
+            .write_response_header(Box::new(header), is_head)
+            .await?;
+        if !is_head {
+            session.write_response_body(Some(icon), true).await?;
+        }
+
+        Ok(true)
+    }
+}
+
+impl Default for FaviconHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_favicon_only_for_sites_without_one() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut site: SiteConfig = toml::from_str(&format!(
+            r#"
+            name = "test"
+            hostname = "example.com"
+            port = 8080
+            static_dir = "{}"
+            "#,
+            temp_dir.path().display()
+        ))
+        .unwrap();
+
+        // The built-in icon is a 16x16 ICO
+        assert!(FaviconHandler::serves(&site));
+        assert_eq!(&DEFAULT_FAVICON[..4], &[0, 0, 1, 0]);
+        assert_eq!(DEFAULT_FAVICON[6], 16);
+
+        let handler = FaviconHandler::new();
+        let custom = temp_dir.path().join("brand.ico");
+        std::fs::write(&custom, b"custom").unwrap();
+        site.favicon.file = Some(custom.to_str().unwrap().to_string());
+        assert_eq!(handler.icon(&site).await, "custom");
+
+        // Read once, until the cache is cleared
+        std::fs::write(&custom, b"edited").unwrap();
+        assert_eq!(handler.icon(&site).await, "custom");
+        handler.clear_cache();
+        assert_eq!(handler.icon(&site).await, "edited");
+
+        site.favicon.file = Some("/nonexistent/brand.ico".to_string());
+        assert_eq!(handler.icon(&site).await, DEFAULT_FAVICON);
+
+        site.favicon.enabled = false;
+        assert!(!FaviconHandler::serves(&site));
+        site.favicon.enabled = true;
+        site.api_only = true;
+        assert!(!FaviconHandler::serves(&site));
+        site.api_only = false;
+
+        // The site's own icon is served as a static file
+        std::fs::write(temp_dir.path().join("favicon.ico"), b"own").unwrap();
+        assert!(!FaviconHandler::serves(&site));
+    }
+}
@@ -0,0 +1,204 @@
+//! In-memory cache of small static files, so frequently requested files are
+//! served without touching the disk. Entries are validated against the file's
+//! modification time and size on every lookup, and the least recently used
+//! entry is evicted when the cache is full.
+
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// A cached file's content and its strong ETag
+#[derive(Debug, Clone)]
+pub struct CachedFile {
+    pub content: Bytes,
+    pub etag: String,
+}
+
+struct Entry {
+    file: CachedFile,
+    modified: Option<SystemTime>,
+    len: u64,
+    last_used: u64,
+}
+
+impl Entry {
+    fn is_fresh(&self, metadata: &Metadata) -> bool {
+        self.len == metadata.len() && self.modified == metadata.modified().ok()
+    }
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, Entry>,
+    clock: u64,
+}
+
+/// LRU cache of file contents keyed by resolved file path
+pub struct FileCache {
+    capacity: usize,
+    max_file_size: u64,
+    state: Mutex<CacheState>,
+}
+
+impl FileCache {
+    /// A cache of up to `capacity` files of at most `max_file_size` bytes each
+    pub fn new(capacity: usize, max_file_size: usize) -> Self {
+        Self {
+            capacity,
+            max_file_size: max_file_size as u64,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Whether a file of this size is small enough to be cached
+    pub fn accepts(&self, len: u64) -> bool {
+        self.capacity > 0 && len <= self.max_file_size
+    }
+
+    /// Look up a file, dropping the entry if the file changed since it was cached
+    pub fn get(&self, path: &str, metadata: &Metadata) -> Option<CachedFile> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.clock += 1;
+        let clock = state.clock;
+
+        match state.entries.get_mut(path) {
+            Some(entry) if entry.is_fresh(metadata) => {
+                entry.last_used = clock;
+                Some(entry.file.clone())
+            }
+            Some(_) => {
+                state.entries.remove(path);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Cache a file read from disk, evicting the least recently used entry
+    /// when full, and return it with its ETag
+    pub fn insert(&self, path: &str, metadata: &Metadata, content: Bytes) -> CachedFile {
+        let file = CachedFile {
+            etag: compute_etag(&content),
+            content,
+        };
+        if !self.accepts(file.content.len() as u64) {
+            return file;
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.clock += 1;
+        let clock = state.clock;
+
+        if !state.entries.contains_key(path) && state.entries.len() >= self.capacity {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+
+        state.entries.insert(
+            path.to_string(),
+            Entry {
+                file: file.clone(),
+                modified: metadata.modified().ok(),
+                len: metadata.len(),
+                last_used: clock,
+            },
+        );
+        file
+    }
+
+    /// Drop every cached file, returning how many were dropped
+    pub fn clear(&self) -> usize {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let dropped = state.entries.len();
+        state.entries.clear();
+        dropped
+    }
+}
+
+/// Strong ETag from a hash of the file's content
+pub fn compute_etag(content: &[u8]) -> String {
+    let digest = Sha256::digest(content);
+    let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn metadata(path: &std::path::Path) -> Metadata {
+        std::fs::metadata(path).unwrap()
+    }
+
+    #[test]
+    fn test_cache_hits_and_invalidates_on_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.html");
+        std::fs::write(&path, "first").unwrap();
+        let key = path.to_str().unwrap();
+
+        let cache = FileCache::new(4, 1024);
+        assert!(cache.get(key, &metadata(&path)).is_none());
+        let inserted = cache.insert(key, &metadata(&path), Bytes::from("first"));
+        assert_eq!(inserted.etag, compute_etag(b"first"));
+
+        let hit = cache.get(key, &metadata(&path)).unwrap();
+        assert_eq!(hit.content, "first");
+        assert_eq!(hit.etag, inserted.etag);
+
+        // A rewrite with a new mtime makes the entry stale
+        std::fs::write(&path, "second").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert!(cache.get(key, &metadata(&path)).is_none());
+        assert_eq!(cache.clear(), 0);
+
+        cache.insert(key, &metadata(&path), Bytes::from("second"));
+        assert_eq!(cache.clear(), 1);
+        assert!(cache.get(key, &metadata(&path)).is_none());
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, name).unwrap();
+                path
+            })
+            .collect();
+        let keys: Vec<&str> = paths.iter().map(|p| p.to_str().unwrap()).collect();
+
+        let cache = FileCache::new(2, 1024);
+        cache.insert(keys[0], &metadata(&paths[0]), Bytes::from("a"));
+        cache.insert(keys[1], &metadata(&paths[1]), Bytes::from("b"));
+        // Touch "a" so "b" is the eviction candidate
+        assert!(cache.get(keys[0], &metadata(&paths[0])).is_some());
+        cache.insert(keys[2], &metadata(&paths[2]), Bytes::from("c"));
+
+        assert!(cache.get(keys[0], &metadata(&paths[0])).is_some());
+        assert!(cache.get(keys[1], &metadata(&paths[1])).is_none());
+        assert!(cache.get(keys[2], &metadata(&paths[2])).is_some());
+        assert_eq!(cache.clear(), 2);
+
+        // Files over the size limit are never cached
+        let small = FileCache::new(2, 1);
+        assert!(!small.accepts(2));
+        small.insert(keys[0], &metadata(&paths[0]), Bytes::from("aa"));
+        assert_eq!(small.clear(), 0);
+        assert!(!FileCache::new(0, 1024).accepts(1));
+    }
+}
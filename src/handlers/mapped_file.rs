@@ -0,0 +1,56 @@
+//! Read-only memory maps of static files, so their bytes are served straight
+//! from the OS page cache instead of being copied into a new buffer per request.
+
+use memmap2::{Mmap, MmapOptions};
+use std::fs::File;
+
+/// A file mapped read-only into memory, unmapped on drop.
+///
+/// The mapping follows the file on disk: if the file is truncated while
+/// mapped, reading past its new end raises SIGBUS and kills the process, and
+/// a file rewritten in place changes under readers. Only map files that are
+/// replaced by renaming.
+pub struct MappedFile(Mmap);
+
+impl MappedFile {
+    /// Map the first `len` bytes of `file`. Empty files cannot be mapped.
+    pub fn map(file: &File, len: usize) -> std::io::Result<Self> {
+        if len == 0 {
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+
+        // SAFETY: mapping a file is only unsound if it changes while mapped;
+        // static files are served on the condition documented above, which
+        // `performance.mmap_static_files` states as well
+        let map = unsafe { MmapOptions::new().len(len).map(file)? };
+        Ok(Self(map))
+    }
+}
+
+impl AsRef<[u8]> for MappedFile {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_file_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(&path, b"mapped bytes").unwrap();
+
+        let file = File::open(&path).unwrap();
+        let mapped = MappedFile::map(&file, 12).unwrap();
+        assert_eq!(mapped.as_ref(), b"mapped bytes");
+
+        // Bytes can own the mapping without copying it
+        let bytes = bytes::Bytes::from_owner(mapped);
+        assert_eq!(&bytes[..6], b"mapped");
+
+        assert!(MappedFile::map(&file, 0).is_err());
+    }
+}
@@ -4,12 +4,29 @@
 //! of content and functionality.
 
 pub mod api_handler;
+pub mod directory_listing;
+pub mod error_response;
+pub mod favicon;
+mod file_cache;
+mod mapped_file;
+pub mod proxy_cache;
 pub mod proxy_handler;
 pub mod static_handler;
+pub mod upstream_balance;
+pub mod upstream_health;
+pub mod upstream_pool;
 pub mod websocket_proxy;
+pub mod well_known;
 
 // Re-export handler types
 pub use api_handler::ApiHandler;
+pub use error_response::{write_json_error, JsonError};
+pub use favicon::FaviconHandler;
+pub use proxy_cache::ProxyCache;
 pub use proxy_handler::ProxyHandler;
 pub use static_handler::StaticFileHandler;
+pub use upstream_balance::UpstreamBalancer;
+pub use upstream_health::UpstreamHealth;
+pub use upstream_pool::UpstreamPools;
 pub use websocket_proxy::WebSocketProxyHandler;
+pub use well_known::WellKnownHandler;
@@ -0,0 +1,735 @@
+//! Shared cache of proxied responses
+//!
+//! Whether and for how long an upstream response is kept follows its
+//! `Cache-Control`, `Expires` and `Vary` headers, the way a shared HTTP cache
+//! handles them (RFC 9111). Only complete responses to `GET` are cached. Each
+//! site has its own entries, and its least recently used entry is evicted
+//! when it holds `max_entries`. Within its `stale-while-revalidate` window an
+//! expired response is still served while a single refresh replaces it
+//! (RFC 5861). Requests missing the same key while it is being fetched wait
+//! for that fetch instead of all going upstream.
+
+use crate::config::site::ProxyCacheConfig;
+use crate::core::utils::http::parse_http_date;
+use bytes::Bytes;
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use pingora::http::RequestHeader;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+
+/// Statuses a shared cache may store (RFC 9110, section 15.1)
+const CACHEABLE_STATUSES: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// The `Cache-Control` directives that decide whether and for how long a
+/// response is cached
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CacheDirectives {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub private: bool,
+    pub public: bool,
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
+    pub stale_while_revalidate: Option<u64>,
+}
+
+impl CacheDirectives {
+    /// Parse a `Cache-Control` header value, ignoring unknown directives
+    pub fn parse(value: &str) -> Self {
+        let mut directives = Self::default();
+        for directive in value.split(',') {
+            let (name, argument) = match directive.split_once('=') {
+                Some((name, argument)) => (name, Some(argument.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+            let seconds = argument.and_then(|argument| argument.parse().ok());
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => directives.no_store = true,
+                "no-cache" => directives.no_cache = true,
+                "private" => directives.private = true,
+                "public" => directives.public = true,
+                "max-age" => directives.max_age = seconds,
+                "s-maxage" => directives.s_maxage = seconds,
+                "stale-while-revalidate" => directives.stale_while_revalidate = seconds,
+                _ => {}
+            }
+        }
+        directives
+    }
+}
+
+/// How long an upstream response may be served from the cache, or `None`
+/// when it must not be stored. `s-maxage` wins over `max-age`, which wins
+/// over `Expires`; `default_ttl` applies when none is given. Responses to
+/// requests with `Authorization` are only stored when marked `public` or
+/// with `s-maxage`.
+pub fn freshness_lifetime(
+    status: u16,
+    headers: &HashMap<String, String>,
+    authorized: bool,
+    default_ttl: u64,
+) -> Option<Duration> {
+    if !CACHEABLE_STATUSES.contains(&status) || headers.contains_key("set-cookie") {
+        return None;
+    }
+    let directives = headers
+        .get("cache-control")
+        .map(|value| CacheDirectives::parse(value))
+        .unwrap_or_default();
+    if directives.no_store || directives.no_cache || directives.private {
+        return None;
+    }
+    if authorized && !directives.public && directives.s_maxage.is_none() {
+        return None;
+    }
+    if headers
+        .get("vary")
+        .is_some_and(|vary| vary.split(',').any(|name| name.trim() == "*"))
+    {
+        return None;
+    }
+
+    let lifetime = match directives.s_maxage.or(directives.max_age) {
+        Some(seconds) => seconds,
+        // An invalid Expires, such as "0", means already expired
+        None => match headers.get("expires") {
+            Some(expires) => {
+                let expires = parse_http_date(expires)?;
+                let date = headers
+                    .get("date")
+                    .and_then(|date| parse_http_date(date))
+                    .unwrap_or_else(unix_now);
+                u64::try_from(expires - date).ok()?
+            }
+            None => default_ttl,
+        },
+    };
+
+    // Time already spent in caches upstream counts against the lifetime
+    let age = headers
+        .get("age")
+        .and_then(|age| age.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    let remaining = lifetime.saturating_sub(age);
+    (remaining > 0).then(|| Duration::from_secs(remaining))
+}
+
+/// A response to keep for later requests, or `None` when the upstream's
+/// headers forbid it or its body is over `config.max_size`
+pub fn cacheable(
+    status: u16,
+    headers: &HashMap<String, String>,
+    body: Bytes,
+    authorized: bool,
+    config: &ProxyCacheConfig,
+) -> Option<CachedResponse> {
+    if body.len() > config.max_size_bytes() {
+        return None;
+    }
+    let lifetime = freshness_lifetime(status, headers, authorized, config.default_ttl)?;
+    // How long it may be served stale while being refreshed
+    let stale_window = headers
+        .get("cache-control")
+        .and_then(|value| CacheDirectives::parse(value).stale_while_revalidate)
+        .unwrap_or(config.stale_while_revalidate);
+
+    let mut headers = headers.clone();
+    headers.retain(|name, _| !matches!(name.as_str(), "connection" | "keep-alive"));
+    Some(
+        CachedResponse::new(status, headers, body, lifetime)
+            .with_stale_window(Duration::from_secs(stale_window)),
+    )
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+/// A cached upstream response
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+    pub body: Bytes,
+    stored: Instant,
+    lifetime: Duration,
+    stale_window: Duration,
+}
+
+impl CachedResponse {
+    /// A response stored now, fresh for `lifetime`
+    pub fn new(
+        status: u16,
+        headers: HashMap<String, String>,
+        body: Bytes,
+        lifetime: Duration,
+    ) -> Self {
+        Self {
+            status,
+            headers,
+            body,
+            stored: Instant::now(),
+            lifetime,
+            stale_window: Duration::ZERO,
+        }
+    }
+
+    /// Keep serving the response for `window` after it expires, while it is
+    /// refreshed
+    pub fn with_stale_window(mut self, window: Duration) -> Self {
+        self.stale_window = window;
+        self
+    }
+
+    /// Seconds since the response was stored, for the `Age` header
+    pub fn age(&self) -> u64 {
+        self.stored.elapsed().as_secs()
+    }
+
+    /// Whether the response expired and is only served while it is refreshed
+    pub fn is_stale(&self) -> bool {
+        self.stored.elapsed() >= self.lifetime
+    }
+
+    fn is_usable(&self) -> bool {
+        self.stored.elapsed() < self.lifetime + self.stale_window
+    }
+}
+
+/// A stored response and the request header values it was selected by
+struct Variant {
+    vary: Vec<(String, Option<String>)>,
+    response: CachedResponse,
+    last_used: u64,
+    /// A refresh of the stale response is under way
+    refreshing: bool,
+}
+
+impl Variant {
+    fn matches(&self, request: &RequestHeader) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| header_value(request, name) == *value)
+    }
+}
+
+/// One site's entries, keyed by host and request target
+#[derive(Default)]
+struct Partition {
+    entries: HashMap<String, Vec<Variant>>,
+    clock: u64,
+}
+
+impl Partition {
+    fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .flat_map(|(key, variants)| {
+                variants
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, variant)| (variant.last_used, key, index))
+            })
+            .min()
+            .map(|(_, key, index)| (key.clone(), index));
+        if let Some((key, index)) = oldest {
+            if let Some(variants) = self.entries.get_mut(&key) {
+                variants.remove(index);
+                if variants.is_empty() {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+/// Completes when the upstream fetch of a key ends, however it ends
+type InFlight = Shared<BoxFuture<'static, ()>>;
+
+/// Outcome of looking a request up in the cache
+pub enum Lookup<'a> {
+    /// A stored response, fresh or within its stale window
+    Hit(CachedResponse),
+    /// Nothing to serve; the request goes upstream
+    Miss(Fetch<'a>),
+}
+
+/// An upstream fetch of a key that was missed. Requests for the same key
+/// wait until it is dropped, then look the key up again.
+pub struct Fetch<'a> {
+    cache: &'a ProxyCache,
+    /// Set when this fetch is the one others wait for
+    leader: Option<(String, oneshot::Sender<()>)>,
+}
+
+impl Drop for Fetch<'_> {
+    fn drop(&mut self) {
+        if let Some((key, done)) = self.leader.take() {
+            self.cache
+                .in_flight
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(&key);
+            drop(done);
+        }
+    }
+}
+
+/// Cached upstream responses of all sites
+#[derive(Default)]
+pub struct ProxyCache {
+    sites: Mutex<HashMap<String, Partition>>,
+    /// Upstream fetches under way, by site and key
+    in_flight: Mutex<HashMap<String, InFlight>>,
+}
+
+impl ProxyCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cache key of a request: its host and target
+    pub fn key(request: &RequestHeader) -> String {
+        let host = header_value(request, "host").unwrap_or_default();
+        format!("{} {}", host.to_ascii_lowercase(), request.uri)
+    }
+
+    /// A response stored for `key` whose `Vary` headers match the request,
+    /// fresh or within its stale window
+    pub fn get(&self, site: &str, key: &str, request: &RequestHeader) -> Option<CachedResponse> {
+        let mut sites = self.sites.lock().unwrap_or_else(|e| e.into_inner());
+        let partition = sites.get_mut(site)?;
+        partition.clock += 1;
+        let clock = partition.clock;
+
+        let variants = partition.entries.get_mut(key)?;
+        variants.retain(|variant| variant.response.is_usable());
+        let found = variants
+            .iter_mut()
+            .find(|variant| variant.matches(request))
+            .map(|variant| {
+                variant.last_used = clock;
+                variant.response.clone()
+            });
+        if variants.is_empty() {
+            partition.entries.remove(key);
+        }
+        found
+    }
+
+    /// Store a response for `key`, replacing the variant with the same `Vary`
+    /// header values, and evicting the least recently used entries of the
+    /// site beyond `config.max_entries`
+    pub fn insert(
+        &self,
+        site: &str,
+        key: &str,
+        request: &RequestHeader,
+        response: CachedResponse,
+        config: &ProxyCacheConfig,
+    ) {
+        let mut vary: Vec<(String, Option<String>)> = response
+            .headers
+            .get("vary")
+            .map(|vary| {
+                vary.split(',')
+                    .map(|name| name.trim().to_ascii_lowercase())
+                    .filter(|name| !name.is_empty())
+                    .map(|name| {
+                        let value = header_value(request, &name);
+                        (name, value)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        // An encoded body only suits clients accepting that encoding, even
+        // when the upstream does not say so
+        if response.headers.contains_key("content-encoding")
+            && !vary.iter().any(|(name, _)| name == "accept-encoding")
+        {
+            let value = header_value(request, "accept-encoding");
+            vary.push(("accept-encoding".to_string(), value));
+        }
+
+        let mut sites = self.sites.lock().unwrap_or_else(|e| e.into_inner());
+        let partition = sites.entry(site.to_string()).or_default();
+        partition.clock += 1;
+        let variant = Variant {
+            vary,
+            response,
+            last_used: partition.clock,
+            refreshing: false,
+        };
+
+        let variants = partition.entries.entry(key.to_string()).or_default();
+        variants.retain(|existing| existing.vary != variant.vary);
+        variants.push(variant);
+        while partition.len() > config.max_entries.max(1) {
+            partition.evict_oldest();
+        }
+    }
+
+    /// The response stored for `key`, or the fetch the request has to make.
+    /// While another request fetches the same key this waits for it, so
+    /// simultaneous misses reach the upstream once.
+    pub async fn lookup(&self, site: &str, key: &str, request: &RequestHeader) -> Lookup<'_> {
+        if let Some(response) = self.get(site, key, request) {
+            return Lookup::Hit(response);
+        }
+
+        let in_flight_key = format!("{} {}", site, key);
+        let pending = {
+            let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+            match in_flight.get(&in_flight_key) {
+                Some(pending) => pending.clone(),
+                None => {
+                    let (done, finished) = oneshot::channel::<()>();
+                    in_flight.insert(in_flight_key.clone(), finished.map(|_| ()).boxed().shared());
+                    return Lookup::Miss(Fetch {
+                        cache: self,
+                        leader: Some((in_flight_key, done)),
+                    });
+                }
+            }
+        };
+
+        pending.await;
+        // The fetch may have left nothing to serve, e.g. an uncacheable or
+        // differently varying response; then this request goes upstream too
+        match self.get(site, key, request) {
+            Some(response) => Lookup::Hit(response),
+            None => Lookup::Miss(Fetch {
+                cache: self,
+                leader: None,
+            }),
+        }
+    }
+
+    /// Claim the refresh of the response stored for `key` and the request.
+    /// Returns false when another request already claimed it.
+    pub fn start_refresh(&self, site: &str, key: &str, request: &RequestHeader) -> bool {
+        self.with_variant(site, key, request, |variants, index| {
+            !std::mem::replace(&mut variants[index].refreshing, true)
+        })
+        .unwrap_or(false)
+    }
+
+    /// Give up a refresh that failed, so a later request can try again
+    pub fn end_refresh(&self, site: &str, key: &str, request: &RequestHeader) {
+        self.with_variant(site, key, request, |variants, index| {
+            variants[index].refreshing = false;
+        });
+    }
+
+    /// Drop the response stored for `key` and the request, e.g. when the
+    /// upstream no longer allows caching it
+    pub fn remove(&self, site: &str, key: &str, request: &RequestHeader) {
+        self.with_variant(site, key, request, |variants, index| {
+            variants.remove(index);
+        });
+    }
+
+    fn with_variant<T>(
+        &self,
+        site: &str,
+        key: &str,
+        request: &RequestHeader,
+        f: impl FnOnce(&mut Vec<Variant>, usize) -> T,
+    ) -> Option<T> {
+        let mut sites = self.sites.lock().unwrap_or_else(|e| e.into_inner());
+        let partition = sites.get_mut(site)?;
+        let variants = partition.entries.get_mut(key)?;
+        let index = variants
+            .iter()
+            .position(|variant| variant.matches(request))?;
+        let result = f(variants, index);
+        if variants.is_empty() {
+            partition.entries.remove(key);
+        }
+        Some(result)
+    }
+
+    /// Drop every cached response, returning how many were dropped
+    pub fn clear(&self) -> usize {
+        let mut sites = self.sites.lock().unwrap_or_else(|e| e.into_inner());
+        let dropped = sites.values().map(Partition::len).sum();
+        sites.clear();
+        dropped
+    }
+}
+
+/// A request header's values, joined as one
+fn header_value(request: &RequestHeader, name: &str) -> Option<String> {
+    let values: Vec<&str> = request
+        .headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    (!values.is_empty()).then(|| values.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn lifetime(pairs: &[(&str, &str)]) -> Option<u64> {
+        freshness_lifetime(200, &headers(pairs), false, 0).map(|ttl| ttl.as_secs())
+    }
+
+    #[test]
+    fn test_cache_control_directives() {
+        assert_eq!(lifetime(&[("cache-control", "max-age=60")]), Some(60));
+        // s-maxage is meant for shared caches and wins over max-age
+        assert_eq!(
+            lifetime(&[("cache-control", "public, max-age=60, s-maxage=300")]),
+            Some(300)
+        );
+        assert_eq!(lifetime(&[("cache-control", "max-age=60, no-store")]), None);
+        assert_eq!(lifetime(&[("cache-control", "private, max-age=60")]), None);
+        assert_eq!(lifetime(&[("cache-control", "no-cache, max-age=60")]), None);
+        assert_eq!(lifetime(&[("cache-control", "max-age=0")]), None);
+        // Time spent in other caches counts
+        assert_eq!(
+            lifetime(&[("cache-control", "max-age=60"), ("age", "45")]),
+            Some(15)
+        );
+
+        // Expires counts from the upstream's Date
+        assert_eq!(
+            lifetime(&[
+                ("date", "Wed, 21 Oct 2026 07:28:00 GMT"),
+                ("expires", "Wed, 21 Oct 2026 07:30:00 GMT"),
+            ]),
+            Some(120)
+        );
+        assert_eq!(
+            lifetime(&[
+                ("cache-control", "max-age=10"),
+                ("expires", "Wed, 21 Oct 2026 07:30:00 GMT"),
+            ]),
+            Some(10)
+        );
+        assert_eq!(lifetime(&[("expires", "0")]), None);
+
+        // Without freshness information only the configured default applies
+        assert_eq!(lifetime(&[]), None);
+        assert_eq!(
+            freshness_lifetime(200, &headers(&[]), false, 30),
+            Some(Duration::from_secs(30))
+        );
+
+        // Personal or uncacheable responses are never stored
+        let public = [("cache-control", "max-age=60")];
+        assert_eq!(freshness_lifetime(500, &headers(&public), false, 0), None);
+        assert_eq!(freshness_lifetime(200, &headers(&public), true, 0), None);
+        assert!(freshness_lifetime(
+            200,
+            &headers(&[("cache-control", "public, max-age=60")]),
+            true,
+            0
+        )
+        .is_some());
+        assert_eq!(
+            lifetime(&[("cache-control", "max-age=60"), ("set-cookie", "id=1")]),
+            None
+        );
+        assert_eq!(
+            lifetime(&[("cache-control", "max-age=60"), ("vary", "*")]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_vary_selects_variants() {
+        let config = ProxyCacheConfig {
+            enabled: true,
+            max_entries: 2,
+            ..Default::default()
+        };
+        let request = |language: &str| {
+            let mut request = RequestHeader::build("GET", b"/page?id=1", None).unwrap();
+            request.insert_header("Host", "example.com").unwrap();
+            request.insert_header("Accept-Language", language).unwrap();
+            request
+        };
+        let response = |body: &'static str| {
+            CachedResponse::new(
+                200,
+                headers(&[("vary", "Accept-Language")]),
+                Bytes::from_static(body.as_bytes()),
+                Duration::from_secs(60),
+            )
+        };
+
+        let cache = ProxyCache::new();
+        let (en, de) = (request("en"), request("de"));
+        let key = ProxyCache::key(&en);
+        assert_eq!(key, "example.com /page?id=1");
+        cache.insert("site", &key, &en, response("hello"), &config);
+        assert_eq!(cache.get("site", &key, &en).unwrap().body, "hello");
+        assert!(cache.get("site", &key, &de).is_none());
+        assert!(cache.get("other", &key, &en).is_none());
+
+        cache.insert("site", &key, &de, response("hallo"), &config);
+        assert_eq!(cache.get("site", &key, &de).unwrap().body, "hallo");
+        assert_eq!(cache.get("site", &key, &en).unwrap().body, "hello");
+
+        // A third entry evicts the least recently used one
+        let fr = request("fr");
+        cache.insert("site", &key, &fr, response("bonjour"), &config);
+        assert!(cache.get("site", &key, &de).is_none());
+        assert_eq!(cache.clear(), 2);
+
+        // Expired responses are not served, and oversized ones not stored
+        let expired = CachedResponse::new(200, HashMap::new(), Bytes::new(), Duration::ZERO);
+        cache.insert("site", &key, &en, expired, &config);
+        assert!(cache.get("site", &key, &en).is_none());
+        let public = headers(&[("cache-control", "max-age=60")]);
+        assert!(cacheable(200, &public, Bytes::from_static(b"hello"), false, &config).is_some());
+        let small = ProxyCacheConfig {
+            max_size: "4B".to_string(),
+            ..config
+        };
+        assert!(cacheable(200, &public, Bytes::from_static(b"hello"), false, &small).is_none());
+    }
+
+    #[test]
+    fn test_stale_while_revalidate() {
+        let config = ProxyCacheConfig {
+            enabled: true,
+            stale_while_revalidate: 30,
+            ..Default::default()
+        };
+        let mut request = RequestHeader::build("GET", b"/slow", None).unwrap();
+        request.insert_header("Host", "example.com").unwrap();
+        let key = ProxyCache::key(&request);
+
+        // The upstream's directive wins over the configured window
+        let stale = |pairs: &[(&str, &str)]| {
+            let response = cacheable(200, &headers(pairs), Bytes::new(), false, &config).unwrap();
+            response.stale_window.as_secs()
+        };
+        assert_eq!(stale(&[("cache-control", "max-age=60")]), 30);
+        assert_eq!(
+            stale(&[("cache-control", "max-age=60, stale-while-revalidate=120")]),
+            120
+        );
+
+        let cache = ProxyCache::new();
+        let expired = CachedResponse::new(
+            200,
+            HashMap::new(),
+            Bytes::from_static(b"old"),
+            Duration::ZERO,
+        )
+        .with_stale_window(Duration::from_secs(60));
+        cache.insert("site", &key, &request, expired, &config);
+
+        // While a slow refresh is under way, every request gets the stale copy
+        // and none starts another refresh
+        let served = cache.get("site", &key, &request).unwrap();
+        assert!(served.is_stale());
+        assert_eq!(served.body, "old");
+        assert!(cache.start_refresh("site", &key, &request));
+        for _ in 0..3 {
+            assert_eq!(cache.get("site", &key, &request).unwrap().body, "old");
+            assert!(!cache.start_refresh("site", &key, &request));
+        }
+
+        // A failed refresh lets the next request try again
+        cache.end_refresh("site", &key, &request);
+        assert!(cache.start_refresh("site", &key, &request));
+
+        // The refreshed response replaces the stale one
+        let fresh = CachedResponse::new(
+            200,
+            HashMap::new(),
+            Bytes::from_static(b"new"),
+            Duration::from_secs(60),
+        );
+        cache.insert("site", &key, &request, fresh, &config);
+        let served = cache.get("site", &key, &request).unwrap();
+        assert!(!served.is_stale());
+        assert_eq!(served.body, "new");
+        assert!(cache.start_refresh("site", &key, &request));
+
+        // An upstream that no longer allows caching drops the entry
+        cache.remove("site", &key, &request);
+        assert!(cache.get("site", &key, &request).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_misses_coalesce() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let mut request = RequestHeader::build("GET", b"/hot", None).unwrap();
+        request.insert_header("Host", "example.com").unwrap();
+        let key = ProxyCache::key(&request);
+        let cache = Arc::new(ProxyCache::new());
+        let upstream_hits = Arc::new(AtomicUsize::new(0));
+
+        // Each client either gets the cached response or fetches it from a
+        // slow upstream, storing it when `store` is set
+        let clients = |store: bool| {
+            (0..10)
+                .map(|_| {
+                    let (cache, upstream_hits) = (cache.clone(), upstream_hits.clone());
+                    let (request, key) = (request.clone(), key.clone());
+                    tokio::spawn(async move {
+                        match cache.lookup("site", &key, &request).await {
+                            Lookup::Hit(response) => response.body,
+                            Lookup::Miss(_fetch) => {
+                                upstream_hits.fetch_add(1, Ordering::SeqCst);
+                                tokio::time::sleep(Duration::from_millis(50)).await;
+                                let body = Bytes::from_static(b"hello");
+                                if store {
+                                    let response = CachedResponse::new(
+                                        200,
+                                        HashMap::new(),
+                                        body.clone(),
+                                        Duration::from_secs(60),
+                                    );
+                                    let config = ProxyCacheConfig::default();
+                                    cache.insert("site", &key, &request, response, &config);
+                                }
+                                body
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for client in clients(true) {
+            assert_eq!(client.await.unwrap(), "hello");
+        }
+        assert_eq!(upstream_hits.load(Ordering::SeqCst), 1);
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+
+        // Without a response to share, the waiting clients go upstream too
+        cache.clear();
+        upstream_hits.store(0, Ordering::SeqCst);
+        for client in clients(false) {
+            assert_eq!(client.await.unwrap(), "hello");
+        }
+        assert_eq!(upstream_hits.load(Ordering::SeqCst), 10);
+    }
+}
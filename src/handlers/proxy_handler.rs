@@ -1,35 +1,96 @@
 use crate::config::site::{ProxyConfig, ProxyRoute, SiteConfig, UpstreamConfig};
+use crate::core::deadline::{reading_body, response_started};
+use crate::core::utils::http::normalize_path;
+use crate::core::utils::net::TrustedProxies;
+use crate::core::LoadBalancingStrategy;
+use crate::handlers::error_response::write_json_error;
+use crate::handlers::proxy_cache::{
+    cacheable, freshness_lifetime, CacheDirectives, CachedResponse, Fetch, Lookup, ProxyCache,
+};
+use crate::handlers::static_handler::StaticFileHandler;
+use crate::handlers::upstream_balance::UpstreamBalancer;
+use crate::handlers::upstream_health::UpstreamHealth;
+use crate::handlers::upstream_pool::UpstreamPools;
 use crate::handlers::websocket_proxy::WebSocketProxyHandler;
-use crate::middleware::compression::{CompressionMethod, CompressionMiddleware};
-use chrono;
-use log::{debug, error, info};
-use pingora::http::{RequestHeader, ResponseHeader};
+use crate::middleware::compression::{
+    merge_vary, ChunkEncoder, CompressionMethod, CompressionMiddleware,
+};
+use crate::middleware::expects_continue;
+use crate::monitoring::tracing::{Span, TraceContext, TRACEPARENT};
+use bytes::{Bytes, BytesMut};
+use log::{debug, error, info, warn};
+use pingora::http::{RequestHeader, ResponseHeader, Version};
 use pingora::prelude::*;
-use serde_json;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use url::Url;
 
+/// Factor weights are multiplied by before slow start reduces them
+const WEIGHT_SCALE: u64 = 100;
+
+/// Join a request path onto the upstream URL's base path, so an upstream of
+/// `http://backend/api/v2` receives `/users` as `/api/v2/users`
+pub fn join_upstream_path(upstream_url: &Url, path: &str) -> String {
+    let base = upstream_url.path().trim_end_matches('/');
+    format!("{}/{}", base, path.trim_start_matches('/'))
+}
+
+/// Identifies an upstream group in the shared balancing state. Sites may
+/// reuse a group name for other servers, so the servers are part of it.
+fn group_key(group: &[UpstreamConfig]) -> String {
+    let mut key = group.first().map(|s| s.name.clone()).unwrap_or_default();
+    for server in group {
+        key.push(' ');
+        key.push_str(&server.url);
+    }
+    key
+}
+
+/// Full URL for a request to `path` on the upstream
+fn upstream_request_url(upstream_url: &Url, path: &str) -> String {
+    format!(
+        "{}://{}{}{}",
+        upstream_url.scheme(),
+        upstream_url.host_str().unwrap_or("localhost"),
+        upstream_url
+            .port()
+            .map(|p| format!(":{}", p))
+            .unwrap_or_default(),
+        join_upstream_path(upstream_url, path)
+    )
+}
+
 pub struct ProxyHandler {
     /// Proxy configuration for the site, including routes and upstreams
     proxy_config: ProxyConfig,
     /// Map of upstream name to list of upstream server configs
     upstreams: HashMap<String, Vec<UpstreamConfig>>,
-    /// Round-robin counters for each upstream
-    round_robin_counters: HashMap<String, Arc<AtomicUsize>>,
-    /// Connection counts for each upstream server (for least-connections balancing)
-    connection_counts: HashMap<String, Arc<AtomicUsize>>,
     /// Handler for WebSocket proxying
     websocket_handler: WebSocketProxyHandler,
+    /// Largest request body forwarded upstream; larger ones get 413
+    max_body_size: Option<usize>,
+    /// Serves the internal locations named by `X-Accel-Redirect`
+    static_handler: Option<Arc<StaticFileHandler>>,
+    /// Keep-alive connections to upstreams, shared across requests
+    pools: Arc<UpstreamPools>,
+    /// Which upstreams are down, shared across requests
+    health: Arc<UpstreamHealth>,
+    /// Round-robin positions and weights, shared across requests
+    balancer: Arc<UpstreamBalancer>,
+    /// Cached upstream responses, shared across requests
+    cache: Arc<ProxyCache>,
+    /// Proxies in front of this server whose forwarding headers are kept
+    trusted_proxies: TrustedProxies,
 }
 
 impl ProxyHandler {
     /// Create a new ProxyHandler from the given proxy configuration
     pub fn new(proxy_config: ProxyConfig) -> Self {
         let mut upstreams = HashMap::new();
-        let mut round_robin_counters = HashMap::new();
-        let mut connection_counts = HashMap::new();
 
         // Group upstreams by name
         for upstream in &proxy_config.upstreams {
@@ -37,21 +98,66 @@ impl ProxyHandler {
                 .entry(upstream.name.clone())
                 .or_insert_with(Vec::new)
                 .push(upstream.clone());
-
-            round_robin_counters.insert(upstream.name.clone(), Arc::new(AtomicUsize::new(0)));
-
-            connection_counts.insert(upstream.url.clone(), Arc::new(AtomicUsize::new(0)));
         }
 
         Self {
             proxy_config: proxy_config.clone(),
             upstreams,
-            round_robin_counters,
-            connection_counts,
             websocket_handler: WebSocketProxyHandler::new(proxy_config),
+            max_body_size: None,
+            static_handler: None,
+            pools: Arc::new(UpstreamPools::new()),
+            health: Arc::new(UpstreamHealth::new()),
+            balancer: Arc::new(UpstreamBalancer::new()),
+            cache: Arc::new(ProxyCache::new()),
+            trusted_proxies: TrustedProxies::default(),
         }
     }
 
+    /// Refuse request bodies larger than `size` bytes
+    pub fn with_max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = Some(size);
+        self
+    }
+
+    /// Follow `X-Accel-Redirect` from upstreams by serving the named internal
+    /// location with this handler
+    pub fn with_static_handler(mut self, static_handler: Arc<StaticFileHandler>) -> Self {
+        self.static_handler = Some(static_handler);
+        self
+    }
+
+    /// Reuse upstream connections from `pools`, which outlive this handler
+    pub fn with_upstream_pools(mut self, pools: Arc<UpstreamPools>) -> Self {
+        self.pools = pools;
+        self
+    }
+
+    /// Track failing upstreams in `health`, which outlives this handler
+    pub fn with_upstream_health(mut self, health: Arc<UpstreamHealth>) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Balance over upstream groups with `balancer`, which outlives this
+    /// handler
+    pub fn with_upstream_balancer(mut self, balancer: Arc<UpstreamBalancer>) -> Self {
+        self.balancer = balancer;
+        self
+    }
+
+    /// Keep cached upstream responses in `cache`, which outlives this handler
+    pub fn with_response_cache(mut self, cache: Arc<ProxyCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Believe the forwarding headers sent by `trusted_proxies`
+    pub fn with_trusted_proxies(mut self, trusted_proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
     /// Find the appropriate proxy route for a given path
     /// Find the most specific proxy route for a given request path
     /// Returns None if proxying is disabled or no route matches.
@@ -71,7 +177,11 @@ impl ProxyHandler {
     /// Select an upstream server for a given upstream name
     /// Select an upstream server for a given upstream name using the configured load balancing method.
     /// Returns an error if no upstreams are available.
-    pub fn select_upstream(&self, upstream_name: &str) -> Result<&UpstreamConfig> {
+    pub fn select_upstream(
+        &self,
+        upstream_name: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<&UpstreamConfig> {
         let upstream_servers = self
             .upstreams
             .get(upstream_name)
@@ -81,69 +191,165 @@ impl ProxyHandler {
             return Err(Error::new_str("No servers available for upstream"));
         }
 
-        let upstream = match self.proxy_config.load_balancing.method.as_str() {
-            "round_robin" => self.select_round_robin(upstream_name, upstream_servers)?,
-            "weighted" => self.select_weighted(upstream_servers)?,
-            "least_connections" => self.select_least_connections(upstream_servers)?,
-            _ => &upstream_servers[0], // Default to first server
+        let strategy = self.proxy_config.load_balancing.strategy().map_err(|e| {
+            error!("{}", e);
+            Error::new_str("Unknown load balancing method")
+        })?;
+
+        let servers = self.available_servers(upstream_name, upstream_servers);
+        if servers.is_empty() {
+            debug!("Every circuit of upstream '{}' is open", upstream_name);
+            return Err(Error::new(ErrorType::HTTPStatus(503)));
+        }
+        let servers = servers.as_slice();
+        let upstream = match strategy {
+            LoadBalancingStrategy::RoundRobin => self.select_round_robin(upstream_servers, servers),
+            LoadBalancingStrategy::WeightedRoundRobin => {
+                self.select_smooth_weighted(upstream_servers, servers)
+            }
+            LoadBalancingStrategy::WeightedRandom => self.select_weighted(servers)?,
+            LoadBalancingStrategy::LeastConnections => self.select_least_connections(servers)?,
+            LoadBalancingStrategy::IpHash => match client_ip {
+                Some(ip) => self.select_ip_hash(ip, servers),
+                None => {
+                    debug!("No client address for ip_hash, using round robin");
+                    self.select_round_robin(upstream_servers, servers)
+                }
+            },
+            LoadBalancingStrategy::Random => self.select_random(servers),
         };
 
         Ok(upstream)
     }
 
-    /// Round-robin load balancing
-    /// Select an upstream server using round-robin load balancing.
-    fn select_round_robin<'a>(
+    /// Servers of a group to balance over: the primaries that are up, else
+    /// the backups that are up. When everything is down the primaries are
+    /// tried anyway, so traffic returns as soon as one recovers, except those
+    /// whose circuit is open. Empty when every circuit is open.
+    fn available_servers<'a>(
         &self,
         upstream_name: &str,
         servers: &'a [UpstreamConfig],
-    ) -> Result<&'a UpstreamConfig> {
-        let counter = self
-            .round_robin_counters
-            .get(upstream_name)
-            .ok_or_else(|| Error::new_str("Round robin counter not found"))?;
+    ) -> Vec<&'a UpstreamConfig> {
+        let up = |backup: bool| -> Vec<&'a UpstreamConfig> {
+            servers
+                .iter()
+                .filter(|s| s.backup == backup && !self.health.is_down(&s.url))
+                .collect()
+        };
+
+        let primaries = up(false);
+        if !primaries.is_empty() {
+            return primaries;
+        }
+
+        let backups = up(true);
+        if !backups.is_empty() {
+            debug!(
+                "All primary servers of '{}' are down, using backups",
+                upstream_name
+            );
+            return backups;
+        }
+
+        let has_primaries = servers.iter().any(|s| !s.backup);
+        servers
+            .iter()
+            .filter(|s| (!s.backup || !has_primaries) && !self.health.is_circuit_open(&s.url))
+            .collect()
+    }
+
+    /// Select an upstream server using round-robin load balancing.
+    fn select_round_robin<'a>(
+        &self,
+        group: &[UpstreamConfig],
+        servers: &[&'a UpstreamConfig],
+    ) -> &'a UpstreamConfig {
+        let index = self.balancer.next_position(&group_key(group)) % servers.len();
+        servers[index]
+    }
 
-        let index = counter.fetch_add(1, Ordering::Relaxed) % servers.len();
-        Ok(&servers[index])
+    /// Select an upstream server using smooth weighted round-robin, see
+    /// [`UpstreamBalancer::pick_weighted`]
+    fn select_smooth_weighted<'a>(
+        &self,
+        group: &[UpstreamConfig],
+        servers: &[&'a UpstreamConfig],
+    ) -> &'a UpstreamConfig {
+        let weights: Vec<(&str, i64)> = servers
+            .iter()
+            .map(|s| (s.url.as_str(), self.effective_weight(s) as i64))
+            .collect();
+        if weights.iter().all(|(_, weight)| *weight == 0) {
+            return servers[0];
+        }
+        servers[self.balancer.pick_weighted(&group_key(group), &weights)]
     }
 
     /// Weighted load balancing
     /// Select an upstream server using weighted random selection.
-    fn select_weighted<'a>(&self, servers: &'a [UpstreamConfig]) -> Result<&'a UpstreamConfig> {
-        let total_weight: u32 = servers.iter().map(|s| s.weight).sum();
+    fn select_weighted<'a>(&self, servers: &[&'a UpstreamConfig]) -> Result<&'a UpstreamConfig> {
+        let total_weight: u64 = servers.iter().map(|s| self.effective_weight(s)).sum();
         if total_weight == 0 {
-            return Ok(&servers[0]);
+            return Ok(servers[0]);
         }
 
-        let random_weight = fastrand::u32(1..=total_weight);
+        let random_weight = fastrand::u64(1..=total_weight);
         let mut current_weight = 0;
 
         for server in servers {
-            current_weight += server.weight;
+            current_weight += self.effective_weight(server);
             if random_weight <= current_weight {
                 return Ok(server);
             }
         }
 
-        Ok(&servers[0])
+        Ok(servers[0])
+    }
+
+    /// A server's weight, scaled by `WEIGHT_SCALE` so that it can be reduced
+    /// finely while the server warms up after coming back (`slow_start`).
+    /// Scaling every weight alike leaves the balancing unchanged.
+    fn effective_weight(&self, server: &UpstreamConfig) -> u64 {
+        let full = server.weight as u64 * WEIGHT_SCALE;
+        if server.slow_start == 0 || full == 0 {
+            return full;
+        }
+        let warmup = self
+            .health
+            .warmup(&server.url, Duration::from_secs(server.slow_start));
+        ((full as f64 * warmup).round() as u64).max(1)
+    }
+
+    /// Select an upstream server by hashing the client IP, so a client sticks to one server.
+    fn select_ip_hash<'a>(
+        &self,
+        client_ip: IpAddr,
+        servers: &[&'a UpstreamConfig],
+    ) -> &'a UpstreamConfig {
+        let mut hasher = DefaultHasher::new();
+        client_ip.hash(&mut hasher);
+        let index = (hasher.finish() % servers.len() as u64) as usize;
+        servers[index]
+    }
+
+    /// Select an upstream server uniformly at random.
+    fn select_random<'a>(&self, servers: &[&'a UpstreamConfig]) -> &'a UpstreamConfig {
+        servers[fastrand::usize(..servers.len())]
     }
 
-    /// Least connections load balancing (uses actual connection tracking)
-    /// Select an upstream server with the least number of active connections.
+    /// Select an upstream server with the least number of requests in
+    /// flight, counted across all requests by the shared balancer
     fn select_least_connections<'a>(
         &self,
-        servers: &'a [UpstreamConfig],
+        servers: &[&'a UpstreamConfig],
     ) -> Result<&'a UpstreamConfig> {
         // Find the server with the least current connections
         let mut min_connections = usize::MAX;
-        let mut selected_server = &servers[0];
+        let mut selected_server = servers[0];
 
-        for server in servers {
-            let connections = self
-                .connection_counts
-                .get(&server.url)
-                .map(|c| c.load(Ordering::Relaxed))
-                .unwrap_or(0);
+        for &server in servers {
+            let connections = self.balancer.connections(&server.url);
 
             if connections < min_connections {
                 min_connections = connections;
@@ -158,20 +364,6 @@ impl ProxyHandler {
         Ok(selected_server)
     }
 
-    /// Increment connection count for a server
-    fn increment_connections(&self, server_url: &str) {
-        if let Some(counter) = self.connection_counts.get(server_url) {
-            counter.fetch_add(1, Ordering::Relaxed);
-        }
-    }
-
-    /// Decrement connection count for a server
-    fn decrement_connections(&self, server_url: &str) {
-        if let Some(counter) = self.connection_counts.get(server_url) {
-            counter.fetch_sub(1, Ordering::Relaxed);
-        }
-    }
-
     /// Create HTTP peer for upstream server (simplified)
     pub fn get_upstream_url(&self, upstream: &UpstreamConfig) -> Result<Url> {
         Url::parse(&upstream.url).map_err(|_| Error::new_str("Invalid upstream URL"))
@@ -201,36 +393,43 @@ impl ProxyHandler {
         session: &Session,
         original_host: &str,
     ) {
-        if self.proxy_config.headers.add_x_forwarded {
-            if let Some(client_addr) = session.client_addr() {
-                req.insert_header("X-Forwarded-For", client_addr.to_string())
-                    .ok();
-            }
-        }
-
-        if self.proxy_config.headers.add_x_forwarded {
-            let proto = if session.req_header().uri.scheme().map(|s| s.as_str()) == Some("https") {
-                "https"
-            } else {
-                "http"
-            };
-            req.insert_header("X-Forwarded-Proto", proto).ok();
-        }
+        let peer = session
+            .client_addr()
+            .and_then(|addr| addr.as_inet())
+            .map(|addr| addr.ip());
+        let https = client_uses_https(session);
 
         if self.proxy_config.headers.add_x_forwarded {
+            if let Some(peer) = peer {
+                let header = |name: &str| {
+                    session
+                        .req_header()
+                        .headers
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                };
+                let (forwarded_for, proto) = forwarded_values(
+                    header("X-Forwarded-For"),
+                    header("X-Forwarded-Proto"),
+                    peer,
+                    self.trusted_proxies.contains(peer),
+                    https,
+                );
+                req.insert_header("X-Forwarded-For", forwarded_for).ok();
+                req.insert_header("X-Forwarded-Proto", proto).ok();
+            }
             req.insert_header("X-Forwarded-Host", original_host).ok();
         }
 
         if self.proxy_config.headers.add_forwarded {
-            if let Some(client_addr) = session.client_addr() {
-                let proto =
-                    if session.req_header().uri.scheme().map(|s| s.as_str()) == Some("https") {
-                        "https"
-                    } else {
-                        "http"
-                    };
-                let forwarded =
-                    format!("for={};proto={};host={}", client_addr, proto, original_host);
+            if let Some(peer) = peer {
+                // IPv6 nodes are quoted and bracketed (RFC 7239)
+                let node = match peer {
+                    IpAddr::V4(ip) => ip.to_string(),
+                    IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+                };
+                let proto = if https { "https" } else { "http" };
+                let forwarded = format!("for={};proto={};host={}", node, proto, original_host);
                 req.insert_header("Forwarded", forwarded).ok();
             }
         }
@@ -241,33 +440,144 @@ impl ProxyHandler {
         }
     }
 
+    /// Custom headers to send upstream for a route: the site-level `headers.add`
+    /// overlaid with the route's `request_headers` (route wins, names compared
+    /// case-insensitively)
+    pub fn route_request_headers(&self, route: &ProxyRoute) -> Vec<(String, String)> {
+        let mut headers: Vec<(String, String)> = self
+            .proxy_config
+            .headers
+            .add
+            .iter()
+            .filter(|(name, _)| {
+                !route
+                    .request_headers
+                    .keys()
+                    .any(|route_name| route_name.eq_ignore_ascii_case(name))
+            })
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        headers.extend(
+            route
+                .request_headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+        headers
+    }
+
+    /// Proxy a WebSocket upgrade to an upstream chosen like for any other
+    /// request, so down upstreams and open circuits are skipped and backups
+    /// take over when every primary is down
+    async fn handle_websocket_upgrade(
+        &self,
+        session: &mut Session,
+        site: &SiteConfig,
+        path: &str,
+    ) -> Result<bool> {
+        let Some(route) = self.websocket_handler.find_websocket_route(path) else {
+            return Ok(false);
+        };
+
+        let client_ip = crate::middleware::client_ip(session, &self.trusted_proxies);
+        let upstream = match self.select_upstream(&route.upstream, client_ip) {
+            Ok(upstream) => upstream,
+            Err(e) if matches!(e.etype(), ErrorType::HTTPStatus(503)) => {
+                write_json_error(
+                    session,
+                    503,
+                    "Service Unavailable",
+                    "No upstream server is available",
+                    Some(site),
+                )
+                .await?;
+                return Ok(true);
+            }
+            Err(e) => {
+                error!("Failed to select upstream: {}", e);
+                return Ok(false);
+            }
+        };
+
+        // Sessions count as connections for least_connections while open
+        let _in_flight = self.balancer.start_request(&upstream.url);
+        self.websocket_handler
+            .handle_websocket_proxy(session, route, upstream, path)
+            .await
+    }
+
     /// Handle a proxy request for a specific site and path
     pub async fn handle_proxy_request(
         &self,
         session: &mut Session,
         site: &SiteConfig,
         path: &str,
+        mut span: Option<&mut Span>,
     ) -> Result<bool> {
         // Check if this is a WebSocket upgrade request
         if WebSocketProxyHandler::is_websocket_upgrade_request(session.req_header()) {
             info!("Detected WebSocket upgrade request for path: {}", path);
-            return self
-                .websocket_handler
-                .handle_websocket_proxy(session, path)
-                .await;
+            return self.handle_websocket_upgrade(session, site, path).await;
         }
 
         // Find matching route for regular HTTP proxy
         if let Some(route) = self.find_proxy_route(path) {
             info!("Proxying request {} to upstream '{}'", path, route.upstream);
 
+            // A fresh cached response spares the upstream. A stale one is
+            // served while a single request refreshes it in the background.
+            // Until this request's fetch ends, others missing the same key
+            // wait for it rather than going upstream too.
+            let fetch = match self.cache_key(session) {
+                Some(key) => match self
+                    .cache
+                    .lookup(&site.name, &key, session.req_header())
+                    .await
+                {
+                    Lookup::Hit(cached) => {
+                        debug!("Serving {} from the proxy cache", path);
+                        if cached.is_stale()
+                            && self
+                                .cache
+                                .start_refresh(&site.name, &key, session.req_header())
+                        {
+                            self.refresh_in_background(session, route, site, path, key);
+                        }
+                        self.write_cached_response(session, cached, route, site)
+                            .await?;
+                        return Ok(true);
+                    }
+                    Lookup::Miss(fetch) => Some(fetch),
+                },
+                None => None,
+            };
+
             // Select upstream server
-            let upstream = match self.select_upstream(&route.upstream) {
+            let client_ip = crate::middleware::client_ip(session, &self.trusted_proxies);
+
+            let upstream = match self.select_upstream(&route.upstream, client_ip) {
                 Ok(upstream) => upstream,
+                Err(e) if matches!(e.etype(), ErrorType::HTTPStatus(503)) => {
+                    write_json_error(
+                        session,
+                        503,
+                        "Service Unavailable",
+                        "No upstream server is available",
+                        Some(site),
+                    )
+                    .await?;
+                    return Ok(true);
+                }
                 Err(e) => {
                     error!("Failed to select upstream: {}", e);
-                    self.send_error_response(session, 502, "Bad Gateway")
-                        .await?;
+                    write_json_error(
+                        session,
+                        502,
+                        "Bad Gateway",
+                        "The upstream server could not be reached",
+                        Some(site),
+                    )
+                    .await?;
                     return Ok(true);
                 }
             };
@@ -277,35 +587,141 @@ impl ProxyHandler {
                 Ok(url) => url,
                 Err(e) => {
                     error!("Failed to parse upstream URL: {}", e);
-                    self.send_error_response(session, 502, "Bad Gateway")
-                        .await?;
+                    write_json_error(
+                        session,
+                        502,
+                        "Bad Gateway",
+                        "The upstream server could not be reached",
+                        Some(site),
+                    )
+                    .await?;
+                    return Ok(true);
+                }
+            };
+
+            if let Some(span) = span.as_deref_mut() {
+                span.set_name(format!("{} {}", session.req_header().method, route.path));
+                span.set_attribute("http.route", route.path.as_str());
+                span.set_attribute("bws.upstream", upstream.url.as_str());
+            }
+            let trace = span.as_deref().map(Span::context);
+
+            // Read the body before picking a connection, so an oversized
+            // upload is refused without touching the upstream
+            let body = match reading_body(self.read_request_body(session)).await? {
+                Some(body) => body,
+                None => {
+                    info!("Refused request body over the size limit for {}", path);
+                    session.set_keepalive(None);
+                    write_json_error(
+                        session,
+                        413,
+                        "Payload Too Large",
+                        "The request body exceeds the size limit",
+                        Some(site),
+                    )
+                    .await?;
                     return Ok(true);
                 }
             };
 
             // Transform the request path
             let new_path = self.transform_path(route, path);
+            let full_upstream_url = upstream_request_url(&upstream_url, &new_path);
+
+            // Pooled client with the upstream's TLS settings
+            let pool = match self.pools.get(
+                upstream,
+                &self.proxy_config.timeout,
+                &self.proxy_config.pool,
+                || upstream_client(&self.proxy_config, upstream),
+            ) {
+                Ok(pool) => pool,
+                Err(e) => {
+                    error!(
+                        "Failed to create HTTP client for '{}': {}",
+                        upstream.name, e
+                    );
+                    write_json_error(
+                        session,
+                        502,
+                        "Bad Gateway",
+                        "The upstream server could not be reached",
+                        Some(site),
+                    )
+                    .await?;
+                    return Ok(true);
+                }
+            };
+            // Wait for a free connection no longer than for a new one
+            let Some(mut lease) = pool
+                .acquire(Duration::from_secs(self.proxy_config.timeout.connect))
+                .await
+            else {
+                warn!(
+                    "No free connection to upstream {} for {}",
+                    upstream.url, path
+                );
+                write_json_error(
+                    session,
+                    503,
+                    "Service Unavailable",
+                    "The upstream server is busy",
+                    Some(site),
+                )
+                .await?;
+                return Ok(true);
+            };
+            let full_upstream_url = with_sni(&full_upstream_url, upstream);
+            debug!("Proxying to upstream URL: {}", full_upstream_url);
+            let mut request = upstream_request(
+                lease.client(),
+                session.req_header().method.as_str(),
+                &full_upstream_url,
+            );
+            for (name, value) in self.upstream_headers(session, route, trace.as_ref()) {
+                request = request.header(name, value);
+            }
+            if !body.is_empty() {
+                request = request.body(body);
+            }
 
             // Track connection for load balancing
-            self.increment_connections(&upstream.url);
+            let in_flight = self.balancer.start_request(&upstream.url);
+            let attempt = self.health.start_request(&upstream.url);
 
             // Perform the proxy request
             let proxy_result = self
-                .proxy_to_upstream(session, &upstream_url, &new_path, route, site)
+                .proxy_to_upstream(session, request, route, site, fetch)
                 .await;
-
-            // Always decrement connection count when done
-            self.decrement_connections(&upstream.url);
+            drop(in_flight);
 
             match proxy_result {
-                Ok(()) => {
+                Ok((status, reusable)) => {
+                    if reusable {
+                        lease.keep_alive();
+                    }
+                    attempt.record_response(status, &self.proxy_config.circuit_breaker);
                     info!("Successfully proxied request {} to {}", path, upstream.url);
                     Ok(true)
                 }
                 Err(e) => {
+                    // Only failures on the upstream's side count against it
+                    if matches!(e.esource(), ErrorSource::Upstream) {
+                        attempt.record_failure(
+                            &self.proxy_config.health_check,
+                            &self.proxy_config.circuit_breaker,
+                        );
+                    }
                     error!("Proxy request failed: {}", e);
-                    self.send_error_response(session, 502, "Bad Gateway")
-                        .await?;
+                    write_json_error(
+                        session,
+                        502,
+                        "Bad Gateway",
+                        "The upstream request failed",
+                        Some(site),
+                    )
+                    .await?;
                     Ok(true)
                 }
             }
@@ -315,23 +731,98 @@ impl ProxyHandler {
         }
     }
 
-    /// Perform the actual proxy request to upstream
+    /// Perform the actual proxy request to upstream. Returns the upstream's
+    /// status and whether its connection can go back to the pool. `fetch` is
+    /// held, keeping requests for the same key waiting, only until the
+    /// response is stored or known not to be.
     async fn proxy_to_upstream(
         &self,
         session: &mut Session,
-        upstream_url: &Url,
-        new_path: &str,
-        _route: &ProxyRoute,
+        req_builder: reqwest::RequestBuilder,
+        route: &ProxyRoute,
         site: &SiteConfig,
-    ) -> Result<()> {
-        // Create a new HTTP client for the upstream request
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(
-                self.proxy_config.timeout.read,
-            ))
-            .build()
-            .map_err(|_| Error::new_str("Failed to create HTTP client"))?;
+        fetch: Option<Fetch<'_>>,
+    ) -> Result<(u16, bool)> {
+        // Send request to upstream
+        let response = req_builder
+            .send()
+            .await
+            .map_err(|_| Error::new_up(ErrorType::Custom("request failed")))?;
+
+        // Get response status
+        let status = response.status().as_u16();
+
+        // Collect headers before consuming response
+        let mut header_map = response_headers(&response);
+
+        // Requests waiting for this fetch go upstream themselves straight
+        // away when there will be nothing stored for them
+        let authorized = session.req_header().headers.contains_key("authorization");
+        let fetch = fetch.filter(|_| {
+            !header_map.contains_key("x-accel-redirect")
+                && !is_streaming_response(status, &header_map)
+                && freshness_lifetime(
+                    status,
+                    &header_map,
+                    authorized,
+                    self.proxy_config.cache.default_ttl,
+                )
+                .is_some()
+        });
+
+        // The upstream may hand delivery of a file back to the static file handler
+        if let Some(target) = header_map.get("x-accel-redirect") {
+            // The unread upstream body means the connection cannot be reused
+            return self
+                .internal_redirect(session, site, target)
+                .await
+                .map(|()| (status, false));
+        }
+
+        // Event streams and bodies of unknown length are relayed as they
+        // arrive; buffering them to compress would hold back real-time data
+        if is_streaming_response(status, &header_map) {
+            return self
+                .relay_response(session, status, header_map, route, site, response)
+                .await
+                .map(|reusable| (status, reusable));
+        }
+
+        let keep_alive = upstream_keeps_alive(&header_map);
+
+        // Get response body (this consumes the response)
+        let body_bytes = response
+            .bytes()
+            .await
+            .map_err(|_| Error::new_up(ErrorType::Custom("response read failed")))?;
+
+        // Keep a copy for later requests when the upstream allows it
+        if let Some(key) = self.cache_key(session) {
+            let cache = &self.proxy_config.cache;
+            if let Some(response) =
+                cacheable(status, &header_map, body_bytes.clone(), authorized, cache)
+            {
+                self.cache
+                    .insert(&site.name, &key, session.req_header(), response, cache);
+            }
+            header_map.insert("x-cache".to_string(), "MISS".to_string());
+        }
+        drop(fetch);
+
+        self.write_buffered_response(session, status, header_map, body_bytes, route, site)
+            .await?;
+
+        Ok((status, keep_alive))
+    }
 
+    /// Headers sent upstream: the client's, less the framing and replaced
+    /// ones, plus the proxy and route headers and the trace context
+    fn upstream_headers(
+        &self,
+        session: &Session,
+        route: &ProxyRoute,
+        trace: Option<&TraceContext>,
+    ) -> Vec<(String, String)> {
         // Get original host header
         let original_host = session
             .req_header()
@@ -340,96 +831,62 @@ impl ProxyHandler {
             .and_then(|h| h.to_str().ok())
             .unwrap_or("localhost");
 
-        // Build upstream URL with new path
-        let full_upstream_url = format!(
-            "{}://{}{}{}",
-            upstream_url.scheme(),
-            upstream_url.host_str().unwrap_or("localhost"),
-            upstream_url
-                .port()
-                .map(|p| format!(":{}", p))
-                .unwrap_or_default(),
-            new_path
-        );
-
-        debug!("Proxying to upstream URL: {}", full_upstream_url);
-
-        // Create upstream request
-        let method = session.req_header().method.clone();
-        let mut req_builder = match method.as_str() {
-            "GET" => client.get(&full_upstream_url),
-            "POST" => client.post(&full_upstream_url),
-            "PUT" => client.put(&full_upstream_url),
-            "DELETE" => client.delete(&full_upstream_url),
-            "PATCH" => client.patch(&full_upstream_url),
-            "HEAD" => client.head(&full_upstream_url),
-            "OPTIONS" => client.request(reqwest::Method::OPTIONS, &full_upstream_url),
-            _ => client.get(&full_upstream_url), // Default to GET
+        // Custom headers for this route replace any client-sent value
+        let custom_headers = self.route_request_headers(route);
+        let is_replaced = |name: &str| {
+            self.proxy_config
+                .headers
+                .remove
+                .iter()
+                .any(|removed| removed.eq_ignore_ascii_case(name))
+                || custom_headers
+                    .iter()
+                    .any(|(custom, _)| custom.eq_ignore_ascii_case(name))
+                || (trace.is_some() && name.eq_ignore_ascii_case(TRACEPARENT))
         };
 
-        // Copy headers from original request
-        for (name, value) in session.req_header().headers.iter() {
-            if let Ok(value_str) = value.to_str() {
-                let name_str = name.as_str();
-                // Skip host header as we'll set it appropriately
-                if name_str.to_lowercase() != "host" {
-                    req_builder = req_builder.header(name_str, value_str);
-                }
-            }
-        }
-
-        // Add proxy headers
+        // Original request headers plus the proxy headers (X-Forwarded-*, Forwarded)
         let mut temp_header = session.req_header().clone();
         self.add_proxy_headers(&mut temp_header, session, original_host);
 
-        // Copy proxy headers to request
+        let mut headers = Vec::new();
         for (name, value) in temp_header.headers.iter() {
             if let Ok(value_str) = value.to_str() {
                 let name_str = name.as_str();
-                if name_str.starts_with("X-Forwarded") || name_str == "Forwarded" {
-                    req_builder = req_builder.header(name_str, value_str);
+                // Skip host header as we'll set it appropriately, and the
+                // framing headers since the body is re-sent as read
+                if !matches!(
+                    name_str,
+                    "host" | "expect" | "content-length" | "transfer-encoding"
+                ) && !is_replaced(name_str)
+                {
+                    headers.push((name_str.to_string(), value_str.to_string()));
                 }
             }
         }
 
-        // Read request body if present
-        let body = if method.as_str() == "POST"
-            || method.as_str() == "PUT"
-            || method.as_str() == "PATCH"
-        {
-            // For now, we'll handle requests without body.
-            // Full body proxying would require reading from session.read_request_body()
-            Vec::new()
-        } else {
-            Vec::new()
-        };
-
-        if !body.is_empty() {
-            req_builder = req_builder.body(body);
-        }
-
-        // Send request to upstream
-        let response = req_builder
-            .send()
-            .await
-            .map_err(|_| Error::new_str("Upstream request failed"))?;
-
-        // Get response status
-        let status = response.status().as_u16();
+        headers.extend(custom_headers);
 
-        // Collect headers before consuming response
-        let mut header_map = std::collections::HashMap::new();
-        for (name, value) in response.headers().iter() {
-            if let Ok(value_str) = value.to_str() {
-                header_map.insert(name.as_str().to_string(), value_str.to_string());
-            }
+        // Continue the request's trace in the upstream
+        if let Some(trace) = trace {
+            headers.push((TRACEPARENT.to_string(), trace.to_header()));
         }
+        headers
+    }
 
-        // Get response body (this consumes the response)
-        let body_bytes = response
-            .bytes()
-            .await
-            .map_err(|_| Error::new_str("Failed to read upstream response"))?;
+    /// Send a complete response, compressed when the site compresses its type
+    /// and the client accepts an encoding
+    async fn write_buffered_response(
+        &self,
+        session: &mut Session,
+        status: u16,
+        mut header_map: HashMap<String, String>,
+        body_bytes: Bytes,
+        route: &ProxyRoute,
+        site: &SiteConfig,
+    ) -> Result<()> {
+        self.rewrite_redirects(session, route, site, &mut header_map);
+        rewrite_cookies(route, &mut header_map);
 
         // Check if response should be compressed
         let content_type = header_map
@@ -439,9 +896,9 @@ impl ProxyHandler {
 
         let compression_middleware = CompressionMiddleware::new(site.compression.clone());
 
-        let (final_body, encoding) = if compression_middleware
-            .should_compress(&content_type, body_bytes.len())
-        {
+        let negotiated = may_compress_response(status, &header_map)
+            && compression_middleware.should_compress(&content_type, body_bytes.len());
+        let (final_body, encoding) = if negotiated {
             // Get the best compression method based on Accept-Encoding header
             let accept_encoding = session
                 .req_header()
@@ -481,55 +938,1925 @@ impl ProxyHandler {
         // Add collected headers (except content-length which we'll update)
         for (name, value) in header_map {
             if name.to_lowercase() != "content-length" {
-                resp_header.insert_header(name, value)?;
+                insert_upstream_header(&mut resp_header, name, value)?;
             }
         }
 
+        // Route-specific response headers
+        for (name, value) in &route.response_headers {
+            resp_header.insert_header(name.clone(), value.clone())?;
+        }
+
         // Update content length and add encoding header
         resp_header.insert_header("Content-Length", final_body.len().to_string())?;
 
         if let Some(method) = encoding {
             if !matches!(method, CompressionMethod::None) {
                 resp_header.insert_header("Content-Encoding", method.as_str())?;
-                resp_header.insert_header("Vary", "Accept-Encoding")?;
             }
         }
 
+        // Merge with any Vary the upstream already sent
+        if negotiated {
+            merge_vary(&mut resp_header, "Accept-Encoding")?;
+        }
+
         // Send response back to client
         session
             .write_response_header(Box::new(resp_header), false)
             .await?;
+        response_started();
         session.write_response_body(Some(final_body), true).await?;
 
         Ok(())
     }
 
-    /// Send an error response
-    async fn send_error_response(
+    /// Point the `Location` and `Refresh` headers of a route with
+    /// `proxy_redirect` at the URL the client used instead of the upstream
+    fn rewrite_redirects(
+        &self,
+        session: &Session,
+        route: &ProxyRoute,
+        site: &SiteConfig,
+        header_map: &mut HashMap<String, String>,
+    ) {
+        if !route.proxy_redirect {
+            return;
+        }
+        let upstreams = self
+            .upstreams
+            .get(&route.upstream)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let request = session.req_header();
+        let host = request
+            .headers
+            .get("host")
+            .and_then(|h| h.to_str().ok())
+            .or_else(|| request.uri.authority().map(|a| a.as_str()))
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{}:{}", site.hostname, site.port));
+        let scheme = if client_uses_https(session) {
+            "https"
+        } else {
+            "http"
+        };
+        let public = format!("{}://{}", scheme, host);
+
+        if let Some(location) = header_map.get_mut("location") {
+            *location = public_redirect(location, upstreams, &public, route);
+        }
+        if let Some(refresh) = header_map.get_mut("refresh") {
+            *refresh = public_refresh(refresh, upstreams, &public, route);
+        }
+    }
+
+    /// Serve a response from the proxy cache
+    async fn write_cached_response(
         &self,
         session: &mut Session,
-        status_code: u16,
-        message: &str,
+        cached: CachedResponse,
+        route: &ProxyRoute,
+        site: &SiteConfig,
     ) -> Result<()> {
-        let error_response = serde_json::json!({
-            "error": message,
-            "status": status_code,
-            "timestamp": chrono::Utc::now().to_rfc3339()
+        // Add the time spent here to any the upstream reported
+        let age = cached.age()
+            + cached
+                .headers
+                .get("age")
+                .and_then(|age| age.trim().parse::<u64>().ok())
+                .unwrap_or(0);
+        let label = if cached.is_stale() { "STALE" } else { "HIT" };
+        let mut header_map = cached.headers;
+        header_map.insert("age".to_string(), age.to_string());
+        header_map.insert("x-cache".to_string(), label.to_string());
+        self.write_buffered_response(session, cached.status, header_map, cached.body, route, site)
+            .await
+    }
+
+    /// Fetch a fresh copy of the stale response stored for `key` without
+    /// holding up the request that found it. The refresh claimed for the
+    /// entry ends when the new response is stored or the fetch fails.
+    fn refresh_in_background(
+        &self,
+        session: &Session,
+        route: &ProxyRoute,
+        site: &SiteConfig,
+        path: &str,
+        key: String,
+    ) {
+        let request = session.req_header().clone();
+        let client_ip = crate::middleware::client_ip(session, &self.trusted_proxies);
+        let target = self
+            .select_upstream(&route.upstream, client_ip)
+            .and_then(|upstream| {
+                let upstream_url = self.get_upstream_url(upstream)?;
+                let pool = self
+                    .pools
+                    .get(
+                        upstream,
+                        &self.proxy_config.timeout,
+                        &self.proxy_config.pool,
+                        || upstream_client(&self.proxy_config, upstream),
+                    )
+                    .map_err(|e| Error::because(ErrorType::InternalError, "upstream client", e))?;
+                let url = upstream_request_url(&upstream_url, &self.transform_path(route, path));
+                Ok((upstream.url.clone(), with_sni(&url, upstream), pool))
+            });
+        let (address, url, pool) = match target {
+            Ok(target) => target,
+            Err(e) => {
+                warn!("Cannot refresh cached response for {}: {}", path, e);
+                self.cache.end_refresh(&site.name, &key, &request);
+                return;
+            }
+        };
+
+        let headers = self.upstream_headers(session, route, None);
+        let cache = self.cache.clone();
+        let health = self.health.clone();
+        let balancer = self.balancer.clone();
+        let config = self.proxy_config.clone();
+        let site_name = site.name.clone();
+        let path = path.to_string();
+        tokio::spawn(async move {
+            let wait = Duration::from_secs(config.timeout.connect);
+            let Some(mut lease) = pool.acquire(wait).await else {
+                warn!("No free connection to refresh cached response for {}", path);
+                cache.end_refresh(&site_name, &key, &request);
+                return;
+            };
+            let mut builder = upstream_request(lease.client(), "GET", &url);
+            for (name, value) in headers {
+                builder = builder.header(name, value);
+            }
+            let _in_flight = balancer.start_request(&address);
+            let attempt = health.start_request(&address);
+
+            let fetched = async {
+                let response = builder.send().await.ok()?;
+                let status = response.status().as_u16();
+                let header_map = response_headers(&response);
+                if is_streaming_response(status, &header_map)
+                    || header_map.contains_key("x-accel-redirect")
+                {
+                    return Some((status, None));
+                }
+                let keep_alive = upstream_keeps_alive(&header_map);
+                let body = response.bytes().await.ok()?;
+                if keep_alive {
+                    lease.keep_alive();
+                }
+                let authorized = request.headers.contains_key("authorization");
+                Some((
+                    status,
+                    cacheable(status, &header_map, body, authorized, &config.cache),
+                ))
+            }
+            .await;
+
+            match fetched {
+                Some((status, response)) => {
+                    attempt.record_response(status, &config.circuit_breaker);
+                    match response {
+                        Some(response) => {
+                            debug!("Refreshed cached response for {}", path);
+                            cache.insert(&site_name, &key, &request, response, &config.cache);
+                        }
+                        None => cache.remove(&site_name, &key, &request),
+                    }
+                }
+                None => {
+                    warn!("Failed to refresh cached response for {}", path);
+                    attempt.record_failure(&config.health_check, &config.circuit_breaker);
+                    cache.end_refresh(&site_name, &key, &request);
+                }
+            }
         });
+    }
 
-        let response_body = error_response.to_string();
-        let response_bytes = response_body.into_bytes();
-        let mut header = ResponseHeader::build(status_code, Some(3))?;
-        header.insert_header("Content-Type", "application/json")?;
-        header.insert_header("Content-Length", response_bytes.len().to_string())?;
+    /// Cache key of a `GET` the proxy cache may answer and store, or `None`
+    /// when the cache is off or the client asked to bypass it
+    fn cache_key(&self, session: &Session) -> Option<String> {
+        let request = session.req_header();
+        if !self.proxy_config.cache.enabled || request.method != "GET" {
+            return None;
+        }
+        let directives = request
+            .headers
+            .get("cache-control")
+            .and_then(|value| value.to_str().ok())
+            .map(CacheDirectives::parse)
+            .unwrap_or_default();
+        (!directives.no_cache && !directives.no_store).then(|| ProxyCache::key(request))
+    }
+
+    /// Relay an upstream response chunk by chunk, compressing it as it goes
+    /// when the site compresses its type. The client gets a chunked response,
+    /// flushed after every chunk. Returns whether the upstream connection can
+    /// go back to the pool.
+    async fn relay_response(
+        &self,
+        session: &mut Session,
+        status: u16,
+        mut header_map: HashMap<String, String>,
+        route: &ProxyRoute,
+        site: &SiteConfig,
+        mut response: reqwest::Response,
+    ) -> Result<bool> {
+        self.rewrite_redirects(session, route, site, &mut header_map);
+        rewrite_cookies(route, &mut header_map);
+
+        // Bodies ended by closing the connection leave nothing to reuse
+        let keep_alive = upstream_keeps_alive(&header_map)
+            && (header_map.contains_key("content-length")
+                || header_map
+                    .get("transfer-encoding")
+                    .is_some_and(|value| value.to_ascii_lowercase().contains("chunked")));
+
+        // The length is unknown, so min_size cannot apply; event streams are
+        // never among the compressed types
+        let content_type = header_map
+            .get("content-type")
+            .map_or("application/octet-stream", String::as_str);
+        let negotiated = site.compression.enabled
+            && session.req_header().method != "HEAD"
+            && may_compress_response(status, &header_map)
+            && site.compression.compresses_type(content_type);
+
+        let mut resp_header = ResponseHeader::build(status, Some(4))?;
+        for (name, value) in header_map {
+            if !matches!(name.as_str(), "content-length" | "transfer-encoding") {
+                insert_upstream_header(&mut resp_header, name, value)?;
+            }
+        }
+        for (name, value) in &route.response_headers {
+            resp_header.insert_header(name.clone(), value.clone())?;
+        }
+
+        let mut encoder = None;
+        if negotiated {
+            let accept_encoding = session
+                .req_header()
+                .headers
+                .get("accept-encoding")
+                .and_then(|h| h.to_str().ok());
+            let method = CompressionMiddleware::new(site.compression.clone())
+                .get_best_compression(accept_encoding);
+            encoder = ChunkEncoder::new(&method, site.compression.level);
+            if encoder.is_some() {
+                resp_header.insert_header("Content-Encoding", method.as_str())?;
+            }
+            merge_vary(&mut resp_header, "Accept-Encoding")?;
+        }
+
+        // HTTP/1.0 clients cannot read chunks, so the body ends when the
+        // connection closes (HTTP/2 drops the header and frames the body itself)
+        if session.req_header().version == Version::HTTP_10 {
+            session.set_keepalive(None);
+        } else {
+            resp_header.insert_header("Transfer-Encoding", "chunked")?;
+        }
 
         session
-            .write_response_header(Box::new(header), false)
-            .await?;
-        session
-            .write_response_body(Some(response_bytes.into()), true)
+            .write_response_header(Box::new(resp_header), false)
             .await?;
+        response_started();
+
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    let chunk = match encoder.as_mut() {
+                        Some(encoder) => encoder.encode(&chunk).map_err(|e| {
+                            warn!("Compressing upstream stream failed: {}", e);
+                            Error::new(ErrorType::Custom("compression failed"))
+                        })?,
+                        None => chunk,
+                    };
+                    if !chunk.is_empty() {
+                        session.write_response_body(Some(chunk), false).await?;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    // Headers are out, so all that is left is to cut the response short
+                    warn!("Upstream stream ended with an error: {}", e);
+                    return Err(Error::new_up(ErrorType::Custom("stream failed")));
+                }
+            }
+        }
+        if let Some(encoder) = encoder {
+            let tail = encoder.finish().map_err(|e| {
+                warn!("Compressing upstream stream failed: {}", e);
+                Error::new(ErrorType::Custom("compression failed"))
+            })?;
+            session.write_response_body(Some(tail), false).await?;
+        }
+        session.write_response_body(None, true).await?;
+
+        Ok(keep_alive)
+    }
+
+    /// Serve the internal location an upstream named in `X-Accel-Redirect`.
+    /// Targets outside the site's internal locations are refused, so an
+    /// upstream cannot expose arbitrary files.
+    async fn internal_redirect(
+        &self,
+        session: &mut Session,
+        site: &SiteConfig,
+        target: &str,
+    ) -> Result<()> {
+        let location = target.split('?').next().unwrap_or(target);
+        let location = normalize_path(location)
+            .ok()
+            .filter(|location| site.is_internal_location(location));
+
+        match (location, &self.static_handler) {
+            (Some(location), Some(static_handler)) => {
+                debug!("Internal redirect to {} on site '{}'", location, site.name);
+                static_handler.handle(session, site, &location).await
+            }
+            _ => {
+                warn!(
+                    "Refused X-Accel-Redirect to {:?} on site '{}': not an internal location",
+                    target, site.name
+                );
+                Err(Error::new_str("Invalid internal redirect"))
+            }
+        }
+    }
+
+    /// Read the whole request body, first sending `100 Continue` to a client
+    /// waiting for it. Returns None when the body is over `max_body_size`.
+    async fn read_request_body(&self, session: &mut Session) -> Result<Option<Bytes>> {
+        let limit = self.max_body_size.unwrap_or(usize::MAX);
+        let declared = session
+            .req_header()
+            .headers
+            .get("content-length")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.trim().parse::<u64>().ok());
+        if declared.is_some_and(|len| len > limit as u64) {
+            return Ok(None);
+        }
+
+        // Without a length or chunked framing a request has no body; HTTP/1.0
+        // would otherwise be read until the client closes the connection
+        let chunked = session
+            .req_header()
+            .headers
+            .get("transfer-encoding")
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|h| h.to_ascii_lowercase().contains("chunked"));
+        if declared.is_none() && !chunked {
+            return Ok(Some(Bytes::new()));
+        }
+
+        if expects_continue(session.req_header()) {
+            session.write_continue_response().await?;
+        }
+
+        let mut body = BytesMut::new();
+        while let Some(chunk) = session.read_request_body().await? {
+            if body.len() + chunk.len() > limit {
+                return Ok(None);
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(Some(body.freeze()))
+    }
+}
+
+/// Create the upstream request for a client method
+fn upstream_request(client: &reqwest::Client, method: &str, url: &str) -> reqwest::RequestBuilder {
+    match method {
+        "GET" => client.get(url),
+        "POST" => client.post(url),
+        "PUT" => client.put(url),
+        "DELETE" => client.delete(url),
+        "PATCH" => client.patch(url),
+        "HEAD" => client.head(url),
+        "OPTIONS" => client.request(reqwest::Method::OPTIONS, url),
+        _ => client.get(url), // Default to GET
+    }
+}
+
+/// HTTP client for requests to `upstream`. `read` limits each wait for
+/// upstream data rather than the whole response, so long-lived streams stay
+/// open while data keeps arriving; the server's request deadline bounds the
+/// total.
+///
+/// HTTPS upstreams are verified unless `tls_verify` is off, trusting the
+/// built-in roots plus any `ca_file`. With `sni`, requests go to the `sni`
+/// name (see [`with_sni`]) and every name resolves to the URL's real host.
+/// Redirects are passed on to the client rather than followed.
+fn upstream_client(
+    proxy_config: &ProxyConfig,
+    upstream: &UpstreamConfig,
+) -> std::result::Result<reqwest::Client, Box<dyn std::error::Error + Send + Sync>> {
+    let timeout = &proxy_config.timeout;
+    let pool = &proxy_config.pool;
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(std::time::Duration::from_secs(timeout.connect))
+        .read_timeout(std::time::Duration::from_secs(timeout.read))
+        .pool_idle_timeout(std::time::Duration::from_secs(pool.idle_timeout))
+        .pool_max_idle_per_host(pool.max_idle)
+        .redirect(reqwest::redirect::Policy::none());
+
+    if !upstream.tls_verify {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    if let Some(ca_file) = &upstream.ca_file {
+        for cert in reqwest::Certificate::from_pem_bundle(&std::fs::read(ca_file)?)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if upstream.sni.is_some() {
+        let url = Url::parse(&upstream.url)?;
+        let host = match url.host() {
+            Some(url::Host::Ipv6(ip)) => ip.to_string(),
+            Some(host) => host.to_string(),
+            None => return Err("Upstream URL has no host".into()),
+        };
+        builder = builder.dns_resolver(Arc::new(UpstreamResolver { host }));
+    }
+
+    Ok(builder.build()?)
+}
+
+/// An upstream response's headers, by lowercase name. `Set-Cookie` cannot be
+/// folded into one comma-separated value, so its values are kept one per line.
+fn response_headers(response: &reqwest::Response) -> HashMap<String, String> {
+    let mut headers: HashMap<String, String> = HashMap::new();
+    for (name, value) in response.headers().iter() {
+        if let Ok(value_str) = value.to_str() {
+            match headers.get_mut(name.as_str()) {
+                Some(cookies) if name.as_str() == "set-cookie" => {
+                    cookies.push('\n');
+                    cookies.push_str(value_str);
+                }
+                _ => {
+                    headers.insert(name.as_str().to_string(), value_str.to_string());
+                }
+            }
+        }
+    }
+    headers
+}
 
+/// Add an upstream header to a client response, one `Set-Cookie` header per
+/// cookie collected by [`response_headers`]
+fn insert_upstream_header(
+    resp_header: &mut ResponseHeader,
+    name: String,
+    value: String,
+) -> Result<()> {
+    if name == "set-cookie" {
+        for cookie in value.split('\n') {
+            resp_header.append_header(name.clone(), cookie)?;
+        }
         Ok(())
+    } else {
+        resp_header.insert_header(name, value)
+    }
+}
+
+/// Apply a route's `cookie_domain` to the upstream's `Set-Cookie` headers
+fn rewrite_cookies(route: &ProxyRoute, header_map: &mut HashMap<String, String>) {
+    let (Some(domain), Some(cookies)) = (&route.cookie_domain, header_map.get_mut("set-cookie"))
+    else {
+        return;
+    };
+    *cookies = cookies
+        .split('\n')
+        .map(|cookie| rewrite_cookie_domain(cookie, domain))
+        .collect::<Vec<_>>()
+        .join("\n");
+}
+
+/// A `Set-Cookie` value with its `Domain` attribute set to `domain`, or
+/// removed when `domain` is empty so the cookie belongs to the host the
+/// client asked for. Cookies without a `Domain` and all other attributes
+/// are kept as sent.
+fn rewrite_cookie_domain(cookie: &str, domain: &str) -> String {
+    let mut parts = cookie.split(';');
+    let mut rewritten = parts.next().unwrap_or_default().to_string();
+    for attribute in parts {
+        let name = attribute.split('=').next().unwrap_or_default().trim();
+        if !name.eq_ignore_ascii_case("domain") {
+            rewritten.push(';');
+            rewritten.push_str(attribute);
+        } else if !domain.is_empty() {
+            rewritten.push_str("; Domain=");
+            rewritten.push_str(domain);
+        }
+    }
+    rewritten
+}
+
+/// Whether the client reached BWS over TLS
+fn client_uses_https(session: &Session) -> bool {
+    session
+        .digest()
+        .is_some_and(|digest| digest.ssl_digest.is_some())
+        || session.req_header().uri.scheme().map(|s| s.as_str()) == Some("https")
+}
+
+/// A redirect target from an upstream as the client should see it. Absolute
+/// URLs on one of `upstreams` move to `public`, and paths lose the upstream's
+/// base path and get back the prefix `strip_prefix` removed. Other targets,
+/// such as other hosts or paths relative to the current one, are kept.
+fn public_redirect(
+    location: &str,
+    upstreams: &[UpstreamConfig],
+    public: &str,
+    route: &ProxyRoute,
+) -> String {
+    let (path, absolute) = if location.starts_with('/') && !location.starts_with("//") {
+        (location.to_string(), false)
+    } else {
+        let Ok(url) = Url::parse(location) else {
+            return location.to_string();
+        };
+        if !upstreams
+            .iter()
+            .any(|upstream| is_upstream_url(upstream, &url))
+        {
+            return location.to_string();
+        }
+        (url[url::Position::BeforePath..].to_string(), true)
+    };
+
+    // Undo join_upstream_path for upstreams with a base path
+    let path = upstreams
+        .iter()
+        .filter_map(|upstream| Url::parse(&upstream.url).ok())
+        .map(|url| url.path().trim_end_matches('/').to_string())
+        .filter(|base| !base.is_empty())
+        .find_map(|base| {
+            path.strip_prefix(base.as_str())
+                .filter(|rest| rest.is_empty() || rest.starts_with(['/', '?', '#']))
+                .map(str::to_string)
+        })
+        .unwrap_or(path);
+    let path = if path.starts_with('/') {
+        path
+    } else {
+        format!("/{}", path)
+    };
+
+    let prefix = if route.strip_prefix {
+        route.path.trim_end_matches('/')
+    } else {
+        ""
+    };
+    if absolute {
+        format!("{}{}{}", public, prefix, path)
+    } else {
+        format!("{}{}", prefix, path)
+    }
+}
+
+/// A `Refresh` header with its `url=` target rewritten by [`public_redirect`]
+fn public_refresh(
+    refresh: &str,
+    upstreams: &[UpstreamConfig],
+    public: &str,
+    route: &ProxyRoute,
+) -> String {
+    match refresh.to_ascii_lowercase().find("url=") {
+        Some(start) => {
+            let (delay, target) = refresh.split_at(start + "url=".len());
+            let target = target.trim().trim_matches(['\'', '"']);
+            format!(
+                "{}{}",
+                delay,
+                public_redirect(target, upstreams, public, route)
+            )
+        }
+        None => refresh.to_string(),
+    }
+}
+
+/// Whether `url` points at `upstream`, by its address or its `sni` name
+fn is_upstream_url(upstream: &UpstreamConfig, url: &Url) -> bool {
+    let Ok(upstream_url) = Url::parse(&upstream.url) else {
+        return false;
+    };
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let names_upstream = upstream_url
+        .host_str()
+        .into_iter()
+        .chain(upstream.sni.as_deref())
+        .any(|name| name.eq_ignore_ascii_case(host));
+    names_upstream && url.port_or_known_default() == upstream_url.port_or_known_default()
+}
+
+/// Whether the upstream left its connection open after this response
+fn upstream_keeps_alive(headers: &HashMap<String, String>) -> bool {
+    !headers.get("connection").is_some_and(|value| {
+        value
+            .split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case("close"))
+    })
+}
+
+/// Address a request URL to the upstream's `sni` name, which is then sent as
+/// the TLS server name and the Host header
+fn with_sni(url: &str, upstream: &UpstreamConfig) -> String {
+    let Some(sni) = &upstream.sni else {
+        return url.to_string();
+    };
+    let Ok(mut sni_url) = Url::parse(url) else {
+        return url.to_string();
+    };
+    match sni_url.set_host(Some(sni)) {
+        Ok(()) => sni_url.to_string(),
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Resolves every name to the upstream's real host, so a client addressing
+/// the `sni` name still connects to the configured address
+struct UpstreamResolver {
+    host: String,
+}
+
+impl reqwest::dns::Resolve for UpstreamResolver {
+    fn resolve(&self, _name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = self.host.clone();
+        Box::pin(async move {
+            let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// Whether an upstream response is relayed as it arrives instead of being
+/// buffered: Server-Sent Events, and bodies sent without a Content-Length
+/// (chunked or delimited by closing the connection)
+fn is_streaming_response(status: u16, headers: &HashMap<String, String>) -> bool {
+    let event_stream = headers.get("content-type").is_some_and(|content_type| {
+        content_type
+            .split(';')
+            .next()
+            .is_some_and(|mime_type| mime_type.trim().eq_ignore_ascii_case("text/event-stream"))
+    });
+    let has_body = !matches!(status, 100..=199 | 204 | 304);
+    event_stream || (has_body && !headers.contains_key("content-length"))
+}
+
+/// Whether a buffered upstream response may be compressed. Partial content
+/// keeps its upstream encoding, since `Content-Range` counts bytes of that
+/// encoding, and a body the upstream already encoded is never encoded twice.
+fn may_compress_response(status: u16, headers: &HashMap<String, String>) -> bool {
+    status != 206
+        && !headers.contains_key("content-range")
+        && !headers.contains_key("content-encoding")
+}
+
+/// `X-Forwarded-For` and `X-Forwarded-Proto` for the upstream request. A
+/// trusted proxy's chain is extended with the proxy's address and its scheme
+/// is kept; whatever anyone else sent is replaced.
+fn forwarded_values(
+    incoming_for: Option<&str>,
+    incoming_proto: Option<&str>,
+    peer: IpAddr,
+    peer_trusted: bool,
+    https: bool,
+) -> (String, String) {
+    let proto = if https { "https" } else { "http" };
+    if !peer_trusted {
+        return (peer.to_string(), proto.to_string());
+    }
+
+    let forwarded_for = match incoming_for.map(str::trim) {
+        Some(chain) if !chain.is_empty() => format!("{}, {}", chain, peer),
+        _ => peer.to_string(),
+    };
+    let proto = incoming_proto
+        .map(|value| value.trim().to_ascii_lowercase())
+        .filter(|value| value == "http" || value == "https")
+        .unwrap_or_else(|| proto.to_string());
+    (forwarded_for, proto)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn create_test_config(method: &str, weights: &[u32]) -> ProxyConfig {
+        let mut config = ProxyConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        config.load_balancing.method = method.to_string();
+        config.upstreams = weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| UpstreamConfig {
+                name: "backend".to_string(),
+                url: format!("http://127.0.0.1:{}", 3001 + i),
+                weight: *weight,
+                max_conns: None,
+                health_path: None,
+                tls_verify: true,
+                ca_file: None,
+                sni: None,
+                backup: false,
+                slow_start: 0,
+            })
+            .collect();
+        config
+    }
+
+    #[test]
+    fn test_random_selection_is_roughly_uniform() {
+        let handler = ProxyHandler::new(create_test_config("random", &[1, 1, 1, 1]));
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for _ in 0..8000 {
+            let upstream = handler.select_upstream("backend", None).unwrap();
+            *counts.entry(upstream.url.clone()).or_insert(0) += 1;
+        }
+
+        // Every upstream is picked, each close to 1/4 of the time
+        assert_eq!(counts.len(), 4);
+        for count in counts.values() {
+            assert!((1600..=2400).contains(count), "skewed count: {}", count);
+        }
+    }
+
+    #[test]
+    fn test_smooth_weighted_round_robin_interleaving() {
+        let handler = ProxyHandler::new(create_test_config("weighted", &[5, 1, 1]));
+
+        let order: Vec<String> = (0..14)
+            .map(|_| {
+                handler
+                    .select_upstream("backend", None)
+                    .unwrap()
+                    .url
+                    .clone()
+            })
+            .collect();
+
+        // nginx SWRR sequence for weights {a:5, b:1, c:1}: a a b a c a a, repeated
+        let (a, b, c) = (
+            "http://127.0.0.1:3001",
+            "http://127.0.0.1:3002",
+            "http://127.0.0.1:3003",
+        );
+        let cycle = [a, a, b, a, c, a, a];
+        let expected: Vec<&str> = cycle.iter().chain(cycle.iter()).copied().collect();
+        assert_eq!(order, expected);
+    }
+
+    #[test]
+    fn test_smooth_weighted_round_robin_exact_share() {
+        let handler = ProxyHandler::new(create_test_config("weighted", &[3, 1]));
+
+        let order: Vec<String> = (0..8)
+            .map(|_| {
+                handler
+                    .select_upstream("backend", None)
+                    .unwrap()
+                    .url
+                    .clone()
+            })
+            .collect();
+
+        // Weight 3 gets exactly 3 of every 4 requests, never 4 in a row
+        for window in order.chunks(4) {
+            let heavy = window
+                .iter()
+                .filter(|url| *url == "http://127.0.0.1:3001")
+                .count();
+            assert_eq!(heavy, 3);
+        }
+        assert_eq!(order[1], "http://127.0.0.1:3001");
+        assert_eq!(order[2], "http://127.0.0.1:3002");
+    }
+
+    #[test]
+    fn test_balancing_carries_over_between_handlers() {
+        // The service builds a handler per request, sharing only the balancer
+        let balancer = Arc::new(UpstreamBalancer::new());
+        let pick = |method: &str, weights: &[u32]| {
+            ProxyHandler::new(create_test_config(method, weights))
+                .with_upstream_balancer(balancer.clone())
+                .select_upstream("backend", None)
+                .unwrap()
+                .url
+                .clone()
+        };
+        let (a, b) = ("http://127.0.0.1:3001", "http://127.0.0.1:3002");
+
+        let weighted: Vec<String> = (0..4).map(|_| pick("weighted", &[3, 1])).collect();
+        assert_eq!(weighted, [a, a, b, a]);
+
+        let round_robin: Vec<String> = (0..4).map(|_| pick("round_robin", &[1, 1, 1])).collect();
+        assert_eq!(round_robin, [a, b, "http://127.0.0.1:3003", a]);
+
+        // Requests still in flight from other handlers steer least connections
+        let busy = balancer.start_request(a);
+        assert_eq!(pick("least_connections", &[1, 1]), b);
+        let _busier = balancer.start_request(b);
+        let _busiest = balancer.start_request(b);
+        assert_eq!(pick("least_connections", &[1, 1]), a);
+        drop(busy);
+        assert_eq!(pick("least_connections", &[1, 1]), a);
+    }
+
+    #[test]
+    fn test_backup_failover_and_recovery() {
+        let mut config = create_test_config("round_robin", &[1, 1, 1]);
+        config.upstreams[2].backup = true;
+        let handler = ProxyHandler::new(config.clone());
+        let (a, b, backup) = (
+            "http://127.0.0.1:3001",
+            "http://127.0.0.1:3002",
+            "http://127.0.0.1:3003",
+        );
+        let picks = |n: usize| -> Vec<String> {
+            (0..n)
+                .map(|_| {
+                    handler
+                        .select_upstream("backend", None)
+                        .unwrap()
+                        .url
+                        .clone()
+                })
+                .collect()
+        };
+        let fail = |url: &str| {
+            for _ in 0..config.health_check.retries {
+                handler
+                    .health
+                    .record_failure(url, &config.health_check, &config.circuit_breaker);
+            }
+        };
+
+        // The backup is idle while any primary is up
+        assert!(picks(6).iter().all(|url| url != backup));
+        fail(a);
+        assert!(picks(4).iter().all(|url| url == b));
+
+        // Every primary down: the backup takes over
+        fail(b);
+        assert!(picks(4).iter().all(|url| url == backup));
+
+        // A recovered primary takes the traffic back
+        handler
+            .health
+            .record_response(a, 200, &config.circuit_breaker);
+        assert!(picks(4).iter().all(|url| url == a));
+
+        // Nothing up: keep trying the primaries
+        fail(a);
+        fail(backup);
+        let urls = picks(4);
+        assert!(urls.iter().all(|url| url == a || url == b));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_upgrades_fail_over() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A backup that records the upgrade it is asked for
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let backup = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let n = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request[..n]).to_string()
+        });
+
+        let site: SiteConfig = toml::from_str(&format!(
+            r#"
+            name = "test"
+            hostname = "example.com"
+            port = 8080
+            static_dir = "/tmp"
+
+            [proxy]
+            enabled = true
+
+            [[proxy.upstreams]]
+            name = "backend"
+            url = "http://127.0.0.1:3001"
+
+            [[proxy.upstreams]]
+            name = "backend"
+            url = "http://{}"
+            backup = true
+
+            [[proxy.routes]]
+            path = "/ws"
+            upstream = "backend"
+            websocket = true
+            "#,
+            addr
+        ))
+        .unwrap();
+        let handler = ProxyHandler::new(site.proxy.clone());
+        for _ in 0..site.proxy.health_check.retries {
+            handler.health.record_failure(
+                "http://127.0.0.1:3001",
+                &site.proxy.health_check,
+                &site.proxy.circuit_breaker,
+            );
+        }
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        client
+            .write_all(
+                b"GET /ws/chat HTTP/1.1\r\nHost: example.com\r\nUpgrade: websocket\r\n\
+                  Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut session = Session::new_h1(Box::new(server));
+        assert!(session.read_request().await.unwrap());
+        let _ = handler
+            .handle_proxy_request(&mut session, &site, "/ws/chat", None)
+            .await;
+
+        // The primary is down, so the upgrade went to the backup
+        let request = tokio::time::timeout(std::time::Duration::from_secs(5), backup)
+            .await
+            .expect("upgrade did not reach the backup")
+            .unwrap();
+        assert!(request.starts_with("GET /ws/chat "), "{}", request);
+    }
+
+    #[test]
+    fn test_open_circuits_are_not_selected() {
+        let mut config = create_test_config("round_robin", &[1, 1]);
+        config.circuit_breaker.enabled = true;
+        config.circuit_breaker.failure_threshold = 1;
+        let handler = ProxyHandler::new(config.clone());
+        let open = |url: &str| {
+            handler
+                .health
+                .record_response(url, 503, &config.circuit_breaker)
+        };
+
+        open("http://127.0.0.1:3001");
+        for _ in 0..4 {
+            let upstream = handler.select_upstream("backend", None).unwrap();
+            assert_eq!(upstream.url, "http://127.0.0.1:3002");
+        }
+
+        // Every circuit open: fail fast instead of hammering the upstreams
+        open("http://127.0.0.1:3002");
+        let err = handler.select_upstream("backend", None).unwrap_err();
+        assert!(matches!(err.etype(), ErrorType::HTTPStatus(503)));
+    }
+
+    #[test]
+    fn test_slow_start_after_recovery() {
+        let mut config = create_test_config("weighted", &[1, 1]);
+        config.upstreams[1].slow_start = 600;
+        let handler = ProxyHandler::new(config.clone());
+        let recovering = "http://127.0.0.1:3002";
+        let share = || {
+            (0..202)
+                .filter(|_| handler.select_upstream("backend", None).unwrap().url == recovering)
+                .count()
+        };
+
+        // Not recovering from anything: an even split
+        assert_eq!(share(), 101);
+
+        // Just back up: it starts from almost no traffic
+        for _ in 0..config.health_check.retries {
+            handler.health.record_failure(
+                recovering,
+                &config.health_check,
+                &config.circuit_breaker,
+            );
+        }
+        handler
+            .health
+            .record_response(recovering, 200, &config.circuit_breaker);
+        assert!(share() <= 4);
+    }
+
+    #[test]
+    fn test_route_request_headers_override_site_headers() {
+        let mut config = create_test_config("round_robin", &[1]);
+        config
+            .headers
+            .add
+            .insert("X-Api-Key".to_string(), "site-key".to_string());
+        config
+            .headers
+            .add
+            .insert("X-Site".to_string(), "main".to_string());
+        let route = ProxyRoute {
+            path: "/v2".to_string(),
+            upstream: "backend".to_string(),
+            strip_prefix: false,
+            rewrite_target: None,
+            websocket: false,
+            request_headers: HashMap::from([
+                ("x-api-key".to_string(), "route-key".to_string()),
+                ("X-Api-Version".to_string(), "2".to_string()),
+            ]),
+            response_headers: HashMap::new(),
+            proxy_redirect: false,
+            cookie_domain: None,
+        };
+        let handler = ProxyHandler::new(config);
+
+        let mut headers = handler.route_request_headers(&route);
+        headers.sort();
+        assert_eq!(
+            headers,
+            [
+                ("X-Api-Version".to_string(), "2".to_string()),
+                ("X-Site".to_string(), "main".to_string()),
+                ("x-api-key".to_string(), "route-key".to_string()),
+            ]
+        );
+    }
+
+    fn test_upstream(url: &str) -> UpstreamConfig {
+        UpstreamConfig {
+            name: "backend".to_string(),
+            url: url.to_string(),
+            weight: 1,
+            max_conns: None,
+            health_path: None,
+            tls_verify: true,
+            ca_file: None,
+            sni: None,
+            backup: false,
+            slow_start: 0,
+        }
+    }
+
+    /// HTTPS backend answering "ok" with a self-signed certificate for
+    /// `backend.internal`. Returns its port and the certificate as PEM.
+    fn spawn_tls_backend() -> (u16, String) {
+        use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+        use std::io::{Read, Write};
+
+        let cert =
+            rcgen::generate_simple_self_signed(vec!["backend.internal".to_string()]).unwrap();
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![CertificateDer::from(cert.serialize_der().unwrap())],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.serialize_private_key_der())),
+        )
+        .unwrap();
+        let config = Arc::new(config);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let connection = rustls::ServerConnection::new(config.clone()).unwrap();
+                let mut tls = rustls::StreamOwned::new(connection, stream);
+                let mut request = [0u8; 1024];
+                // Clients refusing the certificate end the handshake here
+                if tls.read(&mut request).is_ok() {
+                    let _ = tls.write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                    );
+                    let _ = tls.flush();
+                }
+            }
+        });
+
+        (port, cert.serialize_pem().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_upstream_tls_options() {
+        let (port, ca_pem) = spawn_tls_backend();
+        let dir = tempfile::tempdir().unwrap();
+        let ca_file = dir.path().join("ca.pem");
+        std::fs::write(&ca_file, ca_pem).unwrap();
+
+        let fetch = |upstream: UpstreamConfig| async move {
+            let client = upstream_client(&ProxyConfig::default(), &upstream).unwrap();
+            let url = with_sni(&format!("{}/status", upstream.url), &upstream);
+            client
+                .get(url)
+                .send()
+                .await
+                .map(|response| response.status())
+        };
+        let by_ip = format!("https://127.0.0.1:{}", port);
+
+        // Internal CA plus the certificate's name, while connecting by IP
+        let mut upstream = test_upstream(&by_ip);
+        upstream.ca_file = Some(ca_file.to_string_lossy().to_string());
+        upstream.sni = Some("backend.internal".to_string());
+        assert_eq!(fetch(upstream.clone()).await.unwrap(), 200);
+
+        // The IP does not match the certificate's name
+        upstream.sni = None;
+        assert!(fetch(upstream.clone()).await.is_err());
+
+        // The name matches, but the CA is not trusted
+        upstream.ca_file = None;
+        upstream.sni = Some("backend.internal".to_string());
+        assert!(fetch(upstream.clone()).await.is_err());
+
+        // Verification off accepts any certificate
+        let mut unverified = test_upstream(&by_ip);
+        unverified.tls_verify = false;
+        assert_eq!(fetch(unverified).await.unwrap(), 200);
+
+        // A missing CA file fails the client
+        let mut broken = test_upstream(&by_ip);
+        broken.ca_file = Some(dir.path().join("missing.pem").to_string_lossy().to_string());
+        assert!(upstream_client(&ProxyConfig::default(), &broken).is_err());
+    }
+
+    #[test]
+    fn test_with_sni() {
+        let mut upstream = test_upstream("https://10.0.0.5:8443");
+        assert_eq!(
+            with_sni("https://10.0.0.5:8443/api?x=1", &upstream),
+            "https://10.0.0.5:8443/api?x=1"
+        );
+        upstream.sni = Some("api.internal".to_string());
+        assert_eq!(
+            with_sni("https://10.0.0.5:8443/api?x=1", &upstream),
+            "https://api.internal:8443/api?x=1"
+        );
+    }
+
+    #[test]
+    fn test_public_redirect() {
+        let mut upstream = test_upstream("http://10.0.0.5:3000/api");
+        upstream.sni = Some("backend.internal".to_string());
+        let upstreams = [upstream];
+        let route: ProxyRoute = toml::from_str(
+            r#"
+            path = "/app"
+            upstream = "backend"
+            strip_prefix = true
+            proxy_redirect = true
+            "#,
+        )
+        .unwrap();
+        let public = "https://example.com";
+        let rewrite = |location: &str| public_redirect(location, &upstreams, public, &route);
+
+        // Absolute URLs on the upstream, by address or sni name
+        assert_eq!(
+            rewrite("http://10.0.0.5:3000/api/login?next=%2F"),
+            "https://example.com/app/login?next=%2F"
+        );
+        assert_eq!(
+            rewrite("http://backend.internal:3000/api"),
+            "https://example.com/app/"
+        );
+        // Paths on the same host stay relative
+        assert_eq!(rewrite("/api/login"), "/app/login");
+        assert_eq!(rewrite("/other"), "/app/other");
+        // Other hosts, ports and relative paths are left alone
+        assert_eq!(
+            rewrite("https://sso.example.org/"),
+            "https://sso.example.org/"
+        );
+        assert_eq!(
+            rewrite("http://10.0.0.5:8080/api"),
+            "http://10.0.0.5:8080/api"
+        );
+        assert_eq!(rewrite("next"), "next");
+
+        assert_eq!(
+            public_refresh(
+                "5; url=http://10.0.0.5:3000/api/done",
+                &upstreams,
+                public,
+                &route
+            ),
+            "5; url=https://example.com/app/done"
+        );
+        assert_eq!(public_refresh("5", &upstreams, public, &route), "5");
+    }
+
+    #[tokio::test]
+    async fn test_internal_redirects_rewritten() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A backend redirecting to its own address, which clients cannot reach
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await.unwrap();
+                let response = format!(
+                    "HTTP/1.1 302 Found\r\nLocation: http://{}/login?next=%2Fdashboard\r\n\
+                     Content-Length: 0\r\nConnection: close\r\n\r\n",
+                    addr
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        for (proxy_redirect, expected) in [
+            (
+                true,
+                "location: http://example.com/app/login?next=%2fdashboard".to_string(),
+            ),
+            (
+                false,
+                format!("location: http://{}/login?next=%2fdashboard", addr),
+            ),
+        ] {
+            let site: SiteConfig = toml::from_str(&format!(
+                r#"
+                name = "test"
+                hostname = "example.com"
+                port = 8080
+                static_dir = "/tmp"
+
+                [proxy]
+                enabled = true
+
+                [[proxy.upstreams]]
+                name = "backend"
+                url = "http://{}"
+
+                [[proxy.routes]]
+                path = "/app"
+                upstream = "backend"
+                strip_prefix = true
+                proxy_redirect = {}
+                "#,
+                addr, proxy_redirect
+            ))
+            .unwrap();
+            let handler = ProxyHandler::new(site.proxy.clone());
+
+            let (mut client, server) = tokio::io::duplex(1 << 16);
+            client
+                .write_all(b"GET /app/dashboard HTTP/1.1\r\nHost: example.com\r\n\r\n")
+                .await
+                .unwrap();
+            let mut session = Session::new_h1(Box::new(server));
+            assert!(session.read_request().await.unwrap());
+            assert!(handler
+                .handle_proxy_request(&mut session, &site, "/app/dashboard", None)
+                .await
+                .unwrap());
+            drop(session);
+
+            let mut response = Vec::new();
+            client.read_to_end(&mut response).await.unwrap();
+            let head = String::from_utf8_lossy(&response).to_lowercase();
+            // The redirect reaches the client instead of being followed
+            assert!(head.starts_with("http/1.1 302"), "{}", head);
+            assert!(head.contains(&expected), "{}", head);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_busy_upstream_pool_answers_503() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let site: SiteConfig = toml::from_str(
+            r#"
+            name = "test"
+            hostname = "example.com"
+            port = 8080
+            static_dir = "/tmp"
+
+            [proxy]
+            enabled = true
+
+            [proxy.timeout]
+            connect = 1
+
+            [[proxy.upstreams]]
+            name = "backend"
+            url = "http://127.0.0.1:3001"
+            max_conns = 1
+
+            [[proxy.routes]]
+            path = "/api"
+            upstream = "backend"
+            "#,
+        )
+        .unwrap();
+        let handler = ProxyHandler::new(site.proxy.clone());
+
+        // Another request holds the only connection
+        let upstream = &site.proxy.upstreams[0];
+        let pool = handler
+            .pools
+            .get(upstream, &site.proxy.timeout, &site.proxy.pool, || {
+                upstream_client(&site.proxy, upstream)
+            })
+            .unwrap();
+        let _busy = pool.acquire(Duration::from_secs(1)).await.unwrap();
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        client
+            .write_all(b"GET /api/items HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await
+            .unwrap();
+        let mut session = Session::new_h1(Box::new(server));
+        assert!(session.read_request().await.unwrap());
+        assert!(handler
+            .handle_proxy_request(&mut session, &site, "/api/items", None)
+            .await
+            .unwrap());
+        drop(session);
+
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        let head = String::from_utf8_lossy(&response);
+        assert!(head.starts_with("HTTP/1.1 503"), "{}", head);
+    }
+
+    #[test]
+    fn test_rewrite_cookie_domain() {
+        let cookie = "session=abc; Domain=backend.internal; Path=/; Secure; HttpOnly; SameSite=Lax";
+        assert_eq!(
+            rewrite_cookie_domain(cookie, "example.com"),
+            "session=abc; Domain=example.com; Path=/; Secure; HttpOnly; SameSite=Lax"
+        );
+        assert_eq!(
+            rewrite_cookie_domain(cookie, ""),
+            "session=abc; Path=/; Secure; HttpOnly; SameSite=Lax"
+        );
+        // Matched by attribute name only, in any case
+        assert_eq!(
+            rewrite_cookie_domain("domain=x; domain=.internal", ""),
+            "domain=x"
+        );
+        assert_eq!(
+            rewrite_cookie_domain("theme=dark; Path=/", "example.com"),
+            "theme=dark; Path=/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cookie_domains_rewritten() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A backend setting cookies for its internal name
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await.unwrap();
+                stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\n\
+                          Set-Cookie: session=abc; Domain=backend.internal; Path=/; HttpOnly\r\n\
+                          Set-Cookie: theme=dark; domain=.backend.internal; Secure; SameSite=Strict\r\n\
+                          Content-Length: 2\r\nConnection: close\r\n\r\nok",
+                    )
+                    .await
+                    .unwrap();
+            }
+        });
+
+        for (cookie_domain, expected) in [
+            (
+                "example.com",
+                [
+                    "set-cookie: session=abc; domain=example.com; path=/; httponly",
+                    "set-cookie: theme=dark; domain=example.com; secure; samesite=strict",
+                ],
+            ),
+            (
+                "",
+                [
+                    "set-cookie: session=abc; path=/; httponly",
+                    "set-cookie: theme=dark; secure; samesite=strict",
+                ],
+            ),
+        ] {
+            let site: SiteConfig = toml::from_str(&format!(
+                r#"
+                name = "test"
+                hostname = "example.com"
+                port = 8080
+                static_dir = "/tmp"
+
+                [proxy]
+                enabled = true
+
+                [[proxy.upstreams]]
+                name = "backend"
+                url = "http://{}"
+
+                [[proxy.routes]]
+                path = "/"
+                upstream = "backend"
+                cookie_domain = "{}"
+                "#,
+                addr, cookie_domain
+            ))
+            .unwrap();
+            let handler = ProxyHandler::new(site.proxy.clone());
+
+            let (mut client, server) = tokio::io::duplex(1 << 16);
+            client
+                .write_all(b"GET /login HTTP/1.1\r\nHost: example.com\r\n\r\n")
+                .await
+                .unwrap();
+            let mut session = Session::new_h1(Box::new(server));
+            assert!(session.read_request().await.unwrap());
+            assert!(handler
+                .handle_proxy_request(&mut session, &site, "/login", None)
+                .await
+                .unwrap());
+            drop(session);
+
+            let mut response = Vec::new();
+            client.read_to_end(&mut response).await.unwrap();
+            let head = String::from_utf8_lossy(&response).to_lowercase();
+            // Both cookies arrive, each in its own header
+            for cookie in expected {
+                assert!(head.contains(cookie), "{}", head);
+            }
+        }
+    }
+
+    #[test]
+    fn test_streaming_response_detection() {
+        let headers = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+
+        assert!(is_streaming_response(
+            200,
+            &headers(&[("content-type", "text/event-stream; charset=utf-8")])
+        ));
+        // Even with a declared length
+        assert!(is_streaming_response(
+            200,
+            &headers(&[
+                ("content-type", "text/event-stream"),
+                ("content-length", "64")
+            ])
+        ));
+        // Chunked or close-delimited bodies
+        assert!(is_streaming_response(
+            200,
+            &headers(&[
+                ("content-type", "text/html"),
+                ("transfer-encoding", "chunked")
+            ])
+        ));
+        assert!(!is_streaming_response(
+            200,
+            &headers(&[("content-type", "text/html"), ("content-length", "64")])
+        ));
+        // Responses that never have a body
+        assert!(!is_streaming_response(304, &headers(&[])));
+        assert!(!is_streaming_response(204, &headers(&[])));
+    }
+
+    #[test]
+    fn test_forwarded_headers_from_trusted_proxies() {
+        let peer: IpAddr = "10.0.0.5".parse().unwrap();
+
+        // A client cannot forge its address or scheme
+        assert_eq!(
+            forwarded_values(Some("1.2.3.4"), Some("https"), peer, false, false),
+            ("10.0.0.5".to_string(), "http".to_string())
+        );
+
+        // A trusted proxy's chain and scheme are carried on
+        assert_eq!(
+            forwarded_values(Some("198.51.100.9"), Some("HTTPS"), peer, true, false),
+            ("198.51.100.9, 10.0.0.5".to_string(), "https".to_string())
+        );
+        assert_eq!(
+            forwarded_values(None, Some("gopher"), peer, true, true),
+            ("10.0.0.5".to_string(), "https".to_string())
+        );
+    }
+
+    #[test]
+    fn test_partial_and_encoded_responses_not_compressed() {
+        let headers = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+
+        assert!(may_compress_response(
+            200,
+            &headers(&[("content-type", "text/html")])
+        ));
+        // Compressing a range would leave Content-Range counting the wrong bytes
+        assert!(!may_compress_response(
+            206,
+            &headers(&[("content-range", "bytes 0-99/1000")])
+        ));
+        assert!(!may_compress_response(
+            206,
+            &headers(&[("content-type", "text/html")])
+        ));
+        // A precompressed body keeps its single encoding
+        assert!(!may_compress_response(
+            200,
+            &headers(&[("content-encoding", "gzip")])
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sse_upstream_not_buffered() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // An SSE upstream that sends one event and then holds the stream open
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                      Transfer-Encoding: chunked\r\n\r\n\
+                      d\r\ndata: first\n\n\r\n",
+                )
+                .await
+                .unwrap();
+            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+        });
+
+        let upstream = test_upstream(&format!("http://{}", addr));
+        let client = upstream_client(&ProxyConfig::default(), &upstream).unwrap();
+        let mut response = client
+            .get(format!("http://{}/events", addr))
+            .send()
+            .await
+            .unwrap();
+        let headers = response
+            .headers()
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_str().unwrap().to_string()))
+            .collect();
+        assert!(is_streaming_response(response.status().as_u16(), &headers));
+
+        // The first event arrives while the upstream is still open
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), response.chunk())
+            .await
+            .expect("event was buffered")
+            .unwrap()
+            .unwrap();
+        assert_eq!(&chunk[..], b"data: first\n\n");
+    }
+
+    #[tokio::test]
+    async fn test_cache_does_not_hold_back_streams() {
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+        // An SSE upstream holding every stream open for a while
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut request = [0u8; 1024];
+                    let _ = stream.read(&mut request).await;
+                    stream
+                        .write_all(
+                            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                              Transfer-Encoding: chunked\r\n\r\n\
+                              d\r\ndata: first\n\n\r\n",
+                        )
+                        .await
+                        .unwrap();
+                    tokio::time::sleep(Duration::from_secs(3)).await;
+                    let _ = stream.write_all(b"0\r\n\r\n").await;
+                });
+            }
+        });
+
+        let site: SiteConfig = toml::from_str(&format!(
+            r#"
+            name = "test"
+            hostname = "localhost"
+            port = 8080
+            static_dir = "/tmp"
+
+            [proxy]
+            enabled = true
+
+            [proxy.cache]
+            enabled = true
+
+            [[proxy.upstreams]]
+            name = "backend"
+            url = "http://{}"
+
+            [[proxy.routes]]
+            path = "/"
+            upstream = "backend"
+            "#,
+            addr
+        ))
+        .unwrap();
+        let handler = ProxyHandler::new(site.proxy.clone());
+
+        async fn proxy(handler: &ProxyHandler, site: &SiteConfig, server: DuplexStream) {
+            let mut session = Session::new_h1(Box::new(server));
+            assert!(session.read_request().await.unwrap());
+            assert!(handler
+                .handle_proxy_request(&mut session, site, "/events", None)
+                .await
+                .unwrap());
+        }
+        async fn first_event(client: &mut DuplexStream) {
+            let mut received = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !String::from_utf8_lossy(&received).contains("data: first") {
+                let n = client.read(&mut buf).await.unwrap();
+                assert!(n > 0, "stream ended without an event");
+                received.extend_from_slice(&buf[..n]);
+            }
+        }
+
+        // Two clients of the same stream at once: the second must not wait
+        // for the first stream to end
+        let request = b"GET /events HTTP/1.1\r\nHost: localhost:8080\r\n\r\n";
+        let (mut first, first_server) = tokio::io::duplex(1 << 16);
+        let (mut second, second_server) = tokio::io::duplex(1 << 16);
+        first.write_all(request).await.unwrap();
+        second.write_all(request).await.unwrap();
+        let events = async {
+            tokio::time::timeout(Duration::from_secs(2), async {
+                tokio::join!(first_event(&mut first), first_event(&mut second))
+            })
+            .await
+            .expect("a client waited for the other stream to end");
+        };
+        tokio::join!(
+            proxy(&handler, &site, first_server),
+            proxy(&handler, &site, second_server),
+            events
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plain_upstream_responses_compressed() {
+        use std::io::Read;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A backend answering in plain text, with a length or chunked
+        let text = "The upstream sends this uncompressed. ".repeat(100);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let body = text.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 1024];
+                let n = stream.read(&mut request).await.unwrap();
+                let response = if request[..n].starts_with(b"GET /chunked") {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\
+                         Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+                         {:x}\r\n{}\r\n0\r\n\r\n",
+                        body.len(),
+                        body
+                    )
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\
+                         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let site: SiteConfig = toml::from_str(&format!(
+            r#"
+            name = "test"
+            hostname = "localhost"
+            port = 8080
+            static_dir = "/tmp"
+
+            [compression]
+            enabled = true
+
+            [proxy]
+            enabled = true
+
+            [[proxy.upstreams]]
+            name = "backend"
+            url = "http://{}"
+
+            [[proxy.routes]]
+            path = "/"
+            upstream = "backend"
+            "#,
+            addr
+        ))
+        .unwrap();
+        let handler = ProxyHandler::new(site.proxy.clone());
+
+        for path in ["/fixed", "/chunked"] {
+            let (mut client, server) = tokio::io::duplex(1 << 16);
+            let request = format!(
+                "GET {} HTTP/1.1\r\nHost: localhost:8080\r\nAccept-Encoding: gzip\r\n\r\n",
+                path
+            );
+            client.write_all(request.as_bytes()).await.unwrap();
+            let mut session = Session::new_h1(Box::new(server));
+            assert!(session.read_request().await.unwrap());
+            assert!(handler
+                .handle_proxy_request(&mut session, &site, path, None)
+                .await
+                .unwrap());
+            drop(session);
+
+            let mut response = Vec::new();
+            client.read_to_end(&mut response).await.unwrap();
+            let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let head = String::from_utf8_lossy(&response[..split]).to_lowercase();
+            assert!(head.contains("content-encoding: gzip"), "{}", head);
+            assert!(head.contains("vary: accept-encoding"), "{}", head);
+
+            let mut body = response[split + 4..].to_vec();
+            if head.contains("transfer-encoding: chunked") {
+                let mut chunks = Vec::new();
+                let mut rest = &body[..];
+                loop {
+                    let line = rest.windows(2).position(|w| w == b"\r\n").unwrap();
+                    let size =
+                        usize::from_str_radix(std::str::from_utf8(&rest[..line]).unwrap(), 16)
+                            .unwrap();
+                    if size == 0 {
+                        break;
+                    }
+                    chunks.extend_from_slice(&rest[line + 2..line + 2 + size]);
+                    rest = &rest[line + 2 + size + 2..];
+                }
+                body = chunks;
+            }
+            assert!(body.len() < text.len(), "{} was not compressed", path);
+            let mut decoded = String::new();
+            flate2::read::GzDecoder::new(&body[..])
+                .read_to_string(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, text, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy_cache_follows_upstream_cache_control() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A backend whose paths name their Cache-Control, counting requests
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 1024];
+                let n = stream.read(&mut request).await.unwrap();
+                let count = counter.fetch_add(1, Ordering::SeqCst);
+                let request = String::from_utf8_lossy(&request[..n]);
+                let cache_control = match request.split(' ').nth(1).unwrap() {
+                    "/shared" => "public, s-maxage=60",
+                    "/private" => "private, max-age=60",
+                    "/no-store" => "no-store",
+                    _ => "no-cache",
+                };
+                let body = format!("response {}", count);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nCache-Control: {}\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n{}",
+                    cache_control,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let site: SiteConfig = toml::from_str(&format!(
+            r#"
+            name = "test"
+            hostname = "localhost"
+            port = 8080
+            static_dir = "/tmp"
+
+            [proxy]
+            enabled = true
+
+            [proxy.cache]
+            enabled = true
+
+            [[proxy.upstreams]]
+            name = "backend"
+            url = "http://{}"
+
+            [[proxy.routes]]
+            path = "/"
+            upstream = "backend"
+            "#,
+            addr
+        ))
+        .unwrap();
+        let cache = Arc::new(ProxyCache::new());
+        let fetch = |request: String| {
+            let handler = ProxyHandler::new(site.proxy.clone()).with_response_cache(cache.clone());
+            let site = site.clone();
+            async move {
+                let (mut client, server) = tokio::io::duplex(1 << 16);
+                client.write_all(request.as_bytes()).await.unwrap();
+                let mut session = Session::new_h1(Box::new(server));
+                assert!(session.read_request().await.unwrap());
+                let path = session.req_header().uri.path().to_string();
+                assert!(handler
+                    .handle_proxy_request(&mut session, &site, &path, None)
+                    .await
+                    .unwrap());
+                drop(session);
+                let mut response = String::new();
+                client.read_to_string(&mut response).await.unwrap();
+                response.to_lowercase()
+            }
+        };
+        let get = |path: &str| format!("GET {} HTTP/1.1\r\nHost: localhost:8080\r\n\r\n", path);
+
+        // Shared responses are served from the cache until they expire
+        let first = fetch(get("/shared")).await;
+        assert!(first.contains("x-cache: miss"), "{}", first);
+        let second = fetch(get("/shared")).await;
+        assert!(second.contains("x-cache: hit"), "{}", second);
+        assert!(second.contains("age: 0"), "{}", second);
+        assert!(second.ends_with("response 0"));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // The client can ask for a fresh copy
+        let bypass = fetch(
+            "GET /shared HTTP/1.1\r\nHost: localhost:8080\r\nCache-Control: no-cache\r\n\r\n"
+                .to_string(),
+        )
+        .await;
+        assert!(bypass.ends_with("response 1"));
+
+        // Private, no-store and no-cache responses always reach the upstream
+        for path in ["/private", "/no-store", "/no-cache"] {
+            let before = requests.load(Ordering::SeqCst);
+            fetch(get(path)).await;
+            let again = fetch(get(path)).await;
+            assert!(again.contains("x-cache: miss"), "{}: {}", path, again);
+            assert_eq!(requests.load(Ordering::SeqCst), before + 2, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_join_upstream_path() {
+        let plain = Url::parse("http://backend:8080").unwrap();
+        assert_eq!(join_upstream_path(&plain, "/users?id=1"), "/users?id=1");
+        assert_eq!(join_upstream_path(&plain, ""), "/");
+
+        let based = Url::parse("http://backend/api/v2").unwrap();
+        assert_eq!(join_upstream_path(&based, "/users"), "/api/v2/users");
+        assert_eq!(join_upstream_path(&based, "users"), "/api/v2/users");
+        assert_eq!(join_upstream_path(&based, "/"), "/api/v2/");
+
+        let trailing = Url::parse("http://backend/api/v2/").unwrap();
+        assert_eq!(join_upstream_path(&trailing, "/users"), "/api/v2/users");
     }
 }
@@ -1,52 +1,376 @@
 use crate::config::SiteConfig;
-use crate::middleware::compression::{CompressionMethod, CompressionMiddleware};
+use crate::core::deadline::response_started;
+use crate::core::utils::http::{self, RangeRequest};
+use crate::handlers::directory_listing;
+use crate::handlers::file_cache::FileCache;
+use crate::handlers::mapped_file::MappedFile;
+use crate::middleware::compression::{merge_vary, CompressionMethod, CompressionMiddleware};
+use crate::monitoring::metrics::metrics;
+use bytes::Bytes;
 use pingora::http::ResponseHeader;
 use pingora::prelude::*;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::fs;
 
+/// Image types that can be replaced by a variant in a newer format
+const IMAGE_VARIANT_SOURCES: &[&str] = &["jpg", "jpeg", "png", "gif"];
+/// Variant formats, most preferred first
+const IMAGE_VARIANTS: &[&str] = &["avif", "webp"];
+/// Extensions tried for extensionless paths, in order of preference on ties
+const NEGOTIABLE_EXTENSIONS: &[&str] = &["html", "htm", "json", "xml", "txt"];
+
+/// A file's content with its validators
+struct LoadedFile {
+    content: Bytes,
+    /// Content hash for cached files, otherwise derived from mtime and size
+    etag: String,
+    modified: Option<SystemTime>,
+}
+
+/// ETag from a file's modification time and size, for files too large to hash
+fn metadata_etag(metadata: &std::fs::Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs());
+    format!("\"{:x}-{:x}\"", modified, metadata.len())
+}
+
 /// Handler for serving static files from disk.
 pub struct StaticFileHandler {
-    // Future: Add caching, compression, etc.
+    /// Sizes of files served from memory maps (inclusive), if enabled
+    mmap_size_range: Option<(usize, usize)>,
+    /// In-memory cache of small files, if enabled
+    file_cache: Option<FileCache>,
 }
 
 impl StaticFileHandler {
     /// Create a new StaticFileHandler
     pub fn new() -> Self {
-        Self {}
+        Self {
+            mmap_size_range: None,
+            file_cache: None,
+        }
+    }
+
+    /// Serve files whose size is within `min..=max` bytes from memory maps
+    pub fn with_mmap(mut self, min: usize, max: usize) -> Self {
+        self.mmap_size_range = Some((min, max));
+        self
+    }
+
+    /// Keep up to `capacity` files of at most `max_size` bytes in memory
+    pub fn with_file_cache(mut self, capacity: usize, max_size: usize) -> Self {
+        self.file_cache = Some(FileCache::new(capacity, max_size));
+        self
+    }
+
+    /// Drop all cached file contents, e.g. after a configuration reload
+    pub fn clear_file_cache(&self) {
+        if let Some(cache) = &self.file_cache {
+            let dropped = cache.clear();
+            log::debug!("Cleared {} files from the static file cache", dropped);
+        }
     }
 
     /// Handle a static file request for the given session, site, and path.
     /// Resolves the file path, checks security, and serves the file or a 404 page.
     pub async fn handle(&self, session: &mut Session, site: &SiteConfig, path: &str) -> Result<()> {
-        let file_path = self.resolve_file_path(site, path).await;
+        let referer = session
+            .req_header()
+            .headers
+            .get("referer")
+            .map(|h| h.to_str().unwrap_or_default());
+        if self.is_hotlink(site, path, referer) {
+            log::info!(
+                "Blocked hotlink to {} on site '{}' from referer {:?}",
+                path,
+                site.name,
+                referer
+            );
+            return self.handle_hotlink(session, site).await;
+        }
+
+        let accept = session
+            .req_header()
+            .headers
+            .get("accept")
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+        let file_path = self.resolve_file_path(site, path, accept.as_deref()).await;
+        let vary_accept = site.content_negotiation && self.is_negotiable(path);
 
         match file_path {
-            Some(resolved_path) => self.serve_file(session, site, &resolved_path).await,
-            None => self.handle_not_found(session, site).await,
+            Some(resolved_path) => {
+                if let Some(status) = self.blocked_extension_status(site, &resolved_path) {
+                    log::info!(
+                        "Refused {} on site '{}' with {}: extension not allowed",
+                        path,
+                        site.name,
+                        status
+                    );
+                    return self.handle_refused(session, site, status).await;
+                }
+                self.serve_file(session, site, path, &resolved_path, vary_accept)
+                    .await
+            }
+            None => {
+                if self.escapes_root(site, path) {
+                    metrics().record_path_traversal();
+                    return self
+                        .handle_refused(session, site, site.traversal_status)
+                        .await;
+                }
+                if site.autoindex {
+                    if let Some(dir_path) = self.resolve_directory(site, path).await {
+                        return self
+                            .serve_directory_listing(
+                                session,
+                                site,
+                                path,
+                                &dir_path,
+                                accept.as_deref(),
+                            )
+                            .await;
+                    }
+                }
+                self.handle_not_found(session, site).await
+            }
         }
     }
 
-    /// Resolve the requested path to a file on disk, checking for index files and path safety.
-    async fn resolve_file_path(&self, site: &SiteConfig, request_path: &str) -> Option<String> {
+    /// Whether the requested path leads outside `static_dir` or its alias directory
+    fn escapes_root(&self, site: &SiteConfig, request_path: &str) -> bool {
+        let (root, request_path) = site.root_for(request_path);
+        // A missing root fails the safety check too, but is only a miss
+        Path::new(root).is_dir() && !self.is_path_safe(root, &self.clean_path(request_path))
+    }
+
+    /// Resolve the requested path to a directory inside `static_dir` or an alias
+    async fn resolve_directory(&self, site: &SiteConfig, request_path: &str) -> Option<String> {
+        let (root, request_path) = site.root_for(request_path);
         let clean_path = self.clean_path(request_path);
+        if !self.is_path_safe(root, &clean_path) {
+            return None;
+        }
+
+        let dir_path = format!("{}/{}", root, clean_path);
+        fs::metadata(&dir_path)
+            .await
+            .is_ok_and(|m| m.is_dir())
+            .then_some(dir_path)
+    }
+
+    /// Serve a directory listing: JSON when the client prefers
+    /// `application/json` over `text/html`, HTML otherwise
+    async fn serve_directory_listing(
+        &self,
+        session: &mut Session,
+        site: &SiteConfig,
+        request_path: &str,
+        dir_path: &str,
+        accept: Option<&str>,
+    ) -> Result<()> {
+        let entries = match directory_listing::scan_directory(Path::new(dir_path)).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::warn!("Failed to list directory {}: {}", dir_path, e);
+                return self.handle_not_found(session, site).await;
+            }
+        };
+
+        let wants_json = accept.is_some_and(|accept| {
+            http::accept_quality(accept, "application/json")
+                > http::accept_quality(accept, "text/html")
+        });
+        let request_path = request_path.split('?').next().unwrap_or(request_path);
+        let (content_type, body) = if wants_json {
+            ("application/json", directory_listing::render_json(&entries))
+        } else {
+            (
+                "text/html; charset=utf-8",
+                directory_listing::render_html(request_path, &entries),
+            )
+        };
+
+        let mut header = ResponseHeader::build(200, Some(4))?;
+        header.insert_header("Content-Type", content_type)?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+        merge_vary(&mut header, "Accept")?;
+
+        // Cache headers
+        for (key, value) in site.get_cache_headers(request_path, false) {
+            header.insert_header(key, value)?;
+        }
+
+        // CORS headers
+        for (key, value) in site.get_cors_headers() {
+            header.insert_header(key, value)?;
+        }
+
+        // HSTS
+        if let Some((key, value)) = site.get_hsts_header() {
+            header.insert_header(key, value)?;
+        }
+
+        // Custom site headers
+        for (key, value) in &site.headers {
+            header.insert_header(key.clone(), value.clone())?;
+        }
+
+        session
+            .write_response_header(Box::new(header), false)
+            .await?;
+        session
+            .write_response_body(Some(body.into_bytes().into()), true)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Whether the request is for protected media from a Referer outside the
+    /// site and its allowlist
+    fn is_hotlink(&self, site: &SiteConfig, path: &str, referer: Option<&str>) -> bool {
+        let protection = &site.hotlink_protection;
+        if !protection.protects(path) {
+            return false;
+        }
+
+        let Some(referer) = referer.map(str::trim).filter(|r| !r.is_empty()) else {
+            return !protection.allow_empty_referer;
+        };
+
+        match url::Url::parse(referer)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+        {
+            Some(host) => !(site.handles_hostname(&host) || protection.allows_host(&host)),
+            None => true,
+        }
+    }
+
+    /// Answer a hotlink with 403, or a redirect when configured
+    async fn handle_hotlink(&self, session: &mut Session, site: &SiteConfig) -> Result<()> {
+        let protection = &site.hotlink_protection;
+        let body = "Hotlinking is not allowed";
+        let mut header = match (&protection.redirect_url, protection.action.as_str()) {
+            (Some(url), "redirect") => {
+                let mut header = ResponseHeader::build(302, Some(4))?;
+                header.insert_header("Location", url.as_str())?;
+                header
+            }
+            _ => ResponseHeader::build(403, Some(4))?,
+        };
+        header.insert_header("Content-Type", "text/plain; charset=utf-8")?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+
+        // HSTS
+        if let Some((key, value)) = site.get_hsts_header() {
+            header.insert_header(key, value)?;
+        }
+
+        // Custom site headers
+        for (key, value) in &site.headers {
+            header.insert_header(key.clone(), value.clone())?;
+        }
+
+        session
+            .write_response_header(Box::new(header), false)
+            .await?;
+        session
+            .write_response_body(Some(body.as_bytes().to_vec().into()), true)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The status to refuse a file with when its extension is not allowed on the site
+    fn blocked_extension_status(&self, site: &SiteConfig, file_path: &str) -> Option<u16> {
+        let extension = Path::new(file_path)
+            .extension()
+            .and_then(|ext| ext.to_str());
+        (!site.allows_extension(extension)).then_some(site.blocked_extension_status)
+    }
+
+    /// Refuse a disallowed file or path: 404 hides that it exists, 403 says so
+    async fn handle_refused(
+        &self,
+        session: &mut Session,
+        site: &SiteConfig,
+        status: u16,
+    ) -> Result<()> {
+        if status != 403 {
+            return self.handle_not_found(session, site).await;
+        }
+
+        let body = "Forbidden";
+        let mut header = ResponseHeader::build(403, Some(3))?;
+        header.insert_header("Content-Type", "text/plain; charset=utf-8")?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+
+        // HSTS
+        if let Some((key, value)) = site.get_hsts_header() {
+            header.insert_header(key, value)?;
+        }
+
+        // Custom site headers
+        for (key, value) in &site.headers {
+            header.insert_header(key.clone(), value.clone())?;
+        }
+
+        session
+            .write_response_header(Box::new(header), false)
+            .await?;
+        session
+            .write_response_body(Some(body.as_bytes().to_vec().into()), true)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Resolve the requested path to a file on disk, checking for index files and path safety.
+    /// Paths under an alias prefix resolve inside the alias directory instead of static_dir.
+    async fn resolve_file_path(
+        &self,
+        site: &SiteConfig,
+        request_path: &str,
+        accept: Option<&str>,
+    ) -> Option<String> {
+        let (root, alias_path) = site.root_for(request_path);
+        let clean_path = self.clean_path(alias_path);
 
         // Security check: ensure the path is safe before proceeding
-        if !self.is_path_safe(&site.static_dir, &clean_path) {
+        if !self.is_path_safe(root, &clean_path) {
             log::warn!("Blocked path traversal attempt: {}", request_path);
             return None;
         }
 
-        // Try exact path first
-        let file_path = format!("{}/{}", site.static_dir, clean_path);
+        // Prefer a modern image format the client asked for
+        let file_path = format!("{}/{}", root, clean_path);
+        if site.content_negotiation {
+            if let Some(variant) = self.negotiate_image(&file_path, accept).await {
+                return Some(variant);
+            }
+        }
+
+        // Try exact path
         if self.is_file_accessible(&file_path).await {
             return Some(file_path);
         }
 
+        // Extensionless paths can resolve to a file with an acceptable extension
+        if site.content_negotiation && self.is_negotiable(&clean_path) && !clean_path.is_empty() {
+            if let Some(variant) = self.negotiate_extension(&file_path, accept).await {
+                return Some(variant);
+            }
+        }
+
         // If path ends with '/', try index files
         if clean_path.ends_with('/') || clean_path.is_empty() {
             for index_file in site.get_index_files() {
-                let index_path = format!("{}/{}{}", site.static_dir, clean_path, index_file);
+                let index_path = format!("{}/{}{}", root, clean_path, index_file);
                 if self.is_file_accessible(&index_path).await {
                     return Some(index_path);
                 }
@@ -54,16 +378,89 @@ impl StaticFileHandler {
         } else {
             // Try adding '/' and looking for index files
             for index_file in site.get_index_files() {
-                let index_path = format!("{}/{}/{}", site.static_dir, clean_path, index_file);
+                let index_path = format!("{}/{}/{}", root, clean_path, index_file);
                 if self.is_file_accessible(&index_path).await {
                     return Some(index_path);
                 }
             }
         }
 
+        // Directory without an index file: fall back to the configured document
+        if let Some(fallback) = &site.fallback_document {
+            let dir_path = format!("{}/{}", root, clean_path);
+            let is_dir = fs::metadata(&dir_path)
+                .await
+                .map(|m| m.is_dir())
+                .unwrap_or(false);
+
+            if is_dir {
+                let fallback_path =
+                    format!("{}/{}", site.static_dir, fallback.trim_start_matches('/'));
+                if self.is_file_accessible(&fallback_path).await {
+                    log::debug!(
+                        "Serving fallback document {} for directory {}",
+                        fallback_path,
+                        dir_path
+                    );
+                    return Some(fallback_path);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Whether the response for `path` can depend on the Accept header
+    fn is_negotiable(&self, path: &str) -> bool {
+        let path = path.split(['?', '#']).next().unwrap_or(path);
+        if path.ends_with('/') {
+            return false;
+        }
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => IMAGE_VARIANT_SOURCES.contains(&ext.to_ascii_lowercase().as_str()),
+            None => true,
+        }
+    }
+
+    /// An AVIF or WebP file next to a JPEG/PNG/GIF, if the client lists its type
+    async fn negotiate_image(&self, file_path: &str, accept: Option<&str>) -> Option<String> {
+        let accept = accept?;
+        let path = Path::new(file_path);
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        if !IMAGE_VARIANT_SOURCES.contains(&ext.as_str()) {
+            return None;
+        }
+
+        for variant in IMAGE_VARIANTS {
+            let candidate = path.with_extension(variant);
+            let candidate = candidate.to_str()?;
+            if http::accepts_explicitly(accept, self.get_mime_type(candidate))
+                && self.is_file_accessible(candidate).await
+            {
+                return Some(candidate.to_string());
+            }
+        }
         None
     }
 
+    /// The existing `<path>.<ext>` file whose type the client prefers
+    async fn negotiate_extension(&self, file_path: &str, accept: Option<&str>) -> Option<String> {
+        let mut best: Option<(f32, String)> = None;
+        for ext in NEGOTIABLE_EXTENSIONS {
+            let candidate = format!("{}.{}", file_path, ext);
+            // A missing Accept header accepts everything
+            let quality = accept.map_or(1.0, |accept| {
+                http::accept_quality(accept, self.get_mime_type(&candidate))
+            });
+            if quality > best.as_ref().map_or(0.0, |(q, _)| *q)
+                && self.is_file_accessible(&candidate).await
+            {
+                best = Some((quality, candidate));
+            }
+        }
+        best.map(|(_, candidate)| candidate)
+    }
+
     /// Clean and normalize a request path, removing dangerous components and normalizing separators.
     fn clean_path(&self, path: &str) -> String {
         // Remove query parameters and fragments
@@ -121,44 +518,120 @@ impl StaticFileHandler {
     }
 
     /// Serve the given file to the client, applying headers and compression as needed.
+    /// GET requests with a `Range` header get the requested bytes (206), unless
+    /// an `If-Range` validator shows the file has changed since.
     async fn serve_file(
         &self,
         session: &mut Session,
         site: &SiteConfig,
+        request_path: &str,
         file_path: &str,
+        vary_accept: bool,
     ) -> Result<()> {
-        match fs::read(file_path).await {
-            Ok(content) => {
-                let mime_type = self.get_mime_type(file_path);
-                let mut header = ResponseHeader::build(200, Some(4))?;
+        match self.read_file(file_path).await {
+            Ok(file) => {
+                let content = file.content;
+                let content_type = self.content_type(site, file_path);
+                let mime_type = content_type.as_str();
+
+                // Validators, when the site sends them
+                let etag = (site.cache.enabled && site.cache.etag_enabled).then_some(file.etag);
+                let last_modified = file
+                    .modified
+                    .filter(|_| site.cache.enabled && site.cache.last_modified_enabled)
+                    .map(http::http_date);
+
+                let range = self.requested_range(
+                    session,
+                    content.len() as u64,
+                    etag.as_deref(),
+                    last_modified.as_deref(),
+                );
+                if range == RangeRequest::Unsatisfiable {
+                    return self
+                        .handle_range_not_satisfiable(session, site, content.len())
+                        .await;
+                }
+
+                let status = if matches!(range, RangeRequest::Partial { .. }) {
+                    206
+                } else {
+                    200
+                };
+                let mut header = ResponseHeader::build(status, Some(4))?;
 
                 // Basic headers
                 header.insert_header("Content-Type", mime_type)?;
                 header.insert_header("Content-Length", content.len().to_string())?;
+                header.insert_header("Accept-Ranges", "bytes")?;
 
                 // Cache headers
                 let is_static = self.is_static_file(file_path);
-                for (key, value) in site.get_cache_headers(is_static) {
+                for (key, value) in site.get_cache_headers(request_path, is_static) {
                     header.insert_header(key, value)?;
                 }
+                if let Some(etag) = etag {
+                    header.insert_header("ETag", etag)?;
+                }
+                if let Some(last_modified) = last_modified {
+                    header.insert_header("Last-Modified", last_modified)?;
+                }
 
                 // CORS headers
                 for (key, value) in site.get_cors_headers() {
                     header.insert_header(key, value)?;
                 }
 
+                // HSTS
+                if let Some((key, value)) = site.get_hsts_header() {
+                    header.insert_header(key, value)?;
+                }
+
                 // Custom site headers
                 for (key, value) in &site.headers {
                     header.insert_header(key.clone(), value.clone())?;
                 }
 
-                // Check if content should be compressed
-                let content_len = content.len();
                 let compression_middleware = CompressionMiddleware::new(site.compression.clone());
+                let negotiated = compression_middleware.should_compress(mime_type, content.len());
+
+                // A range is served from the identity encoding, uncompressed:
+                // byte offsets always count bytes of the file as stored
+                if let RangeRequest::Partial { start, end } = range {
+                    let body = content.slice(start as usize..=end as usize);
+                    header.remove_header("Content-Length");
+                    header.insert_header("Content-Length", body.len().to_string())?;
+                    header.insert_header(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", start, end, content.len()),
+                    )?;
+                    // The full response would have depended on Accept-Encoding
+                    if negotiated {
+                        merge_vary(&mut header, "Accept-Encoding")?;
+                    }
+                    if vary_accept {
+                        merge_vary(&mut header, "Accept")?;
+                    }
 
-                let (final_content, encoding) = if compression_middleware
-                    .should_compress(mime_type, content_len)
-                {
+                    session
+                        .write_response_header(Box::new(header), false)
+                        .await?;
+                    response_started();
+                    session.write_response_body(Some(body), true).await?;
+
+                    log::debug!(
+                        "Served bytes {}-{} of file: {} ({} bytes)",
+                        start,
+                        end,
+                        file_path,
+                        content.len()
+                    );
+                    return Ok(());
+                }
+
+                // Check if content should be compressed
+                let content_len = content.len();
+                let (final_content, encoding) = if negotiated {
                     // Get the best compression method based on Accept-Encoding header
                     let accept_encoding = session
                         .req_header()
@@ -178,7 +651,7 @@ impl StaticFileHandler {
                                 compression_method,
                                 ((content_len - compressed_content.len()) * 100) / content_len
                             );
-                            (compressed_content.to_vec(), Some(compression_method))
+                            (compressed_content, Some(compression_method))
                         }
                         Err(e) => {
                             log::warn!("Compression failed: {}, serving uncompressed", e);
@@ -196,17 +669,26 @@ impl StaticFileHandler {
                 if let Some(method) = encoding {
                     if !matches!(method, CompressionMethod::None) {
                         header.insert_header("Content-Encoding", method.as_str())?;
-                        header.insert_header("Vary", "Accept-Encoding")?;
                     }
                 }
 
+                // The representation depends on Accept-Encoding whenever compression
+                // was considered, even if the identity encoding was chosen
+                if negotiated {
+                    merge_vary(&mut header, "Accept-Encoding")?;
+                }
+                if vary_accept {
+                    merge_vary(&mut header, "Accept")?;
+                }
+
                 session
                     .write_response_header(Box::new(header), false)
                     .await?;
+                response_started();
 
                 let final_content_len = final_content.len();
                 session
-                    .write_response_body(Some(final_content.into()), true)
+                    .write_response_body(Some(final_content), true)
                     .await?;
 
                 log::debug!(
@@ -225,6 +707,114 @@ impl StaticFileHandler {
         Ok(())
     }
 
+    /// The byte range a GET request asks for. A range whose `If-Range`
+    /// validator no longer matches the file is ignored, so a client resuming
+    /// a download of a changed file gets the whole new file instead.
+    fn requested_range(
+        &self,
+        session: &Session,
+        len: u64,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> RangeRequest {
+        let req = session.req_header();
+        if req.method != "GET" {
+            return RangeRequest::Full;
+        }
+        let header = |name: &str| req.headers.get(name).and_then(|h| h.to_str().ok());
+
+        let Some(range) = header("range") else {
+            return RangeRequest::Full;
+        };
+        if let Some(if_range) = header("if-range") {
+            if !http::if_range_matches(if_range, etag, last_modified) {
+                return RangeRequest::Full;
+            }
+        }
+        http::parse_range(range, len)
+    }
+
+    async fn handle_range_not_satisfiable(
+        &self,
+        session: &mut Session,
+        site: &SiteConfig,
+        len: usize,
+    ) -> Result<()> {
+        let mut header = ResponseHeader::build(416, Some(3))?;
+        header.insert_header("Content-Range", format!("bytes */{}", len))?;
+        header.insert_header("Content-Length", "0")?;
+
+        if let Some((key, value)) = site.get_hsts_header() {
+            header.insert_header(key, value)?;
+        }
+
+        session
+            .write_response_header(Box::new(header), true)
+            .await?;
+        Ok(())
+    }
+
+    /// Read a file from the file cache or disk, memory-mapping it when its size
+    /// is in the mmap range
+    async fn read_file(&self, file_path: &str) -> std::io::Result<LoadedFile> {
+        let metadata = fs::metadata(file_path).await?;
+        let modified = metadata.modified().ok();
+
+        if let Some(cache) = &self.file_cache {
+            if cache.accepts(metadata.len()) {
+                let cached = cache.get(file_path, &metadata);
+                metrics().record_file_cache_lookup(cached.is_some());
+                let cached = match cached {
+                    Some(cached) => cached,
+                    None => {
+                        let content = Bytes::from(fs::read(file_path).await?);
+                        cache.insert(file_path, &metadata, content)
+                    }
+                };
+                return Ok(LoadedFile {
+                    content: cached.content,
+                    etag: cached.etag,
+                    modified,
+                });
+            }
+        }
+
+        let etag = metadata_etag(&metadata);
+        if let Some((min, max)) = self.mmap_size_range {
+            // Opening and mapping may block on the disk, so keep them off the
+            // async workers
+            let path = file_path.to_string();
+            let mapped = tokio::task::spawn_blocking(move || {
+                let file = std::fs::File::open(&path)?;
+                let len = file.metadata()?.len() as usize;
+                if !(min..=max).contains(&len) {
+                    return Ok(None);
+                }
+                MappedFile::map(&file, len).map(Some)
+            })
+            .await
+            .map_err(std::io::Error::other)?;
+
+            match mapped {
+                Ok(Some(mapped)) => {
+                    return Ok(LoadedFile {
+                        content: Bytes::from_owner(mapped),
+                        etag,
+                        modified,
+                    })
+                }
+                Ok(None) => {}
+                Err(e) => log::debug!("Failed to mmap {}, reading instead: {}", file_path, e),
+            }
+        }
+
+        Ok(LoadedFile {
+            content: Bytes::from(fs::read(file_path).await?),
+            etag,
+            modified,
+        })
+    }
+
     async fn handle_not_found(&self, session: &mut Session, site: &SiteConfig) -> Result<()> {
         // Check if site has custom 404 page
         if let Some(error_page) = site.get_error_page(404) {
@@ -234,6 +824,11 @@ impl StaticFileHandler {
                 header.insert_header("Content-Type", "text/html")?;
                 header.insert_header("Content-Length", content.len().to_string())?;
 
+                // HSTS
+                if let Some((key, value)) = site.get_hsts_header() {
+                    header.insert_header(key, value)?;
+                }
+
                 // Custom site headers
                 for (key, value) in &site.headers {
                     header.insert_header(key.clone(), value.clone())?;
@@ -242,6 +837,7 @@ impl StaticFileHandler {
                 session
                     .write_response_header(Box::new(header), false)
                     .await?;
+                response_started();
                 session
                     .write_response_body(Some(content.into()), true)
                     .await?;
@@ -270,6 +866,11 @@ impl StaticFileHandler {
         header.insert_header("Content-Type", "text/html")?;
         header.insert_header("Content-Length", error_html.len().to_string())?;
 
+        // HSTS
+        if let Some((key, value)) = site.get_hsts_header() {
+            header.insert_header(key, value)?;
+        }
+
         // Custom site headers
         for (key, value) in &site.headers {
             header.insert_header(key.clone(), value.clone())?;
@@ -285,6 +886,15 @@ impl StaticFileHandler {
         Ok(())
     }
 
+    /// MIME type for a file, with the site's charset on textual types
+    fn content_type(&self, site: &SiteConfig, file_path: &str) -> String {
+        let mime_type = self.get_mime_type(file_path);
+        match mime_type.split_once("; charset=") {
+            Some((base, _)) => format!("{}; charset={}", base, site.default_charset),
+            None => mime_type.to_string(),
+        }
+    }
+
     fn get_mime_type(&self, file_path: &str) -> &'static str {
         let path = Path::new(file_path);
         match path.extension().and_then(|ext| ext.to_str()) {
@@ -423,6 +1033,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_site_charset() {
+        let handler = StaticFileHandler::new();
+        let mut site = create_test_site("/tmp");
+        assert_eq!(
+            handler.content_type(&site, "page.html"),
+            "text/html; charset=utf-8"
+        );
+
+        site.default_charset = "iso-8859-1".to_string();
+        assert!(site.validate().is_ok());
+        assert_eq!(
+            handler.content_type(&site, "page.html"),
+            "text/html; charset=iso-8859-1"
+        );
+        assert_eq!(
+            handler.content_type(&site, "data.csv"),
+            "text/csv; charset=iso-8859-1"
+        );
+        // Binary types never get a charset
+        assert_eq!(handler.content_type(&site, "logo.png"), "image/png");
+
+        site.default_charset = "utf-8\r\nX-Injected: 1".to_string();
+        assert!(site.validate().is_err());
+    }
+
     #[test]
     fn test_clean_path() {
         let handler = StaticFileHandler::new();
@@ -496,4 +1132,255 @@ mod tests {
         // Clean up
         std::fs::remove_dir_all(&temp_dir).unwrap();
     }
+
+    #[test]
+    fn test_blocked_extensions() {
+        let handler = StaticFileHandler::new();
+        let mut site = create_test_site("/tmp");
+
+        // Stealth mode by default
+        assert_eq!(
+            handler.blocked_extension_status(&site, "/tmp/setup.exe"),
+            Some(404)
+        );
+        assert_eq!(
+            handler.blocked_extension_status(&site, "/tmp/SETUP.EXE"),
+            Some(404)
+        );
+        assert_eq!(
+            handler.blocked_extension_status(&site, "/tmp/index.html"),
+            None
+        );
+        assert_eq!(
+            handler.blocked_extension_status(&site, "/tmp/LICENSE"),
+            None
+        );
+
+        site.blocked_extension_status = 403;
+        assert_eq!(
+            handler.blocked_extension_status(&site, "/tmp/setup.exe"),
+            Some(403)
+        );
+        assert!(site.validate().is_ok());
+
+        // Sites can extend the safe set
+        site.allowed_extensions = vec!["EXE".to_string()];
+        assert_eq!(
+            handler.blocked_extension_status(&site, "/tmp/setup.exe"),
+            None
+        );
+        assert_eq!(
+            handler.blocked_extension_status(&site, "/tmp/run.sh"),
+            Some(403)
+        );
+
+        site.allowed_extensions = vec![".exe".to_string()];
+        assert!(site.validate().is_err());
+        site.allowed_extensions.clear();
+        site.blocked_extension_status = 500;
+        assert!(site.validate().is_err());
+    }
+
+    fn create_test_site(static_dir: &str) -> SiteConfig {
+        SiteConfig {
+            name: "test".to_string(),
+            hostname: "localhost".to_string(),
+            port: 8080,
+            static_dir: static_dir.to_string(),
+            default: true,
+            index_files: vec!["index.html".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hotlink_protection() {
+        let handler = StaticFileHandler::new();
+        let mut site = create_test_site("/tmp");
+        site.hostname = "example.com".to_string();
+        site.hotlink_protection.enabled = true;
+        site.hotlink_protection.allowed_referers = vec!["*.partner.org".to_string()];
+
+        // Allowed: the site itself and allowlisted hosts
+        assert!(!handler.is_hotlink(&site, "/logo.png", Some("https://example.com/about")));
+        assert!(!handler.is_hotlink(&site, "/logo.png", Some("http://cdn.partner.org/page")));
+
+        // Disallowed: other hosts, and unparseable referers
+        assert!(handler.is_hotlink(&site, "/logo.PNG", Some("https://evil.example.net/")));
+        assert!(handler.is_hotlink(&site, "/video.mp4?t=1", Some("https://partner.org/")));
+        assert!(handler.is_hotlink(&site, "/logo.png", Some("not a url")));
+
+        // Pages and unprotected types are never blocked
+        assert!(!handler.is_hotlink(&site, "/index.html", Some("https://evil.example.net/")));
+
+        // Empty referers follow allow_empty_referer
+        assert!(!handler.is_hotlink(&site, "/logo.png", None));
+        assert!(!handler.is_hotlink(&site, "/logo.png", Some("")));
+        site.hotlink_protection.allow_empty_referer = false;
+        assert!(handler.is_hotlink(&site, "/logo.png", None));
+
+        // Disabled protection allows everything
+        site.hotlink_protection.enabled = false;
+        assert!(!handler.is_hotlink(&site, "/logo.png", Some("https://evil.example.net/")));
+    }
+
+    #[tokio::test]
+    async fn test_content_negotiation() {
+        let handler = StaticFileHandler::new();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let static_dir = temp_dir.path().to_str().unwrap();
+        for file in ["page.html", "page.json", "img.jpg", "img.avif", "logo.png"] {
+            std::fs::write(temp_dir.path().join(file), file).unwrap();
+        }
+        let path = |file: &str| Some(format!("{}/{}", static_dir, file));
+
+        let mut site = create_test_site(static_dir);
+        let browser = Some("text/html,application/xhtml+xml,*/*;q=0.8");
+        let images = Some("image/avif,image/webp,*/*;q=0.8");
+
+        // Disabled: only exact paths resolve
+        assert!(handler
+            .resolve_file_path(&site, "/page", browser)
+            .await
+            .is_none());
+        assert_eq!(
+            handler.resolve_file_path(&site, "/img.jpg", images).await,
+            path("img.jpg")
+        );
+
+        site.content_negotiation = true;
+        assert_eq!(
+            handler.resolve_file_path(&site, "/page", browser).await,
+            path("page.html")
+        );
+        assert_eq!(
+            handler
+                .resolve_file_path(&site, "/page", Some("application/json"))
+                .await,
+            path("page.json")
+        );
+        assert!(handler
+            .resolve_file_path(&site, "/page", Some("image/png"))
+            .await
+            .is_none());
+
+        // Image variants are used only when the client lists their type
+        assert_eq!(
+            handler.resolve_file_path(&site, "/img.jpg", images).await,
+            path("img.avif")
+        );
+        assert_eq!(
+            handler
+                .resolve_file_path(&site, "/img.jpg", Some("*/*"))
+                .await,
+            path("img.jpg")
+        );
+        assert_eq!(
+            handler.resolve_file_path(&site, "/logo.png", images).await,
+            path("logo.png")
+        );
+
+        assert!(handler.is_negotiable("/img.jpg"));
+        assert!(handler.is_negotiable("/page"));
+        assert!(!handler.is_negotiable("/style.css"));
+        assert!(!handler.is_negotiable("/docs/"));
+    }
+
+    #[tokio::test]
+    async fn test_fallback_document_for_directories() {
+        let handler = StaticFileHandler::new();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let static_dir = temp_dir.path().to_str().unwrap();
+
+        std::fs::create_dir_all(temp_dir.path().join("empty")).unwrap();
+        std::fs::write(temp_dir.path().join("_fallback.html"), "fallback").unwrap();
+
+        let mut site = create_test_site(static_dir);
+
+        // Without a fallback document, a directory with no index is not found
+        assert!(handler
+            .resolve_file_path(&site, "/empty/", None)
+            .await
+            .is_none());
+
+        site.fallback_document = Some("_fallback.html".to_string());
+        let expected = format!("{}/_fallback.html", static_dir);
+
+        // Directory requests (with or without trailing slash) use the fallback
+        assert_eq!(
+            handler.resolve_file_path(&site, "/empty/", None).await,
+            Some(expected.clone())
+        );
+        assert_eq!(
+            handler.resolve_file_path(&site, "/empty", None).await,
+            Some(expected)
+        );
+
+        // Missing files are still 404s, not fallbacks
+        assert!(handler
+            .resolve_file_path(&site, "/missing.html", None)
+            .await
+            .is_none());
+        assert!(handler
+            .resolve_file_path(&site, "/missing/", None)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_alias_roots() {
+        let handler = StaticFileHandler::new();
+        let static_dir = tempfile::tempdir().unwrap();
+        let volume = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(static_dir.path().join("index.html"), "site").unwrap();
+        std::fs::write(volume.path().join("app.zip"), "zip").unwrap();
+        std::fs::write(volume.path().join("index.html"), "downloads").unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+
+        let volume_dir = volume.path().to_str().unwrap();
+        let mut site = create_test_site(static_dir.path().to_str().unwrap());
+        site.aliases = vec![("/downloads/".to_string(), volume_dir.to_string())];
+        assert!(site.validate().is_ok());
+
+        // The alias prefix maps onto the alias directory
+        assert_eq!(
+            handler
+                .resolve_file_path(&site, "/downloads/app.zip", None)
+                .await,
+            Some(format!("{}/app.zip", volume_dir))
+        );
+        assert_eq!(
+            handler.resolve_file_path(&site, "/downloads", None).await,
+            Some(format!("{}/index.html", volume_dir))
+        );
+        // Only whole path segments match the prefix
+        assert!(handler
+            .resolve_file_path(&site, "/downloadsx/app.zip", None)
+            .await
+            .is_none());
+        assert!(handler
+            .resolve_file_path(&site, "/app.zip", None)
+            .await
+            .is_none());
+
+        // Traversal cannot leave the alias directory, not even into static_dir
+        assert!(handler
+            .resolve_file_path(&site, "/downloads/../index.html", None)
+            .await
+            .is_some_and(|path| path.starts_with(volume_dir)));
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(outside.path(), volume.path().join("escape")).unwrap();
+            assert!(handler
+                .resolve_file_path(&site, "/downloads/escape/secret.txt", None)
+                .await
+                .is_none());
+        }
+
+        site.aliases = vec![("/".to_string(), volume_dir.to_string())];
+        assert!(site.validate().is_err());
+        site.aliases = vec![("downloads/".to_string(), volume_dir.to_string())];
+        assert!(site.validate().is_err());
+    }
 }
@@ -0,0 +1,147 @@
+//! Load-balancing state of proxy upstream groups
+//!
+//! Round-robin positions, smooth weighted round-robin weights and the
+//! requests in flight to each server carry over from one request to the
+//! next. Like the connection pools, this state lives in the server and
+//! outlives the per-request `ProxyHandler`.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub struct UpstreamBalancer {
+    /// Requests handed out so far per group, for round robin
+    positions: Mutex<HashMap<String, usize>>,
+    /// Current smooth weighted round-robin weight per group and server URL
+    weights: Mutex<HashMap<String, HashMap<String, i64>>>,
+    /// Requests in flight per server URL, for least connections
+    connections: Mutex<HashMap<String, usize>>,
+}
+
+/// A request counted against its server until dropped, so a request that is
+/// cancelled halfway is not counted forever
+pub struct InFlight<'a> {
+    balancer: &'a UpstreamBalancer,
+    url: String,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut connections = self.balancer.connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&self.url) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                connections.remove(&self.url);
+            }
+        }
+    }
+}
+
+impl UpstreamBalancer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Round-robin position of the next request to `group`, counting up from 0
+    pub fn next_position(&self, group: &str) -> usize {
+        let mut positions = self.positions.lock().unwrap();
+        let position = positions.entry(group.to_string()).or_insert(0);
+        let next = *position;
+        *position = position.wrapping_add(1);
+        next
+    }
+
+    /// Smooth weighted round-robin (as in nginx): each pick adds every
+    /// server's weight to its current weight, chooses the highest, then
+    /// subtracts the total weight from the chosen one. A weight 3/1 pair
+    /// yields A, A, B, A rather than bursts. `servers` are URLs and weights;
+    /// returns the index of the chosen one.
+    pub fn pick_weighted(&self, group: &str, servers: &[(&str, i64)]) -> usize {
+        let total_weight: i64 = servers.iter().map(|(_, weight)| weight).sum();
+        let mut groups = self.weights.lock().unwrap();
+        let current = groups.entry(group.to_string()).or_default();
+
+        let mut best = 0;
+        let mut best_weight = i64::MIN;
+        for (i, (url, weight)) in servers.iter().enumerate() {
+            let server = current.entry(url.to_string()).or_insert(0);
+            *server += weight;
+            if *server > best_weight {
+                best = i;
+                best_weight = *server;
+            }
+        }
+        if let Some(chosen) = servers.get(best).and_then(|(url, _)| current.get_mut(*url)) {
+            *chosen -= total_weight;
+        }
+        best
+    }
+
+    /// Count a request to `url` until the returned guard is dropped
+    pub fn start_request(&self, url: &str) -> InFlight<'_> {
+        *self
+            .connections
+            .lock()
+            .unwrap()
+            .entry(url.to_string())
+            .or_insert(0) += 1;
+        InFlight {
+            balancer: self,
+            url: url.to_string(),
+        }
+    }
+
+    /// Requests in flight to `url`
+    pub fn connections(&self, url: &str) -> usize {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(url)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Start every group afresh, e.g. after a configuration reload. Requests
+    /// in flight stay counted until they end.
+    pub fn clear(&self) {
+        self.positions.lock().unwrap().clear();
+        self.weights.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_groups_are_balanced_independently() {
+        let balancer = UpstreamBalancer::new();
+        assert_eq!(balancer.next_position("a"), 0);
+        assert_eq!(balancer.next_position("a"), 1);
+        assert_eq!(balancer.next_position("b"), 0);
+
+        let servers = [("http://a", 2), ("http://b", 1)];
+        let picks: Vec<usize> = (0..3)
+            .map(|_| balancer.pick_weighted("a", &servers))
+            .collect();
+        assert_eq!(picks, [0, 1, 0]);
+
+        balancer.clear();
+        assert_eq!(balancer.next_position("a"), 0);
+    }
+
+    #[test]
+    fn test_requests_counted_until_dropped() {
+        let balancer = UpstreamBalancer::new();
+        let first = balancer.start_request("http://a");
+        let second = balancer.start_request("http://a");
+        assert_eq!(balancer.connections("http://a"), 2);
+        assert_eq!(balancer.connections("http://b"), 0);
+
+        drop(first);
+        balancer.clear();
+        assert_eq!(balancer.connections("http://a"), 1);
+        drop(second);
+        assert_eq!(balancer.connections("http://a"), 0);
+    }
+}
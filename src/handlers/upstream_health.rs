@@ -0,0 +1,478 @@
+//! Passive health of proxy upstreams
+//!
+//! An upstream is marked down after `health_check.retries` requests in a row
+//! fail to reach it, and is tried again once `health_check.interval` seconds
+//! have passed. A request that gets through marks it up again.
+//!
+//! With `circuit_breaker` enabled, 5xx responses count as well: enough of
+//! them within the window open the upstream's circuit and it gets no traffic
+//! for the cooldown. Then the circuit is half-open and a single test request
+//! decides whether it closes again or reopens.
+//!
+//! An upstream that comes back, up again or with its circuit closed, can
+//! warm up: its `slow_start` scales its weight from almost nothing to full.
+//!
+//! Like the connection pools, this state lives in the server and outlives
+//! the per-request `ProxyHandler`.
+
+use crate::config::site::{CircuitBreakerConfig, HealthCheckConfig};
+use crate::monitoring::metrics::metrics;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// State of an upstream's circuit breaker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Routing normally
+    Closed,
+    /// Failing; no requests until the cooldown has passed
+    Open,
+    /// Cooldown over; one test request decides the next state
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+/// Failures of one upstream
+#[derive(Debug, Default)]
+struct FailureState {
+    /// Requests in a row that could not reach the upstream
+    failures: u32,
+    down_until: Option<Instant>,
+    /// When recent failures counted by the circuit breaker happened
+    errors: VecDeque<Instant>,
+    /// Set while the circuit is open; half-open once it has passed
+    open_until: Option<Instant>,
+    /// The half-open test request is in flight
+    testing: bool,
+    /// When the upstream last came back
+    recovered_at: Option<Instant>,
+}
+
+impl FailureState {
+    fn circuit(&self, now: Instant) -> CircuitState {
+        match self.open_until {
+            None => CircuitState::Closed,
+            Some(until) if now < until => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Open, or half-open with the test request in flight
+    fn circuit_blocks(&self, now: Instant) -> bool {
+        match self.circuit(now) {
+            CircuitState::Closed => false,
+            CircuitState::Open => true,
+            CircuitState::HalfOpen => self.testing,
+        }
+    }
+
+    fn is_down(&self, now: Instant) -> bool {
+        self.circuit_blocks(now) || self.down_until.is_some_and(|until| now < until)
+    }
+
+    fn circuit_failed(&mut self, url: &str, breaker: &CircuitBreakerConfig, now: Instant) {
+        match self.circuit(now) {
+            CircuitState::Closed => {
+                let window = Duration::from_secs(breaker.window);
+                self.errors.push_back(now);
+                while self
+                    .errors
+                    .front()
+                    .is_some_and(|at| now.duration_since(*at) >= window)
+                {
+                    self.errors.pop_front();
+                }
+                if self.errors.len() >= breaker.failure_threshold as usize {
+                    warn!(
+                        "Circuit for upstream {} opened after {} failures in {}s",
+                        url,
+                        self.errors.len(),
+                        breaker.window
+                    );
+                    self.open(url, breaker, now);
+                }
+            }
+            CircuitState::HalfOpen => {
+                warn!("Circuit for upstream {} reopened, test request failed", url);
+                self.open(url, breaker, now);
+            }
+            // Requests sent before the circuit opened
+            CircuitState::Open => {}
+        }
+    }
+
+    fn circuit_succeeded(&mut self, url: &str, now: Instant) {
+        if self.circuit(now) == CircuitState::HalfOpen {
+            info!(
+                "Circuit for upstream {} closed, test request succeeded",
+                url
+            );
+            self.open_until = None;
+            self.testing = false;
+            self.errors.clear();
+            self.recovered_at = Some(now);
+            metrics().record_upstream_circuit(url, CircuitState::Closed);
+        }
+    }
+
+    fn open(&mut self, url: &str, breaker: &CircuitBreakerConfig, now: Instant) {
+        self.open_until = Some(now + Duration::from_secs(breaker.cooldown));
+        self.testing = false;
+        self.errors.clear();
+        metrics().record_upstream_circuit(url, CircuitState::Open);
+    }
+}
+
+/// A request to an upstream until its outcome is recorded or it is dropped
+pub struct UpstreamRequest<'a> {
+    health: &'a UpstreamHealth,
+    url: String,
+    /// This is the half-open test request
+    testing: bool,
+}
+
+impl UpstreamRequest<'_> {
+    /// The upstream answered with `status`
+    pub fn record_response(mut self, status: u16, breaker: &CircuitBreakerConfig) {
+        self.testing = false;
+        self.health.record_response(&self.url, status, breaker);
+    }
+
+    /// The request could not reach the upstream or read its response
+    pub fn record_failure(
+        mut self,
+        health_check: &HealthCheckConfig,
+        breaker: &CircuitBreakerConfig,
+    ) {
+        self.testing = false;
+        self.health.record_failure(&self.url, health_check, breaker);
+    }
+}
+
+impl Drop for UpstreamRequest<'_> {
+    fn drop(&mut self) {
+        if self.testing {
+            if let Some(state) = self.health.states.lock().unwrap().get_mut(&self.url) {
+                state.testing = false;
+            }
+        }
+    }
+}
+
+/// Health of all upstreams, keyed by upstream URL
+#[derive(Default)]
+pub struct UpstreamHealth {
+    states: Mutex<HashMap<String, FailureState>>,
+}
+
+impl UpstreamHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `url` should get no requests: it has been failing and is not
+    /// yet due for a retry, or its circuit is open
+    pub fn is_down(&self, url: &str) -> bool {
+        self.is_down_at(url, Instant::now())
+    }
+
+    /// Whether the circuit of `url` lets no requests through right now
+    pub fn is_circuit_open(&self, url: &str) -> bool {
+        let now = Instant::now();
+        self.states
+            .lock()
+            .unwrap()
+            .get(url)
+            .is_some_and(|state| state.circuit_blocks(now))
+    }
+
+    /// Current circuit state of `url`
+    pub fn circuit_state(&self, url: &str) -> CircuitState {
+        let now = Instant::now();
+        self.states
+            .lock()
+            .unwrap()
+            .get(url)
+            .map_or(CircuitState::Closed, |state| state.circuit(now))
+    }
+
+    /// Share of its full weight `url` gets while warming up for `slow_start`
+    /// after coming back, from 0 to 1
+    pub fn warmup(&self, url: &str, slow_start: Duration) -> f64 {
+        self.warmup_at(url, slow_start, Instant::now())
+    }
+
+    /// A request is about to be sent to `url`. When the circuit is half-open
+    /// it becomes the test request, and others wait for its outcome. Record
+    /// the outcome on the returned guard; dropped without one, e.g. when the
+    /// client went away or the request timed out, it tells nothing about the
+    /// upstream and lets another request test it.
+    pub fn start_request(&self, url: &str) -> UpstreamRequest<'_> {
+        let now = Instant::now();
+        let mut testing = false;
+        if let Some(state) = self.states.lock().unwrap().get_mut(url) {
+            if state.circuit(now) == CircuitState::HalfOpen && !state.testing {
+                state.testing = true;
+                testing = true;
+                metrics().record_upstream_circuit(url, CircuitState::HalfOpen);
+            }
+        }
+        UpstreamRequest {
+            health: self,
+            url: url.to_string(),
+            testing,
+        }
+    }
+
+    /// The upstream answered a request with `status`
+    pub fn record_response(&self, url: &str, status: u16, breaker: &CircuitBreakerConfig) {
+        self.record_response_at(url, status, breaker, Instant::now());
+    }
+
+    /// A request could not reach the upstream or read its response
+    pub fn record_failure(
+        &self,
+        url: &str,
+        health_check: &HealthCheckConfig,
+        breaker: &CircuitBreakerConfig,
+    ) {
+        self.record_failure_at(url, health_check, breaker, Instant::now());
+    }
+
+    /// Forget all failures, e.g. after a configuration reload
+    pub fn clear(&self) {
+        self.states.lock().unwrap().clear();
+        metrics().clear_upstream_circuits();
+    }
+
+    fn is_down_at(&self, url: &str, now: Instant) -> bool {
+        self.states
+            .lock()
+            .unwrap()
+            .get(url)
+            .is_some_and(|state| state.is_down(now))
+    }
+
+    fn warmup_at(&self, url: &str, slow_start: Duration, now: Instant) -> f64 {
+        let recovered_at = self
+            .states
+            .lock()
+            .unwrap()
+            .get(url)
+            .and_then(|state| state.recovered_at);
+        match recovered_at {
+            Some(at) if !slow_start.is_zero() => {
+                (now.duration_since(at).as_secs_f64() / slow_start.as_secs_f64()).min(1.0)
+            }
+            _ => 1.0,
+        }
+    }
+
+    fn record_response_at(
+        &self,
+        url: &str,
+        status: u16,
+        breaker: &CircuitBreakerConfig,
+        now: Instant,
+    ) {
+        let mut states = self.states.lock().unwrap();
+        let Some(state) = states.get_mut(url) else {
+            if breaker.enabled && status >= 500 {
+                states
+                    .entry(url.to_string())
+                    .or_default()
+                    .circuit_failed(url, breaker, now);
+            }
+            return;
+        };
+
+        state.failures = 0;
+        if state.down_until.take().is_some() {
+            info!("Upstream {} is back up", url);
+            state.recovered_at = Some(now);
+        }
+        if breaker.enabled && status >= 500 {
+            state.circuit_failed(url, breaker, now);
+        } else {
+            state.circuit_succeeded(url, now);
+        }
+    }
+
+    fn record_failure_at(
+        &self,
+        url: &str,
+        health_check: &HealthCheckConfig,
+        breaker: &CircuitBreakerConfig,
+        now: Instant,
+    ) {
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(url.to_string()).or_default();
+        state.failures = state.failures.saturating_add(1);
+
+        // Once down, a failed retry marks it down again straight away
+        if state.failures >= health_check.retries.max(1) {
+            if state.down_until.is_none_or(|until| now >= until) {
+                warn!(
+                    "Upstream {} is down after {} failed requests, retrying in {}s",
+                    url, state.failures, health_check.interval
+                );
+            }
+            state.down_until = Some(now + Duration::from_secs(health_check.interval));
+        }
+
+        if breaker.enabled {
+            state.circuit_failed(url, breaker, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "http://127.0.0.1:3001";
+
+    #[test]
+    fn test_down_after_retries_and_recovery() {
+        let health = UpstreamHealth::new();
+        let config = HealthCheckConfig {
+            retries: 2,
+            interval: 10,
+            ..Default::default()
+        };
+        let breaker = CircuitBreakerConfig::default();
+        let start = Instant::now();
+
+        health.record_failure_at(URL, &config, &breaker, start);
+        assert!(!health.is_down_at(URL, start));
+        health.record_failure_at(URL, &config, &breaker, start);
+        assert!(health.is_down_at(URL, start));
+
+        // Due for a retry after the interval; one more failure is enough
+        let later = start + Duration::from_secs(10);
+        assert!(!health.is_down_at(URL, later));
+        health.record_failure_at(URL, &config, &breaker, later);
+        assert!(health.is_down_at(URL, later));
+
+        // Any response means it is reachable again
+        health.record_response_at(URL, 503, &breaker, later);
+        assert!(!health.is_down_at(URL, later));
+        health.record_failure_at(URL, &config, &breaker, later);
+        assert!(!health.is_down_at(URL, later));
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let health = UpstreamHealth::new();
+        let breaker = CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 3,
+            window: 10,
+            cooldown: 30,
+        };
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let state = |now: Instant| health.states.lock().unwrap()[URL].circuit(now);
+
+        // Failures spread wider than the window do not open it
+        health.record_response_at(URL, 500, &breaker, at(0));
+        health.record_response_at(URL, 502, &breaker, at(5));
+        health.record_response_at(URL, 200, &breaker, at(8));
+        health.record_response_at(URL, 503, &breaker, at(12));
+        assert_eq!(state(at(12)), CircuitState::Closed);
+
+        // Three within 10s do
+        health.record_response_at(URL, 500, &breaker, at(14));
+        assert_eq!(state(at(14)), CircuitState::Open);
+        assert!(health.is_down_at(URL, at(14)));
+
+        // Half-open after the cooldown: one test request at a time
+        assert_eq!(state(at(44)), CircuitState::HalfOpen);
+        assert!(!health.is_down_at(URL, at(44)));
+        health.states.lock().unwrap().get_mut(URL).unwrap().testing = true;
+        assert!(health.is_down_at(URL, at(44)));
+
+        // A failed test reopens it, a successful one closes it
+        health.record_response_at(URL, 500, &breaker, at(45));
+        assert_eq!(state(at(45)), CircuitState::Open);
+        assert_eq!(state(at(75)), CircuitState::HalfOpen);
+        health.record_response_at(URL, 200, &breaker, at(75));
+        assert_eq!(state(at(75)), CircuitState::Closed);
+        assert!(!health.is_down_at(URL, at(75)));
+
+        // Disabled: 5xx responses are ignored
+        let other = "http://127.0.0.1:3002";
+        for _ in 0..5 {
+            health.record_response(other, 500, &CircuitBreakerConfig::default());
+        }
+        assert_eq!(health.circuit_state(other), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_abandoned_test_request_lets_another_test() {
+        let health = UpstreamHealth::new();
+        let breaker = CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 1,
+            window: 10,
+            cooldown: 0,
+        };
+        health.record_response(URL, 500, &breaker);
+        assert_eq!(health.circuit_state(URL), CircuitState::HalfOpen);
+
+        // Other requests wait while the test request is in flight
+        let test = health.start_request(URL);
+        let other = health.start_request(URL);
+        assert!(health.is_circuit_open(URL));
+        drop(other);
+        assert!(health.is_circuit_open(URL));
+
+        // Dropped without an outcome, e.g. cancelled by the request timeout
+        drop(test);
+        assert!(!health.is_circuit_open(URL));
+
+        // An outcome recorded on the guard still decides the state
+        health.start_request(URL).record_response(200, &breaker);
+        assert_eq!(health.circuit_state(URL), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_slow_start_ramp() {
+        let health = UpstreamHealth::new();
+        let config = HealthCheckConfig {
+            retries: 1,
+            ..Default::default()
+        };
+        let breaker = CircuitBreakerConfig::default();
+        let slow_start = Duration::from_secs(60);
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        // Never down: full weight
+        assert_eq!(health.warmup_at(URL, slow_start, at(0)), 1.0);
+
+        health.record_failure_at(URL, &config, &breaker, at(0));
+        health.record_response_at(URL, 200, &breaker, at(30));
+        assert_eq!(health.warmup_at(URL, slow_start, at(30)), 0.0);
+        assert_eq!(health.warmup_at(URL, slow_start, at(45)), 0.25);
+        assert_eq!(health.warmup_at(URL, slow_start, at(60)), 0.5);
+        assert_eq!(health.warmup_at(URL, slow_start, at(90)), 1.0);
+        assert_eq!(health.warmup_at(URL, slow_start, at(200)), 1.0);
+
+        // Without slow_start it takes full load at once
+        assert_eq!(health.warmup_at(URL, Duration::ZERO, at(30)), 1.0);
+    }
+}
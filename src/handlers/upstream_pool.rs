@@ -0,0 +1,305 @@
+//! Shared HTTP clients for proxy upstreams
+//!
+//! Each upstream gets one long-lived client whose keep-alive pool is reused
+//! across requests, instead of connecting afresh for every request. Pools
+//! live in the server and outlive the per-request `ProxyHandler`.
+
+use crate::config::site::{PoolConfig, TimeoutConfig, UpstreamConfig};
+use crate::monitoring::metrics::{metrics, UpstreamPoolStats};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Everything a client is built from; a change starts a new pool
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+struct PoolKey {
+    url: String,
+    tls_verify: bool,
+    ca_file: Option<String>,
+    sni: Option<String>,
+    max_conns: Option<u32>,
+    timeout: (u64, u64),
+    pool: (u64, usize),
+}
+
+impl PoolKey {
+    fn new(upstream: &UpstreamConfig, timeout: &TimeoutConfig, pool: &PoolConfig) -> Self {
+        Self {
+            url: upstream.url.clone(),
+            tls_verify: upstream.tls_verify,
+            ca_file: upstream.ca_file.clone(),
+            sni: upstream.sni.clone(),
+            max_conns: upstream.max_conns,
+            timeout: (timeout.connect, timeout.read),
+            pool: (pool.idle_timeout, pool.max_idle),
+        }
+    }
+}
+
+/// Pools of all upstreams, keyed by upstream address and client settings
+#[derive(Default)]
+pub struct UpstreamPools {
+    pools: Mutex<HashMap<PoolKey, Arc<UpstreamPool>>>,
+}
+
+impl UpstreamPools {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pool for `upstream`, creating it with a client from `build` on first use
+    pub fn get<E>(
+        &self,
+        upstream: &UpstreamConfig,
+        timeout: &TimeoutConfig,
+        pool: &PoolConfig,
+        build: impl FnOnce() -> Result<reqwest::Client, E>,
+    ) -> Result<Arc<UpstreamPool>, E> {
+        let key = PoolKey::new(upstream, timeout, pool);
+        let mut pools = self.pools.lock().unwrap();
+        if let Some(existing) = pools.get(&key) {
+            return Ok(existing.clone());
+        }
+
+        let created = Arc::new(UpstreamPool::new(
+            upstream.url.clone(),
+            build()?,
+            upstream.max_conns,
+            pool,
+        ));
+        pools.insert(key, created.clone());
+        Ok(created)
+    }
+
+    /// Drop every pool, e.g. after a configuration reload. Requests in flight
+    /// finish on the pools they hold.
+    pub fn clear(&self) {
+        self.pools.lock().unwrap().clear();
+        metrics().clear_upstream_pools();
+    }
+}
+
+/// Keep-alive connections to one upstream
+pub struct UpstreamPool {
+    address: String,
+    client: reqwest::Client,
+    /// Bounds connections to `max_conns`: each request holds one connection
+    limit: Option<Arc<Semaphore>>,
+    active: AtomicUsize,
+    ledger: Mutex<IdleLedger>,
+}
+
+impl UpstreamPool {
+    fn new(
+        address: String,
+        client: reqwest::Client,
+        max_conns: Option<u32>,
+        pool: &PoolConfig,
+    ) -> Self {
+        Self {
+            address,
+            client,
+            limit: max_conns.map(|max| Arc::new(Semaphore::new(max as usize))),
+            active: AtomicUsize::new(0),
+            ledger: Mutex::new(IdleLedger::new(
+                Duration::from_secs(pool.idle_timeout),
+                pool.max_idle,
+            )),
+        }
+    }
+
+    /// Take a connection for one request, waiting up to `wait` while
+    /// `max_conns` are busy; `None` when none came free in time. The client
+    /// reuses an idle connection when there is one.
+    pub async fn acquire(self: &Arc<Self>, wait: Duration) -> Option<PoolLease> {
+        let permit = match &self.limit {
+            Some(limit) => tokio::time::timeout(wait, limit.clone().acquire_owned())
+                .await
+                .ok()?
+                .ok(),
+            None => None,
+        };
+        self.ledger.lock().unwrap().take(Instant::now());
+        self.active.fetch_add(1, Ordering::Relaxed);
+        self.report();
+
+        Some(PoolLease {
+            pool: self.clone(),
+            _permit: permit,
+            reusable: false,
+        })
+    }
+
+    pub fn stats(&self) -> UpstreamPoolStats {
+        UpstreamPoolStats {
+            active: self.active.load(Ordering::Relaxed),
+            idle: self.ledger.lock().unwrap().idle(Instant::now()),
+        }
+    }
+
+    fn release(&self, reusable: bool) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        if reusable {
+            self.ledger.lock().unwrap().put(Instant::now());
+        }
+        self.report();
+    }
+
+    fn report(&self) {
+        metrics().record_upstream_pool(&self.address, self.stats());
+    }
+}
+
+/// A connection held for one request; returned to the pool when dropped
+pub struct PoolLease {
+    pool: Arc<UpstreamPool>,
+    _permit: Option<OwnedSemaphorePermit>,
+    reusable: bool,
+}
+
+impl PoolLease {
+    pub fn client(&self) -> &reqwest::Client {
+        &self.pool.client
+    }
+
+    /// Mark the connection as kept alive: the response was read to the end
+    /// and the upstream did not ask to close it
+    pub fn keep_alive(&mut self) {
+        self.reusable = true;
+    }
+}
+
+impl Drop for PoolLease {
+    fn drop(&mut self) {
+        self.pool.release(self.reusable);
+    }
+}
+
+/// When each idle connection went idle, following the client's pool rules:
+/// requests take an idle connection before opening one, at most `max_idle`
+/// are kept, and they close after `idle_timeout`. Connections the upstream
+/// closes on its own are only noticed when they expire.
+struct IdleLedger {
+    idle_since: VecDeque<Instant>,
+    idle_timeout: Duration,
+    max_idle: usize,
+}
+
+impl IdleLedger {
+    fn new(idle_timeout: Duration, max_idle: usize) -> Self {
+        Self {
+            idle_since: VecDeque::new(),
+            idle_timeout,
+            max_idle,
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self
+            .idle_since
+            .front()
+            .is_some_and(|since| now.duration_since(*since) >= self.idle_timeout)
+        {
+            self.idle_since.pop_front();
+        }
+    }
+
+    /// Hand the most recently idle connection to a request, if any
+    fn take(&mut self, now: Instant) -> bool {
+        self.expire(now);
+        self.idle_since.pop_back().is_some()
+    }
+
+    fn put(&mut self, now: Instant) {
+        self.expire(now);
+        if self.idle_since.len() < self.max_idle {
+            self.idle_since.push_back(now);
+        }
+    }
+
+    fn idle(&mut self, now: Instant) -> usize {
+        self.expire(now);
+        self.idle_since.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream(max_conns: Option<u32>) -> UpstreamConfig {
+        UpstreamConfig {
+            name: "backend".to_string(),
+            url: "http://127.0.0.1:3001".to_string(),
+            weight: 1,
+            max_conns,
+            health_path: None,
+            tls_verify: true,
+            ca_file: None,
+            sni: None,
+            backup: false,
+            slow_start: 0,
+        }
+    }
+
+    #[test]
+    fn test_idle_ledger() {
+        let start = Instant::now();
+        let mut ledger = IdleLedger::new(Duration::from_secs(10), 2);
+
+        // Nothing idle yet: the first request opens a connection
+        assert!(!ledger.take(start));
+        for _ in 0..3 {
+            ledger.put(start);
+        }
+        assert_eq!(ledger.idle(start), 2); // capped at max_idle
+
+        // Reused, then the rest expires
+        assert!(ledger.take(start + Duration::from_secs(1)));
+        assert_eq!(ledger.idle(start + Duration::from_secs(1)), 1);
+        assert_eq!(ledger.idle(start + Duration::from_secs(10)), 0);
+    }
+
+    #[tokio::test]
+    async fn test_pool_reuse_and_max_conns() {
+        let pools = UpstreamPools::new();
+        let timeout = TimeoutConfig::default();
+        let config = PoolConfig::default();
+        let build = || reqwest::Client::builder().build();
+
+        // One pool per upstream and settings
+        let pool = pools
+            .get(&upstream(Some(1)), &timeout, &config, build)
+            .unwrap();
+        let same = pools
+            .get(&upstream(Some(1)), &timeout, &config, build)
+            .unwrap();
+        assert!(Arc::ptr_eq(&pool, &same));
+        let other = pools
+            .get(&upstream(None), &timeout, &config, build)
+            .unwrap();
+        assert!(!Arc::ptr_eq(&pool, &other));
+
+        let wait = Duration::from_millis(50);
+        let mut lease = pool.acquire(wait).await.unwrap();
+        assert_eq!(pool.stats(), UpstreamPoolStats { active: 1, idle: 0 });
+
+        // max_conns = 1: a second request waits for the first, then gives up
+        assert!(pool.acquire(wait).await.is_none());
+        assert_eq!(pool.stats(), UpstreamPoolStats { active: 1, idle: 0 });
+
+        lease.keep_alive();
+        drop(lease);
+        assert_eq!(pool.stats(), UpstreamPoolStats { active: 0, idle: 1 });
+
+        // The next request reuses the idle connection
+        let lease = pool.acquire(wait).await.unwrap();
+        assert_eq!(pool.stats(), UpstreamPoolStats { active: 1, idle: 0 });
+
+        // Connections that cannot be reused are not counted as idle
+        drop(lease);
+        assert_eq!(pool.stats(), UpstreamPoolStats { active: 0, idle: 0 });
+    }
+}
@@ -1,41 +1,24 @@
 use crate::config::site::{ProxyConfig, ProxyRoute, UpstreamConfig};
+use crate::core::deadline::response_started;
+use crate::handlers::error_response::write_json_error;
+use crate::handlers::proxy_handler::join_upstream_path;
 use futures_util::{SinkExt, StreamExt};
 use log::{debug, error, info, warn};
 use pingora::http::RequestHeader;
 use pingora::prelude::*;
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
 use url::Url;
 
+/// Relays WebSocket upgrades to the upstream chosen by `ProxyHandler`, which
+/// balances them like any other request
 pub struct WebSocketProxyHandler {
     proxy_config: ProxyConfig,
-    upstreams: HashMap<String, Vec<UpstreamConfig>>,
-    round_robin_counters: HashMap<String, Arc<AtomicUsize>>,
 }
 
 impl WebSocketProxyHandler {
     pub fn new(proxy_config: ProxyConfig) -> Self {
-        let mut upstreams = HashMap::new();
-        let mut round_robin_counters = HashMap::new();
-
-        // Group upstreams by name
-        for upstream in &proxy_config.upstreams {
-            upstreams
-                .entry(upstream.name.clone())
-                .or_insert_with(Vec::new)
-                .push(upstream.clone());
-
-            round_robin_counters.insert(upstream.name.clone(), Arc::new(AtomicUsize::new(0)));
-        }
-
-        Self {
-            proxy_config,
-            upstreams,
-            round_robin_counters,
-        }
+        Self { proxy_config }
     }
 
     /// Check if a request should be upgraded to WebSocket
@@ -72,126 +55,52 @@ impl WebSocketProxyHandler {
             .max_by_key(|route| route.path.len())
     }
 
-    /// Select an upstream server using load balancing
-    pub fn select_upstream(&self, upstream_name: &str) -> Result<&UpstreamConfig> {
-        let upstream_servers = self
-            .upstreams
-            .get(upstream_name)
-            .ok_or_else(|| Error::new_str("Upstream not found"))?;
-
-        if upstream_servers.is_empty() {
-            return Err(Error::new_str("No servers available for upstream"));
-        }
-
-        let upstream = match self.proxy_config.load_balancing.method.as_str() {
-            "round_robin" => self.select_round_robin(upstream_name, upstream_servers)?,
-            "weighted" => self.select_weighted(upstream_servers)?,
-            _ => &upstream_servers[0], // Default to first server
-        };
-
-        Ok(upstream)
-    }
-
-    /// Round-robin load balancing
-    fn select_round_robin<'a>(
+    /// Handle WebSocket proxy connection with full bidirectional relay
+    pub async fn handle_websocket_proxy(
         &self,
-        upstream_name: &str,
-        servers: &'a [UpstreamConfig],
-    ) -> Result<&'a UpstreamConfig> {
-        let counter = self
-            .round_robin_counters
-            .get(upstream_name)
-            .ok_or_else(|| Error::new_str("Round robin counter not found"))?;
-
-        let index = counter.fetch_add(1, Ordering::Relaxed) % servers.len();
-        Ok(&servers[index])
-    }
-
-    /// Weighted load balancing
-    fn select_weighted<'a>(&self, servers: &'a [UpstreamConfig]) -> Result<&'a UpstreamConfig> {
-        let total_weight: u32 = servers.iter().map(|s| s.weight).sum();
-        if total_weight == 0 {
-            return Ok(&servers[0]);
-        }
-
-        let random_weight = fastrand::u32(1..=total_weight);
-        let mut current_weight = 0;
+        session: &mut Session,
+        route: &ProxyRoute,
+        upstream: &UpstreamConfig,
+        path: &str,
+    ) -> Result<bool> {
+        info!(
+            "Proxying WebSocket request {} to upstream '{}' ({})",
+            path, route.upstream, upstream.url
+        );
 
-        for server in servers {
-            current_weight += server.weight;
-            if random_weight <= current_weight {
-                return Ok(server);
+        // Convert upstream URL to WebSocket URL
+        let ws_url = match self.get_websocket_url(upstream, route, path) {
+            Ok(url) => url,
+            Err(e) => {
+                error!("Failed to construct WebSocket URL: {}", e);
+                return Ok(false);
             }
-        }
-
-        Ok(&servers[0])
-    }
-
-    /// Handle WebSocket proxy connection with full bidirectional relay
-    pub async fn handle_websocket_proxy(&self, session: &mut Session, path: &str) -> Result<bool> {
-        // Find matching WebSocket route
-        if let Some(route) = self.find_websocket_route(path) {
-            info!(
-                "Proxying WebSocket request {} to upstream '{}'",
-                path, route.upstream
-            );
-
-            // Select upstream server
-            let upstream = match self.select_upstream(&route.upstream) {
-                Ok(upstream) => upstream,
-                Err(e) => {
-                    error!("Failed to select upstream: {}", e);
-                    return Ok(false);
-                }
-            };
-
-            // Convert upstream URL to WebSocket URL
-            let ws_url = match self.get_websocket_url(upstream, route, path) {
-                Ok(url) => url,
-                Err(e) => {
-                    error!("Failed to construct WebSocket URL: {}", e);
-                    return Ok(false);
-                }
-            };
-
-            // Handle the WebSocket upgrade and proxy
-            match self.proxy_websocket_with_relay(session, &ws_url).await {
-                Ok(()) => {
-                    info!("WebSocket proxy completed successfully");
-                    Ok(true)
-                }
-                Err(e) => {
-                    error!("WebSocket proxy failed: {}", e);
-                    // Send error response if we haven't sent headers yet
-                    if session.response_written().is_none() {
-                        let mut resp = match pingora::http::ResponseHeader::build(502, None) {
-                            Ok(r) => r,
-                            Err(e) => {
-                                error!("Failed to build error response header: {}", e);
-                                return Ok(false);
-                            }
-                        };
-
-                        if let Err(e) = resp.insert_header("Content-Type", "text/plain") {
-                            error!("Failed to insert content-type header: {}", e);
-                        }
+        };
 
-                        if let Err(e) = session.write_response_header(Box::new(resp), false).await {
-                            error!("Failed to send error response: {}", e);
-                        }
-                        if let Err(e) = session
-                            .write_response_body(Some("WebSocket proxy error".into()), true)
-                            .await
-                        {
-                            error!("Failed to send error body: {}", e);
-                        }
+        // Handle the WebSocket upgrade and proxy
+        match self.proxy_websocket_with_relay(session, &ws_url).await {
+            Ok(()) => {
+                info!("WebSocket proxy completed successfully");
+                Ok(true)
+            }
+            Err(e) => {
+                error!("WebSocket proxy failed: {}", e);
+                // Send error response if we haven't sent headers yet
+                if session.response_written().is_none() {
+                    if let Err(e) = write_json_error(
+                        session,
+                        502,
+                        "Bad Gateway",
+                        "The WebSocket upstream could not be reached",
+                        None,
+                    )
+                    .await
+                    {
+                        error!("Failed to send error response: {}", e);
                     }
-                    Ok(false)
                 }
+                Ok(false)
             }
-        } else {
-            // No matching WebSocket route
-            Ok(false)
         }
     }
 
@@ -313,6 +222,7 @@ impl WebSocketProxyHandler {
         session
             .write_response_header(Box::new(resp_builder), false)
             .await?;
+        response_started();
 
         info!("WebSocket upgrade successful, starting message relay simulation");
 
@@ -385,7 +295,7 @@ impl WebSocketProxyHandler {
                 .port()
                 .map(|p| format!(":{}", p))
                 .unwrap_or_default(),
-            target_path
+            join_upstream_path(&upstream_url, target_path)
         );
 
         Ok(ws_url)
@@ -501,12 +411,24 @@ mod tests {
                     url: "http://localhost:3001".to_string(),
                     weight: 1,
                     max_conns: None,
+                    health_path: None,
+                    tls_verify: true,
+                    ca_file: None,
+                    sni: None,
+                    backup: false,
+                    slow_start: 0,
                 },
                 UpstreamConfig {
                     name: "websocket_upstream".to_string(),
                     url: "http://localhost:3002".to_string(),
                     weight: 1,
                     max_conns: None,
+                    health_path: None,
+                    tls_verify: true,
+                    ca_file: None,
+                    sni: None,
+                    backup: false,
+                    slow_start: 0,
                 },
             ],
             routes: vec![
@@ -516,6 +438,10 @@ mod tests {
                     strip_prefix: true,
                     rewrite_target: None,
                     websocket: true,
+                    request_headers: HashMap::new(),
+                    response_headers: HashMap::new(),
+                    proxy_redirect: false,
+                    cookie_domain: None,
                 },
                 ProxyRoute {
                     path: "/api".to_string(),
@@ -523,6 +449,10 @@ mod tests {
                     strip_prefix: false,
                     rewrite_target: None,
                     websocket: false,
+                    request_headers: HashMap::new(),
+                    response_headers: HashMap::new(),
+                    proxy_redirect: false,
+                    cookie_domain: None,
                 },
             ],
             health_check: Default::default(),
@@ -535,6 +465,9 @@ mod tests {
                 read: 30,
                 write: 30,
             },
+            pool: Default::default(),
+            circuit_breaker: Default::default(),
+            cache: Default::default(),
             headers: ProxyHeadersConfig {
                 preserve_host: true,
                 add_forwarded: true,
@@ -588,6 +521,12 @@ mod tests {
             url: "http://localhost:3001".to_string(),
             weight: 1,
             max_conns: None,
+            health_path: None,
+            tls_verify: true,
+            ca_file: None,
+            sni: None,
+            backup: false,
+            slow_start: 0,
         };
 
         let route = &ProxyRoute {
@@ -596,6 +535,10 @@ mod tests {
             strip_prefix: true,
             rewrite_target: None,
             websocket: true,
+            request_headers: HashMap::new(),
+            response_headers: HashMap::new(),
+            proxy_redirect: false,
+            cookie_domain: None,
         };
 
         let ws_url = handler
@@ -609,24 +552,36 @@ mod tests {
             url: "https://localhost:3001".to_string(),
             weight: 1,
             max_conns: None,
+            health_path: None,
+            tls_verify: true,
+            ca_file: None,
+            sni: None,
+            backup: false,
+            slow_start: 0,
         };
 
         let wss_url = handler
             .get_websocket_url(https_upstream, route, "/ws/chat")
             .unwrap();
         assert_eq!(wss_url, "wss://localhost:3001/chat");
-    }
-
-    #[test]
-    fn test_upstream_selection() {
-        let proxy_config = create_test_config();
-        let handler = WebSocketProxyHandler::new(proxy_config);
 
-        // Test round-robin selection
-        let upstream1 = handler.select_upstream("websocket_upstream").unwrap();
-        let upstream2 = handler.select_upstream("websocket_upstream").unwrap();
+        // Test with an upstream base path
+        let based_upstream = &UpstreamConfig {
+            name: "test".to_string(),
+            url: "http://localhost:3001/socket/".to_string(),
+            weight: 1,
+            max_conns: None,
+            health_path: None,
+            tls_verify: true,
+            ca_file: None,
+            sni: None,
+            backup: false,
+            slow_start: 0,
+        };
 
-        // Should alternate between upstreams
-        assert_ne!(upstream1.url, upstream2.url);
+        let ws_url = handler
+            .get_websocket_url(based_upstream, route, "/ws/chat")
+            .unwrap();
+        assert_eq!(ws_url, "ws://localhost:3001/socket/chat");
     }
 }
@@ -0,0 +1,121 @@
+use crate::config::{SiteConfig, WellKnownDocument};
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+use std::collections::HashMap;
+
+/// Handler for configured `/.well-known/` documents such as `security.txt`.
+/// ACME challenges are handled before this and never reach it.
+pub struct WellKnownHandler {}
+
+impl WellKnownHandler {
+    /// Create a new WellKnownHandler
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Find the document for `path`, preferring the site's own over the server-wide one
+    pub fn find_document<'a>(
+        site: &'a SiteConfig,
+        server_documents: &'a HashMap<String, WellKnownDocument>,
+        path: &str,
+    ) -> Option<&'a WellKnownDocument> {
+        let name = path.strip_prefix("/.well-known/")?;
+        site.well_known
+            .get(name)
+            .or_else(|| server_documents.get(name))
+    }
+
+    /// Serve the configured document for `path`. Returns `Ok(false)` when no
+    /// document is configured (or its file cannot be read) so the request falls
+    /// through to static files.
+    pub async fn handle(
+        &self,
+        session: &mut Session,
+        site: &SiteConfig,
+        server_documents: &HashMap<String, WellKnownDocument>,
+        path: &str,
+    ) -> Result<bool> {
+        let Some(document) = Self::find_document(site, server_documents, path) else {
+            return Ok(false);
+        };
+
+        let content = match (&document.content, &document.file) {
+            (Some(content), _) => content.clone().into_bytes(),
+            (None, Some(file)) => match tokio::fs::read(file).await {
+                Ok(content) => content,
+                Err(e) => {
+                    log::warn!("Failed to read well-known document {}: {}", file, e);
+                    return Ok(false);
+                }
+            },
+            (None, None) => return Ok(false),
+        };
+
+        let mut header = ResponseHeader::build(200, Some(4))?;
+        header.insert_header("Content-Type", document.content_type.as_str())?;
+        header.insert_header("Content-Length", content.len().to_string())?;
+        if let Some((key, value)) = site.get_hsts_header() {
+            header.insert_header(key, value)?;
+        }
+        for (key, value) in &site.headers {
+            header.insert_header(key.clone(), value.clone())?;
+        }
+
+        let is_head = session.req_header().method == "HEAD";
+        session
+            .write_response_header(Box::new(header), is_head)
+            .await?;
+        if !is_head {
+            session
+                .write_response_body(Some(content.into()), true)
+                .await?;
+        }
+
+        Ok(true)
+    }
+}
+
+impl Default for WellKnownHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_site_documents_override_server_documents() {
+        let document = |content: &str| WellKnownDocument {
+            content: Some(content.to_string()),
+            ..Default::default()
+        };
+
+        let mut site: SiteConfig = toml::from_str(
+            r#"
+            name = "test"
+            hostname = "example.com"
+            port = 8080
+            static_dir = "/tmp"
+            "#,
+        )
+        .unwrap();
+        site.well_known
+            .insert("security.txt".to_string(), document("site"));
+
+        let server_documents = HashMap::from([
+            ("security.txt".to_string(), document("server")),
+            ("change-password".to_string(), document("server")),
+        ]);
+
+        let found = |path| {
+            WellKnownHandler::find_document(&site, &server_documents, path)
+                .and_then(|d| d.content.as_deref())
+        };
+        assert_eq!(found("/.well-known/security.txt"), Some("site"));
+        assert_eq!(found("/.well-known/change-password"), Some("server"));
+        assert_eq!(found("/.well-known/acme-challenge/token"), None);
+        assert_eq!(found("/security.txt"), None);
+    }
+}
@@ -1,8 +1,9 @@
 use crate::config::site::CompressionConfig;
 use brotli::enc::BrotliEncoderParams;
 use bytes::Bytes;
-use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
 use flate2::Compression;
+use pingora::http::ResponseHeader;
 use std::io::Write;
 
 #[derive(Debug, Clone)]
@@ -11,23 +12,74 @@ pub enum CompressionMethod {
     Gzip,
     Deflate,
     Brotli,
+    Zstd,
 }
 
 impl CompressionMethod {
+    /// Server preference when a site does not configure one
+    pub const DEFAULT_PREFERENCE: [CompressionMethod; 4] = [
+        CompressionMethod::Brotli,
+        CompressionMethod::Zstd,
+        CompressionMethod::Gzip,
+        CompressionMethod::Deflate,
+    ];
+
+    /// Look up an encoding by its `Content-Encoding` name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "gzip" => Some(CompressionMethod::Gzip),
+            "deflate" => Some(CompressionMethod::Deflate),
+            "br" => Some(CompressionMethod::Brotli),
+            "zstd" => Some(CompressionMethod::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Choose the best supported encoding from an `Accept-Encoding` header,
+    /// honoring q-values. Ties are broken by server preference (br > zstd > gzip > deflate).
     pub fn from_accept_encoding(accept_encoding: &str) -> Self {
-        // Parse Accept-Encoding header and choose best compression method
-        let encoding = accept_encoding.to_lowercase();
+        Self::negotiate(accept_encoding, &Self::DEFAULT_PREFERENCE)
+    }
 
-        // Priority order: brotli > gzip > deflate
-        if encoding.contains("br") {
-            CompressionMethod::Brotli
-        } else if encoding.contains("gzip") {
-            CompressionMethod::Gzip
-        } else if encoding.contains("deflate") {
-            CompressionMethod::Deflate
-        } else {
-            CompressionMethod::None
+    /// Choose the best encoding in `preference` from an `Accept-Encoding` header,
+    /// honoring q-values. Ties go to the encoding listed first.
+    pub fn negotiate(accept_encoding: &str, preference: &[CompressionMethod]) -> Self {
+        let preferences = parse_accept_encoding(accept_encoding);
+
+        // Quality for a coding: explicit entry, else the wildcard, else not acceptable
+        let quality = |coding: &str| -> Option<f32> {
+            preferences
+                .iter()
+                .find(|(name, _)| name == coding)
+                .or_else(|| preferences.iter().find(|(name, _)| name == "*"))
+                .map(|(_, q)| *q)
+        };
+
+        let mut best = CompressionMethod::None;
+        let mut best_q = 0.0;
+        for method in preference {
+            if let Some(q) = quality(method.as_str()) {
+                if q > best_q {
+                    best_q = q;
+                    best = method.clone();
+                }
+            }
         }
+
+        // "identity;q=0" forbids an uncompressed response, so fall back to the
+        // most widely supported encoding offered, unless that was refused too
+        // (e.g. "*;q=0") and nothing acceptable is left
+        if matches!(best, CompressionMethod::None) && quality("identity") == Some(0.0) {
+            return preference
+                .iter()
+                .find(|method| matches!(method, CompressionMethod::Gzip))
+                .or(preference.first())
+                .filter(|method| quality(method.as_str()) != Some(0.0))
+                .cloned()
+                .unwrap_or(CompressionMethod::None);
+        }
+
+        best
     }
 
     pub fn as_str(&self) -> &'static str {
@@ -36,35 +88,35 @@ impl CompressionMethod {
             CompressionMethod::Gzip => "gzip",
             CompressionMethod::Deflate => "deflate",
             CompressionMethod::Brotli => "br",
+            CompressionMethod::Zstd => "zstd",
         }
     }
 }
 
+/// Zstd level for a configured compression level (0-9). Zstd treats 0 as its
+/// default level, so 0 maps to the fastest level instead.
+fn zstd_level(level: u32) -> i32 {
+    level.clamp(1, 22) as i32
+}
+
 pub struct CompressionMiddleware {
     config: CompressionConfig,
+    preference: Vec<CompressionMethod>,
 }
 
 impl CompressionMiddleware {
     pub fn new(config: CompressionConfig) -> Self {
-        Self { config }
+        let preference = config
+            .algorithms
+            .iter()
+            .filter_map(|name| CompressionMethod::from_name(name))
+            .collect();
+        Self { config, preference }
     }
 
     /// Check if content should be compressed based on content type and size
     pub fn should_compress(&self, content_type: &str, content_length: usize) -> bool {
-        if !self.config.enabled {
-            return false;
-        }
-
-        // Check minimum size
-        if content_length < self.config.min_size {
-            return false;
-        }
-
-        // Check if content type is in the list of compressible types
-        self.config
-            .types
-            .iter()
-            .any(|t| content_type.to_lowercase().starts_with(&t.to_lowercase()))
+        self.config.should_compress(content_type, content_length)
     }
 
     /// Compress content using the specified method
@@ -78,6 +130,7 @@ impl CompressionMiddleware {
             CompressionMethod::Gzip => self.compress_gzip(content),
             CompressionMethod::Deflate => self.compress_deflate(content),
             CompressionMethod::Brotli => self.compress_brotli(content),
+            CompressionMethod::Zstd => self.compress_zstd(content),
         }
     }
 
@@ -88,8 +141,11 @@ impl CompressionMiddleware {
         Ok(Bytes::from(compressed))
     }
 
+    /// The HTTP `deflate` coding is DEFLATE wrapped in the zlib format (RFC 9110),
+    /// not raw DEFLATE. Clients that expect zlib fail on raw streams, while the
+    /// ones that also sniff for raw streams accept zlib.
     fn compress_deflate(&self, content: &[u8]) -> Result<Bytes, Box<dyn std::error::Error>> {
-        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(self.config.level));
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(self.config.level));
         encoder.write_all(content)?;
         let compressed = encoder.finish()?;
         Ok(Bytes::from(compressed))
@@ -115,21 +171,84 @@ impl CompressionMiddleware {
         Ok(Bytes::from(compressed))
     }
 
-    /// Get the best compression method based on Accept-Encoding header
+    fn compress_zstd(&self, content: &[u8]) -> Result<Bytes, Box<dyn std::error::Error>> {
+        let compressed = zstd::bulk::compress(content, zstd_level(self.config.level))?;
+        Ok(Bytes::from(compressed))
+    }
+
+    /// Get the best compression method based on Accept-Encoding header and
+    /// the site's preferred algorithms
     pub fn get_best_compression(&self, accept_encoding: Option<&str>) -> CompressionMethod {
         match accept_encoding {
-            Some(encoding) => CompressionMethod::from_accept_encoding(encoding),
+            Some(encoding) => CompressionMethod::negotiate(encoding, &self.preference),
             None => CompressionMethod::None,
         }
     }
 
     /// Check if a content type is compressible
     pub fn is_compressible_type(&self, content_type: &str) -> bool {
-        self.config
-            .types
-            .iter()
-            .any(|t| content_type.to_lowercase().starts_with(&t.to_lowercase()))
+        self.config.compresses_type(content_type)
+    }
+}
+
+/// Parse an `Accept-Encoding` header into lowercase codings with their q-values.
+/// Entries without a q-value default to 1.0; malformed q-values are treated as 0.
+pub fn parse_accept_encoding(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let coding = parts.next()?.trim().to_lowercase();
+            if coding.is_empty() {
+                return None;
+            }
+
+            let q = parts
+                .filter_map(|param| {
+                    let (key, value) = param.split_once('=')?;
+                    key.trim()
+                        .eq_ignore_ascii_case("q")
+                        .then(|| value.trim().parse::<f32>().unwrap_or(0.0))
+                })
+                .next()
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+
+            Some((coding, q))
+        })
+        .collect()
+}
+
+/// Add `value` to the response's `Vary` header, merging with any existing
+/// values into a single header without duplicates (case-insensitive).
+pub fn merge_vary(header: &mut ResponseHeader, value: &str) -> pingora::Result<()> {
+    let mut values: Vec<String> = Vec::new();
+    let existing = header
+        .headers
+        .get_all("vary")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .chain(value.split(','));
+
+    for item in existing {
+        let item = item.trim();
+        if item.is_empty() || values.iter().any(|v| v.eq_ignore_ascii_case(item)) {
+            continue;
+        }
+        values.push(item.to_string());
     }
+
+    // "*" already varies on everything
+    let merged = if values.iter().any(|v| v == "*") {
+        "*".to_string()
+    } else {
+        values.join(", ")
+    };
+
+    header.remove_header("Vary");
+    header.insert_header("Vary", merged)?;
+    Ok(())
 }
 
 /// Streaming compression wrapper for large files
@@ -151,7 +270,7 @@ impl StreamingCompressor {
                 Box::new(GzEncoder::new(writer, Compression::new(self.level)))
             }
             CompressionMethod::Deflate => {
-                Box::new(DeflateEncoder::new(writer, Compression::new(self.level)))
+                Box::new(ZlibEncoder::new(writer, Compression::new(self.level)))
             }
             CompressionMethod::Brotli => {
                 let params = BrotliEncoderParams {
@@ -163,8 +282,91 @@ impl StreamingCompressor {
                     &params,
                 ))
             }
+            CompressionMethod::Zstd => Box::new(
+                zstd::stream::write::Encoder::new(writer, zstd_level(self.level))
+                    .expect("zstd encoder creation only fails when out of memory")
+                    .auto_finish(),
+            ),
+        }
+    }
+}
+
+/// Encoder for a body compressed as it is relayed: each chunk is flushed so
+/// the client can decode it without waiting for the rest of the body
+pub enum ChunkEncoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl ChunkEncoder {
+    /// Encoder for `method`, or `None` when it leaves the body as it is
+    pub fn new(method: &CompressionMethod, level: u32) -> Option<Self> {
+        match method {
+            CompressionMethod::None => None,
+            CompressionMethod::Gzip => Some(ChunkEncoder::Gzip(GzEncoder::new(
+                Vec::new(),
+                Compression::new(level),
+            ))),
+            CompressionMethod::Deflate => Some(ChunkEncoder::Deflate(ZlibEncoder::new(
+                Vec::new(),
+                Compression::new(level),
+            ))),
+            CompressionMethod::Brotli => {
+                let params = BrotliEncoderParams {
+                    quality: level as i32,
+                    ..Default::default()
+                };
+                Some(ChunkEncoder::Brotli(Box::new(
+                    brotli::CompressorWriter::with_params(Vec::new(), 4096, &params),
+                )))
+            }
+            CompressionMethod::Zstd => {
+                zstd::stream::write::Encoder::new(Vec::new(), zstd_level(level))
+                    .ok()
+                    .map(ChunkEncoder::Zstd)
+            }
         }
     }
+
+    /// Compress `chunk`, returning the output it completes
+    pub fn encode(&mut self, chunk: &[u8]) -> std::io::Result<Bytes> {
+        let output = match self {
+            ChunkEncoder::Gzip(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+            ChunkEncoder::Deflate(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+            ChunkEncoder::Brotli(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+            ChunkEncoder::Zstd(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+        };
+        Ok(Bytes::from(std::mem::take(output)))
+    }
+
+    /// End the stream, returning its remaining output
+    pub fn finish(self) -> std::io::Result<Bytes> {
+        let output = match self {
+            ChunkEncoder::Gzip(encoder) => encoder.finish()?,
+            ChunkEncoder::Deflate(encoder) => encoder.finish()?,
+            ChunkEncoder::Brotli(encoder) => encoder.into_inner(),
+            ChunkEncoder::Zstd(encoder) => encoder.finish()?,
+        };
+        Ok(Bytes::from(output))
+    }
 }
 
 #[cfg(test)]
@@ -182,6 +384,12 @@ mod tests {
             ],
             level: 6,
             min_size: 1024,
+            algorithms: vec![
+                "br".to_string(),
+                "zstd".to_string(),
+                "gzip".to_string(),
+                "deflate".to_string(),
+            ],
         }
     }
 
@@ -212,6 +420,68 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_accept_encoding_q_values() {
+        assert_eq!(
+            parse_accept_encoding("br;q=1.0, gzip;q=0.8, *;q=0.1"),
+            vec![
+                ("br".to_string(), 1.0),
+                ("gzip".to_string(), 0.8),
+                ("*".to_string(), 0.1)
+            ]
+        );
+
+        // Typical browser headers
+        assert!(matches!(
+            CompressionMethod::from_accept_encoding("gzip, deflate, br, zstd"),
+            CompressionMethod::Brotli
+        ));
+        assert!(matches!(
+            CompressionMethod::from_accept_encoding("gzip, deflate"),
+            CompressionMethod::Gzip
+        ));
+
+        // Explicit preferences beat server preference
+        assert!(matches!(
+            CompressionMethod::from_accept_encoding("br;q=0.5, gzip;q=0.8"),
+            CompressionMethod::Gzip
+        ));
+        assert!(matches!(
+            CompressionMethod::from_accept_encoding("gzip;q=0, deflate"),
+            CompressionMethod::Deflate
+        ));
+
+        // Wildcard covers codings not listed explicitly
+        assert!(matches!(
+            CompressionMethod::from_accept_encoding("gzip;q=0.5, *;q=0.9"),
+            CompressionMethod::Brotli
+        ));
+        assert!(matches!(
+            CompressionMethod::from_accept_encoding("*;q=0"),
+            CompressionMethod::None
+        ));
+
+        // Substrings are not codings
+        assert!(matches!(
+            CompressionMethod::from_accept_encoding("xbr, notgzip"),
+            CompressionMethod::None
+        ));
+
+        // identity;q=0 means an uncompressed response is not acceptable
+        assert!(matches!(
+            CompressionMethod::from_accept_encoding("identity;q=0"),
+            CompressionMethod::Gzip
+        ));
+        assert!(matches!(
+            CompressionMethod::from_accept_encoding("gzip;q=0, identity;q=0"),
+            CompressionMethod::None
+        ));
+        assert!(matches!(
+            CompressionMethod::from_accept_encoding("identity"),
+            CompressionMethod::None
+        ));
+    }
+
     #[test]
     fn test_should_compress() {
         let middleware = CompressionMiddleware::new(create_test_config());
@@ -258,6 +528,132 @@ mod tests {
         assert!(compressed.len() < test_data.len());
     }
 
+    #[test]
+    fn test_deflate_uses_zlib_framing() {
+        use flate2::read::ZlibDecoder;
+        use std::io::Read;
+
+        let middleware = CompressionMiddleware::new(create_test_config());
+        let test_data = b"Hello, World! This is a test string for compression. ".repeat(100);
+        let compressed = middleware
+            .compress(&test_data, CompressionMethod::Deflate)
+            .unwrap();
+
+        // zlib header: deflate method with a 32K window, checksummed
+        assert_eq!(compressed[0], 0x78);
+        assert_eq!(u16::from_be_bytes([compressed[0], compressed[1]]) % 31, 0);
+
+        let mut decompressed = Vec::new();
+        ZlibDecoder::new(compressed.as_ref())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, test_data);
+
+        // Deflate loses ties with gzip but still honors explicit q-values
+        assert!(matches!(
+            middleware.get_best_compression(Some("deflate, gzip;q=0.9")),
+            CompressionMethod::Deflate
+        ));
+        assert!(matches!(
+            middleware.get_best_compression(Some("deflate, gzip")),
+            CompressionMethod::Gzip
+        ));
+    }
+
+    #[test]
+    fn test_zstd_negotiation_and_round_trip() {
+        assert!(matches!(
+            CompressionMethod::from_accept_encoding("gzip, zstd"),
+            CompressionMethod::Zstd
+        ));
+
+        // The site's preference order breaks ties between acceptable encodings
+        let mut config = create_test_config();
+        config.algorithms = vec!["zstd".to_string(), "br".to_string()];
+        let middleware = CompressionMiddleware::new(config);
+        assert!(matches!(
+            middleware.get_best_compression(Some("gzip, deflate, br, zstd")),
+            CompressionMethod::Zstd
+        ));
+        // q-values still win over the preference order
+        assert!(matches!(
+            middleware.get_best_compression(Some("br, zstd;q=0.5")),
+            CompressionMethod::Brotli
+        ));
+        // Encodings missing from the list are never chosen
+        assert!(matches!(
+            middleware.get_best_compression(Some("gzip")),
+            CompressionMethod::None
+        ));
+        assert!(matches!(
+            middleware.get_best_compression(Some("identity;q=0")),
+            CompressionMethod::Zstd
+        ));
+
+        let test_data = b"Hello, World! This is a test string for compression. ".repeat(100);
+        let compressed = middleware
+            .compress(&test_data, CompressionMethod::Zstd)
+            .unwrap();
+        assert!(compressed.len() < test_data.len());
+        assert_eq!(zstd::decode_all(compressed.as_ref()).unwrap(), test_data);
+
+        // The streaming writer produces a complete frame once dropped
+        let output = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        struct Shared(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut writer = StreamingCompressor::new(CompressionMethod::Zstd, 0)
+            .create_writer(Shared(output.clone()));
+        writer.write_all(&test_data).unwrap();
+        drop(writer);
+        let streamed = output.lock().unwrap().clone();
+        assert_eq!(zstd::decode_all(streamed.as_slice()).unwrap(), test_data);
+    }
+
+    #[test]
+    fn test_chunk_encoder_round_trip() {
+        use std::io::Read;
+
+        let chunks = ["first chunk, ".repeat(50), "second chunk".repeat(50)];
+        let expected = chunks.concat();
+        for method in CompressionMethod::DEFAULT_PREFERENCE {
+            let mut encoder = ChunkEncoder::new(&method, 6).unwrap();
+            let mut encoded = Vec::new();
+            for chunk in &chunks {
+                let output = encoder.encode(chunk.as_bytes()).unwrap();
+                // Every chunk is flushed as it arrives
+                assert!(!output.is_empty(), "{:?}", method);
+                encoded.extend_from_slice(&output);
+            }
+            encoded.extend_from_slice(&encoder.finish().unwrap());
+
+            let mut decoded = Vec::new();
+            match method {
+                CompressionMethod::Gzip => {
+                    flate2::read::GzDecoder::new(&encoded[..]).read_to_end(&mut decoded)
+                }
+                CompressionMethod::Deflate => {
+                    flate2::read::ZlibDecoder::new(&encoded[..]).read_to_end(&mut decoded)
+                }
+                CompressionMethod::Brotli => {
+                    brotli::Decompressor::new(&encoded[..], 4096).read_to_end(&mut decoded)
+                }
+                _ => zstd::stream::read::Decoder::new(&encoded[..])
+                    .unwrap()
+                    .read_to_end(&mut decoded),
+            }
+            .unwrap();
+            assert_eq!(decoded, expected.as_bytes(), "{:?}", method);
+        }
+        assert!(ChunkEncoder::new(&CompressionMethod::None, 6).is_none());
+    }
+
     #[test]
     fn test_compression_disabled() {
         let mut config = create_test_config();
@@ -279,6 +675,25 @@ mod tests {
         assert!(!middleware.is_compressible_type("video/mp4"));
     }
 
+    #[test]
+    fn test_event_streams_not_compressed() {
+        // Even a wildcard covering them leaves SSE and compressed formats alone
+        let mut config = create_test_config();
+        config.types = vec!["*/*".to_string()];
+        let middleware = CompressionMiddleware::new(config);
+
+        assert!(!middleware.should_compress("text/event-stream", 2048));
+        assert!(!middleware.should_compress("text/event-stream; charset=utf-8", 2048));
+        assert!(!middleware.should_compress("application/gzip", 2048));
+        assert!(!middleware.should_compress("video/mp4", 2048));
+        assert!(middleware.should_compress("image/svg+xml", 2048));
+
+        // Prefixes of a configured type are not matches
+        let middleware = CompressionMiddleware::new(create_test_config());
+        assert!(!middleware.should_compress("text/htmlx", 2048));
+        assert!(!middleware.should_compress("text/event-stream", 2048));
+    }
+
     #[test]
     fn test_best_compression_selection() {
         let middleware = CompressionMiddleware::new(create_test_config());
@@ -304,4 +719,28 @@ mod tests {
             CompressionMethod::None
         ));
     }
+
+    #[test]
+    fn test_merge_vary() {
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        merge_vary(&mut header, "Accept-Encoding").unwrap();
+        assert_eq!(header.headers.get("vary").unwrap(), "Accept-Encoding");
+
+        // Existing values are kept and duplicates collapse into one header
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        header.append_header("Vary", "Origin").unwrap();
+        header
+            .append_header("Vary", "accept-encoding, Cookie")
+            .unwrap();
+        merge_vary(&mut header, "Accept-Encoding").unwrap();
+        let values: Vec<_> = header.headers.get_all("vary").iter().collect();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0], "Origin, accept-encoding, Cookie");
+
+        // Wildcard subsumes everything else
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        header.insert_header("Vary", "*").unwrap();
+        merge_vary(&mut header, "Accept-Encoding").unwrap();
+        assert_eq!(header.headers.get("vary").unwrap(), "*");
+    }
 }
@@ -0,0 +1,458 @@
+//! GeoIP access control using MaxMind GeoLite2/GeoIP2 country databases.
+//!
+//! Only what a country lookup needs of the MaxMind DB format is implemented:
+//! walking the binary search tree and decoding the data section.
+//! See https://maxmind.github.io/MaxMind-DB/ for the format.
+
+use crate::config::GeoIpConfig;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Marks the start of the metadata section, near the end of the file
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+/// Lookup results kept before the cache is cleared
+const MAX_CACHED_LOOKUPS: usize = 65_536;
+
+/// A decoded value from the data section
+#[derive(Debug, Clone, PartialEq)]
+pub enum MmdbValue {
+    String(String),
+    Double(f64),
+    Bytes(Vec<u8>),
+    Uint(u128),
+    Int(i32),
+    Map(Vec<(String, MmdbValue)>),
+    Array(Vec<MmdbValue>),
+    Bool(bool),
+    Float(f32),
+}
+
+impl MmdbValue {
+    /// Value for `key` if this is a map
+    pub fn get(&self, key: &str) -> Option<&MmdbValue> {
+        match self {
+            MmdbValue::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MmdbValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_uint(&self) -> Option<u128> {
+        match self {
+            MmdbValue::Uint(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+/// A MaxMind DB loaded into memory
+pub struct GeoIpDatabase {
+    data: Vec<u8>,
+    node_count: usize,
+    record_size: usize,
+    ip_version: u16,
+    data_start: usize,
+}
+
+impl GeoIpDatabase {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_bytes(data).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, String> {
+        let marker = data
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)
+            .ok_or("not a MaxMind DB (metadata marker not found)")?;
+        let metadata_start = marker + METADATA_MARKER.len();
+        let metadata = Decoder {
+            data: &data[metadata_start..],
+        }
+        .decode(0)
+        .map(|(value, _)| value)
+        .ok_or("invalid metadata")?;
+
+        let field = |name: &str| {
+            metadata
+                .get(name)
+                .and_then(MmdbValue::as_uint)
+                .ok_or(format!("metadata is missing {}", name))
+        };
+        let node_count = field("node_count")? as usize;
+        let record_size = field("record_size")? as usize;
+        let ip_version = field("ip_version")? as u16;
+        if !matches!(record_size, 24 | 28 | 32) {
+            return Err(format!("unsupported record size {}", record_size));
+        }
+
+        let tree_size = record_size * 2 / 8 * node_count;
+        let data_start = tree_size + 16;
+        if data_start > marker {
+            return Err("search tree is larger than the file".to_string());
+        }
+
+        Ok(Self {
+            data,
+            node_count,
+            record_size,
+            ip_version,
+            data_start,
+        })
+    }
+
+    /// The record stored for `ip`, if the database has one
+    pub fn lookup(&self, ip: IpAddr) -> Option<MmdbValue> {
+        let (bits, bit_count, start_node) = match (ip, self.ip_version) {
+            (IpAddr::V4(ip), 4) => (u32::from(ip) as u128, 32, 0),
+            (IpAddr::V4(ip), _) => {
+                // IPv4 addresses live under ::/96 in IPv6 databases
+                let mut node = 0;
+                for _ in 0..96 {
+                    if node >= self.node_count {
+                        break;
+                    }
+                    node = self.record(node, 0)?;
+                }
+                (u32::from(ip) as u128, 32, node)
+            }
+            (IpAddr::V6(_), 4) => return None,
+            (IpAddr::V6(ip), _) => (u128::from(ip), 128, 0),
+        };
+
+        let mut node = start_node;
+        for i in 0..bit_count {
+            if node >= self.node_count {
+                break;
+            }
+            let bit = (bits >> (bit_count - 1 - i)) & 1;
+            node = self.record(node, bit as usize)?;
+        }
+
+        if node <= self.node_count {
+            return None;
+        }
+        let offset = node - self.node_count - 16;
+        Decoder {
+            data: &self.data[self.data_start..],
+        }
+        .decode(offset)
+        .map(|(value, _)| value)
+    }
+
+    /// ISO 3166-1 country code for `ip`
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        let record = self.lookup(ip)?;
+        record
+            .get("country")
+            .or_else(|| record.get("registered_country"))
+            .and_then(|country| country.get("iso_code"))
+            .and_then(MmdbValue::as_str)
+            .map(str::to_string)
+    }
+
+    /// Left (0) or right (1) record of a search tree node
+    fn record(&self, node: usize, side: usize) -> Option<usize> {
+        let node_size = self.record_size * 2 / 8;
+        let bytes = self.data.get(node * node_size..(node + 1) * node_size)?;
+        let be = |b: &[u8]| b.iter().fold(0usize, |acc, &x| (acc << 8) | x as usize);
+        Some(match (self.record_size, side) {
+            (24, 0) => be(&bytes[0..3]),
+            (24, _) => be(&bytes[3..6]),
+            (28, 0) => ((bytes[3] as usize & 0xf0) << 20) | be(&bytes[0..3]),
+            (28, _) => ((bytes[3] as usize & 0x0f) << 24) | be(&bytes[4..7]),
+            (_, 0) => be(&bytes[0..4]),
+            (_, _) => be(&bytes[4..8]),
+        })
+    }
+}
+
+/// Decoder for the MaxMind DB data format, over one section
+struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl Decoder<'_> {
+    /// Decode the value at `offset`, returning it and the offset after it
+    fn decode(&self, offset: usize) -> Option<(MmdbValue, usize)> {
+        self.decode_depth(offset, 0)
+    }
+
+    fn decode_depth(&self, offset: usize, depth: usize) -> Option<(MmdbValue, usize)> {
+        // Guard against pointer cycles and absurd nesting in corrupt files
+        if depth > 32 {
+            return None;
+        }
+
+        let control = *self.data.get(offset)?;
+        let mut offset = offset + 1;
+        let mut kind = control >> 5;
+
+        if kind == 1 {
+            // Pointer: the value lives elsewhere; continue after the pointer
+            let size = ((control >> 3) & 0x3) as usize;
+            let low = (control & 0x7) as usize;
+            let bytes = self.data.get(offset..offset + size + 1)?;
+            let be = |b: &[u8]| b.iter().fold(0usize, |acc, &x| (acc << 8) | x as usize);
+            let target = match size {
+                0 => (low << 8) | bytes[0] as usize,
+                1 => ((low << 16) | be(&bytes[..2])) + 2048,
+                2 => ((low << 24) | be(&bytes[..3])) + 526_336,
+                _ => be(&bytes[..4]),
+            };
+            let (value, _) = self.decode_depth(target, depth + 1)?;
+            return Some((value, offset + size + 1));
+        }
+
+        if kind == 0 {
+            kind = 7 + *self.data.get(offset)?;
+            offset += 1;
+        }
+
+        let mut size = (control & 0x1f) as usize;
+        if size >= 29 {
+            let extra = size - 28;
+            let bytes = self.data.get(offset..offset + extra)?;
+            let n = bytes.iter().fold(0usize, |acc, &x| (acc << 8) | x as usize);
+            size = match extra {
+                1 => 29 + n,
+                2 => 285 + n,
+                _ => 65_821 + n,
+            };
+            offset += extra;
+        }
+
+        let take = |len: usize| self.data.get(offset..offset + len);
+        let uint =
+            |len: usize| take(len).map(|b| b.iter().fold(0u128, |acc, &x| (acc << 8) | x as u128));
+
+        match kind {
+            2 => Some((
+                MmdbValue::String(String::from_utf8_lossy(take(size)?).into_owned()),
+                offset + size,
+            )),
+            3 => {
+                let bytes: [u8; 8] = take(8)?.try_into().ok()?;
+                Some((MmdbValue::Double(f64::from_be_bytes(bytes)), offset + 8))
+            }
+            4 => Some((MmdbValue::Bytes(take(size)?.to_vec()), offset + size)),
+            5 | 6 | 9 | 10 => Some((MmdbValue::Uint(uint(size)?), offset + size)),
+            7 => {
+                let mut entries = Vec::with_capacity(size.min(64));
+                for _ in 0..size {
+                    let (key, next) = self.decode_depth(offset, depth + 1)?;
+                    let (value, next) = self.decode_depth(next, depth + 1)?;
+                    entries.push((key.as_str()?.to_string(), value));
+                    offset = next;
+                }
+                Some((MmdbValue::Map(entries), offset))
+            }
+            8 => Some((MmdbValue::Int(uint(size)? as u32 as i32), offset + size)),
+            11 => {
+                let mut items = Vec::with_capacity(size.min(64));
+                for _ in 0..size {
+                    let (item, next) = self.decode_depth(offset, depth + 1)?;
+                    items.push(item);
+                    offset = next;
+                }
+                Some((MmdbValue::Array(items), offset))
+            }
+            14 => Some((MmdbValue::Bool(size != 0), offset)),
+            15 => {
+                let bytes: [u8; 4] = take(4)?.try_into().ok()?;
+                Some((MmdbValue::Float(f32::from_be_bytes(bytes)), offset + 4))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Allows or denies requests by the client's country, per site
+pub struct GeoIpMiddleware {
+    // Databases by path; `None` when a database could not be opened
+    databases: Mutex<HashMap<String, Option<Arc<GeoIpDatabase>>>>,
+    countries: Mutex<HashMap<(String, IpAddr), Option<String>>>,
+}
+
+impl GeoIpMiddleware {
+    pub fn new() -> Self {
+        Self {
+            databases: Mutex::new(HashMap::new()),
+            countries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a request from `ip` is allowed. Without a usable database
+    /// every request is allowed.
+    pub fn allows(&self, config: &GeoIpConfig, ip: IpAddr) -> bool {
+        if !config.enabled {
+            return true;
+        }
+        let Some(path) = config.database.as_deref() else {
+            return true;
+        };
+        let Some(database) = self.database(path) else {
+            return true;
+        };
+
+        match self.country(path, &database, ip) {
+            Some(country) => config.allows_country(&country),
+            None => config.allow_unknown,
+        }
+    }
+
+    fn database(&self, path: &str) -> Option<Arc<GeoIpDatabase>> {
+        let mut databases = self.databases.lock().unwrap_or_else(|e| e.into_inner());
+        databases
+            .entry(path.to_string())
+            .or_insert_with(|| match GeoIpDatabase::open(path) {
+                Ok(database) => {
+                    log::info!("Loaded GeoIP database {}", path);
+                    Some(Arc::new(database))
+                }
+                Err(e) => {
+                    log::warn!("GeoIP database unavailable, not filtering: {}", e);
+                    None
+                }
+            })
+            .clone()
+    }
+
+    fn country(&self, path: &str, database: &GeoIpDatabase, ip: IpAddr) -> Option<String> {
+        let key = (path.to_string(), ip);
+        let mut countries = self.countries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(country) = countries.get(&key) {
+            return country.clone();
+        }
+
+        let country = database.country(ip);
+        if countries.len() >= MAX_CACHED_LOOKUPS {
+            countries.clear();
+        }
+        countries.insert(key, country.clone());
+        country
+    }
+}
+
+impl Default for GeoIpMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut out = vec![0x40 | s.len() as u8];
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    fn map(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut out = vec![0xe0 | entries.len() as u8];
+        for (key, value) in entries {
+            out.extend(string(key));
+            out.extend_from_slice(value);
+        }
+        out
+    }
+
+    fn uint16(n: u16) -> Vec<u8> {
+        let mut out = vec![0xa2];
+        out.extend_from_slice(&n.to_be_bytes());
+        out
+    }
+
+    fn country_record(iso_code: &str) -> Vec<u8> {
+        map(&[("country", map(&[("iso_code", string(iso_code))]))])
+    }
+
+    /// An IPv4 database (24-bit records) with 1.0.0.0/8 in US and 2.0.0.0/8 in DE
+    fn test_database() -> Vec<u8> {
+        const NODES: usize = 9;
+        let us = country_record("US");
+        let de = country_record("DE");
+        let data_pointer = |offset: usize| NODES + 16 + offset;
+
+        // Nodes 0-5 follow the shared zero bits, node 6 splits 1.x from 2.x
+        let mut tree: Vec<(usize, usize)> = (0..6).map(|n| (n + 1, NODES)).collect();
+        tree.push((7, 8));
+        tree.push((NODES, data_pointer(0)));
+        tree.push((data_pointer(us.len()), NODES));
+
+        let mut db = Vec::new();
+        for (left, right) in tree {
+            db.extend_from_slice(&(left as u32).to_be_bytes()[1..]);
+            db.extend_from_slice(&(right as u32).to_be_bytes()[1..]);
+        }
+        db.extend_from_slice(&[0; 16]);
+        db.extend(us);
+        db.extend(de);
+        db.extend_from_slice(METADATA_MARKER);
+        db.extend(map(&[
+            ("node_count", uint16(NODES as u16)),
+            ("record_size", uint16(24)),
+            ("ip_version", uint16(4)),
+        ]));
+        db
+    }
+
+    #[test]
+    fn test_country_lookup() {
+        let db = GeoIpDatabase::from_bytes(test_database()).unwrap();
+        assert_eq!(
+            db.country("1.2.3.4".parse().unwrap()).as_deref(),
+            Some("US")
+        );
+        assert_eq!(
+            db.country("2.200.0.1".parse().unwrap()).as_deref(),
+            Some("DE")
+        );
+        assert_eq!(db.country("3.0.0.1".parse().unwrap()), None);
+        assert_eq!(db.country("::1".parse().unwrap()), None);
+
+        assert!(GeoIpDatabase::from_bytes(b"not a database".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_country_allow_and_deny() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("country.mmdb");
+        std::fs::write(&path, test_database()).unwrap();
+
+        let geoip = GeoIpMiddleware::new();
+        let mut config = GeoIpConfig {
+            enabled: true,
+            database: Some(path.to_str().unwrap().to_string()),
+            deny_countries: vec!["DE".to_string()],
+            ..Default::default()
+        };
+        let us: IpAddr = "1.1.1.1".parse().unwrap();
+        let de: IpAddr = "2.2.2.2".parse().unwrap();
+        let unknown: IpAddr = "192.168.1.1".parse().unwrap();
+
+        assert!(geoip.allows(&config, us));
+        assert!(!geoip.allows(&config, de));
+        assert!(geoip.allows(&config, unknown));
+
+        config.deny_countries.clear();
+        config.allow_countries = vec!["us".to_string()];
+        config.allow_unknown = false;
+        assert!(geoip.allows(&config, us));
+        assert!(!geoip.allows(&config, de));
+        assert!(!geoip.allows(&config, unknown));
+
+        // A missing database allows everything
+        config.database = Some("/nonexistent/GeoLite2-Country.mmdb".to_string());
+        assert!(geoip.allows(&config, de));
+    }
+}
@@ -1,9 +1,137 @@
 pub mod compression;
+pub mod geoip;
+pub mod redis_rate_limit;
+pub mod user_agent;
 
+pub use geoip::GeoIpMiddleware;
+pub use user_agent::{UserAgentFilterMiddleware, UserAgentVerdict};
+
+use crate::config::{HstsConfig, RateLimitStoreConfig};
+use crate::core::utils::{http, net};
 use async_trait::async_trait;
 use pingora::prelude::*;
+use pingora::protocols::SocketDigest;
 use std::collections::HashMap;
-use std::time::Instant;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+/// Resolve the real client IP for a session, trusting forwarding headers only
+/// when the connecting peer is one of `trusted_proxies`.
+pub fn client_ip(session: &Session, trusted_proxies: &net::TrustedProxies) -> Option<IpAddr> {
+    let peer = session.client_addr()?.as_inet()?;
+    let header = |name: &str| {
+        session
+            .req_header()
+            .headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+    };
+
+    Some(net::extract_client_ip(
+        peer,
+        header("X-Forwarded-For"),
+        header("X-Real-IP"),
+        trusted_proxies,
+    ))
+}
+
+/// Reject requests whose target host is ambiguous: more than one `Host`
+/// header, or a `Host` header that disagrees with the authority of an
+/// absolute-form request target. Either can make a front proxy and this
+/// server pick different sites for the same request.
+pub fn validate_host(req: &RequestHeader) -> std::result::Result<(), &'static str> {
+    let mut hosts = req.headers.get_all("host").iter();
+    let host = hosts.next();
+    if hosts.next().is_some() {
+        return Err("Multiple Host headers");
+    }
+
+    // HTTP/1 absolute-form targets arrive as the path; HTTP/2 sets the authority
+    let target = if req.uri.authority().is_some() {
+        req.uri.to_string()
+    } else {
+        req.uri.path().to_string()
+    };
+    let absolute = target.starts_with("http://") || target.starts_with("https://");
+
+    if let (Some(host), true) = (host, absolute) {
+        let host = host.to_str().map_err(|_| "Invalid Host header")?;
+        let target = url::Url::parse(&target).map_err(|_| "Invalid request target")?;
+        let (host_name, host_port) = http::split_host_port(host).ok_or("Invalid Host header")?;
+        let default_port = if target.scheme() == "https" { 443 } else { 80 };
+        let matches = target
+            .host_str()
+            .is_some_and(|name| name.eq_ignore_ascii_case(host_name))
+            && host_port.unwrap_or(default_port)
+                == target.port_or_known_default().unwrap_or(default_port);
+        if !matches {
+            return Err("Host header does not match the request target");
+        }
+    }
+
+    Ok(())
+}
+
+/// Reject requests with more than `max_headers` header fields, or whose
+/// fields add up to more than `max_size` bytes (each counted as it is sent,
+/// `name: value` plus the line break)
+pub fn check_header_limits(
+    req: &RequestHeader,
+    max_size: usize,
+    max_headers: usize,
+) -> std::result::Result<(), &'static str> {
+    if req.headers.len() > max_headers {
+        return Err("Too many request header fields");
+    }
+
+    let size: usize = req
+        .headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+        .sum();
+    if size > max_size {
+        return Err("Request header fields too large");
+    }
+
+    Ok(())
+}
+
+/// Whether the client waits for `100 Continue` before sending its body
+pub fn expects_continue(req: &RequestHeader) -> bool {
+    req.headers
+        .get_all("expect")
+        .iter()
+        .any(|value| value.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+}
+
+/// Check the `Expect` header before any body is read. `100-continue` is the
+/// only expectation defined, and a declared body larger than `max_body_size`
+/// would be refused anyway, so the client should not send it.
+pub fn validate_expectation(
+    req: &RequestHeader,
+    max_body_size: usize,
+) -> std::result::Result<(), &'static str> {
+    let mut expectations = req.headers.get_all("expect").iter().peekable();
+    if expectations.peek().is_none() {
+        return Ok(());
+    }
+    if expectations.any(|value| !value.as_bytes().eq_ignore_ascii_case(b"100-continue")) {
+        return Err("Unsupported expectation");
+    }
+
+    let content_length = req
+        .headers
+        .get("content-length")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    if content_length.is_some_and(|len| len > max_body_size as u64) {
+        return Err("Request body exceeds the maximum size");
+    }
+
+    Ok(())
+}
 
 #[async_trait]
 pub trait Middleware: Send + Sync {
@@ -48,11 +176,20 @@ impl MiddlewareStack {
 // Logging middleware
 pub struct LoggingMiddleware {
     log_requests: bool,
+    trusted_proxies: net::TrustedProxies,
 }
 
 impl LoggingMiddleware {
     pub fn new(log_requests: bool) -> Self {
-        Self { log_requests }
+        Self {
+            log_requests,
+            trusted_proxies: net::TrustedProxies::default(),
+        }
+    }
+
+    pub fn with_trusted_proxies(mut self, trusted_proxies: net::TrustedProxies) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
     }
 }
 
@@ -68,9 +205,8 @@ impl Middleware for LoggingMiddleware {
                 "Request started: {} {} from {}",
                 session.req_header().method,
                 session.req_header().uri,
-                session
-                    .client_addr()
-                    .map(|addr| addr.to_string())
+                client_ip(session, &self.trusted_proxies)
+                    .map(|ip| ip.to_string())
                     .unwrap_or_else(|| "unknown".to_string())
             );
         }
@@ -96,98 +232,192 @@ impl Middleware for LoggingMiddleware {
     }
 }
 
-// Rate limiting middleware (currently unused)
-#[allow(dead_code)]
-pub struct RateLimitMiddleware {
-    requests_per_minute: u32,
-    burst_size: u32,
-    clients: HashMap<String, ClientInfo>,
-}
+// Rate limiting
 
-#[derive(Debug, Clone)]
-#[allow(dead_code)]
-struct ClientInfo {
-    request_count: u32,
-    last_reset: Instant,
-    tokens: u32,
+/// Storage for rate limit buckets, so limits can be shared between instances
+#[async_trait]
+pub trait RateLimitBackend: Send + Sync {
+    /// Take a token for `client` in `scope`, or return how long until one is available
+    async fn check(
+        &self,
+        scope: &str,
+        client: IpAddr,
+        requests_per_minute: u32,
+        burst_size: u32,
+    ) -> std::result::Result<(), Duration>;
 }
 
-#[allow(dead_code)]
-impl RateLimitMiddleware {
-    pub fn new(requests_per_minute: u32, burst_size: u32) -> Self {
-        Self {
-            requests_per_minute,
-            burst_size,
-            clients: HashMap::new(),
+/// Build the backend selected by the configuration (validated beforehand)
+pub fn rate_limit_backend(config: &RateLimitStoreConfig) -> Arc<dyn RateLimitBackend> {
+    if config.backend == "redis" {
+        let redis_url = config.redis_url.as_deref().unwrap_or_default();
+        match redis_rate_limit::RedisRateLimiter::new(redis_url, &config.key_prefix) {
+            Ok(limiter) => return Arc::new(limiter),
+            Err(e) => log::error!(
+                "Invalid redis_url '{}': {}, using in-memory rate limiting",
+                redis_url,
+                e
+            ),
         }
     }
+    Arc::new(RateLimiter::new())
+}
 
-    fn get_client_ip(&self, session: &Session) -> String {
-        // Try to get real IP from headers (for reverse proxy setups)
-        if let Some(forwarded_for) = session.req_header().headers.get("X-Forwarded-For") {
-            if let Ok(forwarded_str) = forwarded_for.to_str() {
-                if let Some(ip) = forwarded_str.split(',').next() {
-                    return ip.trim().to_string();
-                }
-            }
-        }
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    /// When the bucket is full again if the client sends nothing more; from
+    /// then on it is no different from a new one and can be dropped
+    full_at: Option<Instant>,
+}
 
-        if let Some(real_ip) = session.req_header().headers.get("X-Real-IP") {
-            if let Ok(ip_str) = real_ip.to_str() {
-                return ip_str.to_string();
-            }
-        }
+/// How often buckets that refilled are dropped
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
 
-        session
-            .client_addr()
-            .map(|addr| addr.to_string())
-            .unwrap_or_else(|| "unknown".to_string())
-    }
-
-    fn is_allowed(&mut self, client_ip: &str) -> bool {
-        let now = Instant::now();
-        let client_info = self
-            .clients
-            .entry(client_ip.to_string())
-            .or_insert(ClientInfo {
-                request_count: 0,
-                last_reset: now,
-                tokens: self.burst_size,
-            });
+#[derive(Default)]
+struct Buckets {
+    map: HashMap<(String, IpAddr), TokenBucket>,
+    swept_at: Option<Instant>,
+}
 
-        // Reset counters if a minute has passed
-        if now.duration_since(client_info.last_reset).as_secs() >= 60 {
-            client_info.request_count = 0;
-            client_info.last_reset = now;
-            client_info.tokens = self.burst_size;
+impl Buckets {
+    /// Drop the buckets that have refilled, each at its own rate. Runs at
+    /// most once per `SWEEP_INTERVAL`, so its cost is spread over requests.
+    fn sweep(&mut self, now: Instant) {
+        if self
+            .swept_at
+            .is_some_and(|at| now.saturating_duration_since(at) < SWEEP_INTERVAL)
+        {
+            return;
         }
+        self.swept_at = Some(now);
+        self.map
+            .retain(|_, bucket| bucket.full_at.is_none_or(|at| at > now));
+    }
+}
+
+/// Per-client token buckets. Each bucket is keyed by a scope (e.g. a site
+/// name) and the client IP, so scopes with their own limits never share budget.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a token for `client` in `scope`. Buckets hold up to `burst_size`
+    /// tokens and refill at `requests_per_minute`. When the bucket is empty,
+    /// returns how long until the next token is available.
+    pub fn check(
+        &self,
+        scope: &str,
+        client: IpAddr,
+        requests_per_minute: u32,
+        burst_size: u32,
+    ) -> std::result::Result<(), Duration> {
+        self.check_at(
+            scope,
+            client,
+            requests_per_minute,
+            burst_size,
+            Instant::now(),
+        )
+    }
+
+    fn check_at(
+        &self,
+        scope: &str,
+        client: IpAddr,
+        requests_per_minute: u32,
+        burst_size: u32,
+        now: Instant,
+    ) -> std::result::Result<(), Duration> {
+        let rate = requests_per_minute as f64 / 60.0;
+        let capacity = burst_size as f64;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        buckets.sweep(now);
+
+        let bucket = buckets
+            .map
+            .entry((scope.to_string(), client))
+            .or_insert(TokenBucket {
+                tokens: capacity,
+                last_refill: now,
+                full_at: None,
+            });
 
-        // Add tokens based on time elapsed
-        let seconds_elapsed = now.duration_since(client_info.last_reset).as_secs();
-        let tokens_to_add = (seconds_elapsed * self.requests_per_minute as u64) / 60;
-        client_info.tokens = (client_info.tokens + tokens_to_add as u32).min(self.burst_size);
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(capacity);
+        bucket.last_refill = now;
 
-        // Check if request is allowed
-        if client_info.tokens > 0 {
-            client_info.tokens -= 1;
-            client_info.request_count += 1;
-            true
+        // Time to refill `tokens`; never without a rate
+        let refill = |tokens: f64| Duration::try_from_secs_f64(tokens / rate).ok();
+        let result = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
         } else {
-            false
+            Err(refill(1.0 - bucket.tokens).unwrap_or(Duration::MAX))
+        };
+        bucket.full_at = refill(capacity - bucket.tokens).and_then(|wait| now.checked_add(wait));
+        result
+    }
+}
+
+#[async_trait]
+impl RateLimitBackend for RateLimiter {
+    async fn check(
+        &self,
+        scope: &str,
+        client: IpAddr,
+        requests_per_minute: u32,
+        burst_size: u32,
+    ) -> std::result::Result<(), Duration> {
+        RateLimiter::check(self, scope, client, requests_per_minute, burst_size)
+    }
+}
+
+// Rate limiting middleware
+pub struct RateLimitMiddleware {
+    requests_per_minute: u32,
+    burst_size: u32,
+    limiter: RateLimiter,
+    trusted_proxies: net::TrustedProxies,
+}
+
+impl RateLimitMiddleware {
+    pub fn new(requests_per_minute: u32, burst_size: u32) -> Self {
+        Self {
+            requests_per_minute,
+            burst_size,
+            limiter: RateLimiter::new(),
+            trusted_proxies: net::TrustedProxies::default(),
         }
     }
+
+    pub fn with_trusted_proxies(mut self, trusted_proxies: net::TrustedProxies) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
 }
 
 #[async_trait]
 impl Middleware for RateLimitMiddleware {
     async fn before_request(&self, session: &mut Session) -> Result<bool> {
-        let client_ip = self.get_client_ip(session);
+        let Some(client_ip) = client_ip(session, &self.trusted_proxies) else {
+            return Ok(true);
+        };
 
-        // Note: This implementation has a concurrency issue with the mutable reference.
-        // In a real implementation, you'd use Arc<Mutex<>> or similar
-        // For now, we'll allow all requests
-        log::debug!("Rate limit check for client: {}", client_ip);
-        Ok(true)
+        let allowed = self
+            .limiter
+            .check("", client_ip, self.requests_per_minute, self.burst_size)
+            .is_ok();
+        if !allowed {
+            log::debug!("Rate limit exceeded for client: {}", client_ip);
+        }
+        Ok(allowed)
     }
 
     async fn after_response(&self, _session: &mut Session) -> Result<()> {
@@ -219,14 +449,15 @@ impl SecurityHeadersMiddleware {
         self
     }
 
-    pub fn with_hsts(mut self, max_age: u32, include_subdomains: bool) -> Self {
-        let value = if include_subdomains {
-            format!("max-age={}; includeSubDomains", max_age)
-        } else {
-            format!("max-age={}", max_age)
+    pub fn with_hsts(mut self, max_age: u64, include_subdomains: bool, preload: bool) -> Self {
+        let hsts = HstsConfig {
+            enabled: true,
+            max_age,
+            include_subdomains,
+            preload,
         };
         self.headers
-            .insert("Strict-Transport-Security".to_string(), value);
+            .insert("Strict-Transport-Security".to_string(), hsts.header_value());
         self
     }
 }
@@ -248,6 +479,99 @@ impl Middleware for SecurityHeadersMiddleware {
     }
 }
 
+// Per-site concurrency limiting
+#[derive(Default)]
+pub struct ConcurrencyLimiter {
+    in_flight: Mutex<HashMap<String, Arc<AtomicUsize>>>,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserve a slot for `key` if fewer than `limit` requests are in flight.
+    /// The returned counter must be passed to `release` when the request ends.
+    pub fn try_acquire(&self, key: &str, limit: usize) -> Option<Arc<AtomicUsize>> {
+        let counter = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+
+        counter
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current < limit).then_some(current + 1)
+            })
+            .ok()
+            .map(|_| counter)
+    }
+
+    pub fn release(slot: &AtomicUsize) {
+        slot.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    pub fn in_flight(&self, key: &str) -> usize {
+        self.in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(key)
+            .map_or(0, |counter| counter.load(Ordering::Acquire))
+    }
+}
+
+// Server-wide connection limiting
+
+/// Connections open across the server, each counted from its first request
+/// until it closes. A connection is known by the socket digest its stream
+/// holds for as long as it is open, so idle keep-alive connections stay
+/// counted between requests.
+#[derive(Default)]
+pub struct ConnectionLimiter {
+    open: Mutex<HashMap<usize, Weak<SocketDigest>>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a request on `connection` may be served: always for a
+    /// connection already counted, otherwise only while fewer than `limit`
+    /// connections are open
+    pub fn admit(&self, connection: &Arc<SocketDigest>, limit: usize) -> bool {
+        let mut open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+
+        // The allocation outlives the connection while it is tracked, so its
+        // address is not reused by another one
+        let key = Arc::as_ptr(connection) as usize;
+        if open.contains_key(&key) {
+            return true;
+        }
+
+        if open.len() >= limit {
+            open.retain(|_, connection| connection.strong_count() > 0);
+            if open.len() >= limit {
+                return false;
+            }
+        }
+        open.insert(key, Arc::downgrade(connection));
+        true
+    }
+
+    /// Connections currently open
+    pub fn open(&self) -> usize {
+        self.open
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .filter(|connection| connection.strong_count() > 0)
+            .count()
+    }
+}
+
 // CORS middleware
 pub struct CorsMiddleware {
     allow_origins: Vec<String>,
@@ -348,6 +672,92 @@ impl Default for CorsMiddleware {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_validate_host() {
+        let request = |target: &str, hosts: &[&str]| {
+            let mut req = RequestHeader::build("GET", target.as_bytes(), None).unwrap();
+            for host in hosts {
+                req.append_header("Host", *host).unwrap();
+            }
+            req
+        };
+
+        assert!(validate_host(&request("/", &["example.com"])).is_ok());
+        assert!(validate_host(&request("/", &[])).is_ok());
+
+        // Duplicate Host headers, even identical ones, are rejected
+        assert!(validate_host(&request("/", &["example.com", "evil.com"])).is_err());
+        assert!(validate_host(&request("/", &["example.com", "example.com"])).is_err());
+
+        // Absolute-form targets must agree with the Host header
+        assert!(validate_host(&request("http://example.com/a", &["example.com"])).is_ok());
+        assert!(validate_host(&request("http://Example.com:80/a", &["example.com"])).is_ok());
+        assert!(validate_host(&request("https://example.com/a", &["example.com:443"])).is_ok());
+        assert!(validate_host(&request("http://[::1]:8080/", &["[::1]:8080"])).is_ok());
+        assert!(validate_host(&request("http://example.com/", &[])).is_ok());
+        assert!(validate_host(&request("http://evil.com/a", &["example.com"])).is_err());
+        assert!(validate_host(&request("http://example.com:8080/", &["example.com"])).is_err());
+        assert!(validate_host(&request("http://example.com/", &["example.com:x"])).is_err());
+
+        // HTTP/2 requests carry the authority in the URI itself
+        let mut req = request("/", &["example.com"]);
+        req.set_uri("https://evil.com/".parse().unwrap());
+        assert!(validate_host(&req).is_err());
+        req.set_uri("https://example.com/".parse().unwrap());
+        assert!(validate_host(&req).is_ok());
+    }
+
+    #[test]
+    fn test_header_limits() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.append_header("Host", "example.com").unwrap();
+        req.append_header("Cookie", "a".repeat(100)).unwrap();
+        // "host: example.com\r\n" + "cookie: <100>\r\n"
+        let size = 19 + 110;
+
+        assert!(check_header_limits(&req, size, 2).is_ok());
+        assert!(check_header_limits(&req, size - 1, 2).is_err());
+        assert!(check_header_limits(&req, size, 1).is_err());
+
+        // A header bomb of many small fields hits the count limit
+        for i in 0..100 {
+            req.append_header(format!("X-Pad-{}", i), "1").unwrap();
+        }
+        assert!(check_header_limits(&req, 64 * 1024, 100).is_err());
+        assert!(check_header_limits(&req, 64 * 1024, 102).is_ok());
+    }
+
+    #[test]
+    fn test_validate_expectation() {
+        let request = |headers: &[(&'static str, &str)]| {
+            let mut req = RequestHeader::build("POST", b"/upload", None).unwrap();
+            for (name, value) in headers {
+                req.append_header(*name, *value).unwrap();
+            }
+            req
+        };
+
+        let plain = request(&[("Content-Length", "2048")]);
+        assert!(!expects_continue(&plain));
+        assert!(validate_expectation(&plain, 1024).is_ok());
+
+        let small = request(&[("Expect", "100-Continue"), ("Content-Length", "1024")]);
+        assert!(expects_continue(&small));
+        assert!(validate_expectation(&small, 1024).is_ok());
+
+        // A body that would be refused is refused before it is sent
+        let large = request(&[("Expect", "100-continue"), ("Content-Length", "1025")]);
+        assert!(validate_expectation(&large, 1024).is_err());
+
+        // Chunked uploads have no declared size to check up front
+        let chunked = request(&[("Expect", "100-continue"), ("Transfer-Encoding", "chunked")]);
+        assert!(validate_expectation(&chunked, 1024).is_ok());
+
+        let unknown = request(&[("Expect", "200-ok")]);
+        assert!(!expects_continue(&unknown));
+        assert!(validate_expectation(&unknown, 1024).is_err());
+    }
+
     #[test]
     fn test_middleware_stack_creation() {
         let stack = MiddlewareStack::new();
@@ -370,14 +780,121 @@ mod tests {
         assert_eq!(middleware.burst_size, 10);
     }
 
+    #[test]
+    fn test_rate_limiter_scopes_are_independent() {
+        let limiter = RateLimiter::new();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let start = Instant::now();
+
+        // Burst of 2 at 60 requests per minute
+        assert!(limiter.check_at("public", client, 60, 2, start).is_ok());
+        assert!(limiter.check_at("public", client, 60, 2, start).is_ok());
+        let wait = limiter
+            .check_at("public", client, 60, 2, start)
+            .unwrap_err();
+        assert!(wait <= Duration::from_secs(1));
+
+        // Another site's budget is untouched
+        assert!(limiter.check_at("internal", client, 600, 5, start).is_ok());
+
+        // Tokens refill over time
+        let later = start + Duration::from_secs(1);
+        assert!(limiter.check_at("public", client, 60, 2, later).is_ok());
+        assert!(limiter.check_at("public", client, 60, 2, later).is_err());
+    }
+
+    #[test]
+    fn test_rate_limiter_drops_refilled_buckets() {
+        let limiter = RateLimiter::new();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let start = Instant::now();
+        let buckets = || limiter.buckets.lock().unwrap().map.len();
+
+        // Emptied: refills in 2 minutes at 1 request per minute, and in
+        // 200ms at 600 per minute
+        for _ in 0..2 {
+            assert!(limiter.check_at("slow", client, 1, 2, start).is_ok());
+            assert!(limiter.check_at("fast", client, 600, 2, start).is_ok());
+        }
+        assert_eq!(buckets(), 2);
+
+        // Swept once a minute, each bucket judged by its own rate
+        let later = start + SWEEP_INTERVAL;
+        assert!(limiter.check_at("other", client, 600, 2, later).is_ok());
+        assert_eq!(buckets(), 2);
+        assert!(limiter
+            .buckets
+            .lock()
+            .unwrap()
+            .map
+            .contains_key(&("slow".to_string(), client)));
+
+        // The slow bucket kept its state: one token refilled, not two
+        assert!(limiter.check_at("slow", client, 1, 2, later).is_ok());
+        assert!(limiter.check_at("slow", client, 1, 2, later).is_err());
+    }
+
+    #[test]
+    fn test_concurrency_limiter() {
+        let limiter = Arc::new(ConcurrencyLimiter::new());
+
+        // Only `limit` slots can be held at once, even under contention
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let limiter = limiter.clone();
+                std::thread::spawn(move || limiter.try_acquire("site", 3))
+            })
+            .collect();
+        let slots: Vec<_> = handles
+            .into_iter()
+            .filter_map(|handle| handle.join().unwrap())
+            .collect();
+        assert_eq!(slots.len(), 3);
+        assert_eq!(limiter.in_flight("site"), 3);
+
+        // Other sites are counted separately
+        assert!(limiter.try_acquire("other", 1).is_some());
+
+        for slot in &slots {
+            ConcurrencyLimiter::release(slot);
+        }
+        assert_eq!(limiter.in_flight("site"), 0);
+        assert!(limiter.try_acquire("site", 3).is_some());
+    }
+
+    #[test]
+    fn test_connection_limiter() {
+        let limiter = ConnectionLimiter::new();
+        let first = Arc::new(SocketDigest::from_raw_fd(10));
+        let second = Arc::new(SocketDigest::from_raw_fd(11));
+        let third = Arc::new(SocketDigest::from_raw_fd(12));
+
+        assert!(limiter.admit(&first, 2));
+        assert!(limiter.admit(&second, 2));
+        assert!(!limiter.admit(&third, 2));
+        assert_eq!(limiter.open(), 2);
+
+        // Further requests on an open connection are not turned away
+        assert!(limiter.admit(&first, 2));
+
+        // A closed connection frees its place
+        drop(first);
+        assert_eq!(limiter.open(), 1);
+        assert!(limiter.admit(&third, 2));
+        assert_eq!(limiter.open(), 2);
+    }
+
     #[test]
     fn test_security_headers_middleware() {
         let middleware = SecurityHeadersMiddleware::new()
             .with_header("Custom-Header".to_string(), "Custom-Value".to_string())
-            .with_hsts(31536000, true);
+            .with_hsts(31536000, true, true);
 
         assert!(middleware.headers.contains_key("Custom-Header"));
-        assert!(middleware.headers.contains_key("Strict-Transport-Security"));
+        assert_eq!(
+            middleware.headers["Strict-Transport-Security"],
+            "max-age=31536000; includeSubDomains; preload"
+        );
     }
 
     #[test]
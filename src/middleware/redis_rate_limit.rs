@@ -0,0 +1,299 @@
+//! Redis-backed rate limiting, so every instance behind a load balancer
+//! draws from the same per-client token buckets.
+//!
+//! Each check runs a Lua token-bucket script, which Redis executes atomically.
+//! All checks share one multiplexed connection: concurrent checks are
+//! pipelined on it instead of waiting for each other, and it reconnects on
+//! its own after Redis goes away.
+
+use super::RateLimitBackend;
+use async_trait::async_trait;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{ConnectionAddr, ErrorKind, RedisResult, Script};
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+/// Longest a rate limit check may wait on Redis before the request is allowed
+const REDIS_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Token bucket stored as a hash of `tokens` and `ts` (ms). Uses the Redis
+/// clock so instances with skewed clocks agree. Returns 0 when a token was
+/// taken, otherwise the milliseconds until the next token.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local rate = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(bucket[1]) or capacity
+local ts = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * rate)
+local wait = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+else
+  wait = math.ceil((1 - tokens) / rate)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', tostring(now))
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / rate))
+return wait
+"#;
+
+/// Rate limiter whose buckets live in Redis
+pub struct RedisRateLimiter {
+    client: redis::Client,
+    key_prefix: String,
+    script: Script,
+    /// Opened by the first check; one that fails leaves it to the next
+    connection: OnceCell<ConnectionManager>,
+}
+
+impl RedisRateLimiter {
+    /// Limiter for a `redis://` URL; TLS (`rediss://`) and Unix sockets are
+    /// not supported
+    pub fn new(redis_url: &str, key_prefix: &str) -> RedisResult<Self> {
+        let client = redis::Client::open(redis_url)?;
+        if !matches!(client.get_connection_info().addr, ConnectionAddr::Tcp(..)) {
+            return Err((
+                ErrorKind::InvalidClientConfig,
+                "only redis:// URLs are supported",
+            )
+                .into());
+        }
+
+        Ok(Self {
+            client,
+            key_prefix: key_prefix.to_string(),
+            script: Script::new(TOKEN_BUCKET_SCRIPT),
+            connection: OnceCell::new(),
+        })
+    }
+
+    /// Redis key for a client's bucket; the global scope is stored as `*`
+    pub fn key(&self, scope: &str, client: IpAddr) -> String {
+        let scope = if scope.is_empty() { "*" } else { scope };
+        format!("{}:{}:{}", self.key_prefix, scope, client)
+    }
+
+    async fn connection(&self) -> RedisResult<ConnectionManager> {
+        let config = ConnectionManagerConfig::new()
+            .set_connection_timeout(REDIS_TIMEOUT)
+            .set_response_timeout(REDIS_TIMEOUT);
+        self.connection
+            .get_or_try_init(|| ConnectionManager::new_with_config(self.client.clone(), config))
+            .await
+            .cloned()
+    }
+
+    async fn take_token(
+        &self,
+        key: &str,
+        requests_per_minute: u32,
+        burst_size: u32,
+    ) -> RedisResult<u64> {
+        let mut connection = self.connection().await?;
+        let rate = requests_per_minute as f64 / 60_000.0;
+        let wait: i64 = self
+            .script
+            .key(key)
+            .arg(burst_size)
+            .arg(rate)
+            .invoke_async(&mut connection)
+            .await?;
+        Ok(wait.max(0) as u64)
+    }
+}
+
+#[async_trait]
+impl RateLimitBackend for RedisRateLimiter {
+    async fn check(
+        &self,
+        scope: &str,
+        client: IpAddr,
+        requests_per_minute: u32,
+        burst_size: u32,
+    ) -> std::result::Result<(), Duration> {
+        // Nothing to refill at, and the script would divide by it. The
+        // configuration does not allow it.
+        if requests_per_minute == 0 {
+            return Err(Duration::MAX);
+        }
+
+        let key = self.key(scope, client);
+        let result = tokio::time::timeout(
+            REDIS_TIMEOUT,
+            self.take_token(&key, requests_per_minute, burst_size),
+        )
+        .await;
+
+        match result {
+            Ok(Ok(0)) => Ok(()),
+            Ok(Ok(wait_ms)) => Err(Duration::from_millis(wait_ms)),
+            // Fail open: an unavailable Redis must not take the site down
+            Ok(Err(e)) => {
+                log::warn!("Redis rate limit check failed, allowing request: {}", e);
+                Ok(())
+            }
+            Err(_) => {
+                log::warn!("Redis rate limit check timed out, allowing request");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Read one command, sent as an array of bulk strings
+    async fn read_command(stream: &mut BufReader<TcpStream>) -> Option<Vec<String>> {
+        async fn read_length(stream: &mut BufReader<TcpStream>) -> Option<usize> {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.ok()?;
+            line.get(1..)?.trim_end().parse().ok()
+        }
+
+        let mut args = Vec::new();
+        for _ in 0..read_length(stream).await? {
+            let mut arg = vec![0; read_length(stream).await? + 2];
+            stream.read_exact(&mut arg).await.ok()?;
+            arg.truncate(arg.len() - 2);
+            args.push(String::from_utf8(arg).ok()?);
+        }
+        Some(args)
+    }
+
+    /// A stand-in Redis on one connection: `answer` replies to each script
+    /// call and sees all calls pending at once; any other command gets `+OK`
+    async fn serve(
+        stream: TcpStream,
+        batch: usize,
+        mut answer: impl FnMut(&[String]) -> String,
+    ) -> Vec<Vec<String>> {
+        let mut stream = BufReader::new(stream);
+        let mut commands = Vec::new();
+        let mut pending = Vec::new();
+        while let Some(command) = read_command(&mut stream).await {
+            commands.push(command.clone());
+            if command[0] != "EVALSHA" {
+                stream.get_mut().write_all(b"+OK\r\n").await.unwrap();
+                continue;
+            }
+            pending.push(command);
+            if pending.len() == batch {
+                for command in pending.drain(..) {
+                    let reply = answer(&command);
+                    stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+                }
+            }
+        }
+        commands
+    }
+
+    #[tokio::test]
+    async fn test_redis_rate_limiter_uses_script_result() {
+        // Allows the first check and denies the second
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut replies = [":0\r\n", ":1500\r\n"].into_iter();
+            serve(stream, 1, move |_| replies.next().unwrap().to_string()).await
+        });
+
+        let limiter = RedisRateLimiter::new(&format!("redis://{}", addr), "bws:ratelimit").unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        assert!(limiter.check("public", client, 60, 1).await.is_ok());
+        assert_eq!(
+            limiter.check("public", client, 60, 1).await,
+            Err(Duration::from_millis(1500))
+        );
+
+        drop(limiter);
+        let commands = server.await.unwrap();
+        let script = commands
+            .iter()
+            .find(|command| command[0] == "EVALSHA")
+            .unwrap();
+        assert_eq!(script[3], "bws:ratelimit:public:203.0.113.7");
+        assert_eq!(script[4], "1");
+    }
+
+    #[tokio::test]
+    async fn test_redis_checks_share_one_connection() {
+        // Answers only once both checks are pending on the same connection
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut replies = [":0\r\n", ":1500\r\n"].into_iter();
+            let served = tokio::spawn(serve(stream, 2, move |_| {
+                replies.next().unwrap().to_string()
+            }));
+            let another = tokio::time::timeout(Duration::from_millis(200), listener.accept());
+            (another.await.is_ok(), served)
+        });
+
+        let limiter = RedisRateLimiter::new(&format!("redis://{}", addr), "bws").unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let (first, second) = tokio::join!(
+            limiter.check("public", client, 60, 1),
+            limiter.check("public", client, 60, 1)
+        );
+        let mut results = [first, second];
+        results.sort();
+        assert_eq!(results, [Ok(()), Err(Duration::from_millis(1500))]);
+
+        let (opened_another, _served) = server.await.unwrap();
+        assert!(!opened_another);
+    }
+
+    #[tokio::test]
+    async fn test_redis_credentials_are_percent_decoded() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve(stream, 1, |_| ":0\r\n".to_string()).await
+        });
+
+        let url = format!("redis://rate%20limiter:p%40ss%2Fword@{}/2", addr);
+        let limiter = RedisRateLimiter::new(&url, "bws").unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        assert!(limiter.check("", client, 60, 1).await.is_ok());
+
+        drop(limiter);
+        let commands = server.await.unwrap();
+        assert_eq!(commands[0], ["AUTH", "rate limiter", "p@ss/word"]);
+        assert_eq!(commands[1], ["SELECT", "2"]);
+    }
+
+    #[tokio::test]
+    async fn test_redis_rate_limiter_fails_open() {
+        // Nothing listens on this port once the listener is dropped
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let limiter = RedisRateLimiter::new(&format!("redis://{}", addr), "bws").unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        assert!(limiter.check("", client, 60, 1).await.is_ok());
+        assert_eq!(limiter.key("", client), "bws:*:203.0.113.7");
+
+        // Without a rate no token ever comes
+        assert_eq!(limiter.check("", client, 0, 1).await, Err(Duration::MAX));
+    }
+
+    #[test]
+    fn test_redis_url_must_be_plain_tcp() {
+        assert!(RedisRateLimiter::new("redis://cache:6379/0", "bws").is_ok());
+        assert!(RedisRateLimiter::new("rediss://cache:6380", "bws").is_err());
+        assert!(RedisRateLimiter::new("redis+unix:///tmp/redis.sock", "bws").is_err());
+        assert!(RedisRateLimiter::new("not a url", "bws").is_err());
+    }
+}
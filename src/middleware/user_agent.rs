@@ -0,0 +1,400 @@
+//! User-Agent filtering: block or throttle clients by their User-Agent, with
+//! an allowlist for known-good bots.
+//!
+//! Crawlers are easy to impersonate, so allowed agents claiming to be a major
+//! search engine can be verified the way the search engines document: a
+//! reverse DNS lookup of the client IP must give a host in the crawler's
+//! domain, and that host must resolve back to the same IP.
+
+use crate::config::UserAgentFilterConfig;
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+/// Crawler User-Agent tokens and the domains their reverse DNS must end in
+const KNOWN_CRAWLERS: &[(&str, &[&str])] = &[
+    (
+        "googlebot",
+        &["googlebot.com", "google.com", "googleusercontent.com"],
+    ),
+    ("bingbot", &["search.msn.com"]),
+    ("applebot", &["applebot.apple.com"]),
+    ("duckduckbot", &["duckduckgo.com"]),
+    ("yandexbot", &["yandex.ru", "yandex.net", "yandex.com"]),
+];
+
+/// How long a verification result is reused for an IP
+const VERIFICATION_TTL: Duration = Duration::from_secs(3600);
+/// Verification results kept before the cache is cleared
+const MAX_VERIFIED_IPS: usize = 10_000;
+/// Longest a reverse DNS lookup may take
+const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What to do with a request, based on its User-Agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserAgentVerdict {
+    Allow,
+    Block,
+    Throttle,
+}
+
+/// Applies a site's `user_agent_filter` to requests
+pub struct UserAgentFilterMiddleware {
+    patterns: Mutex<HashMap<String, Regex>>,
+    verified: Mutex<HashMap<IpAddr, (bool, Instant)>>,
+}
+
+impl UserAgentFilterMiddleware {
+    pub fn new() -> Self {
+        Self {
+            patterns: Mutex::new(HashMap::new()),
+            verified: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Decide what to do with a request from `client` sending `user_agent`
+    pub async fn check(
+        &self,
+        config: &UserAgentFilterConfig,
+        user_agent: &str,
+        client: Option<IpAddr>,
+    ) -> UserAgentVerdict {
+        if !config.enabled {
+            return UserAgentVerdict::Allow;
+        }
+
+        if self.matches_any(&config.allow, user_agent) {
+            if !config.verify_crawlers {
+                return UserAgentVerdict::Allow;
+            }
+            let Some(domains) = claimed_crawler_domains(user_agent) else {
+                return UserAgentVerdict::Allow;
+            };
+            return match client {
+                Some(ip) if self.verify_crawler(ip, domains).await => UserAgentVerdict::Allow,
+                _ => {
+                    log::info!(
+                        "Blocking unverified crawler {:?} from {:?}",
+                        user_agent,
+                        client
+                    );
+                    UserAgentVerdict::Block
+                }
+            };
+        }
+
+        self.classify(config, user_agent)
+    }
+
+    /// Match `user_agent` against the block and throttle patterns
+    pub fn classify(&self, config: &UserAgentFilterConfig, user_agent: &str) -> UserAgentVerdict {
+        if self.matches_any(&config.block, user_agent) {
+            UserAgentVerdict::Block
+        } else if self.matches_any(&config.throttle, user_agent) {
+            UserAgentVerdict::Throttle
+        } else {
+            UserAgentVerdict::Allow
+        }
+    }
+
+    fn matches_any(&self, patterns: &[String], user_agent: &str) -> bool {
+        if patterns.is_empty() {
+            return false;
+        }
+
+        let mut compiled = self.patterns.lock().unwrap_or_else(|e| e.into_inner());
+        patterns.iter().any(|pattern| {
+            if !compiled.contains_key(pattern) {
+                // Patterns are validated with the configuration
+                match RegexBuilder::new(pattern).case_insensitive(true).build() {
+                    Ok(re) => {
+                        compiled.insert(pattern.clone(), re);
+                    }
+                    Err(_) => return false,
+                }
+            }
+            compiled[pattern].is_match(user_agent)
+        })
+    }
+
+    async fn verify_crawler(&self, ip: IpAddr, domains: &[&str]) -> bool {
+        {
+            let verified = self.verified.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((ok, at)) = verified.get(&ip) {
+                if at.elapsed() < VERIFICATION_TTL {
+                    return *ok;
+                }
+            }
+        }
+
+        let ok = match reverse_dns(ip).await {
+            Ok(Some(host)) if host_in_domains(&host, domains) => {
+                // Forward-confirm, since anyone can publish any PTR record
+                tokio::net::lookup_host((host.as_str(), 0))
+                    .await
+                    .is_ok_and(|mut addrs| addrs.any(|addr| addr.ip() == ip))
+            }
+            Ok(_) => false,
+            Err(e) => {
+                log::warn!("Reverse DNS lookup for {} failed: {}", ip, e);
+                false
+            }
+        };
+
+        let mut verified = self.verified.lock().unwrap_or_else(|e| e.into_inner());
+        if verified.len() >= MAX_VERIFIED_IPS {
+            verified.clear();
+        }
+        verified.insert(ip, (ok, Instant::now()));
+        ok
+    }
+}
+
+impl Default for UserAgentFilterMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Domains a User-Agent must verify against, if it claims to be a known crawler
+fn claimed_crawler_domains(user_agent: &str) -> Option<&'static [&'static str]> {
+    let user_agent = user_agent.to_ascii_lowercase();
+    KNOWN_CRAWLERS
+        .iter()
+        .find(|(token, _)| user_agent.contains(token))
+        .map(|(_, domains)| *domains)
+}
+
+/// Whether `host` is one of `domains` or a subdomain of one
+fn host_in_domains(host: &str, domains: &[&str]) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    domains
+        .iter()
+        .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+}
+
+/// The `in-addr.arpa` / `ip6.arpa` name for a reverse lookup of `ip`
+fn ptr_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::new();
+            for byte in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// First nameserver from /etc/resolv.conf
+fn system_nameserver() -> SocketAddr {
+    std::fs::read_to_string("/etc/resolv.conf")
+        .ok()
+        .and_then(|conf| {
+            conf.lines().find_map(|line| {
+                let mut parts = line.split_whitespace();
+                match parts.next() {
+                    Some("nameserver") => parts.next()?.parse::<IpAddr>().ok(),
+                    _ => None,
+                }
+            })
+        })
+        .map(|ip| SocketAddr::new(ip, 53))
+        .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 53)))
+}
+
+/// Look up the PTR record for `ip` with the system nameserver
+async fn reverse_dns(ip: IpAddr) -> std::io::Result<Option<String>> {
+    let nameserver = system_nameserver();
+    let bind_addr: SocketAddr = if nameserver.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(nameserver).await?;
+
+    let id = fastrand::u16(..);
+    socket.send(&ptr_query(id, &ptr_name(ip))).await?;
+
+    let mut buf = [0u8; 512];
+    let len = tokio::time::timeout(DNS_TIMEOUT, socket.recv(&mut buf))
+        .await
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+    Ok(parse_ptr_response(id, &buf[..len]))
+}
+
+/// A recursive DNS query for the PTR record of `name`
+fn ptr_query(id: u16, name: &str) -> Vec<u8> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    // Root label, QTYPE=PTR, QCLASS=IN
+    query.extend_from_slice(&[0, 0, 12, 0, 1]);
+    query
+}
+
+/// The first PTR answer in a DNS response, if any
+fn parse_ptr_response(id: u16, message: &[u8]) -> Option<String> {
+    if message.len() < 12 || message[..2] != id.to_be_bytes() || message[3] & 0x0f != 0 {
+        return None;
+    }
+    let questions = u16::from_be_bytes([message[4], message[5]]);
+    let answers = u16::from_be_bytes([message[6], message[7]]);
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = read_name(message, offset)?.1 + 4;
+    }
+    for _ in 0..answers {
+        offset = read_name(message, offset)?.1;
+        let record = message.get(offset..offset + 10)?;
+        let record_type = u16::from_be_bytes([record[0], record[1]]);
+        let data_len = u16::from_be_bytes([record[8], record[9]]) as usize;
+        offset += 10;
+        if record_type == 12 {
+            return read_name(message, offset).map(|(name, _)| name);
+        }
+        offset += data_len;
+    }
+    None
+}
+
+/// Read a possibly compressed name at `offset`, returning it and the offset
+/// just past it
+fn read_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bound pointer chains so a malicious response cannot loop forever
+    for _ in 0..128 {
+        let len = *message.get(offset)? as usize;
+        if len & 0xc0 == 0xc0 {
+            let pointer = ((len & 0x3f) << 8) | *message.get(offset + 1)? as usize;
+            end.get_or_insert(offset + 2);
+            offset = pointer;
+        } else if len == 0 {
+            let name = labels.join(".");
+            return Some((name, end.unwrap_or(offset + 1)));
+        } else {
+            let label = message.get(offset + 1..offset + 1 + len)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            offset += 1 + len;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter_config() -> UserAgentFilterConfig {
+        UserAgentFilterConfig {
+            enabled: true,
+            block: vec!["ahrefsbot|semrushbot".to_string(), "^$".to_string()],
+            throttle: vec!["bot|crawler|spider".to_string()],
+            allow: vec!["googlebot".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_user_agent_matching() {
+        let filter = UserAgentFilterMiddleware::new();
+        let config = filter_config();
+        let (filter, config) = (&filter, &config);
+        let check = move |ua: &'static str| filter.check(config, ua, None);
+
+        assert_eq!(
+            check("Mozilla/5.0 (compatible; AhrefsBot/7.0)").await,
+            UserAgentVerdict::Block
+        );
+        assert_eq!(check("").await, UserAgentVerdict::Block);
+        assert_eq!(
+            check("Mozilla/5.0 (compatible; MJ12bot/v1.4.8)").await,
+            UserAgentVerdict::Throttle
+        );
+        assert_eq!(
+            check("Mozilla/5.0 (X11; Linux x86_64) Firefox/128.0").await,
+            UserAgentVerdict::Allow
+        );
+        // Allowlisted bots skip the throttle patterns
+        assert_eq!(
+            check("Mozilla/5.0 (compatible; Googlebot/2.1)").await,
+            UserAgentVerdict::Allow
+        );
+
+        let disabled = UserAgentFilterConfig {
+            enabled: false,
+            ..filter_config()
+        };
+        assert_eq!(
+            filter.check(&disabled, "AhrefsBot", None).await,
+            UserAgentVerdict::Allow
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unverifiable_crawler_is_blocked() {
+        let filter = UserAgentFilterMiddleware::new();
+        let config = UserAgentFilterConfig {
+            verify_crawlers: true,
+            ..filter_config()
+        };
+
+        // Without a client address the claim cannot be verified
+        assert_eq!(
+            filter.check(&config, "Googlebot/2.1", None).await,
+            UserAgentVerdict::Block
+        );
+        assert_eq!(claimed_crawler_domains("Mozilla/5.0 Firefox"), None);
+        assert!(host_in_domains(
+            "crawl-66-249-66-1.googlebot.com.",
+            claimed_crawler_domains("Googlebot/2.1").unwrap()
+        ));
+        assert!(!host_in_domains(
+            "googlebot.com.evil.net",
+            &["googlebot.com"]
+        ));
+    }
+
+    #[test]
+    fn test_ptr_query_and_response() {
+        assert_eq!(
+            ptr_name("66.249.66.1".parse().unwrap()),
+            "1.66.249.66.in-addr.arpa"
+        );
+        assert!(ptr_name("2001:db8::1".parse().unwrap())
+            .starts_with("1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2"));
+
+        // Answer to our query, with the answer name compressed to the question
+        let mut response = ptr_query(0x1234, "1.66.249.66.in-addr.arpa");
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 1;
+        response.extend_from_slice(&[0xc0, 12, 0, 12, 0, 1, 0, 0, 0x0e, 0x10]);
+        let host = b"\x11crawl-66-249-66-1\x09googlebot\x03com\x00";
+        response.extend_from_slice(&(host.len() as u16).to_be_bytes());
+        response.extend_from_slice(host);
+
+        assert_eq!(
+            parse_ptr_response(0x1234, &response).as_deref(),
+            Some("crawl-66-249-66-1.googlebot.com")
+        );
+        // Responses to other queries are ignored
+        assert_eq!(parse_ptr_response(0x4321, &response), None);
+    }
+}
@@ -0,0 +1,406 @@
+//! Access logging in Common, Combined and JSON formats
+
+use chrono::{DateTime, FixedOffset};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Supported access log line formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// Common Log Format: `host ident authuser [date] "request" status bytes`
+    Common,
+    /// Combined Log Format: Common plus `"referer" "user-agent"`
+    Combined,
+    /// Combined plus `bytes_received bytes_sent`, like Apache's `combinedio`
+    CombinedIo,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "common" | "clf" => Ok(AccessLogFormat::Common),
+            "combined" => Ok(AccessLogFormat::Combined),
+            "combinedio" => Ok(AccessLogFormat::CombinedIo),
+            "json" => Ok(AccessLogFormat::Json),
+            _ => Err(format!("Unknown log format: {}", format)),
+        }
+    }
+}
+
+/// A single completed request, ready to be formatted
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    pub client_ip: String,
+    pub user: Option<String>,
+    pub time: DateTime<FixedOffset>,
+    pub method: String,
+    pub uri: String,
+    pub version: String,
+    pub status: u16,
+    /// Request body bytes read from the client
+    pub bytes_received: usize,
+    /// Response body bytes written to the client
+    pub bytes_sent: usize,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AccessLogEntry {
+    /// Format the entry as a single log line (without trailing newline)
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Common => self.format_common(),
+            AccessLogFormat::Combined => self.format_combined(),
+            AccessLogFormat::CombinedIo => format!(
+                "{} {} {}",
+                self.format_combined(),
+                self.bytes_received,
+                self.bytes_sent
+            ),
+            AccessLogFormat::Json => serde_json::json!({
+                "client_ip": self.client_ip,
+                "user": self.user,
+                "time": self.time.to_rfc3339(),
+                "method": self.method,
+                "uri": self.uri,
+                "version": self.version,
+                "status": self.status,
+                "bytes_received": self.bytes_received,
+                "bytes_sent": self.bytes_sent,
+                "referer": self.referer,
+                "user_agent": self.user_agent,
+            })
+            .to_string(),
+        }
+    }
+
+    fn format_combined(&self) -> String {
+        format!(
+            "{} \"{}\" \"{}\"",
+            self.format_common(),
+            escape_quoted(self.referer.as_deref().unwrap_or("-")),
+            escape_quoted(self.user_agent.as_deref().unwrap_or("-"))
+        )
+    }
+
+    fn format_common(&self) -> String {
+        // CLF uses "-" for an empty body
+        let bytes = if self.bytes_sent == 0 {
+            "-".to_string()
+        } else {
+            self.bytes_sent.to_string()
+        };
+
+        format!(
+            "{} - {} [{}] \"{} {} {}\" {} {}",
+            self.client_ip,
+            self.user.as_deref().unwrap_or("-"),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            escape_quoted(&self.uri),
+            self.version,
+            self.status,
+            bytes
+        )
+    }
+}
+
+/// Escape characters that would break a quoted log field
+fn escape_quoted(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn open_append(path: &str) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Writes access log lines to files, or to the application log when no file is configured.
+/// Sites may log to their own file and in their own format; sites sharing a
+/// path share one file handle.
+pub struct AccessLogger {
+    format: AccessLogFormat,
+    site_formats: HashMap<String, AccessLogFormat>,
+    file: Option<Arc<Mutex<File>>>,
+    site_files: HashMap<String, Arc<Mutex<File>>>,
+    open_files: HashMap<String, Arc<Mutex<File>>>,
+}
+
+impl AccessLogger {
+    /// Create a logger writing to `path` (appending), or to the application log if `None`
+    pub fn new(format: AccessLogFormat, path: Option<&str>) -> std::io::Result<Self> {
+        let mut logger = Self {
+            format,
+            site_formats: HashMap::new(),
+            file: None,
+            site_files: HashMap::new(),
+            open_files: HashMap::new(),
+        };
+
+        if let Some(path) = path {
+            logger.file = Some(logger.open(path)?);
+        }
+
+        Ok(logger)
+    }
+
+    /// Send entries for `site_name` to `path` instead of the global access log
+    pub fn add_site_log(&mut self, site_name: &str, path: &str) -> std::io::Result<()> {
+        let file = self.open(path)?;
+        self.site_files.insert(site_name.to_string(), file);
+        Ok(())
+    }
+
+    /// Write entries for `site_name` in `format` instead of the global one
+    pub fn set_site_format(&mut self, site_name: &str, format: AccessLogFormat) {
+        self.site_formats.insert(site_name.to_string(), format);
+    }
+
+    /// Open `path` for appending, reusing an existing handle for the same path
+    fn open(&mut self, path: &str) -> std::io::Result<Arc<Mutex<File>>> {
+        if let Some(file) = self.open_files.get(path) {
+            return Ok(file.clone());
+        }
+
+        let file = Arc::new(Mutex::new(open_append(path)?));
+        self.open_files.insert(path.to_string(), file.clone());
+        Ok(file)
+    }
+
+    /// Open every log file again by its path, so entries go to a new file
+    /// once `logrotate` or similar has moved the old one away. A file that
+    /// cannot be opened keeps its old handle.
+    pub fn reopen(&self) {
+        for (path, file) in &self.open_files {
+            match open_append(path) {
+                Ok(reopened) => {
+                    if let Ok(mut file) = file.lock() {
+                        *file = reopened;
+                    }
+                }
+                Err(e) => log::error!("Failed to reopen access log {}: {}", path, e),
+            }
+        }
+    }
+
+    pub fn format(&self) -> AccessLogFormat {
+        self.format
+    }
+
+    /// The format entries for the given site are written in
+    pub fn format_for(&self, site_name: Option<&str>) -> AccessLogFormat {
+        site_name
+            .and_then(|name| self.site_formats.get(name))
+            .copied()
+            .unwrap_or(self.format)
+    }
+
+    /// Whether entries for the given site are written to a dedicated access log file
+    pub fn has_file(&self, site_name: Option<&str>) -> bool {
+        self.writer(site_name).is_some()
+    }
+
+    fn writer(&self, site_name: Option<&str>) -> Option<&Arc<Mutex<File>>> {
+        site_name
+            .and_then(|name| self.site_files.get(name))
+            .or(self.file.as_ref())
+    }
+
+    /// Write an entry to the site's log file, the global file, or the application log
+    pub fn log(&self, site_name: Option<&str>, entry: &AccessLogEntry) {
+        let line = entry.format(self.format_for(site_name));
+
+        match self.writer(site_name) {
+            Some(file) => {
+                if let Ok(mut file) = file.lock() {
+                    if let Err(e) = writeln!(file, "{}", line) {
+                        log::error!("Failed to write access log: {}", e);
+                    }
+                }
+            }
+            None => log::info!("{}", line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_entry() -> AccessLogEntry {
+        AccessLogEntry {
+            client_ip: "127.0.0.1".to_string(),
+            user: None,
+            time: DateTime::parse_from_rfc3339("2000-10-10T13:55:36-07:00").unwrap(),
+            method: "GET".to_string(),
+            uri: "/apache_pb.gif".to_string(),
+            version: "HTTP/1.0".to_string(),
+            status: 200,
+            bytes_received: 0,
+            bytes_sent: 2326,
+            referer: Some("http://www.example.com/start.html".to_string()),
+            user_agent: Some("Mozilla/4.08 [en] (Win98; I ;Nav)".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_log_format_parsing() {
+        assert_eq!(
+            "combined".parse::<AccessLogFormat>(),
+            Ok(AccessLogFormat::Combined)
+        );
+        assert_eq!(
+            "CLF".parse::<AccessLogFormat>(),
+            Ok(AccessLogFormat::Common)
+        );
+        assert_eq!("json".parse::<AccessLogFormat>(), Ok(AccessLogFormat::Json));
+        assert_eq!(
+            "combinedio".parse::<AccessLogFormat>(),
+            Ok(AccessLogFormat::CombinedIo)
+        );
+        assert!("apache".parse::<AccessLogFormat>().is_err());
+    }
+
+    #[test]
+    fn test_common_log_format() {
+        let line = create_test_entry().format(AccessLogFormat::Common);
+        assert_eq!(
+            line,
+            r#"127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif HTTP/1.0" 200 2326"#
+        );
+    }
+
+    #[test]
+    fn test_combined_log_format_parses() {
+        let mut entry = create_test_entry();
+        entry.user_agent = Some("quote\"agent".to_string());
+        let line = entry.format(AccessLogFormat::Combined);
+
+        // host ident authuser [date] "request" status bytes "referer" "user-agent"
+        let (prefix, rest) = line.split_once(" [").unwrap();
+        assert_eq!(
+            prefix.split(' ').collect::<Vec<_>>(),
+            ["127.0.0.1", "-", "-"]
+        );
+
+        let (date, rest) = rest.split_once("] ").unwrap();
+        assert!(DateTime::parse_from_str(date, "%d/%b/%Y:%H:%M:%S %z").is_ok());
+
+        let fields: Vec<&str> = rest.split('"').collect();
+        assert_eq!(fields[1], "GET /apache_pb.gif HTTP/1.0");
+        assert_eq!(fields[2].trim(), "200 2326");
+        assert_eq!(fields[3], "http://www.example.com/start.html");
+        assert!(rest.ends_with(r#""quote\"agent""#));
+
+        // Empty bodies are logged as "-"
+        entry.bytes_sent = 0;
+        assert!(entry
+            .format(AccessLogFormat::Combined)
+            .contains("\" 200 - \""));
+    }
+
+    #[test]
+    fn test_byte_counts_logged() {
+        let mut entry = create_test_entry();
+        entry.bytes_received = 512;
+        let line = entry.format(AccessLogFormat::CombinedIo);
+        assert!(line.starts_with(&entry.format(AccessLogFormat::Combined)));
+        assert!(line.ends_with("\" 512 2326"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&entry.format(AccessLogFormat::Json)).unwrap();
+        assert_eq!(json["bytes_received"], 512);
+        assert_eq!(json["bytes_sent"], 2326);
+    }
+
+    #[test]
+    fn test_per_site_access_logs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let global = temp_dir.path().join("access.log");
+        let shared = temp_dir.path().join("shared.log");
+        let shared_path = shared.to_str().unwrap();
+
+        let mut logger =
+            AccessLogger::new(AccessLogFormat::Common, Some(global.to_str().unwrap())).unwrap();
+        logger.add_site_log("blog", shared_path).unwrap();
+        logger.add_site_log("docs", shared_path).unwrap();
+
+        // Sites on the same path share one handle
+        assert!(Arc::ptr_eq(
+            &logger.site_files["blog"],
+            &logger.site_files["docs"]
+        ));
+        assert_eq!(logger.open_files.len(), 2);
+
+        let mut entry = create_test_entry();
+        logger.log(Some("blog"), &entry);
+        entry.uri = "/docs".to_string();
+        logger.log(Some("docs"), &entry);
+        entry.uri = "/other".to_string();
+        logger.log(Some("shop"), &entry);
+        logger.log(None, &entry);
+
+        let shared_log = std::fs::read_to_string(&shared).unwrap();
+        assert_eq!(shared_log.lines().count(), 2);
+        assert!(shared_log.contains("/apache_pb.gif") && shared_log.contains("/docs"));
+
+        let global_log = std::fs::read_to_string(&global).unwrap();
+        assert_eq!(global_log.lines().count(), 2);
+        assert!(!global_log.contains("/docs"));
+    }
+
+    #[test]
+    fn test_reopen_after_rotation() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("access.log");
+        let rotated = temp_dir.path().join("access.log.1");
+
+        let mut logger =
+            AccessLogger::new(AccessLogFormat::Common, Some(path.to_str().unwrap())).unwrap();
+        logger.add_site_log("blog", path.to_str().unwrap()).unwrap();
+        let entry = create_test_entry();
+        logger.log(None, &entry);
+
+        // Renamed away: writes follow the old file until reopened
+        std::fs::rename(&path, &rotated).unwrap();
+        logger.log(None, &entry);
+        logger.reopen();
+        logger.log(None, &entry);
+        logger.log(Some("blog"), &entry);
+
+        let old = std::fs::read_to_string(&rotated).unwrap();
+        assert_eq!(old.lines().count(), 2);
+        let new = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(new.lines().count(), 2);
+    }
+
+    #[test]
+    fn test_per_site_log_formats() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("access.log");
+
+        let mut logger =
+            AccessLogger::new(AccessLogFormat::Combined, Some(path.to_str().unwrap())).unwrap();
+        logger.set_site_format("api", AccessLogFormat::Json);
+        assert_eq!(logger.format_for(Some("api")), AccessLogFormat::Json);
+        assert_eq!(logger.format_for(Some("www")), AccessLogFormat::Combined);
+        assert_eq!(logger.format_for(None), AccessLogFormat::Combined);
+
+        let entry = create_test_entry();
+        logger.log(Some("api"), &entry);
+        logger.log(Some("www"), &entry);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let json: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(json["status"], 200);
+        assert_eq!(lines[1], entry.format(AccessLogFormat::Combined));
+    }
+}
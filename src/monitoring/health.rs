@@ -1,18 +1,34 @@
 use crate::config::SiteConfig;
+use crate::core::utils::string;
+use crate::monitoring::metrics::metrics;
 use pingora::http::ResponseHeader;
 use pingora::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub struct HealthHandler {
     start_time: std::time::Instant,
+    /// Set while draining: readiness reports not-ready so load balancers stop
+    /// sending new traffic, while requests keep being served
+    draining: AtomicBool,
 }
 
 impl HealthHandler {
     pub fn new() -> Self {
         Self {
             start_time: std::time::Instant::now(),
+            draining: AtomicBool::new(false),
         }
     }
 
+    /// Start or stop draining
+    pub fn set_draining(&self, draining: bool) {
+        self.draining.store(draining, Ordering::Relaxed);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
     pub async fn handle(&self, session: &mut Session, _site: Option<&SiteConfig>) -> Result<()> {
         let path = session.req_header().uri.path();
 
@@ -26,15 +42,22 @@ impl HealthHandler {
     }
 
     async fn handle_basic_health(&self, session: &mut Session) -> Result<()> {
-        let response = serde_json::json!({
+        let response = self.basic_health();
+        self.send_json_response(session, 200, &response).await
+    }
+
+    fn basic_health(&self) -> serde_json::Value {
+        let uptime = self.start_time.elapsed();
+
+        serde_json::json!({
             "status": "ok",
             "timestamp": chrono::Utc::now().to_rfc3339(),
             "service": "bws-web-server",
             "version": env!("CARGO_PKG_VERSION"),
-            "uptime_seconds": self.start_time.elapsed().as_secs()
-        });
-
-        self.send_json_response(session, 200, &response).await
+            "uptime_seconds": uptime.as_secs(),
+            "uptime": string::humanize_duration(uptime),
+            "active_connections": metrics().active_connections()
+        })
     }
 
     async fn handle_detailed_health(&self, session: &mut Session) -> Result<()> {
@@ -54,6 +77,7 @@ impl HealthHandler {
                 "seconds": uptime.as_secs(),
                 "human_readable": self.format_duration(uptime)
             },
+            "active_connections": metrics().active_connections(),
             "memory": memory_info,
             "system": system_info,
             "features": {
@@ -76,6 +100,7 @@ impl HealthHandler {
         let response = serde_json::json!({
             "status": if is_ready { "ready" } else { "not_ready" },
             "timestamp": chrono::Utc::now().to_rfc3339(),
+            "draining": self.is_draining(),
             "checks": {
                 "configuration_loaded": true,
                 "ssl_manager_initialized": true,
@@ -103,9 +128,7 @@ impl HealthHandler {
     }
 
     fn check_readiness(&self) -> bool {
-        // Implement readiness checks here
-        // For now, assume always ready
-        true
+        !self.is_draining()
     }
 
     fn check_liveness(&self) -> bool {
@@ -215,10 +238,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_basic_health_reports_connections_and_uptime() {
+        let handler = HealthHandler::new();
+        let health = handler.basic_health();
+
+        assert_eq!(health["status"], "ok");
+        assert_eq!(health["uptime"], "0s");
+        assert!(health["active_connections"].is_u64());
+    }
+
     #[test]
     fn test_readiness_check() {
         let handler = HealthHandler::new();
         assert!(handler.check_readiness());
+
+        // Draining reports not-ready until undrained, without affecting liveness
+        handler.set_draining(true);
+        assert!(!handler.check_readiness());
+        assert!(handler.check_liveness());
+        handler.set_draining(false);
+        assert!(handler.check_readiness());
     }
 
     #[test]
@@ -3,8 +3,11 @@
 //! This module provides metrics collection, aggregation, and reporting
 //! functionality for monitoring server performance and health.
 
+use crate::config::MetricsConfig;
 use crate::core::{BwsResult, HealthStatus};
+use crate::handlers::upstream_health::CircuitState;
 use std::collections::HashMap;
+use std::fmt::Write;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
@@ -18,15 +21,42 @@ pub struct MetricsCollector {
     /// Response time histogram
     response_times: Arc<RwLock<Vec<Duration>>>,
 
+    /// Request durations in the configured buckets
+    latency: Arc<RwLock<LatencyHistogram>>,
+
     /// Active connections counter
     active_connections: AtomicU64,
 
-    /// Total bytes served
-    bytes_served: AtomicU64,
+    /// Request body bytes received
+    bytes_in: AtomicU64,
+
+    /// Response body bytes sent
+    bytes_out: AtomicU64,
 
     /// Error counters by type
     error_counts: Arc<RwLock<HashMap<String, AtomicU64>>>,
 
+    /// Requests exceeding the slow request threshold
+    slow_requests: AtomicU64,
+
+    /// Requests refused for trying to escape a site's directory
+    path_traversal_attempts: AtomicU64,
+
+    /// Static files served from the in-memory file cache
+    file_cache_hits: AtomicU64,
+
+    /// Cacheable static files that had to be read from disk
+    file_cache_misses: AtomicU64,
+
+    /// Connection pool counts by upstream address
+    upstream_pools: Arc<RwLock<HashMap<String, UpstreamPoolStats>>>,
+
+    /// Circuit breaker states by upstream address
+    upstream_circuits: Arc<RwLock<HashMap<String, CircuitState>>>,
+
+    /// Consecutive certificate renewal failures by domain
+    certificate_renewal_failures: Arc<RwLock<HashMap<String, u32>>>,
+
     /// Server start time
     start_time: Instant,
 }
@@ -37,15 +67,33 @@ impl MetricsCollector {
         Self {
             request_counts: Arc::new(RwLock::new(HashMap::new())),
             response_times: Arc::new(RwLock::new(Vec::new())),
+            latency: Arc::new(RwLock::new(LatencyHistogram::new(
+                MetricsConfig::default().buckets,
+            ))),
             active_connections: AtomicU64::new(0),
-            bytes_served: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
             error_counts: Arc::new(RwLock::new(HashMap::new())),
+            slow_requests: AtomicU64::new(0),
+            path_traversal_attempts: AtomicU64::new(0),
+            file_cache_hits: AtomicU64::new(0),
+            file_cache_misses: AtomicU64::new(0),
+            upstream_pools: Arc::new(RwLock::new(HashMap::new())),
+            upstream_circuits: Arc::new(RwLock::new(HashMap::new())),
+            certificate_renewal_failures: Arc::new(RwLock::new(HashMap::new())),
             start_time: Instant::now(),
         }
     }
 
-    /// Record a request with status code and response time
-    pub fn record_request(&self, status_code: u16, response_time: Duration, bytes: u64) {
+    /// Record a request with status code, response time and the body bytes
+    /// it received and sent
+    pub fn record_request(
+        &self,
+        status_code: u16,
+        response_time: Duration,
+        bytes_in: u64,
+        bytes_out: u64,
+    ) {
         // Increment request counter
         if let Ok(mut counts) = self.request_counts.write() {
             counts
@@ -64,8 +112,22 @@ impl MetricsCollector {
             }
         }
 
-        // Add bytes served
-        self.bytes_served.fetch_add(bytes, Ordering::Relaxed);
+        if let Ok(mut latency) = self.latency.write() {
+            latency.observe(response_time);
+        }
+
+        self.bytes_in.fetch_add(bytes_in, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes_out, Ordering::Relaxed);
+    }
+
+    /// Use `buckets` (upper bounds in seconds) for the request duration
+    /// histogram. Changing them starts the histogram over.
+    pub fn set_latency_buckets(&self, buckets: &[f64]) {
+        if let Ok(mut latency) = self.latency.write() {
+            if latency.buckets != buckets {
+                *latency = LatencyHistogram::new(buckets.to_vec());
+            }
+        }
     }
 
     /// Increment active connections
@@ -78,6 +140,11 @@ impl MetricsCollector {
         self.active_connections.fetch_sub(1, Ordering::Relaxed);
     }
 
+    /// Current number of in-flight requests
+    pub fn active_connections(&self) -> u64 {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
     /// Record an error
     pub fn record_error(&self, error_type: &str) {
         if let Ok(mut errors) = self.error_counts.write() {
@@ -88,6 +155,61 @@ impl MetricsCollector {
         }
     }
 
+    /// Record a request that exceeded the slow request threshold
+    pub fn record_slow_request(&self) {
+        self.slow_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a request refused for path traversal
+    pub fn record_path_traversal(&self) {
+        self.path_traversal_attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a lookup in the static file cache
+    pub fn record_file_cache_lookup(&self, hit: bool) {
+        if hit {
+            self.file_cache_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.file_cache_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record the current connection counts of an upstream's pool
+    pub fn record_upstream_pool(&self, address: &str, stats: UpstreamPoolStats) {
+        if let Ok(mut pools) = self.upstream_pools.write() {
+            pools.insert(address.to_string(), stats);
+        }
+    }
+
+    /// Forget the counts of all upstream pools
+    pub fn clear_upstream_pools(&self) {
+        if let Ok(mut pools) = self.upstream_pools.write() {
+            pools.clear();
+        }
+    }
+
+    /// Record a change of an upstream's circuit breaker state
+    pub fn record_upstream_circuit(&self, address: &str, state: CircuitState) {
+        if let Ok(mut circuits) = self.upstream_circuits.write() {
+            circuits.insert(address.to_string(), state);
+        }
+    }
+
+    /// Forget the circuit states of all upstreams
+    pub fn clear_upstream_circuits(&self) {
+        if let Ok(mut circuits) = self.upstream_circuits.write() {
+            circuits.clear();
+        }
+    }
+
+    /// Record the number of renewal attempts in a row that failed for a
+    /// domain's certificate, 0 after a successful renewal
+    pub fn record_certificate_renewal(&self, domain: &str, consecutive_failures: u32) {
+        if let Ok(mut failures) = self.certificate_renewal_failures.write() {
+            failures.insert(domain.to_string(), consecutive_failures);
+        }
+    }
+
     /// Get current metrics snapshot
     pub fn get_metrics(&self) -> BwsResult<MetricsSnapshot> {
         let request_counts = self
@@ -118,6 +240,40 @@ impl MetricsCollector {
             .map(|(k, v)| (k.clone(), v.load(Ordering::Relaxed)))
             .collect();
 
+        let upstream_pools = self
+            .upstream_pools
+            .read()
+            .map_err(|_| {
+                crate::core::BwsError::Internal("Failed to read upstream pools".to_string())
+            })?
+            .clone();
+
+        let latency = self
+            .latency
+            .read()
+            .map_err(|_| {
+                crate::core::BwsError::Internal("Failed to read latency histogram".to_string())
+            })?
+            .clone();
+
+        let upstream_circuits = self
+            .upstream_circuits
+            .read()
+            .map_err(|_| {
+                crate::core::BwsError::Internal("Failed to read upstream circuits".to_string())
+            })?
+            .clone();
+
+        let certificate_renewal_failures = self
+            .certificate_renewal_failures
+            .read()
+            .map_err(|_| {
+                crate::core::BwsError::Internal(
+                    "Failed to read certificate renewal failures".to_string(),
+                )
+            })?
+            .clone();
+
         let avg_response_time = if response_times.is_empty() {
             Duration::from_millis(0)
         } else {
@@ -138,10 +294,19 @@ impl MetricsCollector {
             uptime: self.start_time.elapsed(),
             request_counts,
             active_connections: self.active_connections.load(Ordering::Relaxed),
-            bytes_served: self.bytes_served.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
             avg_response_time,
             p95_response_time,
+            latency,
             error_counts,
+            slow_requests: self.slow_requests.load(Ordering::Relaxed),
+            path_traversal_attempts: self.path_traversal_attempts.load(Ordering::Relaxed),
+            file_cache_hits: self.file_cache_hits.load(Ordering::Relaxed),
+            file_cache_misses: self.file_cache_misses.load(Ordering::Relaxed),
+            upstream_pools,
+            upstream_circuits,
+            certificate_renewal_failures,
             health_status: self.get_health_status(),
         })
     }
@@ -192,6 +357,58 @@ impl Default for MetricsCollector {
     }
 }
 
+/// Request durations counted in buckets, the way a Prometheus histogram is
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyHistogram {
+    /// Upper bounds in seconds, ascending; `+Inf` follows implicitly
+    pub buckets: Vec<f64>,
+    /// Observations per bucket, not cumulative, with those above every
+    /// bound last
+    counts: Vec<u64>,
+    /// Total of all observations in seconds
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl LatencyHistogram {
+    pub fn new(buckets: Vec<f64>) -> Self {
+        Self {
+            counts: vec![0; buckets.len() + 1],
+            buckets,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = self.buckets.partition_point(|bound| *bound < seconds);
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    /// Observations at or below each bound, then the total for `+Inf`
+    pub fn cumulative_counts(&self) -> Vec<u64> {
+        self.counts
+            .iter()
+            .scan(0, |total, count| {
+                *total += count;
+                Some(*total)
+            })
+            .collect()
+    }
+}
+
+/// Connection counts of an upstream's pool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UpstreamPoolStats {
+    /// Connections carrying a request
+    pub active: usize,
+    /// Keep-alive connections waiting for the next request
+    pub idle: usize,
+}
+
 /// Snapshot of current metrics
 #[derive(Debug, Clone)]
 pub struct MetricsSnapshot {
@@ -204,8 +421,11 @@ pub struct MetricsSnapshot {
     /// Current active connections
     pub active_connections: u64,
 
-    /// Total bytes served
-    pub bytes_served: u64,
+    /// Request body bytes received
+    pub bytes_in: u64,
+
+    /// Response body bytes sent
+    pub bytes_out: u64,
 
     /// Average response time
     pub avg_response_time: Duration,
@@ -213,9 +433,33 @@ pub struct MetricsSnapshot {
     /// 95th percentile response time
     pub p95_response_time: Duration,
 
+    /// Request durations in the configured buckets
+    pub latency: LatencyHistogram,
+
     /// Error counts by type
     pub error_counts: HashMap<String, u64>,
 
+    /// Requests exceeding the slow request threshold
+    pub slow_requests: u64,
+
+    /// Requests refused for trying to escape a site's directory
+    pub path_traversal_attempts: u64,
+
+    /// Static files served from the in-memory file cache
+    pub file_cache_hits: u64,
+
+    /// Cacheable static files that had to be read from disk
+    pub file_cache_misses: u64,
+
+    /// Connection pool counts by upstream address
+    pub upstream_pools: HashMap<String, UpstreamPoolStats>,
+
+    /// Circuit breaker states by upstream address, as of their last change
+    pub upstream_circuits: HashMap<String, CircuitState>,
+
+    /// Consecutive certificate renewal failures by domain
+    pub certificate_renewal_failures: HashMap<String, u32>,
+
     /// Overall health status
     pub health_status: HealthStatus,
 }
@@ -227,13 +471,194 @@ impl MetricsSnapshot {
             "uptime_seconds": self.uptime.as_secs(),
             "request_counts": self.request_counts,
             "active_connections": self.active_connections,
-            "bytes_served": self.bytes_served,
+            "bytes_in": self.bytes_in,
+            "bytes_out": self.bytes_out,
             "avg_response_time_ms": self.avg_response_time.as_millis(),
             "p95_response_time_ms": self.p95_response_time.as_millis(),
             "error_counts": self.error_counts,
+            "slow_requests": self.slow_requests,
+            "path_traversal_attempts": self.path_traversal_attempts,
+            "file_cache_hits": self.file_cache_hits,
+            "file_cache_misses": self.file_cache_misses,
+            "upstream_pools": self
+                .upstream_pools
+                .iter()
+                .map(|(address, stats)| {
+                    (
+                        address.clone(),
+                        serde_json::json!({ "active": stats.active, "idle": stats.idle }),
+                    )
+                })
+                .collect::<serde_json::Map<_, _>>(),
+            "upstream_circuits": self.upstream_circuits,
+            "certificate_renewal_failures": self.certificate_renewal_failures,
             "health_status": format!("{:?}", self.health_status),
         })
     }
+
+    /// Render in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        prometheus_family(
+            &mut out,
+            "requests_total",
+            "counter",
+            "Requests served, by status code",
+        );
+        let mut statuses: Vec<_> = self.request_counts.iter().collect();
+        statuses.sort();
+        for (status, count) in statuses {
+            let _ = writeln!(out, "bws_requests_total{{status=\"{}\"}} {}", status, count);
+        }
+
+        prometheus_family(
+            &mut out,
+            "request_duration_seconds",
+            "histogram",
+            "Time taken to serve requests",
+        );
+        let cumulative = self.latency.cumulative_counts();
+        for (bound, count) in self.latency.buckets.iter().zip(&cumulative) {
+            let _ = writeln!(
+                out,
+                "bws_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "bws_request_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            self.latency.count
+        );
+        let _ = writeln!(out, "bws_request_duration_seconds_sum {}", self.latency.sum);
+        let _ = writeln!(
+            out,
+            "bws_request_duration_seconds_count {}",
+            self.latency.count
+        );
+
+        prometheus_family(
+            &mut out,
+            "active_connections",
+            "gauge",
+            "Requests in progress",
+        );
+        let _ = writeln!(out, "bws_active_connections {}", self.active_connections);
+
+        prometheus_family(
+            &mut out,
+            "bytes_in_total",
+            "counter",
+            "Request body bytes received",
+        );
+        let _ = writeln!(out, "bws_bytes_in_total {}", self.bytes_in);
+
+        prometheus_family(
+            &mut out,
+            "bytes_out_total",
+            "counter",
+            "Response body bytes sent",
+        );
+        let _ = writeln!(out, "bws_bytes_out_total {}", self.bytes_out);
+
+        prometheus_family(
+            &mut out,
+            "path_traversal_attempts_total",
+            "counter",
+            "Requests refused for trying to escape a site's directory",
+        );
+        let _ = writeln!(
+            out,
+            "bws_path_traversal_attempts_total {}",
+            self.path_traversal_attempts
+        );
+
+        prometheus_family(
+            &mut out,
+            "uptime_seconds",
+            "gauge",
+            "Seconds since the server started",
+        );
+        let _ = writeln!(out, "bws_uptime_seconds {}", self.uptime.as_secs());
+
+        prometheus_family(
+            &mut out,
+            "upstream_connections",
+            "gauge",
+            "Pooled upstream connections, by state",
+        );
+        let mut pools: Vec<_> = self.upstream_pools.iter().collect();
+        pools.sort_by(|a, b| a.0.cmp(b.0));
+        for (address, stats) in pools {
+            let upstream = escape_label(address);
+            for (state, count) in [("active", stats.active), ("idle", stats.idle)] {
+                let _ = writeln!(
+                    out,
+                    "bws_upstream_connections{{upstream=\"{}\",state=\"{}\"}} {}",
+                    upstream, state, count
+                );
+            }
+        }
+
+        prometheus_family(
+            &mut out,
+            "upstream_circuit_state",
+            "gauge",
+            "Circuit breaker state of upstreams, 1 for the current state",
+        );
+        let mut circuits: Vec<_> = self.upstream_circuits.iter().collect();
+        circuits.sort_by(|a, b| a.0.cmp(b.0));
+        for (address, current) in circuits {
+            let upstream = escape_label(address);
+            for state in [
+                CircuitState::Closed,
+                CircuitState::Open,
+                CircuitState::HalfOpen,
+            ] {
+                let _ = writeln!(
+                    out,
+                    "bws_upstream_circuit_state{{upstream=\"{}\",state=\"{}\"}} {}",
+                    upstream,
+                    state.as_str(),
+                    u8::from(state == *current)
+                );
+            }
+        }
+
+        prometheus_family(
+            &mut out,
+            "certificate_renewal_failures",
+            "gauge",
+            "Certificate renewal attempts in a row that failed, by domain",
+        );
+        let mut renewals: Vec<_> = self.certificate_renewal_failures.iter().collect();
+        renewals.sort();
+        for (domain, failures) in renewals {
+            let _ = writeln!(
+                out,
+                "bws_certificate_renewal_failures{{domain=\"{}\"}} {}",
+                escape_label(domain),
+                failures
+            );
+        }
+
+        out
+    }
+}
+
+/// Escape a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Write the `# HELP` and `# TYPE` lines that start a metric family
+fn prometheus_family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP bws_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE bws_{} {}", name, kind);
 }
 
 /// Global metrics instance
@@ -253,16 +678,93 @@ mod tests {
         let collector = MetricsCollector::new();
 
         // Record some requests
-        collector.record_request(200, Duration::from_millis(100), 1024);
-        collector.record_request(404, Duration::from_millis(50), 512);
-        collector.record_request(500, Duration::from_millis(200), 256);
+        collector.record_request(200, Duration::from_millis(100), 0, 1024);
+        collector.record_request(404, Duration::from_millis(50), 0, 512);
+        collector.record_request(500, Duration::from_millis(200), 64, 256);
 
         let metrics = collector.get_metrics().unwrap();
 
         assert_eq!(metrics.request_counts.get(&200), Some(&1));
         assert_eq!(metrics.request_counts.get(&404), Some(&1));
         assert_eq!(metrics.request_counts.get(&500), Some(&1));
-        assert_eq!(metrics.bytes_served, 1792);
+        assert_eq!(metrics.bytes_in, 64);
+        assert_eq!(metrics.bytes_out, 1792);
+        let text = metrics.to_prometheus();
+        assert!(text.contains("bws_bytes_in_total 64\n"));
+        assert!(text.contains("bws_bytes_out_total 1792\n"));
+
+        collector.record_slow_request();
+        assert_eq!(collector.get_metrics().unwrap().slow_requests, 1);
+
+        collector.record_path_traversal();
+        let snapshot = collector.get_metrics().unwrap();
+        assert_eq!(snapshot.path_traversal_attempts, 1);
+        assert!(snapshot
+            .to_prometheus()
+            .contains("bws_path_traversal_attempts_total 1\n"));
+
+        collector.record_file_cache_lookup(true);
+        collector.record_file_cache_lookup(false);
+        collector.record_file_cache_lookup(true);
+        let snapshot = collector.get_metrics().unwrap();
+        assert_eq!(snapshot.file_cache_hits, 2);
+        assert_eq!(snapshot.file_cache_misses, 1);
+        assert_eq!(snapshot.to_json()["file_cache_hits"], 2);
+
+        collector.record_upstream_pool(
+            "http://10.0.0.1:8080",
+            UpstreamPoolStats { active: 2, idle: 5 },
+        );
+        let json = collector.get_metrics().unwrap().to_json();
+        assert_eq!(json["upstream_pools"]["http://10.0.0.1:8080"]["idle"], 5);
+
+        collector.record_upstream_circuit("http://10.0.0.1:8080", CircuitState::HalfOpen);
+        let json = collector.get_metrics().unwrap().to_json();
+        assert_eq!(
+            json["upstream_circuits"]["http://10.0.0.1:8080"],
+            "half_open"
+        );
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let collector = MetricsCollector::new();
+        collector.set_latency_buckets(&[0.01, 0.1, 1.0]);
+        for ms in [5, 10, 50, 500, 5000] {
+            collector.record_request(200, Duration::from_millis(ms), 0, 0);
+        }
+
+        let snapshot = collector.get_metrics().unwrap();
+        // A duration on a bound counts in that bound's bucket
+        assert_eq!(snapshot.latency.cumulative_counts(), vec![2, 3, 4, 5]);
+        assert_eq!(snapshot.latency.count, 5);
+
+        let text = snapshot.to_prometheus();
+        assert!(text.contains("# TYPE bws_request_duration_seconds histogram\n"));
+        assert!(text.contains("bws_request_duration_seconds_bucket{le=\"0.01\"} 2\n"));
+        assert!(text.contains("bws_request_duration_seconds_bucket{le=\"1\"} 4\n"));
+        assert!(text.contains("bws_request_duration_seconds_bucket{le=\"+Inf\"} 5\n"));
+        assert!(text.contains("bws_request_duration_seconds_count 5\n"));
+        assert!(text.contains("bws_requests_total{status=\"200\"} 5\n"));
+
+        collector.record_upstream_circuit("http://10.0.0.1:8080", CircuitState::Open);
+        let text = collector.get_metrics().unwrap().to_prometheus();
+        assert!(text.contains(
+            "bws_upstream_circuit_state{upstream=\"http://10.0.0.1:8080\",state=\"open\"} 1\n"
+        ));
+        assert!(text.contains(
+            "bws_upstream_circuit_state{upstream=\"http://10.0.0.1:8080\",state=\"closed\"} 0\n"
+        ));
+        assert_eq!(escape_label("a\"b\\c"), "a\\\"b\\\\c");
+
+        collector.record_certificate_renewal("example.com", 3);
+        let text = collector.get_metrics().unwrap().to_prometheus();
+        assert!(text.contains("bws_certificate_renewal_failures{domain=\"example.com\"} 3\n"));
+
+        // New buckets start the histogram over
+        collector.set_latency_buckets(&[0.5]);
+        let snapshot = collector.get_metrics().unwrap();
+        assert_eq!(snapshot.latency.cumulative_counts(), vec![0, 0]);
     }
 
     #[test]
@@ -1,12 +1,18 @@
 //! Monitoring and observability for BWS Web Server
 //!
 //! This module provides health checks, metrics collection,
-//! certificate monitoring, and logging functionality.
+//! certificate monitoring, request tracing, and logging functionality.
 
+pub mod access_log;
 pub mod certificates;
 pub mod health;
 pub mod metrics;
+pub mod statsd;
+pub mod tracing;
 
 // Re-export main types
+pub use access_log::{AccessLogEntry, AccessLogFormat, AccessLogger};
 pub use certificates::CertificateWatcher;
 pub use health::HealthHandler;
+pub use statsd::StatsdExporter;
+pub use tracing::{Span, TraceContext, Tracer};
@@ -0,0 +1,205 @@
+//! StatsD metrics exporter.
+//!
+//! Request metrics are formatted as StatsD lines and pushed over UDP from a
+//! background thread, batched into packets once per flush interval.
+
+use crate::config::StatsdConfig;
+use std::net::UdpSocket;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::time::{Duration, Instant};
+
+/// Metric lines buffered between flushes before new ones are dropped
+const QUEUE_SIZE: usize = 8192;
+/// Largest UDP payload sent, keeping packets under a typical MTU
+const MAX_PACKET_SIZE: usize = 1432;
+
+/// Pushes request metrics to a StatsD agent
+pub struct StatsdExporter {
+    prefix: String,
+    sender: SyncSender<String>,
+}
+
+impl StatsdExporter {
+    /// Connect to the configured agent and start the flush thread
+    pub fn new(config: &StatsdConfig) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").or_else(|_| UdpSocket::bind("[::]:0"))?;
+        socket.connect(&config.address)?;
+
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+        let flush_interval = Duration::from_millis(config.flush_interval_ms);
+        std::thread::Builder::new()
+            .name("bws-statsd".to_string())
+            .spawn(move || flush_loop(receiver, socket, flush_interval))?;
+
+        log::info!("Sending StatsD metrics to {}", config.address);
+        Ok(Self {
+            prefix: config.prefix.clone(),
+            sender,
+        })
+    }
+
+    /// Record a completed request for `site`
+    pub fn record_request(
+        &self,
+        site: Option<&str>,
+        status: u16,
+        duration: Duration,
+        bytes_in: u64,
+        bytes_out: u64,
+    ) {
+        for line in request_lines(&self.prefix, site, status, duration, bytes_in, bytes_out) {
+            // Never block a request on the exporter; drop metrics when backed up
+            if self.sender.try_send(line).is_err() {
+                log::debug!("StatsD queue full, dropping metrics");
+                return;
+            }
+        }
+    }
+}
+
+/// StatsD lines for one request: a request counter, a status counter, a
+/// timer and counters for the body bytes received and sent
+pub fn request_lines(
+    prefix: &str,
+    site: Option<&str>,
+    status: u16,
+    duration: Duration,
+    bytes_in: u64,
+    bytes_out: u64,
+) -> Vec<String> {
+    let site = sanitize(site.unwrap_or("unmatched"));
+    let name = if prefix.is_empty() {
+        site
+    } else {
+        format!("{}.{}", prefix, site)
+    };
+
+    vec![
+        format!("{}.requests:1|c", name),
+        format!("{}.status.{}:1|c", name, status),
+        format!(
+            "{}.request_time:{:.3}|ms",
+            name,
+            duration.as_secs_f64() * 1000.0
+        ),
+        format!("{}.bytes_in:{}|c", name, bytes_in),
+        format!("{}.bytes_out:{}|c", name, bytes_out),
+    ]
+}
+
+/// Replace characters that have meaning in the StatsD protocol or metric paths
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn flush_loop(receiver: Receiver<String>, socket: UdpSocket, flush_interval: Duration) {
+    let mut pending: Vec<String> = Vec::new();
+    let mut next_flush = Instant::now() + flush_interval;
+
+    loop {
+        match receiver.recv_timeout(next_flush.saturating_duration_since(Instant::now())) {
+            Ok(line) => {
+                pending.push(line);
+                if Instant::now() < next_flush {
+                    continue;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                send_packets(&socket, &pending);
+                return;
+            }
+        }
+
+        send_packets(&socket, &pending);
+        pending.clear();
+        next_flush = Instant::now() + flush_interval;
+    }
+}
+
+/// Send lines newline-separated, packing as many as fit in each packet
+fn send_packets(socket: &UdpSocket, lines: &[String]) {
+    let mut packet = String::new();
+    for line in lines {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET_SIZE {
+            send_packet(socket, &packet);
+            packet.clear();
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(line);
+    }
+    if !packet.is_empty() {
+        send_packet(socket, &packet);
+    }
+}
+
+fn send_packet(socket: &UdpSocket, packet: &str) {
+    if let Err(e) = socket.send(packet.as_bytes()) {
+        log::debug!("Failed to send StatsD metrics: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_lines() {
+        let lines = request_lines(
+            "bws",
+            Some("api.example"),
+            404,
+            Duration::from_micros(12_500),
+            0,
+            153,
+        );
+        assert_eq!(
+            lines,
+            [
+                "bws.api_example.requests:1|c",
+                "bws.api_example.status.404:1|c",
+                "bws.api_example.request_time:12.500|ms",
+                "bws.api_example.bytes_in:0|c",
+                "bws.api_example.bytes_out:153|c",
+            ]
+        );
+
+        let lines = request_lines("", None, 200, Duration::from_millis(1), 0, 0);
+        assert_eq!(lines[0], "unmatched.requests:1|c");
+    }
+
+    #[test]
+    fn test_exporter_flushes_over_udp() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let config = StatsdConfig {
+            enabled: true,
+            address: agent.local_addr().unwrap().to_string(),
+            flush_interval_ms: 50,
+            ..Default::default()
+        };
+
+        let exporter = StatsdExporter::new(&config).unwrap();
+        exporter.record_request(Some("main"), 200, Duration::from_millis(3), 0, 0);
+
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+        let n = agent.recv(&mut buf).unwrap();
+        let packet = String::from_utf8_lossy(&buf[..n]);
+        let lines: Vec<&str> = packet.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "bws.main.requests:1|c");
+        assert_eq!(lines[1], "bws.main.status.200:1|c");
+    }
+}
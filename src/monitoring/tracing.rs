@@ -0,0 +1,376 @@
+//! Request tracing with W3C trace context propagation and OTLP export.
+//!
+//! Each request gets a server span, continuing the client's `traceparent`
+//! when one is sent. Finished spans are exported as OTLP/HTTP JSON in batches
+//! from a background thread, so requests never wait on the collector.
+
+use crate::config::TracingConfig;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Header carrying the W3C trace context
+pub const TRACEPARENT: &str = "traceparent";
+
+/// Spans buffered for export before new ones are dropped
+const QUEUE_SIZE: usize = 4096;
+/// Spans sent to the collector in one request
+const BATCH_SIZE: usize = 512;
+/// Longest a finished span waits before being exported
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+const SPAN_KIND_SERVER: u8 = 2;
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// Trace and span identifiers, as carried in a `traceparent` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Start a new trace
+    pub fn new_root() -> Self {
+        Self {
+            trace_id: fastrand::u128(1..),
+            span_id: fastrand::u64(1..),
+            sampled: true,
+        }
+    }
+
+    /// A new span in the same trace
+    pub fn child(&self) -> Self {
+        Self {
+            span_id: fastrand::u64(1..),
+            ..*self
+        }
+    }
+
+    /// Parse a `traceparent` header (`version-traceid-spanid-flags`).
+    /// Unknown future versions are parsed by their first four fields.
+    pub fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+
+        let is_hex = |s: &str, len: usize| {
+            s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        if !is_hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+        if !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+            return None;
+        }
+
+        let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+        let span_id = u64::from_str_radix(span_id, 16).ok()?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        if trace_id == 0 || span_id == 0 {
+            return None;
+        }
+
+        Some(Self {
+            trace_id,
+            span_id,
+            sampled: flags & 0x01 != 0,
+        })
+    }
+
+    /// Value for the `traceparent` header
+    pub fn to_header(&self) -> String {
+        format!(
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id, self.span_id, self.sampled as u8
+        )
+    }
+}
+
+/// A server span for one request
+#[derive(Debug, Clone)]
+pub struct Span {
+    context: TraceContext,
+    parent_span_id: Option<u64>,
+    name: String,
+    start: SystemTime,
+    end: Option<SystemTime>,
+    attributes: Vec<(String, serde_json::Value)>,
+    error: bool,
+}
+
+impl Span {
+    /// Start a span, continuing `parent` when the client sent one
+    pub fn start(name: impl Into<String>, parent: Option<TraceContext>) -> Self {
+        let context = parent.map_or_else(TraceContext::new_root, |p| p.child());
+        Self {
+            context,
+            parent_span_id: parent.map(|p| p.span_id),
+            name: name.into(),
+            start: SystemTime::now(),
+            end: None,
+            attributes: Vec::new(),
+            error: false,
+        }
+    }
+
+    /// Context to propagate to upstreams
+    pub fn context(&self) -> TraceContext {
+        self.context
+    }
+
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        self.name = name.into();
+    }
+
+    pub fn set_attribute(&mut self, key: &str, value: impl Into<String>) {
+        self.attributes.push((
+            key.to_string(),
+            serde_json::json!({ "stringValue": value.into() }),
+        ));
+    }
+
+    pub fn set_int_attribute(&mut self, key: &str, value: i64) {
+        // OTLP JSON encodes 64-bit integers as strings
+        self.attributes.push((
+            key.to_string(),
+            serde_json::json!({ "intValue": value.to_string() }),
+        ));
+    }
+
+    /// Record the response status; 5xx responses mark the span as failed
+    pub fn set_status(&mut self, status: u16) {
+        self.set_int_attribute("http.response.status_code", status as i64);
+        self.error = status >= 500;
+    }
+
+    fn end(&mut self) {
+        self.end.get_or_insert_with(SystemTime::now);
+    }
+
+    fn to_otlp(&self) -> serde_json::Value {
+        let nanos = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+                .to_string()
+        };
+
+        let mut span = serde_json::json!({
+            "traceId": format!("{:032x}", self.context.trace_id),
+            "spanId": format!("{:016x}", self.context.span_id),
+            "name": self.name,
+            "kind": SPAN_KIND_SERVER,
+            "startTimeUnixNano": nanos(self.start),
+            "endTimeUnixNano": nanos(self.end.unwrap_or(self.start)),
+            "attributes": self
+                .attributes
+                .iter()
+                .map(|(key, value)| serde_json::json!({ "key": key, "value": value }))
+                .collect::<Vec<_>>(),
+        });
+        if let Some(parent) = self.parent_span_id {
+            span["parentSpanId"] = format!("{:016x}", parent).into();
+        }
+        if self.error {
+            span["status"] = serde_json::json!({ "code": STATUS_CODE_ERROR });
+        }
+        span
+    }
+}
+
+/// OTLP/HTTP JSON request body for a batch of spans
+pub fn otlp_request(service_name: &str, spans: &[Span]) -> serde_json::Value {
+    serde_json::json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": service_name }
+                }]
+            },
+            "scopeSpans": [{
+                "scope": { "name": "bws", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans.iter().map(Span::to_otlp).collect::<Vec<_>>()
+            }]
+        }]
+    })
+}
+
+/// Creates request spans and hands finished ones to the export thread
+pub struct Tracer {
+    sender: SyncSender<Span>,
+}
+
+impl Tracer {
+    /// Start the export thread for the configured collector
+    pub fn new(config: &TracingConfig) -> std::io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+        let endpoint = config.endpoint.clone();
+        let service_name = config.service_name.clone();
+
+        std::thread::Builder::new()
+            .name("bws-trace-export".to_string())
+            .spawn(move || export_loop(receiver, &endpoint, &service_name))?;
+
+        log::info!("Exporting request traces to {}", config.endpoint);
+        Ok(Self { sender })
+    }
+
+    /// End the span and queue it for export. Unsampled spans are dropped, as
+    /// are spans arriving faster than the collector accepts them.
+    pub fn finish(&self, mut span: Span) {
+        if !span.context.sampled {
+            return;
+        }
+
+        span.end();
+        match self.sender.try_send(span) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => log::debug!("Trace export queue full, dropping span"),
+            Err(TrySendError::Disconnected(_)) => {
+                log::debug!("Trace exporter stopped, dropping span")
+            }
+        }
+    }
+}
+
+fn export_loop(receiver: Receiver<Span>, endpoint: &str, service_name: &str) {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log::error!("Failed to create async runtime for trace export: {e}");
+            return;
+        }
+    };
+    let client = reqwest::Client::new();
+
+    // Block for the first span of a batch, then collect more until the batch
+    // is full or the export interval has passed
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + EXPORT_INTERVAL;
+        let mut batch = vec![first];
+        while batch.len() < BATCH_SIZE {
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(span) => batch.push(span),
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        let body = otlp_request(service_name, &batch);
+        let result = runtime.block_on(async {
+            client
+                .post(endpoint)
+                .timeout(Duration::from_secs(10))
+                .json(&body)
+                .send()
+                .await?
+                .error_for_status()
+        });
+        if let Err(e) = result {
+            log::warn!(
+                "Failed to export {} spans to {}: {}",
+                batch.len(),
+                endpoint,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_traceparent_parse_and_format() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::parse(header).unwrap();
+        assert_eq!(context.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(context.span_id, 0x00f067aa0ba902b7);
+        assert!(context.sampled);
+        assert_eq!(context.to_header(), header);
+
+        let child = context.child();
+        assert_eq!(child.trace_id, context.trace_id);
+        assert_ne!(child.span_id, context.span_id);
+
+        // Future versions may append fields
+        assert!(TraceContext::parse(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra"
+        )
+        .is_some_and(|c| !c.sampled));
+
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert!(TraceContext::parse(invalid).is_none(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_span_otlp_encoding() {
+        let parent = TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        let mut span = Span::start("GET", parent);
+        span.set_attribute("http.route", "/api/");
+        span.set_status(502);
+        span.end();
+
+        let body = otlp_request("bws-test", &[span]);
+        let resource = &body["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            "bws-test"
+        );
+
+        let encoded = &resource["scopeSpans"][0]["spans"][0];
+        assert_eq!(encoded["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(encoded["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(encoded["kind"], SPAN_KIND_SERVER);
+        assert_eq!(encoded["status"]["code"], STATUS_CODE_ERROR);
+        assert_eq!(encoded["attributes"][0]["value"]["stringValue"], "/api/");
+        assert_eq!(encoded["attributes"][1]["value"]["intValue"], "502");
+    }
+
+    #[test]
+    fn test_tracer_exports_to_collector() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = TracingConfig {
+            enabled: true,
+            endpoint: format!("http://{}/v1/traces", listener.local_addr().unwrap()),
+            ..Default::default()
+        };
+
+        let tracer = Tracer::new(&config).unwrap();
+        let span = Span::start("GET", None);
+        let trace_id = format!("{:032x}", span.context().trace_id);
+        tracer.finish(span);
+        // Dropping the tracer flushes the pending batch
+        drop(tracer);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut request = String::new();
+        let mut buf = [0u8; 4096];
+        while !request.contains(&trace_id) {
+            let n = stream.read(&mut buf).unwrap();
+            assert!(n > 0, "collector connection closed early");
+            request.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+        assert!(request.starts_with("POST /v1/traces "));
+    }
+}
@@ -42,13 +42,25 @@ impl ConfigReloadService {
         // Validate new configuration
         new_config.validate()?;
 
-        // Update configuration
-        {
-            let mut config = self.config.write().await;
-            *config = new_config;
+        let mut config = self.config.write().await;
+        let changed = config.changed_sections(&new_config);
+        if changed.is_empty() {
+            log::info!(
+                "Configuration at {} is unchanged, skipping reload",
+                config_path
+            );
+            return Ok(());
         }
 
-        log::info!("Configuration reloaded successfully from {}", config_path);
+        // Update configuration
+        *config = new_config;
+        drop(config);
+
+        log::info!(
+            "Configuration reloaded successfully from {} (changed: {})",
+            config_path,
+            changed.join(", ")
+        );
         Ok(())
     }
 }
@@ -3,8 +3,9 @@
 //! This module provides a secure management API service that runs on localhost only.
 //! It handles administrative operations like configuration reload with proper security checks.
 
-use crate::config::ManagementConfig;
-use crate::handlers::ApiHandler;
+use crate::config::{ManagementConfig, ServerConfig, ValidationIssue, ValidationReport};
+use crate::handlers::JsonError;
+use crate::monitoring::metrics::metrics;
 use crate::server::WebServerService;
 use async_trait::async_trait;
 use pingora::http::ResponseHeader;
@@ -14,15 +15,15 @@ use std::sync::Arc;
 /// Management API Service with localhost-only security
 #[derive(Clone)]
 pub struct ManagementApiService {
-    api_handler: ApiHandler,
+    web_service: Arc<WebServerService>,
     config: ManagementConfig,
 }
 
 impl ManagementApiService {
     /// Create a new Management API service
-    pub fn new(_web_service: Arc<WebServerService>, config: ManagementConfig) -> Self {
+    pub fn new(web_service: Arc<WebServerService>, config: ManagementConfig) -> Self {
         Self {
-            api_handler: ApiHandler::new(),
+            web_service,
             config,
         }
     }
@@ -57,37 +58,44 @@ impl ManagementApiService {
         &self,
         session: &mut Session,
         status: u16,
+        code: &str,
         message: &str,
     ) -> Result<()> {
-        let error_body = format!(r#"{{"error": "{}"}}"#, message);
-        let mut header = ResponseHeader::build(status, Some(4))?;
-        header.insert_header("Content-Type", "application/json; charset=utf-8")?;
-        header.insert_header("Content-Length", error_body.len().to_string())?;
-        header.insert_header("Cache-Control", "no-cache, no-store, must-revalidate")?;
-
-        session
-            .write_response_header(Box::new(header), false)
-            .await?;
-        session
-            .write_response_body(Some(error_body.into_bytes().into()), true)
-            .await?;
-
-        Ok(())
+        JsonError::new(status, code, message)?
+            .with_header("Cache-Control", "no-cache, no-store, must-revalidate")?
+            .write(session)
+            .await
     }
 
     /// Send JSON success response
     async fn send_success_response(&self, session: &mut Session, message: &str) -> Result<()> {
-        let success_body = format!(r#"{{"message": "{}"}}"#, message);
+        self.send_json_response(session, format!(r#"{{"message": "{}"}}"#, message))
+            .await
+    }
+
+    /// Send a JSON document with status 200
+    async fn send_json_response(&self, session: &mut Session, body: String) -> Result<()> {
+        self.send_ok_response(session, "application/json; charset=utf-8", body)
+            .await
+    }
+
+    /// Send `body` with status 200
+    async fn send_ok_response(
+        &self,
+        session: &mut Session,
+        content_type: &str,
+        body: String,
+    ) -> Result<()> {
         let mut header = ResponseHeader::build(200, Some(4))?;
-        header.insert_header("Content-Type", "application/json; charset=utf-8")?;
-        header.insert_header("Content-Length", success_body.len().to_string())?;
+        header.insert_header("Content-Type", content_type)?;
+        header.insert_header("Content-Length", body.len().to_string())?;
         header.insert_header("Cache-Control", "no-cache, no-store, must-revalidate")?;
 
         session
             .write_response_header(Box::new(header), false)
             .await?;
         session
-            .write_response_body(Some(success_body.into_bytes().into()), true)
+            .write_response_body(Some(body.into_bytes().into()), true)
             .await?;
 
         Ok(())
@@ -110,6 +118,14 @@ impl ProxyHttp for ManagementApiService {
     }
 
     async fn request_filter(&self, session: &mut Session, _ctx: &mut Self::CTX) -> Result<bool> {
+        // Liveness of the management API itself, open to orchestrators that
+        // probe from elsewhere; it reveals nothing beyond being up
+        if session.req_header().method == "GET" && session.req_header().uri.path() == "/health" {
+            self.send_json_response(session, r#"{"status":"ok"}"#.to_string())
+                .await?;
+            return Ok(true);
+        }
+
         // Security check: only allow localhost requests
         if !self.is_localhost_request(session) {
             log::warn!(
@@ -119,7 +135,7 @@ impl ProxyHttp for ManagementApiService {
                     .map(|addr| addr.to_string())
                     .unwrap_or_else(|| "unknown".to_string())
             );
-            self.send_error_response(session, 403, "Access denied: localhost only")
+            self.send_error_response(session, 403, "Forbidden", "Access denied: localhost only")
                 .await?;
             return Ok(true);
         }
@@ -127,7 +143,7 @@ impl ProxyHttp for ManagementApiService {
         // API key authentication check
         if !self.check_api_key(session) {
             log::warn!("Management API access denied: invalid or missing API key");
-            self.send_error_response(session, 401, "Unauthorized: invalid API key")
+            self.send_error_response(session, 401, "Unauthorized", "Invalid or missing API key")
                 .await?;
             return Ok(true);
         }
@@ -138,26 +154,110 @@ impl ProxyHttp for ManagementApiService {
         match (method, path) {
             ("POST", "/api/config/reload") => {
                 log::info!("Management API: Config reload requested");
+                if self.web_service.get_config_path().await.is_none() {
+                    self.send_error_response(
+                        session,
+                        404,
+                        "Not Found",
+                        "The server was not started from a configuration file",
+                    )
+                    .await?;
+                    return Ok(true);
+                }
 
-                // Delegate to the main API handler for the actual reload logic
-                match self.api_handler.handle(session, None).await {
-                    Ok(_) => {
+                // Applied the same way as a reload through SIGHUP
+                let reloaded = self
+                    .web_service
+                    .reload_config_file()
+                    .await
+                    .map_err(|e| e.to_string());
+                match reloaded {
+                    Ok(()) => {
                         log::info!("Configuration reloaded successfully via management API");
                         self.send_success_response(session, "Configuration reloaded successfully")
                             .await?;
                     }
                     Err(e) => {
                         log::error!("Configuration reload failed via management API: {}", e);
-                        self.send_error_response(session, 500, "Configuration reload failed")
-                            .await?;
-                        return Ok(true);
+                        self.send_error_response(
+                            session,
+                            400,
+                            "Configuration reload failed",
+                            &format!("Failed to reload configuration: {}", e),
+                        )
+                        .await?;
                     }
                 }
                 Ok(true)
             }
+            ("POST", "/api/config/validate") => {
+                let Some(config_path) = self.web_service.get_config_path().await else {
+                    self.send_error_response(
+                        session,
+                        404,
+                        "Not Found",
+                        "The server was not started from a configuration file",
+                    )
+                    .await?;
+                    return Ok(true);
+                };
+                log::info!("Management API: Validating {}", config_path);
+                let report = validation_report(&config_path);
+                self.send_json_response(session, report.to_string()).await?;
+                Ok(true)
+            }
+            ("POST", "/api/drain") => {
+                log::info!("Management API: Draining, readiness now reports not ready");
+                self.web_service.set_draining(true);
+                self.send_success_response(session, "Draining").await?;
+                Ok(true)
+            }
+            ("POST", "/api/undrain") => {
+                log::info!("Management API: Drain ended, readiness restored");
+                self.web_service.set_draining(false);
+                self.send_success_response(session, "Ready").await?;
+                Ok(true)
+            }
+            ("GET", "/api/sites") => {
+                let status = self.web_service.site_status().await;
+                self.send_json_response(session, status.to_string()).await?;
+                Ok(true)
+            }
+            ("GET", "/api/upstreams") => {
+                let status = self.web_service.upstream_status().await;
+                self.send_json_response(session, status.to_string()).await?;
+                Ok(true)
+            }
+            ("GET", "/api/certs") => {
+                let status = self.web_service.certificate_status().await;
+                self.send_json_response(session, status.to_string()).await?;
+                Ok(true)
+            }
+            ("GET", "/metrics") => match metrics().get_metrics() {
+                Ok(snapshot) => {
+                    self.send_ok_response(
+                        session,
+                        "text/plain; version=0.0.4; charset=utf-8",
+                        snapshot.to_prometheus(),
+                    )
+                    .await?;
+                    Ok(true)
+                }
+                Err(e) => {
+                    log::error!("Failed to collect metrics: {}", e);
+                    self.send_error_response(
+                        session,
+                        500,
+                        "Internal Server Error",
+                        "Failed to collect metrics",
+                    )
+                    .await?;
+                    Ok(true)
+                }
+            },
             _ => {
                 // Unknown endpoint
-                self.send_error_response(session, 404, "Endpoint not found")
+                self.send_error_response(session, 404, "Not Found", "Endpoint not found")
                     .await?;
                 Ok(true)
             }
@@ -200,3 +300,129 @@ impl ProxyHttp for ManagementApiService {
         }
     }
 }
+
+/// Errors and warnings for the configuration file at `path`, found the way
+/// `--dry-run` finds them. Nothing is applied.
+fn validation_report(path: &str) -> serde_json::Value {
+    let report = match ServerConfig::load_from_file(path) {
+        Ok(config) => config.validate_detailed(),
+        Err(e) => ValidationReport {
+            errors: vec![ValidationIssue {
+                site: None,
+                message: e.to_string(),
+            }],
+            warnings: Vec::new(),
+        },
+    };
+    serde_json::json!({
+        "config_path": path,
+        "valid": report.is_valid(),
+        "errors": report.errors,
+        "warnings": report.warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServerConfig;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Run `request` through the service and return the raw response
+    async fn respond(service: &ManagementApiService, request: &str) -> String {
+        let (mut client, server) = tokio::io::duplex(4096);
+        client.write_all(request.as_bytes()).await.unwrap();
+
+        let mut session = Session::new_h1(Box::new(server));
+        assert!(session.read_request().await.unwrap());
+        assert!(service.request_filter(&mut session, &mut ()).await.unwrap());
+        drop(session);
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_health_needs_no_api_key() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            [server]
+            name = "test"
+
+            [[sites]]
+            name = "main"
+            hostname = "localhost"
+            port = 8080
+            static_dir = "/tmp"
+            "#,
+        )
+        .unwrap();
+        let service = ManagementApiService::new(
+            Arc::new(WebServerService::new(config)),
+            ManagementConfig {
+                api_key: Some("secret".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let response = respond(&service, "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with(r#"{"status":"ok"}"#));
+
+        // Everything else stays protected
+        let response = respond(
+            &service,
+            "GET /api/upstreams HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        assert!(!response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+
+    #[test]
+    fn test_validation_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let write = |static_dir: &str| {
+            let config = format!(
+                r#"
+                [server]
+                name = "test"
+
+                [[sites]]
+                name = "main"
+                hostname = "localhost"
+                port = 18080
+                static_dir = "{}"
+                index_files = ["index.html"]
+                "#,
+                static_dir
+            );
+            std::fs::write(&path, config).unwrap();
+        };
+
+        write(&dir.path().to_string_lossy());
+        let report = validation_report(&path.to_string_lossy());
+        assert_eq!(report["valid"], true, "{}", report);
+        assert_eq!(report["warnings"][0]["site"], "main");
+        assert_eq!(
+            report["warnings"][0]["message"],
+            "No index files found in static directory"
+        );
+
+        write("/does/not/exist");
+        let report = validation_report(&path.to_string_lossy());
+        assert_eq!(report["valid"], false);
+        assert_eq!(
+            report["errors"][0]["message"],
+            "Static directory '/does/not/exist' does not exist"
+        );
+
+        // A file that does not load reports why
+        std::fs::write(&path, "[server").unwrap();
+        let report = validation_report(&path.to_string_lossy());
+        assert_eq!(report["valid"], false);
+        assert_eq!(report["errors"].as_array().unwrap().len(), 1);
+        assert!(report["errors"][0]["site"].is_null());
+    }
+}
@@ -1,14 +1,65 @@
-use crate::config::{ServerConfig, SiteConfig};
+use crate::config::{MaintenanceConfig, ServerConfig, SiteConfig};
+use crate::core::deadline::until_response_started;
+use crate::core::utils::http::{self, encode_path, normalize_path, PATH_TRAVERSAL};
+use crate::handlers::favicon::FAVICON_PATH;
 use crate::handlers::*;
-use crate::monitoring::HealthHandler;
-use crate::ssl::SslManager;
+use crate::middleware::{
+    check_header_limits, rate_limit_backend, validate_expectation, validate_host,
+    ConcurrencyLimiter, ConnectionLimiter, GeoIpMiddleware, RateLimitBackend,
+    UserAgentFilterMiddleware, UserAgentVerdict,
+};
+use crate::monitoring::metrics::metrics;
+use crate::monitoring::tracing::TRACEPARENT;
+use crate::monitoring::{
+    AccessLogEntry, AccessLogFormat, AccessLogger, HealthHandler, Span, StatsdExporter,
+    TraceContext, Tracer,
+};
+use crate::ssl::{SslConfig, SslManager};
 use async_trait::async_trait;
+use chrono::{DateTime, Local};
 use pingora::http::ResponseHeader;
 use pingora::prelude::*;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::RwLock;
 
+/// Per-request state carried through the proxy phases
+pub struct RequestContext {
+    /// Site matched for this request, if any
+    pub site: Option<SiteConfig>,
+    /// Monotonic start time, for measuring request duration
+    pub start_time: Instant,
+    /// Wall-clock time the request was received, for access logs
+    pub received_at: DateTime<Local>,
+    /// Whether this request is counted in the active connection gauge
+    pub counted: bool,
+    /// Slot held against the site's concurrency limit, released in `logging`
+    pub concurrency_slot: Option<Arc<AtomicUsize>>,
+    /// Trace span for this request, when tracing is enabled
+    pub span: Option<Span>,
+}
+
+impl RequestContext {
+    pub fn new() -> Self {
+        Self {
+            site: None,
+            start_time: Instant::now(),
+            received_at: Local::now(),
+            counted: false,
+            concurrency_slot: None,
+            span: None,
+        }
+    }
+}
+
+impl Default for RequestContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Clone)]
 pub struct WebServerService {
     config: Arc<RwLock<ServerConfig>>,
@@ -17,18 +68,76 @@ pub struct WebServerService {
     static_handler: Arc<StaticFileHandler>,
     api_handler: Arc<ApiHandler>,
     health_handler: Arc<HealthHandler>,
+    well_known_handler: Arc<WellKnownHandler>,
+    favicon_handler: Arc<FaviconHandler>,
+    /// Rebuilt when a reload changes the logging or site configuration
+    access_logger: Arc<std::sync::RwLock<Arc<AccessLogger>>>,
+    /// Connections open across all sites, against `max_connections`
+    connection_limiter: Arc<ConnectionLimiter>,
+    concurrency_limiter: Arc<ConcurrencyLimiter>,
+    user_agent_filter: Arc<UserAgentFilterMiddleware>,
+    geoip: Arc<GeoIpMiddleware>,
+    rate_limiter: Arc<dyn RateLimitBackend>,
+    tracer: Option<Arc<Tracer>>,
+    statsd: Option<Arc<StatsdExporter>>,
+    upstream_pools: Arc<UpstreamPools>,
+    upstream_health: Arc<UpstreamHealth>,
+    upstream_balancer: Arc<UpstreamBalancer>,
+    proxy_cache: Arc<ProxyCache>,
+    /// Ports of the sites at startup; listeners are not added on reload
+    listen_ports: Arc<BTreeSet<u16>>,
 }
 
 impl WebServerService {
     pub fn new(config: ServerConfig) -> Self {
         // Initialize handlers
-        let static_handler = Arc::new(StaticFileHandler::new());
+        let mut static_handler = StaticFileHandler::new();
+        if let Some((min, max)) = config.performance.mmap_size_range() {
+            static_handler = static_handler.with_mmap(min, max);
+        }
+        if let Some((capacity, max_size)) = config.performance.file_cache_settings() {
+            static_handler = static_handler.with_file_cache(capacity, max_size);
+        }
+        let static_handler = Arc::new(static_handler);
         let api_handler = Arc::new(ApiHandler::new());
         let health_handler = Arc::new(HealthHandler::new());
+        let well_known_handler = Arc::new(WellKnownHandler::new());
+
+        let access_logger = Arc::new(std::sync::RwLock::new(Arc::new(
+            Self::create_access_logger(&config),
+        )));
+
+        // Exporters are fixed for the lifetime of the service
+        let tracer = if config.tracing.enabled {
+            Tracer::new(&config.tracing)
+                .map(Arc::new)
+                .map_err(|e| log::error!("Failed to start trace exporter: {}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        let statsd = if config.statsd.enabled {
+            StatsdExporter::new(&config.statsd)
+                .map(Arc::new)
+                .map_err(|e| log::error!("Failed to start StatsD exporter: {}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        let rate_limiter = rate_limit_backend(&config.security.rate_limit_store);
+        metrics().set_latency_buckets(&config.metrics.buckets);
 
         // Initialize SSL managers storage
         let ssl_managers = Arc::new(RwLock::new(HashMap::new()));
 
+        let listen_ports = config
+            .sites
+            .iter()
+            .flat_map(|site| site.all_ports())
+            .collect();
+
         WebServerService {
             config: Arc::new(RwLock::new(config)),
             config_path: Arc::new(RwLock::new(None)),
@@ -36,7 +145,62 @@ impl WebServerService {
             static_handler,
             api_handler,
             health_handler,
+            well_known_handler,
+            favicon_handler: Arc::new(FaviconHandler::new()),
+            access_logger,
+            connection_limiter: Arc::new(ConnectionLimiter::new()),
+            concurrency_limiter: Arc::new(ConcurrencyLimiter::new()),
+            user_agent_filter: Arc::new(UserAgentFilterMiddleware::new()),
+            geoip: Arc::new(GeoIpMiddleware::new()),
+            rate_limiter,
+            tracer,
+            statsd,
+            upstream_pools: Arc::new(UpstreamPools::new()),
+            upstream_health: Arc::new(UpstreamHealth::new()),
+            upstream_balancer: Arc::new(UpstreamBalancer::new()),
+            proxy_cache: Arc::new(ProxyCache::new()),
+            listen_ports: Arc::new(listen_ports),
+        }
+    }
+
+    /// Build the access logger from the logging configuration and per-site
+    /// overrides, falling back to the application log if a file cannot be opened
+    fn create_access_logger(config: &ServerConfig) -> AccessLogger {
+        let format = config.logging.format.parse().unwrap_or_else(|e| {
+            log::warn!("{}, using combined", e);
+            AccessLogFormat::Combined
+        });
+
+        let mut logger = AccessLogger::new(format, config.logging.access_log.as_deref())
+            .unwrap_or_else(|e| {
+                log::error!(
+                    "Failed to open access log {:?}: {}",
+                    config.logging.access_log,
+                    e
+                );
+                AccessLogger::new(format, None).expect("logger without file cannot fail")
+            });
+
+        for site in &config.sites {
+            if let Some(site_format) = &site.log_format {
+                match site_format.parse() {
+                    Ok(site_format) => logger.set_site_format(&site.name, site_format),
+                    Err(e) => log::warn!("{} for site '{}', using {:?}", e, site.name, format),
+                }
+            }
+            if let Some(path) = &site.access_log {
+                if let Err(e) = logger.add_site_log(&site.name, path) {
+                    log::error!(
+                        "Failed to open access log {} for site '{}': {}",
+                        path,
+                        site.name,
+                        e
+                    );
+                }
+            }
         }
+
+        logger
     }
 
     /// Set the configuration file path for hot reloading
@@ -48,6 +212,104 @@ impl WebServerService {
         ApiHandler::set_config_path(path);
     }
 
+    /// Start or stop draining: while draining, `/api/health/ready` answers 503
+    /// so load balancers stop sending new traffic, and requests are still served
+    pub fn set_draining(&self, draining: bool) {
+        self.health_handler.set_draining(draining);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.health_handler.is_draining()
+    }
+
+    /// The access logger for the current configuration
+    fn access_logger(&self) -> Arc<AccessLogger> {
+        self.access_logger
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Reopen the access log files, e.g. after `logrotate` moved them away
+    pub fn reopen_logs(&self) {
+        self.access_logger().reopen();
+    }
+
+    /// Listeners and availability of every configured site. A proxy site is
+    /// down while none of its upstreams gets traffic.
+    pub async fn site_status(&self) -> serde_json::Value {
+        let config = self.config.read().await;
+        let sites: Vec<serde_json::Value> = config
+            .sites
+            .iter()
+            .map(|site| {
+                let upstreams = &site.proxy.upstreams;
+                let upstreams_down = upstreams
+                    .iter()
+                    .filter(|upstream| self.upstream_health.is_down(&upstream.url))
+                    .count();
+                let proxy_down = site.proxy.enabled
+                    && !upstreams.is_empty()
+                    && upstreams_down == upstreams.len();
+                serde_json::json!({
+                    "name": site.name,
+                    "hostname": site.hostname,
+                    "ports": site.all_ports(),
+                    "ssl": site.ssl.enabled,
+                    "proxy": site.proxy.enabled,
+                    "maintenance": site.maintenance.enabled,
+                    "upstreams": upstreams.len(),
+                    "upstreams_down": upstreams_down,
+                    "up": !site.maintenance.enabled && !proxy_down,
+                })
+            })
+            .collect();
+        serde_json::json!({ "sites": sites })
+    }
+
+    /// Health and circuit breaker state of every configured upstream
+    pub async fn upstream_status(&self) -> serde_json::Value {
+        let config = self.config.read().await;
+        let upstreams: Vec<serde_json::Value> = config
+            .sites
+            .iter()
+            .flat_map(|site| {
+                site.proxy.upstreams.iter().map(|upstream| {
+                    serde_json::json!({
+                        "site": site.name,
+                        "name": upstream.name,
+                        "url": upstream.url,
+                        "backup": upstream.backup,
+                        "down": self.upstream_health.is_down(&upstream.url),
+                        "circuit": self.upstream_health.circuit_state(&upstream.url),
+                    })
+                })
+            })
+            .collect();
+        serde_json::json!({ "upstreams": upstreams })
+    }
+
+    /// Expiry and renewal state of the certificate of every SSL-enabled site
+    pub async fn certificate_status(&self) -> serde_json::Value {
+        let ssl_managers = self.ssl_managers.read().await;
+        let mut domains: Vec<_> = ssl_managers.keys().collect();
+        domains.sort();
+
+        let mut certificates = Vec::with_capacity(domains.len());
+        for domain in domains {
+            let manager = &ssl_managers[domain];
+            let certificate = manager.get_certificate_info(domain).await;
+            certificates.push(serde_json::json!({
+                "domain": domain,
+                "auto_cert": manager.is_auto_cert_enabled(),
+                "expires_at": certificate.as_ref().map(|cert| cert.expires_at),
+                "days_until_expiry": certificate.as_ref().map(|cert| cert.days_until_expiry()),
+                "renewal": manager.renewal_status(domain).await,
+            }));
+        }
+        serde_json::json!({ "certificates": certificates })
+    }
+
     /// Get the configuration file path
     pub async fn get_config_path(&self) -> Option<String> {
         self.config_path.read().await.clone()
@@ -57,6 +319,7 @@ impl WebServerService {
     pub async fn initialize_ssl_managers(&self) -> Result<(), Box<dyn std::error::Error>> {
         let config = self.config.read().await;
         let mut ssl_managers = self.ssl_managers.write().await;
+        let shared_challenge_dir = config.acme_challenge_dir.as_deref();
 
         for site in &config.sites {
             if site.ssl.enabled {
@@ -66,7 +329,7 @@ impl WebServerService {
                     site.hostname
                 );
 
-                match SslManager::from_site_config(site).await {
+                match SslManager::from_site_config(site, shared_challenge_dir).await {
                     Ok(Some(ssl_manager)) => {
                         // Initialize certificate for this domain
                         match ssl_manager.ensure_certificate(&site.hostname).await {
@@ -146,6 +409,17 @@ impl WebServerService {
         self.config.read().await.clone()
     }
 
+    /// Reload the configuration file the server was started from, as asked
+    /// by `POST /api/config/reload` or SIGHUP
+    pub async fn reload_config_file(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self
+            .get_config_path()
+            .await
+            .ok_or("The server was not started from a configuration file")?;
+        let new_config = ServerConfig::load_from_file(&path)?;
+        self.reload_config(new_config).await
+    }
+
     pub async fn reload_config(
         &self,
         new_config: ServerConfig,
@@ -153,13 +427,76 @@ impl WebServerService {
         // Validate new configuration
         new_config.validate()?;
 
+        // Listeners are bound once at startup, so a site on a new port would
+        // never receive a request. Nothing has changed yet, so refusing keeps
+        // the running configuration in place.
+        let new_ports: Vec<String> = new_config
+            .sites
+            .iter()
+            .flat_map(|site| site.all_ports())
+            .filter(|port| !self.listen_ports.contains(port))
+            .collect::<BTreeSet<u16>>()
+            .iter()
+            .map(|port| port.to_string())
+            .collect();
+        if !new_ports.is_empty() {
+            let message = format!(
+                "Not listening on port(s) {}; adding ports requires a restart",
+                new_ports.join(", ")
+            );
+            log::warn!(
+                "Reload refused, keeping the current configuration: {}",
+                message
+            );
+            return Err(message.into());
+        }
+        new_config.create_static_dirs()?;
+
         // Update configuration
-        {
+        let changed = {
             let mut config = self.config.write().await;
+            let changed = config.changed_sections(&new_config);
+            if changed.is_empty() {
+                log::info!("Configuration unchanged, skipping reload");
+                return Ok(());
+            }
             *config = new_config;
+            changed
+        };
+
+        // Cached files may belong to sites whose static_dir changed or went away
+        self.static_handler.clear_file_cache();
+        self.favicon_handler.clear_cache();
+
+        // Upstreams may have been removed; pools of unchanged ones refill on
+        // use, failures are counted afresh against the new settings, balancing
+        // starts over, and cached responses are fetched again through the new
+        // routes
+        if changed.contains(&"sites") {
+            self.upstream_pools.clear();
+            self.upstream_health.clear();
+            self.upstream_balancer.clear();
+            self.proxy_cache.clear();
+        }
+
+        if changed.contains(&"metrics") {
+            let config = self.config.read().await;
+            metrics().set_latency_buckets(&config.metrics.buckets);
+        }
+
+        // Log files and formats are set globally and per site
+        if changed.contains(&"logging") || changed.contains(&"sites") {
+            let access_logger = Arc::new(Self::create_access_logger(&*self.config.read().await));
+            *self
+                .access_logger
+                .write()
+                .unwrap_or_else(|e| e.into_inner()) = access_logger;
         }
 
-        log::info!("Configuration reloaded successfully");
+        log::info!(
+            "Configuration reloaded successfully (changed: {})",
+            changed.join(", ")
+        );
         Ok(())
     }
 
@@ -225,21 +562,8 @@ impl WebServerService {
             .and_then(|h| h.to_str().ok())
             .unwrap_or("localhost");
 
-        // Parse host and port
-        let (hostname, port) = if let Some(pos) = host_header.find(':') {
-            let hostname = &host_header[..pos];
-            let port_str = &host_header[pos + 1..];
-            let port = port_str.parse::<u16>().unwrap_or(8080);
-            (hostname, port)
-        } else {
-            // Default to port 80 for HTTP or 443 for HTTPS based on scheme
-            let default_port = if session.req_header().uri.scheme_str() == Some("https") {
-                443
-            } else {
-                80
-            };
-            (host_header, default_port)
-        };
+        let https = session.req_header().uri.scheme_str() == Some("https");
+        let (hostname, port) = host_and_port(host_header, https);
 
         // First try exact hostname:port match
         if let Some(site) = config.find_site_by_host_port(hostname, port).cloned() {
@@ -287,14 +611,23 @@ impl WebServerService {
 
     async fn handle_ssl_redirect(&self, session: &mut Session, site: &SiteConfig) -> Result<bool> {
         if site.redirect_to_https && !self.is_https_request(session) {
+            // Keep the requested hostname, but send the client to the site's
+            // HTTPS port rather than the plain HTTP port it connected to
+            let host = session
+                .req_header()
+                .headers
+                .get("Host")
+                .and_then(|h| h.to_str().ok())
+                .and_then(http::split_host_port)
+                .map_or(site.hostname.as_str(), |(hostname, _)| hostname);
+            let authority = if site.port == 443 {
+                host.to_string()
+            } else {
+                format!("{}:{}", host, site.port)
+            };
             let https_url = format!(
                 "https://{}{}",
-                session
-                    .req_header()
-                    .headers
-                    .get("Host")
-                    .and_then(|h| h.to_str().ok())
-                    .unwrap_or(&site.hostname),
+                authority,
                 session
                     .req_header()
                     .uri
@@ -315,16 +648,17 @@ impl WebServerService {
         Ok(false)
     }
 
+    /// Whether the request arrived over TLS, or names `https` in an
+    /// absolute-form target
     fn is_https_request(&self, session: &Session) -> bool {
-        // Check if the request is HTTPS
-        // This is a simplified check - in production, you might need to check
-        // X-Forwarded-Proto header if behind a reverse proxy
         session
-            .req_header()
-            .uri
-            .scheme()
-            .map(|s| s.as_str() == "https")
-            .unwrap_or(false)
+            .digest()
+            .is_some_and(|digest| digest.ssl_digest.is_some())
+            || session
+                .req_header()
+                .uri
+                .scheme()
+                .is_some_and(|s| s.as_str() == "https")
     }
 
     async fn handle_acme_challenge_for_site(
@@ -350,25 +684,17 @@ impl WebServerService {
             log::warn!("ACME challenge request for site '{}' but SSL auto_cert not enabled (ssl.enabled={}, ssl.auto_cert={})", 
                       site.name, site.ssl.enabled, site.ssl.auto_cert);
 
-            let mut header = ResponseHeader::build(404, Some(3))?;
-            header.insert_header("Content-Type", "application/json")?;
-            header.insert_header("X-Site-Name", &site.hostname)?;
-            header.insert_header("X-ACME-Enabled", "false")?;
-            header.insert_header("X-ACME-Challenge-Status", "ssl-not-enabled")?;
-            header.insert_header("X-ACME-Site", &site.name)?;
-
-            let error_body = format!(
-                r#"{{"error":"ACME Not Enabled","message":"Site {} does not have SSL auto_cert enabled","status":404,"site":"{}","hostname":"{}"}}"#,
-                site.name, site.name, site.hostname
-            );
-            header.insert_header("Content-Length", error_body.len().to_string())?;
-
-            session
-                .write_response_header(Box::new(header), false)
-                .await?;
-            session
-                .write_response_body(Some(error_body.into_bytes().into()), true)
-                .await?;
+            JsonError::new(
+                404,
+                "ACME Not Enabled",
+                &format!("Site {} does not have SSL auto_cert enabled", site.name),
+            )?
+            .with_header("X-Site-Name", site.hostname.as_str())?
+            .with_header("X-ACME-Enabled", "false")?
+            .with_header("X-ACME-Challenge-Status", "ssl-not-enabled")?
+            .with_header("X-ACME-Site", site.name.as_str())?
+            .write(session)
+            .await?;
             return Ok(true);
         }
 
@@ -390,25 +716,17 @@ impl WebServerService {
             log::warn!("ACME challenge request for site '{}' but ACME not enabled in config (acme_config_exists={}, acme_enabled={})", 
                       site.name, site.ssl.acme.is_some(), acme_enabled);
 
-            let mut header = ResponseHeader::build(404, Some(3))?;
-            header.insert_header("Content-Type", "application/json")?;
-            header.insert_header("X-Site-Name", &site.hostname)?;
-            header.insert_header("X-ACME-Enabled", "false")?;
-            header.insert_header("X-ACME-Challenge-Status", "acme-disabled")?;
-            header.insert_header("X-ACME-Site", &site.name)?;
-
-            let error_body = format!(
-                r#"{{"error":"ACME Disabled","message":"Site {} has ACME disabled in configuration","status":404,"site":"{}","hostname":"{}"}}"#,
-                site.name, site.name, site.hostname
-            );
-            header.insert_header("Content-Length", error_body.len().to_string())?;
-
-            session
-                .write_response_header(Box::new(header), false)
-                .await?;
-            session
-                .write_response_body(Some(error_body.into_bytes().into()), true)
-                .await?;
+            JsonError::new(
+                404,
+                "ACME Disabled",
+                &format!("Site {} has ACME disabled in configuration", site.name),
+            )?
+            .with_header("X-Site-Name", site.hostname.as_str())?
+            .with_header("X-ACME-Enabled", "false")?
+            .with_header("X-ACME-Challenge-Status", "acme-disabled")?
+            .with_header("X-ACME-Site", site.name.as_str())?
+            .write(session)
+            .await?;
             return Ok(true);
         }
 
@@ -419,20 +737,53 @@ impl WebServerService {
         );
         let start_time = std::time::Instant::now();
 
-        // For ACME challenges, prioritize filesystem access to avoid blocking on SSL manager initialization
+        // Challenges of orders in progress are answered from memory, without
+        // waiting for the ACME client that is busy with the order
+        let ssl_manager = self.get_ssl_manager_for_domain(&site.hostname).await;
         if let Some(token) = path.strip_prefix("/.well-known/acme-challenge/") {
+            if let Some(response) = ssl_manager
+                .as_ref()
+                .and_then(|manager| manager.pending_challenge(token))
+            {
+                log::info!(
+                    "Serving ACME challenge response from memory for token: {} (took {:?})",
+                    token,
+                    start_time.elapsed()
+                );
+                let mut header = ResponseHeader::build(200, Some(3))?;
+                header.insert_header("Content-Type", "text/plain")?;
+                header.insert_header("Content-Length", response.len().to_string())?;
+
+                // Add ACME-specific headers for debugging
+                header.insert_header("X-Site-Name", &site.hostname)?;
+                header.insert_header("X-ACME-Enabled", "true")?;
+                header.insert_header("X-ACME-Challenge-Source", "memory")?;
+                header.insert_header("X-ACME-Site", &site.name)?;
+
+                session
+                    .write_response_header(Box::new(header), false)
+                    .await?;
+                session
+                    .write_response_body(Some(response.into_bytes().into()), true)
+                    .await?;
+                return Ok(true);
+            }
+
             log::debug!(
                 "Looking for ACME challenge token: {} (took {:?})",
                 token,
                 start_time.elapsed()
             );
 
-            // First try reading challenge file directly from filesystem (fastest path)
-            let challenge_path = std::path::PathBuf::from("./certs")
-                .join("challenges")
-                .join(".well-known")
-                .join("acme-challenge")
-                .join(token);
+            // Then challenges written to disk, possibly by another process
+            let shared_challenge_dir = self.config.read().await.acme_challenge_dir.clone();
+            let challenge_path = std::path::PathBuf::from(SslConfig::challenge_dir(
+                site,
+                shared_challenge_dir.as_deref(),
+            ))
+            .join(".well-known")
+            .join("acme-challenge")
+            .join(token);
 
             log::debug!("Trying to read challenge file from: {challenge_path:?}");
 
@@ -474,7 +825,7 @@ impl WebServerService {
         }
 
         // Fallback to SSL manager (only if filesystem lookup failed)
-        if let Some(ssl_manager) = self.get_ssl_manager_for_domain(&site.hostname).await {
+        if let Some(ssl_manager) = ssl_manager {
             log::debug!(
                 "Found SSL manager for domain '{}' (took {:?})",
                 site.hostname,
@@ -544,27 +895,18 @@ impl WebServerService {
             start_time.elapsed()
         );
 
-        let mut header = ResponseHeader::build(404, Some(3))?;
-        header.insert_header("Content-Type", "application/json")?;
-
-        // Add ACME debugging headers
-        header.insert_header("X-Site-Name", &site.hostname)?;
-        header.insert_header("X-ACME-Enabled", "true")?;
-        header.insert_header("X-ACME-Challenge-Status", "not-found")?;
-        header.insert_header("X-ACME-Site", &site.name)?;
-
-        let error_body = format!(
-            r#"{{"error":"ACME Challenge Not Found","message":"Challenge token not found for site {}","status":404,"site":"{}","hostname":"{}"}}"#,
-            site.name, site.name, site.hostname
-        );
-        header.insert_header("Content-Length", error_body.len().to_string())?;
-
-        session
-            .write_response_header(Box::new(header), false)
-            .await?;
-        session
-            .write_response_body(Some(error_body.into_bytes().into()), true)
-            .await?;
+        // ACME debugging headers
+        JsonError::new(
+            404,
+            "ACME Challenge Not Found",
+            &format!("Challenge token not found for site {}", site.name),
+        )?
+        .with_header("X-Site-Name", site.hostname.as_str())?
+        .with_header("X-ACME-Enabled", "true")?
+        .with_header("X-ACME-Challenge-Status", "not-found")?
+        .with_header("X-ACME-Site", site.name.as_str())?
+        .write(session)
+        .await?;
         Ok(true)
     }
 
@@ -573,6 +915,10 @@ impl WebServerService {
         header: &mut ResponseHeader,
         site: &SiteConfig,
     ) -> Result<()> {
+        if let Some((key, value)) = site.get_hsts_header() {
+            header.insert_header(key, value)?;
+        }
+
         // Apply custom headers from site configuration
         for (key, value) in &site.headers {
             header.insert_header(key.clone(), value.clone())?;
@@ -584,120 +930,118 @@ impl WebServerService {
             header.insert_header(key.clone(), value.clone())?;
         }
 
-        // Hide server header if configured
-        if config.security.hide_server_header {
-            header.remove_header("Server");
-        } else {
-            header.insert_header(
-                "Server",
-                format!("{}/{}", config.server.name, config.server.version),
-            )?;
+        // Server header: hidden, a custom value, or name/version
+        match config.security.server_header_value(&config.server) {
+            Some(value) => header.insert_header("Server", value)?,
+            None => {
+                header.remove_header("Server");
+            }
         }
 
         Ok(())
     }
 
-    async fn handle_404(&self, session: &mut Session, site: Option<&SiteConfig>) -> Result<()> {
-        // Check if site has custom 404 page
+    /// Write a JSON error with the site's headers, including server-wide
+    /// security headers
+    async fn write_json_error(
+        &self,
+        session: &mut Session,
+        site: Option<&SiteConfig>,
+        mut response: JsonError,
+    ) -> Result<()> {
         if let Some(site) = site {
-            if let Some(error_page) = site.get_error_page(404) {
-                let error_page_path = format!("{}/{}", site.static_dir, error_page);
-                if let Ok(content) = tokio::fs::read(&error_page_path).await {
-                    let mut header = ResponseHeader::build(404, Some(3))?;
-                    header.insert_header("Content-Type", "text/html")?;
-                    header.insert_header("Content-Length", content.len().to_string())?;
-                    self.apply_site_headers(&mut header, site).await?;
-
-                    session
-                        .write_response_header(Box::new(header), false)
-                        .await?;
-                    session
-                        .write_response_body(Some(content.into()), true)
-                        .await?;
-                    return Ok(());
-                }
-            }
+            self.apply_site_headers(response.header_mut(), site).await?;
         }
+        response.write(session).await
+    }
 
-        // Default 404 response
-        let error_response = serde_json::json!({
-            "error": "Not Found",
-            "message": "The requested resource was not found",
-            "status": 404
-        });
-
-        let response_body = error_response.to_string();
-        let response_bytes = response_body.into_bytes();
-        let mut header = ResponseHeader::build(404, Some(3))?;
-        header.insert_header("Content-Type", "application/json")?;
-        header.insert_header("Content-Length", response_bytes.len().to_string())?;
-
-        if let Some(site) = site {
-            self.apply_site_headers(&mut header, site).await?;
+    async fn handle_maintenance(&self, session: &mut Session, site: &SiteConfig) -> Result<()> {
+        let body = maintenance_page(&site.maintenance).await;
+        let mut header = ResponseHeader::build(503, Some(5))?;
+        header.insert_header("Content-Type", "text/html; charset=utf-8")?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+        header.insert_header("Cache-Control", "no-store")?;
+        if let Some(seconds) = site.maintenance.retry_after {
+            header.insert_header("Retry-After", seconds.to_string())?;
         }
+        self.apply_site_headers(&mut header, site).await?;
 
         session
             .write_response_header(Box::new(header), false)
             .await?;
         session
-            .write_response_body(Some(response_bytes.into()), true)
+            .write_response_body(Some(body.into_bytes().into()), true)
             .await?;
-
         Ok(())
     }
-}
 
-#[async_trait]
-impl ProxyHttp for WebServerService {
-    type CTX = Option<SiteConfig>;
+    async fn handle_404(&self, session: &mut Session, site: Option<&SiteConfig>) -> Result<()> {
+        // Check if site has custom 404 page
+        if let Some(site) = site {
+            if self.write_error_page(session, site, 404, None).await? {
+                return Ok(());
+            }
+        }
 
-    fn new_ctx(&self) -> Self::CTX {
-        None
+        // Default 404 response
+        self.write_json_error(
+            session,
+            site,
+            JsonError::new(404, "Not Found", "The requested resource was not found")?,
+        )
+        .await
     }
 
-    async fn upstream_peer(
+    /// Answer with the site's configured error page for `status`. Returns
+    /// `Ok(false)` when it has none (or it cannot be read), leaving the
+    /// response to the caller.
+    async fn write_error_page(
         &self,
-        _session: &mut Session,
-        _ctx: &mut Self::CTX,
-    ) -> Result<Box<HttpPeer>> {
-        // Since we're handling requests locally, we don't need an upstream peer
-        Err(Error::new(ErrorType::InternalError).into_down())
-    }
-
-    async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
-        // Find the matching site configuration
-        let site_config = self.find_site_by_request(session).await;
-        *ctx = site_config.clone();
+        session: &mut Session,
+        site: &SiteConfig,
+        status: u16,
+        retry_after: Option<u64>,
+    ) -> Result<bool> {
+        let Some((body, content_type)) = error_page(site, status).await else {
+            return Ok(false);
+        };
+        let mut header = error_page_header(status, content_type, body.len(), retry_after)?;
+        self.apply_site_headers(&mut header, site).await?;
 
-        let path = session.req_header().uri.path().to_string();
-        let host_header = session
-            .req_header()
-            .headers
-            .get("Host")
-            .and_then(|h| h.to_str().ok())
-            .unwrap_or("localhost");
+        session
+            .write_response_header(Box::new(header), false)
+            .await?;
+        session.write_response_body(Some(body), true).await?;
+        Ok(true)
+    }
 
-        // Log the incoming request
-        if let Some(site) = ctx.as_ref() {
-            log::info!(
-                "Incoming request: {} {} (site: {}, static_dir: {}, host: {})",
-                session.req_header().method,
-                session.req_header().uri,
-                site.name,
-                site.static_dir,
-                host_header
-            );
+    /// Canonical, percent-decoded path of a request. HTTP/1 absolute-form
+    /// targets (`GET http://host/path`) arrive as the path and are reduced to
+    /// their path component first.
+    fn request_path(req: &RequestHeader) -> std::result::Result<String, &'static str> {
+        let raw = req.uri.path();
+        if raw.starts_with("http://") || raw.starts_with("https://") {
+            let target = url::Url::parse(raw).map_err(|_| "Invalid request target")?;
+            normalize_path(target.path())
         } else {
-            log::warn!(
-                "No site configuration found for request: {} {} (host: {})",
-                session.req_header().method,
-                session.req_header().uri,
-                host_header
-            );
+            normalize_path(raw)
         }
+    }
 
-        // Handle HTTPS redirect if configured
-        if let Some(site) = ctx.as_ref() {
+    /// Route a request that passed the filters to the handler serving it
+    async fn dispatch(
+        &self,
+        session: &mut Session,
+        ctx: &mut RequestContext,
+        path: &str,
+    ) -> Result<bool> {
+        // Handle HTTPS redirect if configured; ACME challenges are answered
+        // over plain HTTP
+        if let Some(site) = ctx
+            .site
+            .as_ref()
+            .filter(|_| !path.starts_with("/.well-known/acme-challenge/"))
+        {
             if self.handle_ssl_redirect(session, site).await? {
                 return Ok(true);
             }
@@ -708,15 +1052,15 @@ impl ProxyHttp for WebServerService {
             log::debug!(
                 "ACME challenge request detected: {} (site found: {})",
                 path,
-                ctx.is_some()
+                ctx.site.is_some()
             );
-            if let Some(site) = ctx.as_ref() {
+            if let Some(site) = ctx.site.as_ref() {
                 log::debug!(
                     "Calling handle_acme_challenge_for_site for site '{}'",
                     site.name
                 );
                 if self
-                    .handle_acme_challenge_for_site(session, &path, site)
+                    .handle_acme_challenge_for_site(session, path, site)
                     .await?
                 {
                     return Ok(true);
@@ -728,89 +1072,581 @@ impl ProxyHttp for WebServerService {
             } else {
                 // ACME challenge request but no site found
                 log::warn!("ACME challenge request but no site configuration found: {path}");
-                let mut header = ResponseHeader::build(404, Some(3))?;
-                header.insert_header("Content-Type", "application/json")?;
-                header.insert_header("X-ACME-Challenge-Status", "no-site-found")?;
-                header.insert_header("X-ACME-Enabled", "false")?;
-
-                let error_body = r#"{"error":"No Site Found","message":"No site configuration found for ACME challenge","status":404}"#;
-                header.insert_header("Content-Length", error_body.len().to_string())?;
+                JsonError::new(
+                    404,
+                    "No Site Found",
+                    "No site configuration found for ACME challenge",
+                )?
+                .with_header("X-ACME-Challenge-Status", "no-site-found")?
+                .with_header("X-ACME-Enabled", "false")?
+                .write(session)
+                .await?;
+                return Ok(true);
+            }
+        }
 
-                session
-                    .write_response_header(Box::new(header), false)
-                    .await?;
-                session
-                    .write_response_body(Some(error_body.as_bytes().into()), true)
-                    .await?;
+        // Answer everything but health checks while under maintenance
+        if let Some(site) = ctx.site.as_ref() {
+            if site.maintenance.enabled && !path.starts_with("/api/health") {
+                self.handle_maintenance(session, site).await?;
                 return Ok(true);
             }
         }
 
+        // Serve configured well-known documents before static files
+        if path.starts_with("/.well-known/") {
+            if let Some(site) = ctx.site.as_ref() {
+                let config = self.config.read().await;
+                if self
+                    .well_known_handler
+                    .handle(session, site, &config.well_known, path)
+                    .await?
+                {
+                    return Ok(true);
+                }
+            }
+        }
+
+        // Browsers ask every site for an icon; answer for sites without one
+        if path == FAVICON_PATH {
+            if let Some(site) = ctx.site.as_ref() {
+                if self.favicon_handler.handle(session, site).await? {
+                    return Ok(true);
+                }
+            }
+        }
+
         // Route request to appropriate handler
-        match path.as_str() {
+        match path {
             path if path.starts_with("/api/health") => {
-                self.health_handler.handle(session, ctx.as_ref()).await?;
+                self.health_handler
+                    .handle(session, ctx.site.as_ref())
+                    .await?;
+                Ok(true)
+            }
+            "/api/version" if session.req_header().method == "GET" => {
+                let (enabled, server) = {
+                    let config = self.config.read().await;
+                    (config.security.version_endpoint, config.server.clone())
+                };
+                if enabled {
+                    self.api_handler.handle_version(session, &server).await?;
+                } else {
+                    self.api_handler.handle(session, ctx.site.as_ref()).await?;
+                }
                 Ok(true)
             }
             path if path.starts_with("/api/") => {
-                self.api_handler.handle(session, ctx.as_ref()).await?;
+                self.api_handler.handle(session, ctx.site.as_ref()).await?;
                 Ok(true)
             }
             _ => {
                 // Check if site has proxy enabled and route matches
-                if let Some(site) = ctx.as_ref() {
+                if let Some(site) = ctx.site.as_ref() {
                     if site.proxy.enabled {
                         // Check if request matches any proxy routes
                         for route in &site.proxy.routes {
                             if path.starts_with(&route.path) {
-                                // Create a temporary proxy handler for this request
-                                let proxy_handler = ProxyHandler::new(site.proxy.clone());
+                                // Create a temporary proxy handler for this request;
+                                // upstream connections, health and balancing live
+                                // in the service
+                                let (max_body_size, trusted_proxies) = {
+                                    let config = self.config.read().await;
+                                    (
+                                        config.max_request_bytes_for(Some(site)),
+                                        config.trusted_proxies.clone(),
+                                    )
+                                };
+                                let proxy_handler = ProxyHandler::new(site.proxy.clone())
+                                    .with_max_body_size(max_body_size)
+                                    .with_trusted_proxies(trusted_proxies)
+                                    .with_static_handler(self.static_handler.clone())
+                                    .with_upstream_pools(self.upstream_pools.clone())
+                                    .with_upstream_health(self.upstream_health.clone())
+                                    .with_upstream_balancer(self.upstream_balancer.clone())
+                                    .with_response_cache(self.proxy_cache.clone());
                                 return proxy_handler
-                                    .handle_proxy_request(session, site, &path)
+                                    .handle_proxy_request(
+                                        session,
+                                        site,
+                                        &encode_path(path),
+                                        ctx.span.as_mut(),
+                                    )
                                     .await;
                             }
                         }
                     }
 
+                    // Internal locations are only served through X-Accel-Redirect
+                    if site.is_internal_location(path) {
+                        self.handle_404(session, Some(site)).await?;
+                        return Ok(true);
+                    }
+
+                    // API-only sites never serve files from static_dir
+                    if site.api_only {
+                        self.handle_404(session, Some(site)).await?;
+                        return Ok(true);
+                    }
+
                     // No proxy route matched, handle as static files
-                    self.static_handler.handle(session, site, &path).await?;
+                    self.static_handler.handle(session, site, path).await?;
                 } else {
-                    self.handle_404(session, ctx.as_ref()).await?;
+                    self.handle_404(session, ctx.site.as_ref()).await?;
                 }
                 Ok(true)
             }
         }
     }
 
-    async fn connected_to_upstream(
-        &self,
-        _session: &mut Session,
-        _reused: bool,
-        _peer: &HttpPeer,
-        #[cfg(unix)] _fd: std::os::unix::io::RawFd,
-        #[cfg(windows)] _fd: std::os::windows::io::RawSocket,
-        _digest: Option<&pingora::protocols::Digest>,
-        _ctx: &mut Self::CTX,
-    ) -> Result<()> {
-        // Not used for local serving
-        Ok(())
+    /// Reject a malformed request (400) before any site is selected, closing
+    /// the connection since the client's framing can no longer be trusted
+    async fn handle_bad_request(&self, session: &mut Session, message: &str) -> Result<()> {
+        self.handle_request_error(session, 400, "Bad Request", message)
+            .await
     }
 
-    async fn upstream_request_filter(
+    /// Answer a request with a JSON error and close the connection, since
+    /// its body (if any) is left unread
+    async fn handle_request_error(
         &self,
-        _session: &mut Session,
-        _upstream_request: &mut pingora::http::RequestHeader,
-        _ctx: &mut Self::CTX,
+        session: &mut Session,
+        status: u16,
+        error: &str,
+        message: &str,
     ) -> Result<()> {
-        // Not used for local serving
-        Ok(())
+        session.set_keepalive(None);
+        JsonError::new(status, error, message)?
+            .with_header("Connection", "close")?
+            .write(session)
+            .await
     }
 
-    async fn response_filter(
+    /// Reject a request from a blocked client (403), or one over a rate (429)
+    /// or concurrency (503) limit, telling the client how many seconds to wait
+    /// before retrying when that would help
+    async fn handle_rejected(
         &self,
-        _session: &mut Session,
-        _upstream_response: &mut pingora::http::ResponseHeader,
-        _ctx: &mut Self::CTX,
+        session: &mut Session,
+        site: &SiteConfig,
+        status: u16,
+        retry_after: Option<u64>,
+    ) -> Result<()> {
+        if self
+            .write_error_page(session, site, status, retry_after)
+            .await?
+        {
+            return Ok(());
+        }
+
+        let (error, message) = match status {
+            403 => ("Forbidden", "Access denied"),
+            429 => (
+                "Too Many Requests",
+                "Rate limit exceeded, please retry later",
+            ),
+            _ => (
+                "Service Unavailable",
+                "The site is handling too many requests, please retry",
+            ),
+        };
+        let mut response = JsonError::new(status, error, message)?;
+        if let Some(retry_after) = retry_after {
+            response = response.with_header("Retry-After", retry_after.to_string())?;
+        }
+        self.write_json_error(session, Some(site), response).await
+    }
+}
+
+#[async_trait]
+impl ProxyHttp for WebServerService {
+    type CTX = RequestContext;
+
+    fn new_ctx(&self) -> Self::CTX {
+        RequestContext::new()
+    }
+
+    async fn upstream_peer(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut Self::CTX,
+    ) -> Result<Box<HttpPeer>> {
+        // Since we're handling requests locally, we don't need an upstream peer
+        Err(Error::new(ErrorType::InternalError).into_down())
+    }
+
+    async fn early_request_filter(
+        &self,
+        session: &mut Session,
+        _ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        // Apply connection timeouts from the performance configuration (seconds)
+        let (keep_alive_timeout, request_timeout) = {
+            let config = self.config.read().await;
+            (
+                config.performance.keep_alive_timeout,
+                config.performance.request_timeout,
+            )
+        };
+
+        // Idle keep-alive connections close after this many seconds
+        session.set_keepalive(Some(keep_alive_timeout));
+        // Bound slow clients while reading the rest of the request
+        session.set_read_timeout(Some(std::time::Duration::from_secs(request_timeout)));
+
+        Ok(())
+    }
+
+    async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
+        // Track in-flight requests; balanced in `logging`, which runs on every exit path
+        metrics().increment_connections();
+        ctx.counted = true;
+
+        let (max_header_size, max_headers, request_timeout, max_connections) = {
+            let config = self.config.read().await;
+            let security = &config.security;
+            (
+                security.max_header_bytes(),
+                security.max_headers,
+                config.performance.request_timeout,
+                config.performance.max_connections,
+            )
+        };
+
+        // Past the server's connection limit, answer new connections with
+        // 503 and close them rather than take on more work
+        let connection = session
+            .digest()
+            .and_then(|digest| digest.socket_digest.clone());
+        if let Some(connection) = connection {
+            if !self.connection_limiter.admit(&connection, max_connections) {
+                log::warn!(
+                    "Server is at its connection limit ({}), rejecting connection",
+                    max_connections
+                );
+                self.handle_request_error(
+                    session,
+                    503,
+                    "Service Unavailable",
+                    "The server is handling too many connections, please retry",
+                )
+                .await?;
+                return Ok(true);
+            }
+        }
+
+        // Oversized headers are refused before any of them is interpreted
+        if let Err(reason) = check_header_limits(session.req_header(), max_header_size, max_headers)
+        {
+            log::warn!(
+                "Rejected request {} {}: {}",
+                session.req_header().method,
+                session.req_header().uri,
+                reason
+            );
+            self.handle_request_error(session, 431, "Request Header Fields Too Large", reason)
+                .await?;
+            return Ok(true);
+        }
+
+        // An ambiguous Host could route the request to an unexpected site
+        if let Err(reason) = validate_host(session.req_header()) {
+            log::warn!(
+                "Rejected request {} {}: {}",
+                session.req_header().method,
+                session.req_header().uri,
+                reason
+            );
+            self.handle_bad_request(session, reason).await?;
+            return Ok(true);
+        }
+
+        // Route on the canonical path, so encoded dots and separators cannot
+        // slip past the prefix checks below
+        let path = match Self::request_path(session.req_header()) {
+            Ok(path) => path,
+            Err(PATH_TRAVERSAL) => {
+                log::warn!(
+                    "Blocked path traversal attempt: {} {}",
+                    session.req_header().method,
+                    session.req_header().uri
+                );
+                metrics().record_path_traversal();
+                let site = self.find_site_by_request(session).await;
+                match site.as_ref().filter(|site| site.traversal_status == 403) {
+                    Some(site) => self.handle_rejected(session, site, 403, None).await?,
+                    None => self.handle_404(session, site.as_ref()).await?,
+                }
+                return Ok(true);
+            }
+            Err(reason) => {
+                log::warn!(
+                    "Rejected request {} {}: {}",
+                    session.req_header().method,
+                    session.req_header().uri,
+                    reason
+                );
+                self.handle_bad_request(session, reason).await?;
+                return Ok(true);
+            }
+        };
+
+        // Find the matching site configuration
+        let site_config = self.find_site_by_request(session).await;
+        ctx.site = site_config.clone();
+
+        // Refuse an upload before the client sends it, rather than after
+        let max_body_size = self
+            .config
+            .read()
+            .await
+            .max_request_bytes_for(site_config.as_ref());
+        if let Err(reason) = validate_expectation(session.req_header(), max_body_size) {
+            log::warn!(
+                "Rejected request {} {}: {}",
+                session.req_header().method,
+                session.req_header().uri,
+                reason
+            );
+            self.handle_request_error(session, 417, "Expectation Failed", reason)
+                .await?;
+            return Ok(true);
+        }
+
+        let host_header = session
+            .req_header()
+            .headers
+            .get("Host")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("localhost");
+
+        // Start the request span, continuing the client's trace if it sent one
+        if self.tracer.is_some() {
+            let req = session.req_header();
+            let parent = req
+                .headers
+                .get(TRACEPARENT)
+                .and_then(|h| h.to_str().ok())
+                .and_then(TraceContext::parse);
+            let mut span = Span::start(req.method.as_str(), parent);
+            span.set_attribute("http.request.method", req.method.as_str());
+            span.set_attribute("url.path", path.as_str());
+            span.set_attribute("server.address", host_header);
+            if let Some(site) = ctx.site.as_ref() {
+                span.set_attribute("bws.site", site.name.as_str());
+            }
+            ctx.span = Some(span);
+        }
+
+        // Log the incoming request
+        if let Some(site) = ctx.site.as_ref() {
+            log::info!(
+                "Incoming request: {} {} (site: {}, static_dir: {}, host: {})",
+                session.req_header().method,
+                session.req_header().uri,
+                site.name,
+                site.static_dir,
+                host_header
+            );
+        } else {
+            log::warn!(
+                "No site configuration found for request: {} {} (host: {})",
+                session.req_header().method,
+                session.req_header().uri,
+                host_header
+            );
+        }
+
+        // Apply the site's rate limit, or the global one
+        if let Some(site) = ctx.site.as_ref() {
+            let rate_limit = {
+                let config = self.config.read().await;
+                config.rate_limit_for(site).and_then(|(scope, limit)| {
+                    let client_ip = crate::middleware::client_ip(session, &config.trusted_proxies)?;
+                    if limit.is_whitelisted(client_ip) {
+                        return None;
+                    }
+                    Some((
+                        scope.to_string(),
+                        client_ip,
+                        limit.requests_per_minute,
+                        limit.burst_size,
+                    ))
+                })
+            };
+
+            // The backend may be remote, so check without holding the config lock
+            let rate_limited = match rate_limit {
+                Some((scope, client_ip, requests_per_minute, burst_size)) => self
+                    .rate_limiter
+                    .check(&scope, client_ip, requests_per_minute, burst_size)
+                    .await
+                    .err()
+                    .map(|wait| (client_ip, wait)),
+                None => None,
+            };
+
+            if let Some((client_ip, wait)) = rate_limited {
+                log::warn!(
+                    "Rate limit exceeded for {} on site '{}'",
+                    client_ip,
+                    site.name
+                );
+                let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+                self.handle_rejected(session, site, 429, Some(retry_after))
+                    .await?;
+                return Ok(true);
+            }
+        }
+
+        // Deny clients by country
+        if let Some(site) = ctx.site.as_ref().filter(|s| s.geoip.enabled) {
+            let client_ip = {
+                let config = self.config.read().await;
+                crate::middleware::client_ip(session, &config.trusted_proxies)
+            };
+            if let Some(ip) = client_ip.filter(|ip| !self.geoip.allows(&site.geoip, *ip)) {
+                log::warn!("Blocked {} by country on site '{}'", ip, site.name);
+                self.handle_rejected(session, site, 403, None).await?;
+                return Ok(true);
+            }
+        }
+
+        // Block or throttle clients by User-Agent
+        if let Some(site) = ctx.site.as_ref().filter(|s| s.user_agent_filter.enabled) {
+            let user_agent = session
+                .req_header()
+                .headers
+                .get("user-agent")
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            let client_ip = {
+                let config = self.config.read().await;
+                crate::middleware::client_ip(session, &config.trusted_proxies)
+            };
+            let filter = &site.user_agent_filter;
+
+            match self
+                .user_agent_filter
+                .check(filter, &user_agent, client_ip)
+                .await
+            {
+                UserAgentVerdict::Allow => {}
+                UserAgentVerdict::Block => {
+                    log::warn!(
+                        "Blocked user agent {:?} from {:?} on site '{}'",
+                        user_agent,
+                        client_ip,
+                        site.name
+                    );
+                    self.handle_rejected(session, site, 403, None).await?;
+                    return Ok(true);
+                }
+                UserAgentVerdict::Throttle => {
+                    let throttle = filter
+                        .throttle_limit
+                        .as_ref()
+                        .zip(client_ip)
+                        .filter(|(limit, ip)| !limit.is_whitelisted(*ip));
+                    if let Some((limit, ip)) = throttle {
+                        let scope = format!("user-agent:{}", site.name);
+                        if let Err(wait) = self
+                            .rate_limiter
+                            .check(&scope, ip, limit.requests_per_minute, limit.burst_size)
+                            .await
+                        {
+                            log::warn!(
+                                "Throttled user agent {:?} from {} on site '{}'",
+                                user_agent,
+                                ip,
+                                site.name
+                            );
+                            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+                            self.handle_rejected(session, site, 429, Some(retry_after))
+                                .await?;
+                            return Ok(true);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Enforce the site's concurrency limit without queuing
+        if let Some(site) = ctx.site.as_ref() {
+            if let Some(limit) = site.max_concurrent_requests {
+                match self.concurrency_limiter.try_acquire(&site.name, limit) {
+                    Some(slot) => ctx.concurrency_slot = Some(slot),
+                    None => {
+                        log::warn!(
+                            "Site '{}' is at its concurrency limit ({}), rejecting request",
+                            site.name,
+                            limit
+                        );
+                        self.handle_rejected(session, site, 503, Some(1)).await?;
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+
+        // Bound the time until the response starts, so a stuck disk read or a
+        // slow handler answers 504 instead of holding the worker
+        let deadline = std::time::Duration::from_secs(request_timeout);
+        match until_response_started(deadline, self.dispatch(session, ctx, &path)).await {
+            Some(result) => result,
+            None => {
+                log::warn!(
+                    "Request {} {} timed out after {}s",
+                    session.req_header().method,
+                    path,
+                    request_timeout
+                );
+                // A response already under way cannot be replaced; drop the connection
+                if session.response_written().is_some() {
+                    return Err(Error::new(ErrorType::WriteTimedout));
+                }
+                self.handle_request_error(
+                    session,
+                    504,
+                    "Gateway Timeout",
+                    "The request took too long to process",
+                )
+                .await?;
+                Ok(true)
+            }
+        }
+    }
+
+    async fn connected_to_upstream(
+        &self,
+        _session: &mut Session,
+        _reused: bool,
+        _peer: &HttpPeer,
+        #[cfg(unix)] _fd: std::os::unix::io::RawFd,
+        #[cfg(windows)] _fd: std::os::windows::io::RawSocket,
+        _digest: Option<&pingora::protocols::Digest>,
+        _ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        // Not used for local serving
+        Ok(())
+    }
+
+    async fn upstream_request_filter(
+        &self,
+        _session: &mut Session,
+        _upstream_request: &mut pingora::http::RequestHeader,
+        _ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        // Not used for local serving
+        Ok(())
+    }
+
+    async fn response_filter(
+        &self,
+        _session: &mut Session,
+        _upstream_response: &mut pingora::http::ResponseHeader,
+        _ctx: &mut Self::CTX,
     ) -> Result<()> {
         // Not used for local serving
         Ok(())
@@ -822,31 +1658,186 @@ impl ProxyHttp for WebServerService {
         _e: Option<&pingora::Error>,
         ctx: &mut Self::CTX,
     ) {
+        if ctx.counted {
+            metrics().decrement_connections();
+            ctx.counted = false;
+        }
+        if let Some(slot) = ctx.concurrency_slot.take() {
+            ConcurrencyLimiter::release(&slot);
+        }
+        if let (Some(tracer), Some(mut span)) = (&self.tracer, ctx.span.take()) {
+            if let Some(response) = session.response_written() {
+                span.set_status(response.status.as_u16());
+            }
+            tracer.finish(span);
+        }
+
         let config = self.config.read().await;
-        if config.logging.log_requests {
-            let site_name = ctx.as_ref().map(|s| s.name.as_str()).unwrap_or("unknown");
-            let method = session.req_header().method.as_str();
-            let uri = session.req_header().uri.to_string();
-            let status = session
+        let site_name = ctx.site.as_ref().map(|s| s.name.as_str());
+
+        let elapsed = ctx.start_time.elapsed();
+        let status = session
+            .response_written()
+            .map(|r| r.status.as_u16())
+            .unwrap_or(0);
+        let bytes_received = session.body_bytes_read();
+        let bytes_sent = response_body_bytes(session);
+        metrics().record_request(status, elapsed, bytes_received as u64, bytes_sent as u64);
+        if let Some(statsd) = &self.statsd {
+            statsd.record_request(
+                site_name,
+                status,
+                elapsed,
+                bytes_received as u64,
+                bytes_sent as u64,
+            );
+        }
+        if config.logging.is_slow_request(elapsed) {
+            metrics().record_slow_request();
+            log::warn!(
+                "Slow request: {} {} took {:?} (site: {})",
+                session.req_header().method,
+                session.req_header().uri.path(),
+                elapsed,
+                site_name.unwrap_or("-")
+            );
+        }
+        let access_logger = self.access_logger();
+        if !config.logging.log_requests && !access_logger.has_file(site_name) {
+            return;
+        }
+
+        let req = session.req_header();
+        let header = |name: &str| {
+            req.headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+
+        let entry = AccessLogEntry {
+            client_ip: crate::middleware::client_ip(session, &config.trusted_proxies)
+                .map(|ip| ip.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            user: None,
+            time: ctx.received_at.fixed_offset(),
+            method: req.method.to_string(),
+            uri: req.uri.to_string(),
+            version: format!("{:?}", req.version),
+            status: session
                 .response_written()
                 .map(|r| r.status.as_u16())
-                .unwrap_or(0);
+                .unwrap_or(0),
+            bytes_received,
+            bytes_sent,
+            referer: header("Referer"),
+            user_agent: header("User-Agent"),
+        };
+
+        access_logger.log(site_name, &entry);
 
+        if config.logging.log_requests && access_logger.has_file(site_name) {
             log::info!(
-                "Request completed: {} {} {} {} (site: {})",
-                session
-                    .client_addr()
-                    .map(|addr| addr.to_string())
-                    .unwrap_or_else(|| "unknown".to_string()),
-                method,
-                uri,
-                status,
-                site_name
+                "Request completed: {} {} {} {} in {:?} (site: {})",
+                entry.client_ip,
+                entry.method,
+                entry.uri,
+                entry.status,
+                ctx.start_time.elapsed(),
+                ctx.site
+                    .as_ref()
+                    .map(|s| s.name.as_str())
+                    .unwrap_or("unknown")
             );
         }
     }
 }
 
+/// Built-in page for sites under maintenance without a page of their own
+const DEFAULT_MAINTENANCE_PAGE: &str = "<!DOCTYPE html>\n<html>\n<head><title>503 Service Unavailable</title></head>\n<body>\n<h1>Down for maintenance</h1>\n<p>This site is temporarily unavailable. Please try again later.</p>\n</body>\n</html>\n";
+
+/// Body of the maintenance response: inline content, then the configured
+/// file, then the built-in page (also used when the file cannot be read)
+async fn maintenance_page(maintenance: &MaintenanceConfig) -> String {
+    if let Some(content) = &maintenance.content {
+        return content.clone();
+    }
+    if let Some(file) = &maintenance.file {
+        match tokio::fs::read_to_string(file).await {
+            Ok(content) => return content,
+            Err(e) => log::warn!("Failed to read maintenance page {}: {}", file, e),
+        }
+    }
+    DEFAULT_MAINTENANCE_PAGE.to_string()
+}
+
+/// Response body bytes written to the client, counted as they were sent so
+/// chunked, streamed and cut-short responses are exact. Pingora's HTTP/1
+/// counter includes the header block, which is taken back off.
+fn response_body_bytes(session: &Session) -> usize {
+    let sent = session.body_bytes_sent();
+    match session.response_written() {
+        Some(header) if !session.as_downstream().is_http2() => {
+            sent.saturating_sub(http::h1_response_header_len(header))
+        }
+        _ => sent,
+    }
+}
+
+/// Hostname and port a request is addressed to, from its Host header. IPv6
+/// literals keep their brackets, a missing port is the scheme's default, and
+/// an unparseable one falls back to 8080.
+fn host_and_port(host_header: &str, https: bool) -> (&str, u16) {
+    let default_port = if https { 443 } else { 80 };
+    match http::split_host_port(host_header) {
+        Some((hostname, port)) => (hostname, port.unwrap_or(default_port)),
+        None => {
+            let hostname = host_header
+                .rsplit_once(':')
+                .map_or(host_header, |(hostname, _)| hostname);
+            (hostname, 8080)
+        }
+    }
+}
+
+/// Body and `Content-Type` of the site's error page for `status`, read from
+/// its static directory
+async fn error_page(site: &SiteConfig, status: u16) -> Option<(bytes::Bytes, &'static str)> {
+    let page = site.get_error_page(status)?;
+    let path = format!("{}/{}", site.static_dir, page);
+    match tokio::fs::read(&path).await {
+        Ok(content) => {
+            let extension = std::path::Path::new(page)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("html");
+            Some((content.into(), crate::core::fs::get_mime_type(extension)))
+        }
+        Err(e) => {
+            log::warn!("Failed to read error page {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Header for an error page. Pages answering a temporary condition say
+/// when to retry, and are never cached so the page does not outlive it.
+fn error_page_header(
+    status: u16,
+    content_type: &str,
+    len: usize,
+    retry_after: Option<u64>,
+) -> Result<ResponseHeader> {
+    let mut header = ResponseHeader::build(status, Some(4))?;
+    header.insert_header("Content-Type", content_type)?;
+    header.insert_header("Content-Length", len.to_string())?;
+    if let Some(retry_after) = retry_after {
+        header.insert_header("Retry-After", retry_after.to_string())?;
+        header.insert_header("Cache-Control", "no-store")?;
+    }
+    Ok(header)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -865,25 +1856,22 @@ mod tests {
             sites: vec![SiteConfig {
                 name: "test-site".to_string(),
                 hostname: "localhost".to_string(),
-                hostnames: vec![],
                 port: 8080,
                 static_dir: "/tmp/static".to_string(),
                 default: true,
-                api_only: false,
-                headers: HashMap::new(),
-                ssl: crate::config::SiteSslConfig::default(),
-                redirect_to_https: false,
                 index_files: vec!["index.html".to_string()],
-                error_pages: HashMap::new(),
-                compression: Default::default(),
-                cache: Default::default(),
-                access_control: Default::default(),
-                proxy: crate::config::ProxyConfig::default(),
+                ..Default::default()
             }],
             logging: LoggingConfig::default(),
             performance: PerformanceConfig::default(),
             security: SecurityConfig::default(),
             management: ManagementConfig::default(),
+            tracing: Default::default(),
+            statsd: Default::default(),
+            metrics: Default::default(),
+            trusted_proxies: Default::default(),
+            acme_challenge_dir: None,
+            well_known: HashMap::new(),
         }
     }
 
@@ -894,6 +1882,198 @@ mod tests {
         // Service creation should succeed
     }
 
+    #[test]
+    fn test_request_path_is_canonical() {
+        let path = |target: &str| {
+            let req = RequestHeader::build("GET", target.as_bytes(), None).unwrap();
+            WebServerService::request_path(&req)
+        };
+
+        assert_eq!(path("/api/%68ealth").unwrap(), "/api/health");
+        assert_eq!(path("http://example.com/a%20b/").unwrap(), "/a b/");
+        assert!(path("/.well-known/%2e%2e%2fapi/sites").is_err());
+        assert!(path("/static/%252e%252e%252f").is_err());
+    }
+
+    #[test]
+    fn test_host_and_port() {
+        assert_eq!(host_and_port("[::1]:8080", false), ("[::1]", 8080));
+        assert_eq!(host_and_port("[2001:db8::1]", false), ("[2001:db8::1]", 80));
+        assert_eq!(host_and_port("[2001:db8::1]", true), ("[2001:db8::1]", 443));
+        assert_eq!(host_and_port("example.com:80", false), ("example.com", 80));
+        assert_eq!(host_and_port("example.com", true), ("example.com", 443));
+        assert_eq!(
+            host_and_port("example.com:http", false),
+            ("example.com", 8080)
+        );
+
+        // IPv6 virtual hosts route whether configured with brackets or not
+        let mut config = create_test_config();
+        let mut site = config.sites[0].clone();
+        site.name = "ipv6".to_string();
+        site.hostname = "2001:db8::1".to_string();
+        site.hostnames = vec!["[::1]".to_string()];
+        site.default = false;
+        assert!(site.validate().is_ok());
+        config.sites.push(site);
+        for host in ["[::1]:8080", "[2001:db8::1]:8080", "[2001:DB8::1]:8080"] {
+            let (hostname, port) = host_and_port(host, false);
+            let site = config.find_site_by_host_port(hostname, port).unwrap();
+            assert_eq!(site.name, "ipv6", "{}", host);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_deadline() {
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // An upstream stuck before answering /stuck, and streaming /stream
+        // past the deadline once its header is out
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut request = [0u8; 1024];
+                    let n = stream.read(&mut request).await.unwrap();
+                    if !request[..n].starts_with(b"GET /stream") {
+                        tokio::time::sleep(Duration::from_secs(30)).await;
+                        return;
+                    }
+                    stream
+                        .write_all(
+                            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                              Connection: close\r\n\r\ndata: first\n\n",
+                        )
+                        .await
+                        .unwrap();
+                    tokio::time::sleep(Duration::from_millis(1500)).await;
+                    let _ = stream.write_all(b"data: last\n\n").await;
+                });
+            }
+        });
+
+        let site: SiteConfig = toml::from_str(&format!(
+            r#"
+            name = "test"
+            hostname = "localhost"
+            port = 8080
+            static_dir = "/tmp"
+
+            [proxy]
+            enabled = true
+
+            [[proxy.upstreams]]
+            name = "backend"
+            url = "http://{}"
+
+            [[proxy.routes]]
+            path = "/"
+            upstream = "backend"
+            "#,
+            addr
+        ))
+        .unwrap();
+        let mut config = create_test_config();
+        config.performance.request_timeout = 1;
+        config.sites[0].proxy = site.proxy;
+        let service = WebServerService::new(config);
+
+        // A handler that has not started a response is abandoned
+        let started = Instant::now();
+        let response = respond(
+            &service,
+            "GET /stuck HTTP/1.1\r\nHost: localhost:8080\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 504"), "{}", response);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // A response under way runs past the deadline
+        let response = respond(
+            &service,
+            "GET /stream HTTP/1.1\r\nHost: localhost:8080\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("data: last"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("maintenance.html");
+        std::fs::write(&file, "<p>from file</p>").unwrap();
+
+        // Inline content
+        let inline = MaintenanceConfig {
+            enabled: true,
+            content: Some("<p>inline</p>".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(maintenance_page(&inline).await, "<p>inline</p>");
+
+        // File
+        let from_file = MaintenanceConfig {
+            enabled: true,
+            file: Some(file.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        assert_eq!(maintenance_page(&from_file).await, "<p>from file</p>");
+
+        // Built-in page when unset or the file is missing
+        let unset = MaintenanceConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        assert_eq!(maintenance_page(&unset).await, DEFAULT_MAINTENANCE_PAGE);
+        let missing = MaintenanceConfig {
+            enabled: true,
+            file: Some(dir.path().join("gone.html").to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        assert_eq!(maintenance_page(&missing).await, DEFAULT_MAINTENANCE_PAGE);
+
+        // Content and file together are rejected
+        let mut site = create_test_config().sites.remove(0);
+        site.maintenance = from_file.clone();
+        assert!(site.validate().is_ok());
+        site.maintenance = MaintenanceConfig {
+            content: Some("x".to_string()),
+            ..from_file
+        };
+        assert!(site.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_error_page() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("slow-down.html"), "<p>slow down</p>").unwrap();
+
+        let mut site = create_test_config().sites.remove(0);
+        site.static_dir = dir.path().to_string_lossy().to_string();
+        site.error_pages.insert(429, "slow-down.html".to_string());
+        site.error_pages.insert(503, "missing.html".to_string());
+
+        let (body, content_type) = error_page(&site, 429).await.unwrap();
+        assert_eq!(&body[..], b"<p>slow down</p>");
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        // Unconfigured or unreadable pages fall back to the JSON error
+        assert!(error_page(&site, 403).await.is_none());
+        assert!(error_page(&site, 503).await.is_none());
+
+        let header = error_page_header(429, content_type, body.len(), Some(7)).unwrap();
+        assert_eq!(header.status.as_u16(), 429);
+        assert_eq!(header.headers.get("Retry-After").unwrap(), "7");
+        assert_eq!(header.headers.get("Content-Length").unwrap(), "16");
+        assert_eq!(header.headers.get("Cache-Control").unwrap(), "no-store");
+
+        let header = error_page_header(404, content_type, body.len(), None).unwrap();
+        assert!(header.headers.get("Retry-After").is_none());
+    }
+
     #[tokio::test]
     async fn test_config_reload() {
         let config = create_test_config();
@@ -908,4 +2088,251 @@ mod tests {
         let updated_config = service.get_config().await;
         assert_eq!(updated_config.server.name, "updated-server");
     }
+
+    #[tokio::test]
+    async fn test_reload_refuses_new_ports() {
+        let config = create_test_config();
+        let service = WebServerService::new(config.clone());
+
+        // A port the server does not listen on is refused, not silently
+        // unserved, and nothing else in that reload is applied
+        let mut new_config = config.clone();
+        new_config.server.name = "updated-server".to_string();
+        new_config.sites[0].ports.push(9999);
+        let error = service.reload_config(new_config).await.unwrap_err();
+        assert!(error.to_string().contains("9999"));
+        let current = service.get_config().await;
+        assert_eq!(current.server.name, config.server.name);
+        assert!(current.sites[0].ports.is_empty());
+
+        // Ports already listened on are fine
+        let mut new_config = config.clone();
+        new_config.sites[0].ports.push(8080);
+        assert!(service.reload_config(new_config).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_config_file_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let write = |name: &str| {
+            let config = format!(
+                r#"
+                [server]
+                name = "{}"
+
+                [[sites]]
+                name = "main"
+                hostname = "localhost"
+                port = 8080
+                static_dir = "{}"
+                "#,
+                name,
+                dir.path().display()
+            );
+            std::fs::write(&path, config).unwrap();
+        };
+        write("before");
+        let path = path.to_string_lossy().to_string();
+        let service = WebServerService::new(ServerConfig::load_from_file(&path).unwrap());
+
+        // Nothing to reload without a configuration file
+        assert!(service.reload_config_file().await.is_err());
+
+        service.set_config_path(path).await;
+        write("after");
+        service.reload_config_file().await.unwrap();
+        assert_eq!(service.get_config().await.server.name, "after");
+    }
+
+    #[tokio::test]
+    async fn test_reload_reopens_access_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let config = create_test_config();
+        let service = WebServerService::new(config.clone());
+
+        let mut new_config = config;
+        new_config.logging.access_log = Some(path.to_string_lossy().to_string());
+        service.reload_config(new_config).await.unwrap();
+
+        log_request(
+            &service,
+            "GET /logged HTTP/1.1\r\nHost: localhost:8080\r\n\r\n",
+        )
+        .await;
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(log.contains("\"GET /logged HTTP/1.1\""), "{}", log);
+    }
+
+    #[tokio::test]
+    async fn test_reload_applies_site_log_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let mut config = create_test_config();
+        config.logging.access_log = Some(path.to_string_lossy().to_string());
+        let service = WebServerService::new(config.clone());
+        let request = "GET /logged HTTP/1.1\r\nHost: localhost:8080\r\n\r\n";
+
+        log_request(&service, request).await;
+        let mut new_config = config;
+        new_config.sites[0].log_format = Some("json".to_string());
+        service.reload_config(new_config).await.unwrap();
+        log_request(&service, request).await;
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", log);
+        assert!(!lines[0].starts_with('{'), "{}", log);
+        assert!(lines[1].starts_with('{'), "{}", log);
+    }
+
+    /// Run `request` through the service up to and including `logging`
+    async fn log_request(service: &WebServerService, request: &str) {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut session = Session::new_h1(Box::new(server));
+        assert!(session.read_request().await.unwrap());
+        let mut ctx = service.new_ctx();
+        service
+            .request_filter(&mut session, &mut ctx)
+            .await
+            .unwrap();
+        service.logging(&mut session, None, &mut ctx).await;
+    }
+
+    /// Run `request` through the service and return the raw response
+    async fn respond(service: &WebServerService, request: &str) -> String {
+        serve(service, request).await.1
+    }
+
+    /// Run `request` through the service, returning its context as it is
+    /// before `logging` and the raw response
+    async fn serve(service: &WebServerService, request: &str) -> (RequestContext, String) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut session = Session::new_h1(Box::new(server));
+        assert!(session.read_request().await.unwrap());
+        let mut ctx = service.new_ctx();
+        assert!(service
+            .request_filter(&mut session, &mut ctx)
+            .await
+            .unwrap());
+        drop(session);
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        (ctx, response)
+    }
+
+    #[tokio::test]
+    async fn test_api_only_site_serves_no_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "<p>home</p>").unwrap();
+        let mut config = create_test_config();
+        config.sites[0].static_dir = dir.path().to_string_lossy().to_string();
+        config.sites[0].api_only = true;
+        let service = WebServerService::new(config);
+
+        let response = respond(
+            &service,
+            "GET /index.html HTTP/1.1\r\nHost: localhost:8080\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+        assert!(!response.contains("home"));
+    }
+
+    #[tokio::test]
+    async fn test_path_traversal_status_and_metric() {
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("passwd"), "root:x:0:0").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("escape")).unwrap();
+
+        let mut config = create_test_config();
+        config.sites[0].static_dir = dir.path().to_string_lossy().to_string();
+        let attempts = || metrics().get_metrics().unwrap().path_traversal_attempts;
+
+        // Stealth 404 by default, then the configured 403, both for `..` in
+        // the request and for symlinks leading out of static_dir
+        for status in [404, 403] {
+            config.sites[0].traversal_status = status;
+            let service = WebServerService::new(config.clone());
+            for target in ["/../../etc/passwd", "/escape/passwd"] {
+                let before = attempts();
+                let request = format!("GET {} HTTP/1.1\r\nHost: localhost:8080\r\n\r\n", target);
+                let response = respond(&service, &request).await;
+                assert!(
+                    response.starts_with(&format!("HTTP/1.1 {}", status)),
+                    "{}",
+                    response
+                );
+                assert!(!response.contains("root:x"));
+                assert!(attempts() > before);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_limit() {
+        use pingora::protocols::l4::stream::Stream;
+        use pingora::protocols::{GetSocketDigest, SocketDigest};
+        use std::os::unix::io::AsRawFd;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut config = create_test_config();
+        config.performance.max_connections = 2;
+        let service = WebServerService::new(config);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (listener, service) = (&listener, &service);
+
+        // Serve a request on a new connection, which stays open as long as
+        // its session, as with keep-alive
+        let connect = move || async move {
+            let mut client = tokio::net::TcpStream::connect(address).await.unwrap();
+            client
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost:8080\r\n\r\n")
+                .await
+                .unwrap();
+            let (socket, _) = listener.accept().await.unwrap();
+            let fd = socket.as_raw_fd();
+            let mut stream = Stream::from(socket);
+            stream.set_socket_digest(SocketDigest::from_raw_fd(fd));
+
+            let mut session = Session::new_h1(Box::new(stream));
+            assert!(session.read_request().await.unwrap());
+            let mut ctx = service.new_ctx();
+            service
+                .request_filter(&mut session, &mut ctx)
+                .await
+                .unwrap();
+            let status = session.response_written().unwrap().status.as_u16();
+            (client, session, status)
+        };
+
+        let (_first_client, first, status) = connect().await;
+        assert_ne!(status, 503);
+        let (_second_client, _second, status) = connect().await;
+        assert_ne!(status, 503);
+        assert_eq!(service.connection_limiter.open(), 2);
+
+        // Idle connections still count, so a third is refused and closed
+        let (mut client, session, status) = connect().await;
+        assert_eq!(status, 503);
+        drop(session);
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.to_ascii_lowercase().contains("connection: close"));
+
+        // A connection that closes frees its place
+        drop(first);
+        let (_client, _session, status) = connect().await;
+        assert_ne!(status, 503);
+    }
 }
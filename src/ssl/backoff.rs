@@ -0,0 +1,228 @@
+//! Retry and backoff handling for ACME certificate requests.
+//!
+//! Failed orders back off exponentially (with jitter) and the next allowed
+//! attempt is persisted, so restarts do not reset the backoff and hammer the CA.
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::fs;
+
+/// Attempts made for a single order before giving up until the next backoff window
+pub const MAX_ATTEMPTS: u32 = 3;
+/// Base delay between attempts within one order
+pub const RETRY_BASE_DELAY: Duration = Duration::from_secs(5);
+/// Base delay between orders after they have failed
+pub const BACKOFF_BASE_DELAY: Duration = Duration::from_secs(60 * 60);
+/// Upper bound for any backoff delay
+pub const MAX_BACKOFF_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+const RATE_LIMITED_PROBLEM: &str = "urn:ietf:params:acme:error:rateLimited";
+
+/// Persisted backoff state for one set of domains
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct BackoffEntry {
+    pub failures: u32,
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub next_attempt: Option<DateTime<Utc>>,
+}
+
+/// Backoff state for all domains, stored alongside the certificates
+#[derive(Debug)]
+pub struct AcmeBackoff {
+    path: PathBuf,
+    entries: HashMap<String, BackoffEntry>,
+}
+
+impl AcmeBackoff {
+    /// Load state from `path`; a missing or unreadable file starts with no backoff
+    pub async fn load(path: PathBuf) -> Self {
+        let entries = match fs::read_to_string(&path).await {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|e| {
+                warn!(
+                    "Ignoring invalid ACME backoff state {}: {}",
+                    path.display(),
+                    e
+                );
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        Self { path, entries }
+    }
+
+    async fn save(&self) {
+        let result = match toml::to_string_pretty(&self.entries) {
+            Ok(content) => fs::write(&self.path, content)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        if let Err(e) = result {
+            warn!(
+                "Failed to persist ACME backoff state to {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    pub fn entry(&self, key: &str) -> Option<&BackoffEntry> {
+        self.entries.get(key)
+    }
+
+    /// The time before which no new attempt should be made, if still in the future
+    pub fn blocked_until(&self, key: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.entries
+            .get(key)
+            .and_then(|entry| entry.next_attempt)
+            .filter(|next| *next > now)
+    }
+
+    /// Record a failed order and return when the next attempt is allowed.
+    /// `retry_after` (from a rate-limit response) takes precedence over the computed backoff.
+    pub async fn record_failure(
+        &mut self,
+        key: &str,
+        now: DateTime<Utc>,
+        retry_after: Option<DateTime<Utc>>,
+    ) -> DateTime<Utc> {
+        let entry = self.entries.entry(key.to_string()).or_default();
+        entry.failures += 1;
+        entry.last_attempt = Some(now);
+
+        let next = retry_after.unwrap_or_else(|| {
+            let delay = backoff_delay(entry.failures - 1, BACKOFF_BASE_DELAY, MAX_BACKOFF_DELAY);
+            now + chrono::Duration::from_std(delay).unwrap_or_default()
+        });
+        entry.next_attempt = Some(next);
+
+        self.save().await;
+        next
+    }
+
+    /// Clear the backoff after a successful order
+    pub async fn record_success(&mut self, key: &str) {
+        if self.entries.remove(key).is_some() {
+            self.save().await;
+        }
+    }
+}
+
+/// Exponential backoff with jitter: a random delay between half and all of
+/// `base * 2^attempt`, capped at `max`
+pub fn backoff_delay(attempt: u32, base: Duration, max: Duration) -> Duration {
+    let full = base.saturating_mul(2u32.saturating_pow(attempt)).min(max);
+    full.mul_f64(0.5 + fastrand::f64() * 0.5)
+}
+
+/// If the error is an ACME rate-limit problem, return its detail text
+pub fn rate_limit_detail(error: &(dyn std::error::Error + 'static)) -> Option<String> {
+    match error.downcast_ref::<instant_acme::Error>() {
+        Some(instant_acme::Error::Api(problem))
+            if problem.r#type.as_deref() == Some(RATE_LIMITED_PROBLEM)
+                || problem.status == Some(429) =>
+        {
+            Some(problem.detail.clone().unwrap_or_default())
+        }
+        _ => None,
+    }
+}
+
+/// Extract the retry time from a rate-limit detail such as
+/// `"... retry after 2024-05-24T16:09:50Z: see https://..."` or
+/// `"... retry after 2024-05-24 16:09:50 UTC: see https://..."`.
+/// The ACME client does not expose the `Retry-After` header, but
+/// Let's Encrypt repeats it in the problem detail.
+pub fn retry_after_from_detail(detail: &str) -> Option<DateTime<Utc>> {
+    let lower = detail.to_lowercase();
+    let start = lower.find("retry after ")? + "retry after ".len();
+    let rest = &detail[start..];
+
+    // RFC 3339, terminated by ':' followed by a space or the end of the text
+    let rfc3339 = rest.split(": ").next().unwrap_or(rest).trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(rfc3339) {
+        return Some(time.with_timezone(&Utc));
+    }
+
+    let plain = rest.get(..19)?;
+    chrono::NaiveDateTime::parse_from_str(plain, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay_grows_and_is_capped() {
+        let base = Duration::from_secs(10);
+        let max = Duration::from_secs(100);
+
+        for _ in 0..50 {
+            let first = backoff_delay(0, base, max);
+            assert!(first >= Duration::from_secs(5) && first <= base);
+
+            let third = backoff_delay(2, base, max);
+            assert!(third >= Duration::from_secs(20) && third <= Duration::from_secs(40));
+
+            assert!(backoff_delay(30, base, max) <= max);
+        }
+    }
+
+    #[test]
+    fn test_retry_after_from_detail() {
+        let detail = "too many certificates (5) already issued for this exact set of domains \
+                      in the last 168h0m0s, retry after 2024-05-24T16:09:50Z: see \
+                      https://letsencrypt.org/docs/rate-limits/";
+        assert_eq!(
+            retry_after_from_detail(detail).unwrap().to_rfc3339(),
+            "2024-05-24T16:09:50+00:00"
+        );
+
+        let detail = "too many failed authorizations recently: retry after 2024-05-24 16:09:50 UTC";
+        assert_eq!(
+            retry_after_from_detail(detail).unwrap().to_rfc3339(),
+            "2024-05-24T16:09:50+00:00"
+        );
+
+        assert!(retry_after_from_detail("service unavailable").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_backoff_state_persists_across_restarts() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("acme-backoff.toml");
+        let now = Utc::now();
+
+        let mut backoff = AcmeBackoff::load(path.clone()).await;
+        assert!(backoff.blocked_until("example.com", now).is_none());
+
+        let next = backoff.record_failure("example.com", now, None).await;
+        assert!(next >= now + chrono::Duration::minutes(30));
+
+        let retry_after = now + chrono::Duration::hours(5);
+        let next = backoff
+            .record_failure("example.com", now, Some(retry_after))
+            .await;
+        assert_eq!(next, retry_after);
+
+        // A fresh load (e.g. after a restart) keeps the backoff
+        let mut reloaded = AcmeBackoff::load(path.clone()).await;
+        assert_eq!(reloaded.entry("example.com").unwrap().failures, 2);
+        assert!(reloaded.blocked_until("example.com", now).is_some());
+        assert!(reloaded
+            .blocked_until("example.com", retry_after + chrono::Duration::seconds(1))
+            .is_none());
+
+        reloaded.record_success("example.com").await;
+        let cleared = AcmeBackoff::load(path).await;
+        assert!(cleared.entry("example.com").is_none());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::core::error::{BwsError, BwsResult};
 use chrono::{DateTime, Utc};
 use rustls_pemfile::{certs, private_key};
 use serde::{Deserialize, Serialize};
@@ -196,6 +197,13 @@ impl Certificate {
         Ok(true)
     }
 
+    /// Confirm the private key belongs to the certificate
+    pub async fn verify_key_match(&self) -> BwsResult<()> {
+        let cert_data = fs::read(&self.cert_path).await?;
+        let key_data = fs::read(&self.key_path).await?;
+        verify_key_pair(&cert_data, &key_data)
+    }
+
     pub async fn get_rustls_config(
         &self,
     ) -> Result<rustls::ServerConfig, Box<dyn std::error::Error>> {
@@ -338,6 +346,38 @@ struct CertificateInfo {
 }
 
 // Helper functions for certificate management
+/// Check that PEM certificate and private key data parse and that the key's
+/// public key matches the certificate, so a mismatched pair is caught at load
+/// time rather than during the TLS handshake
+pub fn verify_key_pair(cert_pem: &[u8], key_pem: &[u8]) -> BwsResult<()> {
+    let cert_chain = certs(&mut BufReader::new(cert_pem))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| BwsError::Certificate(format!("Failed to parse certificate: {e}")))?;
+    if cert_chain.is_empty() {
+        return Err(BwsError::Certificate(
+            "No certificates found in file".to_string(),
+        ));
+    }
+
+    let key = private_key(&mut BufReader::new(key_pem))
+        .map_err(|e| BwsError::Certificate(format!("Failed to parse private key: {e}")))?
+        .ok_or_else(|| BwsError::Certificate("No private key found".to_string()))?;
+
+    let signing_key = rustls::crypto::aws_lc_rs::default_provider()
+        .key_provider
+        .load_private_key(key)
+        .map_err(|e| BwsError::Certificate(format!("Unsupported private key: {e}")))?;
+
+    match rustls::sign::CertifiedKey::new(cert_chain, signing_key).keys_match() {
+        // Keys whose public half cannot be derived are accepted, as rustls does
+        Ok(()) | Err(rustls::Error::InconsistentKeys(rustls::InconsistentKeys::Unknown)) => Ok(()),
+        Err(rustls::Error::InconsistentKeys(_)) => Err(BwsError::Certificate(
+            "Private key does not match the certificate's public key".to_string(),
+        )),
+        Err(e) => Err(BwsError::Certificate(format!("Invalid certificate: {e}"))),
+    }
+}
+
 pub fn get_certificate_path(domain: &str, cert_dir: &str) -> PathBuf {
     PathBuf::from(cert_dir).join(format!("{domain}.crt"))
 }
@@ -404,4 +444,30 @@ mod tests {
         assert!(cert.covers_domain("www.example.com"));
         assert!(!cert.covers_domain("other.com"));
     }
+
+    #[test]
+    fn test_verify_key_pair() {
+        let first = rcgen::generate_simple_self_signed(vec!["example.com".to_string()]).unwrap();
+        let second = rcgen::generate_simple_self_signed(vec!["example.com".to_string()]).unwrap();
+        let cert_pem = first.serialize_pem().unwrap();
+
+        assert!(verify_key_pair(
+            cert_pem.as_bytes(),
+            first.serialize_private_key_pem().as_bytes()
+        )
+        .is_ok());
+
+        let mismatched = verify_key_pair(
+            cert_pem.as_bytes(),
+            second.serialize_private_key_pem().as_bytes(),
+        );
+        assert!(
+            matches!(mismatched, Err(BwsError::Certificate(msg)) if msg.contains("does not match"))
+        );
+
+        assert!(matches!(
+            verify_key_pair(b"not a certificate", b""),
+            Err(BwsError::Certificate(_))
+        ));
+    }
 }
@@ -1,3 +1,4 @@
+use crate::core::constants;
 use crate::ssl::{acme::*, certificate::*};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -38,6 +39,91 @@ pub struct ManualCertConfig {
     pub auto_renew: bool,
 }
 
+impl SslConfig {
+    /// SSL settings of a site: its certificate files or ACME account, and
+    /// when its certificates are renewed. `shared_challenge_dir` is the
+    /// server-wide `acme_challenge_dir`, if set.
+    pub fn for_site(
+        site: &crate::config::site::SiteConfig,
+        shared_challenge_dir: Option<&str>,
+    ) -> Self {
+        let cert_dir = Self::site_cert_dir(site);
+
+        Self {
+            enabled: site.ssl.enabled,
+            auto_cert: site.ssl.auto_cert,
+            cert_dir: cert_dir.clone(),
+            acme: site.ssl.acme.as_ref().map(|site_acme| AcmeConfig {
+                directory_url: site_acme.directory_url().to_string(),
+                contact_email: site_acme.email.clone(),
+                terms_agreed: !site_acme.email.is_empty(), // Auto-agree if email is provided
+                challenge_dir: Self::challenge_dir(site, shared_challenge_dir),
+                account_key_file: format!("{cert_dir}/acme-account.key"),
+                enabled: site_acme.enabled,
+                staging: site_acme.staging,
+                key_type: site_acme.key_type,
+            }),
+            manual_certs: {
+                let mut manual_certs = HashMap::new();
+                if let (Some(cert_file), Some(key_file)) = (&site.ssl.cert_file, &site.ssl.key_file)
+                {
+                    manual_certs.insert(
+                        site.hostname.clone(),
+                        ManualCertConfig {
+                            cert_file: cert_file.clone(),
+                            key_file: key_file.clone(),
+                            auto_renew: false,
+                        },
+                    );
+                }
+                manual_certs
+            },
+            renewal_check_interval_hours: 24,
+            renewal_days_before_expiry: site
+                .ssl
+                .acme
+                .as_ref()
+                .map_or(constants::CERT_RENEWAL_DAYS, |acme| {
+                    acme.renewal_days_before_expiry
+                }),
+        }
+    }
+}
+
+impl SslConfig {
+    fn site_cert_dir(site: &crate::config::site::SiteConfig) -> String {
+        site.ssl
+            .cert_file
+            .as_ref()
+            .and_then(|path| std::path::Path::new(path).parent())
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|| {
+                if site.ssl.auto_cert {
+                    "./certs".to_string() // Use local directory for ACME auto-certificates
+                } else {
+                    "/etc/bws/certs".to_string() // Use system directory for manual certificates
+                }
+            })
+    }
+
+    /// Directory a site's HTTP-01 challenges are written to and served from:
+    /// the shared directory if there is one, else the site's `challenge_dir`,
+    /// else `challenges` next to its certificates
+    pub fn challenge_dir(
+        site: &crate::config::site::SiteConfig,
+        shared_challenge_dir: Option<&str>,
+    ) -> String {
+        if let Some(dir) = shared_challenge_dir {
+            return dir.to_string();
+        }
+        site.ssl
+            .acme
+            .as_ref()
+            .and_then(|acme| acme.challenge_dir.clone())
+            .unwrap_or_else(|| format!("{}/challenges", Self::site_cert_dir(site)))
+    }
+}
+
 impl Default for SslConfig {
     fn default() -> Self {
         Self {
@@ -47,7 +133,7 @@ impl Default for SslConfig {
             acme: None,
             manual_certs: HashMap::new(),
             renewal_check_interval_hours: 24, // Check daily
-            renewal_days_before_expiry: 30,   // Renew 30 days before expiry
+            renewal_days_before_expiry: constants::CERT_RENEWAL_DAYS,
         }
     }
 }
@@ -57,7 +143,20 @@ pub struct SslManager {
     config: SslConfig,
     certificate_store: Arc<RwLock<CertificateStore>>,
     acme_client: Option<Arc<RwLock<AcmeClient>>>,
+    /// Shared with the ACME client, readable while it holds its lock for an order
+    challenges: ChallengeStore,
     tls_configs: Arc<RwLock<HashMap<String, rustls::ServerConfig>>>,
+    renewals: Arc<RwLock<HashMap<String, RenewalRecord>>>,
+}
+
+/// Outcome of the renewal attempts for a domain's certificate
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RenewalRecord {
+    pub last_success: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_failure: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_error: Option<String>,
+    /// Failed attempts since the last successful renewal
+    pub consecutive_failures: u32,
 }
 
 impl SslManager {
@@ -71,9 +170,11 @@ impl SslManager {
         certificate_store.load().await?;
 
         // Initialize ACME client if auto_cert is enabled
+        let mut challenges = ChallengeStore::default();
         let acme_client = if config.auto_cert {
             if let Some(acme_config) = &config.acme {
                 let client = AcmeClient::new(acme_config.clone());
+                challenges = client.challenges();
                 Some(Arc::new(RwLock::new(client)))
             } else {
                 return Err("ACME configuration required when auto_cert is enabled".into());
@@ -86,7 +187,9 @@ impl SslManager {
             config,
             certificate_store: Arc::new(RwLock::new(certificate_store)),
             acme_client,
+            challenges,
             tls_configs: Arc::new(RwLock::new(HashMap::new())),
+            renewals: Arc::new(RwLock::new(HashMap::new())),
         };
 
         // Load existing certificates
@@ -98,66 +201,13 @@ impl SslManager {
     /// Create SSL manager from site configuration
     pub async fn from_site_config(
         site: &crate::config::site::SiteConfig,
+        shared_challenge_dir: Option<&str>,
     ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
         if !site.ssl.enabled {
             return Ok(None);
         }
 
-        // Determine cert_dir first
-        let cert_dir = site
-            .ssl
-            .cert_file
-            .as_ref()
-            .and_then(|path| std::path::Path::new(path).parent())
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|| {
-                if site.ssl.auto_cert {
-                    "./certs".to_string() // Use local directory for ACME auto-certificates
-                } else {
-                    "/etc/bws/certs".to_string() // Use system directory for manual certificates
-                }
-            });
-
-        let ssl_config = SslConfig {
-            enabled: site.ssl.enabled,
-            auto_cert: site.ssl.auto_cert,
-            cert_dir: cert_dir.clone(),
-            acme: site.ssl.acme.as_ref().map(|site_acme| AcmeConfig {
-                directory_url: if site_acme.staging {
-                    "https://acme-staging-v02.api.letsencrypt.org/directory".to_string()
-                } else {
-                    "https://acme-v02.api.letsencrypt.org/directory".to_string()
-                },
-                contact_email: site_acme.email.clone(),
-                terms_agreed: !site_acme.email.is_empty(), // Auto-agree if email is provided
-                challenge_dir: site_acme.challenge_dir.clone().unwrap_or_else(|| {
-                    // Auto-generate challenge directory based on cert_dir
-                    format!("{cert_dir}/challenges")
-                }),
-                account_key_file: format!("{cert_dir}/acme-account.key"),
-                enabled: site_acme.enabled,
-                staging: site_acme.staging,
-            }),
-            manual_certs: {
-                let mut manual_certs = HashMap::new();
-                if let (Some(cert_file), Some(key_file)) = (&site.ssl.cert_file, &site.ssl.key_file)
-                {
-                    manual_certs.insert(
-                        site.hostname.clone(),
-                        ManualCertConfig {
-                            cert_file: cert_file.clone(),
-                            key_file: key_file.clone(),
-                            auto_renew: false,
-                        },
-                    );
-                }
-                manual_certs
-            },
-            renewal_check_interval_hours: 24,
-            renewal_days_before_expiry: 30,
-        };
-
-        let manager = Self::new(ssl_config).await?;
+        let manager = Self::new(SslConfig::for_site(site, shared_challenge_dir)).await?;
         Ok(Some(manager))
     }
 
@@ -170,15 +220,24 @@ impl SslManager {
         &self,
         domain: &str,
     ) -> Result<bool, Box<dyn std::error::Error>> {
-        // Check if we already have a valid certificate
-        {
+        // Check if we already have a valid certificate. Certificates preloaded from
+        // the store at startup already have a TLS config and need no further work.
+        let has_tls_config = self.tls_configs.read().await.contains_key(domain);
+        let stored_valid = {
             let store = self.certificate_store.read().await;
-            if let Some(cert) = store.get_certificate(domain) {
-                if !cert.is_expired() && cert.validate_certificate_files().await.unwrap_or(false) {
-                    log::info!("Valid certificate already exists for {domain}");
-                    return Ok(true);
+            match store.get_certificate(domain) {
+                Some(cert) if !cert.is_expired() => {
+                    has_tls_config || cert.validate_certificate_files().await.unwrap_or(false)
                 }
+                _ => false,
+            }
+        };
+        if stored_valid {
+            if !has_tls_config {
+                self.update_tls_config(domain).await?;
             }
+            log::info!("Valid certificate already exists for {domain}");
+            return Ok(true);
         }
 
         // Try to obtain certificate
@@ -213,7 +272,7 @@ impl SslManager {
                 &[domain.to_string()]
             );
             let (cert_pem, key_pem): (String, String) = client
-                .obtain_certificate(&[domain.to_string()])
+                .obtain_certificate_with_retry(&[domain.to_string()])
                 .await
                 .map_err(|e| {
                     log::error!("ACME obtain_certificate failed: {e}");
@@ -304,6 +363,9 @@ impl SslManager {
             return Err(format!("Invalid certificate files for domain: {domain}").into());
         }
 
+        // Catch a mismatched certificate/key pair before serving
+        certificate.verify_key_match().await?;
+
         // Add to store
         {
             let mut store = self.certificate_store.write().await;
@@ -335,28 +397,44 @@ impl SslManager {
             store.list_certificates().to_vec()
         };
 
-        for certificate in certificates {
+        let mut loaded = 0;
+        for certificate in &certificates {
+            if certificate.is_expired() {
+                log::warn!(
+                    "Stored certificate for {} has expired, will attempt renewal",
+                    certificate.domain
+                );
+                continue;
+            }
+
             // Validate certificate files
-            if certificate
+            if !certificate
                 .validate_certificate_files()
                 .await
                 .unwrap_or(false)
             {
-                // Update TLS config for valid certificates
-                self.update_tls_config(&certificate.domain).await?;
-            } else {
                 log::warn!(
                     "Certificate files invalid for domain: {}, will attempt renewal",
                     certificate.domain
                 );
+                continue;
             }
-        }
 
-        let store = self.certificate_store.read().await;
-        let cert_count = store.list_certificates().len();
-        drop(store);
+            // Build the TLS config now so HTTPS can be served at boot without ACME
+            match self.update_tls_config(&certificate.domain).await {
+                Ok(()) => loaded += 1,
+                Err(e) => log::warn!(
+                    "Failed to load stored certificate for {}: {}",
+                    certificate.domain,
+                    e
+                ),
+            }
+        }
 
-        log::info!("Loaded certificates for {cert_count} domains");
+        log::info!(
+            "Loaded certificates for {loaded} of {} stored domains",
+            certificates.len()
+        );
         Ok(())
     }
 
@@ -480,6 +558,13 @@ impl SslManager {
         path.starts_with("/.well-known/acme-challenge/")
     }
 
+    /// Key authorization for `token` if an order of this manager is being
+    /// validated. Unlike `get_acme_challenge_response` this never waits for
+    /// the ACME client.
+    pub fn pending_challenge(&self, token: &str) -> Option<String> {
+        self.challenges.get(token)
+    }
+
     pub async fn get_acme_challenge_response(&self, token: &str) -> Option<String> {
         if let Some(acme_client) = &self.acme_client {
             let client = acme_client.read().await;
@@ -551,7 +636,9 @@ impl SslManager {
         if let Some(acme_client) = &self.acme_client {
             let (cert_pem, key_pem) = {
                 let client = acme_client.write().await;
-                client.obtain_certificate(&[domain.to_string()]).await?
+                client
+                    .obtain_certificate_with_retry(&[domain.to_string()])
+                    .await?
             };
 
             // Save certificate files
@@ -591,6 +678,20 @@ impl SslManager {
         }
     }
 
+    /// Whether the certificate for `domain` is missing or within the
+    /// configured number of days of expiring
+    pub async fn needs_renewal(&self, domain: &str) -> bool {
+        let store = self.certificate_store.read().await;
+        store
+            .get_certificate(domain)
+            .is_none_or(|cert| cert.needs_renewal(self.config.renewal_days_before_expiry))
+    }
+
+    /// Outcome of the renewal attempts made for `domain` so far, if any
+    pub async fn renewal_status(&self, domain: &str) -> Option<RenewalRecord> {
+        self.renewals.read().await.get(domain).cloned()
+    }
+
     /// Check and renew certificate for a specific domain (public method)
     ///
     /// # Errors
@@ -600,23 +701,42 @@ impl SslManager {
         &self,
         domain: &str,
     ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        // Check if certificate needs renewal
-        let needs_renewal = {
-            let store = self.certificate_store.read().await;
-            store
-                .get_certificate(domain)
-                .is_none_or(|cert| cert.needs_renewal(self.config.renewal_days_before_expiry))
-        };
-
-        if needs_renewal {
+        if self.needs_renewal(domain).await {
             log::info!("Certificate for {domain} needs renewal");
-            match self.renew_certificate(domain).await {
+            // Only the message is kept, the error itself can't be held across an await
+            let result = self
+                .renew_certificate(domain)
+                .await
+                .map_err(|e| e.to_string());
+            let consecutive_failures = {
+                let mut renewals = self.renewals.write().await;
+                let status = renewals.entry(domain.to_string()).or_default();
+                match &result {
+                    Ok(()) => {
+                        status.last_success = Some(chrono::Utc::now());
+                        status.consecutive_failures = 0;
+                    }
+                    Err(e) => {
+                        status.last_failure = Some(chrono::Utc::now());
+                        status.last_error = Some(e.clone());
+                        status.consecutive_failures += 1;
+                    }
+                }
+                status.consecutive_failures
+            };
+            crate::monitoring::metrics::metrics()
+                .record_certificate_renewal(domain, consecutive_failures);
+
+            match result {
                 Ok(()) => {
                     log::info!("Successfully renewed certificate for {domain}");
                     Ok(true)
                 }
                 Err(e) => {
-                    log::error!("Failed to renew certificate for {domain}: {e}");
+                    log::error!(
+                        "Failed to renew certificate for {domain} \
+                         ({consecutive_failures} failures in a row): {e}"
+                    );
                     Err(format!("Certificate renewal failed: {e}").into())
                 }
             }
@@ -747,4 +867,166 @@ mod tests {
         config.manual_certs.get_mut("example.com").unwrap().key_file = "key.pem".to_string();
         assert!(config.validate().is_ok());
     }
+
+    #[tokio::test]
+    async fn test_preloads_stored_certificates_without_acme() {
+        // main installs the process-wide provider at startup
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cert_dir = temp_dir.path().to_string_lossy().to_string();
+        let domain = "example.com";
+
+        // Store a valid certificate the way a previous run would have
+        let generated = rcgen::generate_simple_self_signed(vec![domain.to_string()]).unwrap();
+        let cert_pem = generated.serialize_pem().unwrap();
+        let certificate = Certificate::from_pem_data(
+            domain.to_string(),
+            get_certificate_path(domain, &cert_dir),
+            get_key_path(domain, &cert_dir),
+            &cert_pem,
+            true,
+        )
+        .unwrap();
+        certificate
+            .save_certificate(&cert_pem, &generated.serialize_private_key_pem())
+            .await
+            .unwrap();
+        let mut store = CertificateStore::new(temp_dir.path().join("certificates.toml"));
+        store.add_certificate(certificate);
+        store.save().await.unwrap();
+
+        // The ACME directory is unreachable, so any ACME round-trip would fail
+        let manager = SslManager::new(SslConfig {
+            enabled: true,
+            auto_cert: true,
+            cert_dir: cert_dir.clone(),
+            acme: Some(AcmeConfig {
+                enabled: true,
+                directory_url: "https://127.0.0.1:9/directory".to_string(),
+                challenge_dir: format!("{cert_dir}/challenges"),
+                account_key_file: format!("{cert_dir}/acme-account.key"),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        assert!(manager.get_tls_config(domain).await.is_some());
+        assert!(manager.get_rustls_config(domain).await.is_ok());
+        assert!(manager.ensure_certificate(domain).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_site_renewal_threshold() {
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cert_dir = temp_dir.path().to_string_lossy().to_string();
+        let site: crate::config::SiteConfig = toml::from_str(&format!(
+            r#"
+            name = "critical"
+            hostname = "example.com"
+            port = 443
+            static_dir = "/tmp"
+
+            [ssl]
+            enabled = true
+            auto_cert = true
+
+            [ssl.acme]
+            enabled = true
+            email = "admin@example.com"
+            challenge_dir = "{cert_dir}/challenges"
+            renewal_days_before_expiry = 45
+            "#
+        ))
+        .unwrap();
+        let config = SslConfig::for_site(&site, None);
+        assert_eq!(config.renewal_days_before_expiry, 45);
+
+        // A certificate 40 days from expiry is renewed under the site's
+        // threshold, but not under the default one
+        let expires_in_40_days = Certificate {
+            domain: "example.com".to_string(),
+            cert_path: PathBuf::new(),
+            key_path: PathBuf::new(),
+            issued_at: chrono::Utc::now() - chrono::Duration::days(50),
+            expires_at: chrono::Utc::now()
+                + chrono::Duration::days(40)
+                + chrono::Duration::hours(1),
+            issuer: "Test CA".to_string(),
+            san_domains: Vec::new(),
+            auto_renew: true,
+            last_renewal_check: None,
+        };
+        for (renewal_days, expected) in [(45, true), (constants::CERT_RENEWAL_DAYS, false)] {
+            // The ACME directory is unreachable, so any ACME round-trip would fail
+            let acme = config.acme.clone().map(|acme| AcmeConfig {
+                directory_url: "https://127.0.0.1:9/directory".to_string(),
+                account_key_file: format!("{cert_dir}/acme-account.key"),
+                ..acme
+            });
+            let manager = SslManager::new(SslConfig {
+                cert_dir: cert_dir.clone(),
+                acme,
+                renewal_days_before_expiry: renewal_days,
+                ..config.clone()
+            })
+            .await
+            .unwrap();
+            manager
+                .certificate_store
+                .write()
+                .await
+                .add_certificate(expires_in_40_days.clone());
+            assert_eq!(manager.needs_renewal("example.com").await, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_renewals_are_tracked() {
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cert_dir = temp_dir.path().to_string_lossy().to_string();
+        // The ACME client refuses to issue while disabled, so every renewal
+        // fails without a network round-trip
+        let manager = SslManager::new(SslConfig {
+            enabled: true,
+            auto_cert: true,
+            cert_dir: cert_dir.clone(),
+            acme: Some(AcmeConfig {
+                enabled: false,
+                contact_email: "admin@example.com".to_string(),
+                terms_agreed: true,
+                challenge_dir: format!("{cert_dir}/challenges"),
+                account_key_file: format!("{cert_dir}/acme-account.key"),
+                ..AcmeConfig::default()
+            }),
+            ..SslConfig::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(manager.renewal_status("failing.example").await, None);
+
+        for attempt in 1..=2 {
+            assert!(manager
+                .check_and_renew_certificate("failing.example")
+                .await
+                .is_err());
+            let status = manager.renewal_status("failing.example").await.unwrap();
+            assert_eq!(status.consecutive_failures, attempt);
+            assert!(status.last_failure.is_some());
+            assert!(status.last_error.is_some());
+            assert_eq!(status.last_success, None);
+        }
+
+        let snapshot = crate::monitoring::metrics::metrics().get_metrics().unwrap();
+        assert_eq!(
+            snapshot.certificate_renewal_failures.get("failing.example"),
+            Some(&2)
+        );
+    }
 }
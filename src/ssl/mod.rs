@@ -1,4 +1,5 @@
 pub mod acme;
+pub mod backoff;
 pub mod certificate;
 pub mod manager;
 pub mod renewal;
@@ -1,5 +1,4 @@
-use bws_web_server::config::{ServerConfig, SiteConfig, SiteSslConfig};
-use std::collections::HashMap;
+use bws_web_server::config::{ServerConfig, SiteConfig};
 
 #[tokio::test]
 async fn test_multi_hostname_integration() {
@@ -15,16 +14,8 @@ async fn test_multi_hostname_integration() {
         port: 8080,
         static_dir: "./test-static".to_string(),
         default: true,
-        api_only: false,
-        headers: HashMap::new(),
-        redirect_to_https: false,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     // Test hostname matching
@@ -107,16 +98,8 @@ async fn test_multi_hostname_ssl_integration() {
         port: 443,
         static_dir: "./test-static".to_string(),
         default: true,
-        api_only: false,
-        headers: HashMap::new(),
-        redirect_to_https: false,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     // Enable SSL with additional domains
@@ -150,16 +133,8 @@ async fn test_multi_hostname_conflict_detection() {
         port: 8080,
         static_dir: "./test-static1".to_string(),
         default: true,
-        api_only: false,
-        headers: HashMap::new(),
-        redirect_to_https: false,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     let site2 = SiteConfig {
@@ -168,17 +143,8 @@ async fn test_multi_hostname_conflict_detection() {
         hostnames: vec!["www.example.com".to_string()], // Conflict with site1
         port: 8080,
         static_dir: "./test-static2".to_string(),
-        default: false,
-        api_only: false,
-        headers: HashMap::new(),
-        redirect_to_https: false,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     let server_config = ServerConfig {
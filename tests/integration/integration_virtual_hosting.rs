@@ -1,4 +1,4 @@
-use bws_web_server::config::{ServerConfig, ServerInfo, SiteConfig, SiteSslConfig};
+use bws_web_server::config::{ServerConfig, ServerInfo, SiteConfig};
 use std::collections::HashMap;
 
 #[tokio::test]
@@ -11,20 +11,13 @@ async fn test_virtual_hosting_multiple_sites_same_port() {
         port: 8080,
         static_dir: "./sites/main".to_string(),
         default: true,
-        api_only: false,
         headers: {
             let mut headers = HashMap::new();
             headers.insert("X-Site".to_string(), "Main".to_string());
             headers
         },
-        redirect_to_https: false,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     let site2 = SiteConfig {
@@ -33,21 +26,13 @@ async fn test_virtual_hosting_multiple_sites_same_port() {
         hostnames: vec![],                        // No additional hostnames
         port: 8080,                               // Same port as site1
         static_dir: "./sites/blog".to_string(),   // Different content directory
-        default: false,
-        api_only: false,
         headers: {
             let mut headers = HashMap::new();
             headers.insert("X-Site".to_string(), "Blog".to_string());
             headers
         },
-        redirect_to_https: false,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     let site3 = SiteConfig {
@@ -56,7 +41,6 @@ async fn test_virtual_hosting_multiple_sites_same_port() {
         hostnames: vec![],                       // No additional hostnames
         port: 8080,                              // Same port as site1 and site2
         static_dir: "./sites/api".to_string(),   // Different content directory
-        default: false,
         api_only: true,
         headers: {
             let mut headers = HashMap::new();
@@ -64,14 +48,8 @@ async fn test_virtual_hosting_multiple_sites_same_port() {
             headers.insert("Content-Type".to_string(), "application/json".to_string());
             headers
         },
-        redirect_to_https: false,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     let site4 = SiteConfig {
@@ -80,8 +58,6 @@ async fn test_virtual_hosting_multiple_sites_same_port() {
         hostnames: vec![],                        // No additional hostnames
         port: 8080,                               // Same port as all other sites
         static_dir: "./sites/docs".to_string(),   // Different content directory
-        default: false,
-        api_only: false,
         headers: {
             let mut headers = HashMap::new();
             headers.insert("X-Site".to_string(), "Documentation".to_string());
@@ -91,14 +67,8 @@ async fn test_virtual_hosting_multiple_sites_same_port() {
             );
             headers
         },
-        redirect_to_https: false,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     // Create server configuration with all sites
@@ -165,39 +135,21 @@ async fn test_virtual_hosting_mixed_ports() {
     let http_site = SiteConfig {
         name: "http-site".to_string(),
         hostname: "example.com".to_string(),
-        hostnames: vec![],
         port: 80,
         static_dir: "./sites/http".to_string(),
         default: true,
-        api_only: false,
-        headers: HashMap::new(),
         redirect_to_https: true, // Redirect to HTTPS
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     let mut https_site = SiteConfig {
         name: "https-site".to_string(),
         hostname: "example.com".to_string(), // Same hostname, different port
-        hostnames: vec![],
         port: 443,
         static_dir: "./sites/https".to_string(),
-        default: false,
-        api_only: false,
-        headers: HashMap::new(),
-        redirect_to_https: false,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     // Enable SSL for HTTPS site
@@ -208,44 +160,30 @@ async fn test_virtual_hosting_mixed_ports() {
         email: "test@example.com".to_string(),
         staging: true,
         challenge_dir: None,
+        directory_url: None,
+        key_type: Default::default(),
+        renewal_days_before_expiry: 30,
     });
 
     let api_http_site = SiteConfig {
         name: "api-http".to_string(),
         hostname: "api.example.com".to_string(),
-        hostnames: vec![],
         port: 80, // Same port as http_site, different hostname
         static_dir: "./sites/api-http".to_string(),
-        default: false,
         api_only: true,
-        headers: HashMap::new(),
         redirect_to_https: true,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     let mut api_https_site = SiteConfig {
         name: "api-https".to_string(),
         hostname: "api.example.com".to_string(), // Same hostname as api_http_site, different port
-        hostnames: vec![],
         port: 443,
         static_dir: "./sites/api-https".to_string(),
-        default: false,
         api_only: true,
-        headers: HashMap::new(),
-        redirect_to_https: false,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     // Enable SSL for API HTTPS site
@@ -256,6 +194,9 @@ async fn test_virtual_hosting_mixed_ports() {
         email: "test@example.com".to_string(),
         staging: true,
         challenge_dir: None,
+        directory_url: None,
+        key_type: Default::default(),
+        renewal_days_before_expiry: 30,
     });
 
     let server_config = ServerConfig {
@@ -306,39 +247,20 @@ async fn test_virtual_hosting_conflict_detection() {
     let site1 = SiteConfig {
         name: "site1".to_string(),
         hostname: "example.com".to_string(),
-        hostnames: vec![],
         port: 8080,
         static_dir: "./sites/site1".to_string(),
         default: true,
-        api_only: false,
-        headers: HashMap::new(),
-        redirect_to_https: false,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     let site2 = SiteConfig {
         name: "site2".to_string(),
         hostname: "example.com".to_string(), // Same hostname as site1 - should conflict
-        hostnames: vec![],
-        port: 8080, // Same port as site1
+        port: 8080,                          // Same port as site1
         static_dir: "./sites/site2".to_string(),
-        default: false,
-        api_only: false,
-        headers: HashMap::new(),
-        redirect_to_https: false,
         index_files: vec!["index.html".to_string()],
-        error_pages: HashMap::new(),
-        compression: Default::default(),
-        cache: Default::default(),
-        access_control: Default::default(),
-        ssl: SiteSslConfig::default(),
-        proxy: Default::default(),
+        ..Default::default()
     };
 
     let server_config = ServerConfig {